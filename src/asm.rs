@@ -0,0 +1,240 @@
+//! Textual p-code assembler.
+//!
+//! Reads the format printed by [`Chunk::disassemble`], so programs can
+//! be written for the VM directly, without the PL/0 front end.
+//!
+//! Each line holds an optional label followed by a colon, and an optional
+//! instruction. Everything after a semicolon is a comment.
+//!
+//! ```text
+//! main:
+//!         int 0 3
+//!         lit 0 7
+//!         jpc 0 done      ; jump targets can be labels or addresses
+//!         opr 0 14
+//! done:
+//!         opr 0 0
+//! ```
+use std::collections::HashMap;
+
+use crate::bytecode::{Instr, OpCode};
+use crate::codegen_bytecode::constant_index;
+use crate::errors::Result;
+use crate::limits::CODE_SIZE;
+use crate::{error, Chunk, Num};
+
+/// Assemble textual p-code into an executable chunk.
+///
+/// The chunk has no debug info, since the comments that the
+/// disassembler prints aren't read back.
+pub fn assemble(filename: &str, text: &str) -> Result<Chunk> {
+    let lines = split_lines(text)
+        .into_iter()
+        .map(|line| tokenize(line, filename))
+        .collect::<Result<Vec<_>>>()?;
+
+    // First pass assigns addresses to labels, so jumps can refer
+    // to labels that are defined further down.
+    let mut labels: HashMap<&str, u16> = HashMap::new();
+    let mut addr: u16 = 0;
+    for line in &lines {
+        if let Some(label) = &line.label {
+            if labels.insert(label.text, addr).is_some() {
+                return error!(E0504, "label '{}' is already defined", label.text)
+                    .with_location(label.span, filename)
+                    .into();
+            }
+        }
+        if let Some(mnemonic) = &line.mnemonic {
+            if addr as usize == CODE_SIZE {
+                return error!(E0505, "program is too large")
+                    .with_location(mnemonic.span, filename)
+                    .with_note(format!("the limit is {CODE_SIZE} instructions"))
+                    .into();
+            }
+            addr += 1;
+        }
+    }
+
+    let mut code = vec![];
+    let mut constants = vec![];
+    for line in &lines {
+        if let Some(mnemonic) = &line.mnemonic {
+            code.push(assemble_instr(
+                mnemonic,
+                &line.operands,
+                &labels,
+                &mut constants,
+                filename,
+            )?);
+        }
+    }
+
+    let mut chunk = Chunk {
+        code,
+        constants,
+        debug: None,
+        stack: None,
+    };
+    chunk.stack = chunk.analyze_stack().ok();
+
+    Ok(chunk)
+}
+
+/// A word in the assembly text, with its byte offset and length.
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    span: (u32, u32),
+}
+
+struct Line<'a> {
+    label: Option<Word<'a>>,
+    mnemonic: Option<Word<'a>>,
+    operands: Vec<Word<'a>>,
+}
+
+/// Split the text into lines with comments removed, keeping
+/// track of the byte offset where each line starts.
+fn split_lines(text: &str) -> Vec<(u32, &str)> {
+    let mut offset = 0;
+    let mut lines = vec![];
+    for line in text.split_inclusive('\n') {
+        let code = line.split(';').next().unwrap_or_default();
+        lines.push((offset, code.trim_end()));
+        offset += line.len() as u32;
+    }
+    lines
+}
+
+fn tokenize<'a>((offset, line): (u32, &'a str), filename: &str) -> Result<Line<'a>> {
+    let mut words = line.split_whitespace().map(|text| {
+        let start = offset + (text.as_ptr() as usize - line.as_ptr() as usize) as u32;
+        Word {
+            text,
+            span: (start, text.len() as u32),
+        }
+    });
+
+    let mut result = Line {
+        label: None,
+        mnemonic: None,
+        operands: vec![],
+    };
+
+    let mut first = words.next();
+    if let Some(word) = first {
+        if let Some(name) = word.text.strip_suffix(':') {
+            if !is_label(name) {
+                return error!(E0503, "invalid label name '{name}'")
+                    .with_location(word.span, filename)
+                    .into();
+            }
+            result.label = Some(Word {
+                text: name,
+                span: (word.span.0, name.len() as u32),
+            });
+            first = words.next();
+        }
+    }
+
+    result.mnemonic = first;
+    result.operands = words.collect();
+
+    Ok(result)
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn assemble_instr(
+    mnemonic: &Word,
+    operands: &[Word],
+    labels: &HashMap<&str, u16>,
+    constants: &mut Vec<Num>,
+    filename: &str,
+) -> Result<Instr> {
+    let opcode = match mnemonic.text.to_ascii_lowercase().as_str() {
+        "nop" => OpCode::NoOp,
+        "lit" => OpCode::Lit,
+        "opr" => OpCode::Return, // Refined by the operation number below.
+        "lod" => OpCode::Load,
+        "sto" => OpCode::Store,
+        "cal" => OpCode::Call,
+        "tcl" => OpCode::TailCall,
+        "int" => OpCode::IncTop,
+        "jmp" => OpCode::Jump,
+        "jpc" => OpCode::JumpIfZero,
+        _ => {
+            return error!(E0501, "unknown mnemonic '{}'", mnemonic.text)
+                .with_location(mnemonic.span, filename)
+                .into()
+        }
+    };
+
+    // The operands of a no-op are meaningless, so they may be left out.
+    if opcode == OpCode::NoOp && operands.is_empty() {
+        return Ok(Instr::default());
+    }
+
+    let [l, a] = operands else {
+        let span = operands.last().map(|word| word.span).unwrap_or(mnemonic.span);
+        return error!(
+            E0503,
+            "'{}' expects 2 operands, found {}",
+            mnemonic.text,
+            operands.len()
+        )
+        .with_location(span, filename)
+        .into();
+    };
+
+    let l = parse_number::<u8>(l, "level", filename)?;
+
+    let (opcode, a) = match opcode {
+        OpCode::Return => {
+            let opr = parse_number::<u16>(a, "operation number", filename)?;
+            match OpCode::from_opr(opr) {
+                // The operation is encoded in the opcode instead.
+                Some(opcode) => (opcode, 0),
+                None => {
+                    return error!(E0503, "unknown operation number {opr}")
+                        .with_location(a.span, filename)
+                        .into()
+                }
+            }
+        }
+        // Literals that don't fit the operand go into the constant pool.
+        OpCode::Lit => {
+            let num = parse_number::<Num>(a, "literal", filename)?;
+            match u16::try_from(num) {
+                Ok(a) => (OpCode::Lit, a),
+                Err(_) => (OpCode::Const, constant_index(constants, num)),
+            }
+        }
+        OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall if is_label(a.text) => {
+            match labels.get(a.text) {
+                Some(addr) => (opcode, *addr),
+                None => {
+                    return error!(E0502, "undefined label '{}'", a.text)
+                        .with_location(a.span, filename)
+                        .into()
+                }
+            }
+        }
+        _ => (opcode, parse_number::<u16>(a, "argument", filename)?),
+    };
+
+    Ok(Instr { opcode, l, a })
+}
+
+fn parse_number<T: std::str::FromStr>(word: &Word, what: &str, filename: &str) -> Result<T> {
+    word.text.parse::<T>().or_else(|_| {
+        error!(E0503, "invalid {what} '{}'", word.text)
+            .with_location(word.span, filename)
+            .into()
+    })
+}
@@ -1,13 +1,15 @@
 #![allow(dead_code)]
+use std::fmt;
+
 use crate::errors::Error;
 use crate::Num;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Program {
     pub block: Block,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Block {
     pub consts: Vec<Const>,
     pub vars: Vec<Var>,
@@ -15,24 +17,24 @@ pub struct Block {
     pub stmt: Stmt,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Const {
     pub ident: Ident,
     pub value: Num,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Var {
     pub ident: Ident,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Proc {
     pub name: Ident,
     pub body: Block,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stmt {
     /// `<ident> := <expression>`
     Assign(Box<AssignStmt>),
@@ -56,42 +58,51 @@ pub struct ErrStmt {
     pub err: Error,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssignStmt {
     pub lhs: Ident,
     pub rhs: Expr,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CallStmt {
     pub name: Ident,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WriteStmt {
     pub expr: Expr,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ReadStmt {
     pub name: Ident,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubBlock {
     pub stmts: Vec<Stmt>,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IfStmt {
     pub head: Cond,
     pub body: Stmt,
+    /// Location of the `if <condition> then` header.
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WhileStmt {
     pub head: Cond,
     pub body: Stmt,
+    /// Location of the `while <condition> do` header.
+    pub span: (u32, u32),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -105,25 +116,25 @@ pub enum CondOp {
     GreatEq, // >=
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Cond {
     Odd(OddCond),
     Bin(BinaryCond),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OddCond {
     pub expr: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BinaryCond {
     pub op: CondOp,
     pub lhs: Expr,
     pub rhs: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expr {
     Num(Num),
     Unary(Box<UnExpr>),
@@ -138,19 +149,20 @@ pub struct ErrExpr {
     pub err: Error,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UnOp {
     Pos, // +
     Neg, // -
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnExpr {
     pub op: UnOp,
     pub expr: Expr,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BinOp {
     Add, // +
     Sub, // -
@@ -158,19 +170,35 @@ pub enum BinOp {
     Div, // /
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BinExpr {
     pub op: BinOp,
     pub lhs: Expr,
     pub rhs: Expr,
+    pub span: (u32, u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ident {
     pub name: String,
+    /// Location of the identifier in the source text.
+    pub span: (u32, u32),
 }
 
 impl Stmt {
+    /// Location of the statement in the source text.
+    pub fn span(&self) -> (u32, u32) {
+        match self {
+            Self::Assign(stmt) => stmt.span,
+            Self::Call(stmt) => stmt.span,
+            Self::Read(stmt) => stmt.span,
+            Self::Write(stmt) => stmt.span,
+            Self::SubBlock(stmt) => stmt.span,
+            Self::If(stmt) => stmt.span,
+            Self::While(stmt) => stmt.span,
+        }
+    }
+
     pub fn as_writeln(&self) -> Option<&WriteStmt> {
         match self {
             Self::Write(stmt) => Some(stmt),
@@ -194,3 +222,141 @@ impl Expr {
         }
     }
 }
+
+/// Prints the program as source text, which parses back into the same tree.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_block(&self.block, f, 0, 0)?;
+        writeln!(f, ".")
+    }
+}
+
+/// Indentation of one nesting level.
+const INDENT: &str = "    ";
+
+/// Write a block, with its declarations at `decl_indent` and its statement
+/// at `indent`. The statement is left without a line break.
+fn fmt_block(block: &Block, f: &mut fmt::Formatter, decl_indent: usize, indent: usize) -> fmt::Result {
+    let pad = INDENT.repeat(decl_indent);
+    if !block.consts.is_empty() {
+        write!(f, "{pad}const ")?;
+        for (idx, const_) in block.consts.iter().enumerate() {
+            let sep = if idx > 0 { ", " } else { "" };
+            write!(f, "{sep}{} = {}", const_.ident.name, const_.value)?;
+        }
+        writeln!(f, ";")?;
+    }
+    if !block.vars.is_empty() {
+        write!(f, "{pad}var ")?;
+        for (idx, var) in block.vars.iter().enumerate() {
+            let sep = if idx > 0 { ", " } else { "" };
+            write!(f, "{sep}{}", var.ident.name)?;
+        }
+        writeln!(f, ";")?;
+    }
+    for proc in &block.procs {
+        writeln!(f, "{pad}procedure {};", proc.name.name)?;
+        fmt_block(&proc.body, f, decl_indent + 1, decl_indent)?;
+        writeln!(f, ";")?;
+    }
+    fmt_stmt(&block.stmt, f, indent)
+}
+
+fn fmt_stmt(stmt: &Stmt, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    let pad = INDENT.repeat(indent);
+    match stmt {
+        Stmt::Assign(assign) => write!(f, "{pad}{} := {}", assign.lhs.name, assign.rhs),
+        Stmt::Call(call) => write!(f, "{pad}call {}", call.name.name),
+        Stmt::Read(read) => write!(f, "{pad}read {}", read.name.name),
+        Stmt::Write(write) => write!(f, "{pad}write {}", write.expr),
+        Stmt::SubBlock(sub_block) => {
+            writeln!(f, "{pad}begin")?;
+            for (idx, stmt) in sub_block.stmts.iter().enumerate() {
+                if idx > 0 {
+                    writeln!(f, ";")?;
+                }
+                fmt_stmt(stmt, f, indent + 1)?;
+            }
+            write!(f, "\n{pad}end")
+        }
+        Stmt::If(if_stmt) => {
+            writeln!(f, "{pad}if {} then", if_stmt.head)?;
+            fmt_body(&if_stmt.body, f, indent)
+        }
+        Stmt::While(while_stmt) => {
+            writeln!(f, "{pad}while {} do", while_stmt.head)?;
+            fmt_body(&while_stmt.body, f, indent)
+        }
+    }
+}
+
+/// Write the body of an `if` or `while`, where `begin` lines up with the header.
+fn fmt_body(body: &Stmt, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    match body {
+        Stmt::SubBlock(_) => fmt_stmt(body, f, indent),
+        _ => fmt_stmt(body, f, indent + 1),
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cond::Odd(odd) => write!(f, "odd {}", odd.expr),
+            Cond::Bin(bin) => {
+                let op = match bin.op {
+                    CondOp::Eq => "=",
+                    CondOp::NotEq => "#",
+                    CondOp::Less => "<",
+                    CondOp::LessEq => "<=",
+                    CondOp::Great => ">",
+                    CondOp::GreatEq => ">=",
+                };
+                write!(f, "{} {op} {}", bin.lhs, bin.rhs)
+            }
+        }
+    }
+}
+
+/// Prints the expression with the fewest parentheses the grammar allows.
+///
+/// A sign can only start an expression, and operators of the same
+/// precedence associate to the left.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Binary(binary) if matches!(binary.op, BinOp::Add | BinOp::Sub) => {
+                let op = if binary.op == BinOp::Add { "+" } else { "-" };
+                write!(f, "{} {op} ", binary.lhs)?;
+                fmt_term(&binary.rhs, f)
+            }
+            Expr::Unary(unary) => {
+                f.write_str(if unary.op == UnOp::Pos { "+" } else { "-" })?;
+                fmt_term(&unary.expr, f)
+            }
+            // Literals can't be negative, so negative numbers are negated.
+            Expr::Num(Num::MIN) => write!(f, "-{} - 1", Num::MAX),
+            Expr::Num(num) if *num < 0 => write!(f, "-{}", num.unsigned_abs()),
+            _ => fmt_term(self, f),
+        }
+    }
+}
+
+fn fmt_term(expr: &Expr, f: &mut fmt::Formatter) -> fmt::Result {
+    match expr {
+        Expr::Binary(binary) if matches!(binary.op, BinOp::Mul | BinOp::Div) => {
+            fmt_term(&binary.lhs, f)?;
+            f.write_str(if binary.op == BinOp::Mul { " * " } else { " / " })?;
+            fmt_factor(&binary.rhs, f)
+        }
+        _ => fmt_factor(expr, f),
+    }
+}
+
+fn fmt_factor(expr: &Expr, f: &mut fmt::Formatter) -> fmt::Result {
+    match expr {
+        Expr::Num(num) if *num >= 0 => write!(f, "{num}"),
+        Expr::Name(name) => f.write_str(&name.name),
+        Expr::Err() => f.write_str("<error>"),
+        _ => write!(f, "({expr})"),
+    }
+}
@@ -1,19 +1,375 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{env, fs};
 
-use pl0;
+use pl0::fuzz::{FuzzCase, Fuzzer};
+use pl0::{self, ErrorCode};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_path: &str = args.get(1).expect("no file path given");
+const USAGE: &str = "usage:
+    pl0 [options] <file>  compile and run a PL/0 program, or run a compiled .pl0c chunk
+    pl0 build <file> [-o <output>]
+                          compile a PL/0 program into a .pl0c chunk
+    pl0 disasm <file>     print the compiled bytecode of a PL/0 program or chunk
+    pl0 emit-c <file> [-o <output>]
+                          translate a PL/0 program into C, printed to stdout
+    pl0 emit-js <file> [-o <output>]
+                          translate a PL/0 program into JavaScript, printed to stdout
+    pl0 emit-llvm <file> [-o <output>]
+                          compile a PL/0 program into LLVM IR, printed to stdout
+    pl0 emit-x86 <file> [-o <output>]
+                          compile a PL/0 program into x86-64 assembly for Linux,
+                          printed to stdout
+    pl0 emit-wasm <file> [-o <output>]
+                          compile a PL/0 program into a .wasm module
+    pl0 emit-wat <file> [-o <output>]
+                          compile a PL/0 program into a WebAssembly text module,
+                          printed to stdout
+    pl0 explain <code>    print a detailed explanation of an error code
+    pl0 fuzz [<runs>]     run random programs with every engine available, and
+                          print the smallest programs they disagree on
+
+options:
+    --error-format=<human|json>
+                          how diagnostics are printed to stderr
+    --opt-level=<0|1|2>   optimization level, 1 runs the peephole optimizer,
+                          2 also propagates constants, inlines small
+                          procedures and removes dead code
+    --inline-threshold=<n>
+                          longest procedure body, in instructions, inlined at
+                          --opt-level=2 (default 8)
+    --report-dead-code    print the dead code removed at --opt-level=2
+    --seed=<n>            first random seed of `fuzz` (default from the clock)
+    -o <output>           file to write a compiled chunk or translated program to";
+
+/// File extension of compiled chunks.
+const CHUNK_EXTENSION: &str = "pl0c";
+
+/// How diagnostics are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    /// Annotated, human readable text.
+    Human,
+    /// One JSON object per line, per diagnostic.
+    Json,
+}
+
+struct Options {
+    error_format: ErrorFormat,
+    /// Output file of `build` and the `emit-*` commands.
+    output: Option<String>,
+    compile: pl0::CompileOptions,
+    /// Print the code removed by dead code elimination.
+    report_dead_code: bool,
+    /// First seed of `fuzz`.
+    seed: Option<u64>,
+    /// Positional arguments remaining after options are removed.
+    args: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match options.args.first().map(String::as_str) {
+        Some("build") => build(options.args.get(1).map(String::as_str), &options),
+        Some("disasm") => disasm(options.args.get(1).map(String::as_str), &options),
+        Some("emit-c") => emit_text(options.args.get(1).map(String::as_str), &options, pl0::compile_to_c),
+        Some("emit-js") => emit_text(options.args.get(1).map(String::as_str), &options, pl0::compile_to_js),
+        Some("emit-llvm") => emit_text(options.args.get(1).map(String::as_str), &options, pl0::compile_to_llvm),
+        Some("emit-x86") => emit_text(options.args.get(1).map(String::as_str), &options, pl0::compile_to_x86),
+        Some("emit-wasm") => emit_wasm(options.args.get(1).map(String::as_str), &options),
+        Some("emit-wat") => emit_text(options.args.get(1).map(String::as_str), &options, pl0::compile_to_wat),
+        Some("explain") => explain(options.args.get(1).map(String::as_str)),
+        Some("fuzz") => fuzz(options.args.get(1).map(String::as_str), &options),
+        Some(file_path) => run(file_path, &options),
+        None => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        error_format: ErrorFormat::Human,
+        output: None,
+        compile: pl0::CompileOptions::new(),
+        report_dead_code: false,
+        seed: None,
+        args: vec![],
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            match args.next() {
+                Some(output) => options.output = Some(output),
+                None => return Err("option \"-o\" requires a file name".to_string()),
+            }
+        } else if let Some(format) = arg.strip_prefix("--error-format=") {
+            options.error_format = match format {
+                "human" => ErrorFormat::Human,
+                "json" => ErrorFormat::Json,
+                _ => return Err(format!("unknown error format {format:?}")),
+            };
+        } else if let Some(level) = arg.strip_prefix("--opt-level=") {
+            options.compile.opt_level = match level.parse::<u8>() {
+                Ok(level @ 0..=2) => level,
+                _ => return Err(format!("unknown optimization level {level:?}")),
+            };
+        } else if let Some(threshold) = arg.strip_prefix("--inline-threshold=") {
+            options.compile.inline_threshold = match threshold.parse() {
+                Ok(threshold) => threshold,
+                _ => return Err(format!("invalid inline threshold {threshold:?}")),
+            };
+        } else if arg == "--report-dead-code" {
+            options.report_dead_code = true;
+        } else if let Some(seed) = arg.strip_prefix("--seed=") {
+            options.seed = match seed.parse() {
+                Ok(seed) => Some(seed),
+                _ => return Err(format!("invalid seed {seed:?}")),
+            };
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option {arg:?}"));
+        } else {
+            options.args.push(arg);
+        }
+    }
+
+    Ok(options)
+}
+
+fn run(file_path: &str, options: &Options) -> ExitCode {
+    let (result, source_text) = load(file_path, options);
+
+    let result = result.and_then(|chunk| {
+        chunk.verify()?;
+        let mut vm = pl0::Vm::new();
+        vm.eval(&chunk)
+    });
+
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            report(&err, source_text.as_str(), options);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Print a diagnostic to stderr in the requested format.
+fn report(err: &pl0::Error, source_text: &str, options: &Options) {
+    match options.error_format {
+        ErrorFormat::Human => eprintln!("{}", err.pretty(source_text)),
+        ErrorFormat::Json => eprintln!("{}", err.json(source_text)),
+    }
+}
+
+/// Compile a source file, or load a compiled chunk, depending on the file extension.
+///
+/// Also returns the source text, for reporting diagnostics. For chunks
+/// with debug info, the source file is read if it can still be found.
+/// Diagnostics fall back to the chunk's line table when it's missing or
+/// has changed since.
+fn load(file_path: &str, options: &Options) -> (pl0::Result<pl0::Chunk>, String) {
+    if Path::new(file_path).extension() == Some(OsStr::new(CHUNK_EXTENSION)) {
+        let bytes = fs::read(file_path).expect("read chunk file");
+        let result = pl0::Chunk::from_bytes(&bytes);
+        let source_text = result
+            .as_ref()
+            .ok()
+            .and_then(|chunk| chunk.debug_info())
+            .and_then(|debug| fs::read_to_string(&debug.file).ok())
+            .unwrap_or_default();
+        (result, source_text)
+    } else {
+        let source_text = fs::read_to_string(file_path).expect("read source file");
+        let result = compile(file_path, source_text.as_str(), options);
+        (result, source_text)
+    }
+}
+
+/// Compile a source file, printing the removed dead code if asked to.
+fn compile(file_path: &str, source_text: &str, options: &Options) -> pl0::Result<pl0::Chunk> {
+    let (chunk, _, removed) = pl0::compile_with_report(file_path, source_text, &options.compile)?;
+    if options.report_dead_code {
+        for removed in removed {
+            eprintln!("{file_path}:{}: removed dead code: {removed}", removed.line);
+        }
+    }
+    Ok(chunk)
+}
+
+fn build(file_path: Option<&str>, options: &Options) -> ExitCode {
+    let Some(file_path) = file_path else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
     let source_text = fs::read_to_string(file_path).expect("read source file");
 
-    match pl0::compile(file_path, source_text.as_str()) {
+    match compile(file_path, source_text.as_str(), options) {
+        Ok(chunk) => {
+            let output = match &options.output {
+                Some(output) => PathBuf::from(output),
+                None => Path::new(file_path).with_extension(CHUNK_EXTENSION),
+            };
+            write_output(&output, &chunk.to_bytes())
+        }
+        Err(err) => {
+            report(&err, source_text.as_str(), options);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn disasm(file_path: Option<&str>, options: &Options) -> ExitCode {
+    let Some(file_path) = file_path else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let (result, source_text) = load(file_path, options);
+
+    match result {
         Ok(chunk) => {
-            let mut vm = pl0::Vm::new();
-            vm.eval(&chunk);
+            print!("{}", chunk.disassemble_with_source(source_text.as_str()));
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            report(&err, source_text.as_str(), options);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Translate a source file into another language, printed to stdout unless
+/// an output file is given.
+fn emit_text(
+    file_path: Option<&str>,
+    options: &Options,
+    translate: fn(&str, &str, &pl0::CompileOptions) -> pl0::Result<String>,
+) -> ExitCode {
+    let Some(file_path) = file_path else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let source_text = fs::read_to_string(file_path).expect("read source file");
+
+    match translate(file_path, source_text.as_str(), &options.compile) {
+        Ok(translated) => match &options.output {
+            Some(output) => write_output(Path::new(output), translated.as_bytes()),
+            None => {
+                print!("{translated}");
+                ExitCode::SUCCESS
+            }
+        },
+        Err(err) => {
+            report(&err, source_text.as_str(), options);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn emit_wasm(file_path: Option<&str>, options: &Options) -> ExitCode {
+    let Some(file_path) = file_path else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let source_text = fs::read_to_string(file_path).expect("read source file");
+
+    match pl0::compile_to_wasm(file_path, source_text.as_str(), &options.compile) {
+        Ok(module) => {
+            let output = match &options.output {
+                Some(output) => PathBuf::from(output),
+                None => Path::new(file_path).with_extension("wasm"),
+            };
+            write_output(&output, &module)
         }
         Err(err) => {
-            eprintln!("{}", err.pretty(source_text.as_str()))
+            report(&err, source_text.as_str(), options);
+            ExitCode::FAILURE
         }
     }
 }
+
+fn write_output(output: &Path, contents: &[u8]) -> ExitCode {
+    match fs::write(output, contents) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: failed to write {}: {err}", output.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn explain(code: Option<&str>) -> ExitCode {
+    let Some(code) = code else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    match ErrorCode::lookup(code) {
+        Some(code) => {
+            println!("{code}: {}\n", code.summary());
+            print!("{}", code.explain());
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprintln!("error: no explanation for unknown error code {code:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run generated programs with every engine, and print the smallest
+/// program for each one they disagree on.
+fn fuzz(runs: Option<&str>, options: &Options) -> ExitCode {
+    let runs = match runs.map(str::parse::<u64>) {
+        None => 1000,
+        Some(Ok(runs)) => runs,
+        Some(Err(_)) => {
+            eprintln!(
+                "error: invalid number of runs {:?}\n\n{USAGE}",
+                runs.unwrap_or_default()
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let first = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    });
+
+    let fuzzer = Fuzzer::new();
+    let engines: Vec<String> = fuzzer.engines().iter().map(ToString::to_string).collect();
+    eprintln!("fuzzing {runs} programs from seed {first} with {}", engines.join(", "));
+
+    let mut failures = 0;
+    for seed in first..first.saturating_add(runs) {
+        let case = FuzzCase::generate(seed);
+        let Some(mismatch) = fuzzer.check(&case) else {
+            continue;
+        };
+        failures += 1;
+        let case = fuzzer.minimize(&case, mismatch.engine);
+        let mismatch = fuzzer.check_engine(&case, mismatch.engine).unwrap_or(mismatch);
+        println!("seed {seed}: {} disagrees with the interpreter", mismatch.engine);
+        println!("interpreter: {}", mismatch.expected);
+        println!("{}: {}", mismatch.engine, mismatch.actual);
+        println!("input: {:?}", case.input());
+        println!("{}", case.source());
+    }
+
+    eprintln!("{failures} of {runs} programs found disagreements");
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
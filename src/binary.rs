@@ -0,0 +1,275 @@
+//! Binary chunk format.
+//!
+//! Compiled chunks can be saved to disk and loaded again later, without
+//! the source text. All numbers are little-endian.
+//!
+//! ```text
+//! magic      4 bytes   "PL0C"
+//! version    u16       FORMAT_VERSION
+//! flags      u16       bit 0 is set when debug info follows
+//! code       u32 count, then per instruction:
+//!                opcode u8, l u8, a u16
+//! constants  u32 count, then per constant:
+//!                value i32
+//! debug      only when flagged:
+//!                file str
+//!                lines u32 count, then per entry:
+//!                    pc u16, line u32, span start u32, span length u32
+//!                procs u32 count, then per procedure:
+//!                    name str, addr u16, end u16, level u8,
+//!                    vars u32 count, then per variable:
+//!                        name str, offset u16
+//! ```
+//!
+//! Strings are a `u32` byte length followed by UTF-8 text.
+use crate::bytecode::{Instr, OpCode};
+use crate::debug::{DebugInfo, LineEntry, ProcInfo, VarInfo};
+use crate::errors::Result;
+use crate::limits::CODE_SIZE;
+use crate::{error, Chunk, Num};
+
+/// Bytes every binary chunk starts with.
+pub const MAGIC: &[u8; 4] = b"PL0C";
+
+/// Version of the binary chunk format.
+///
+/// Bumped whenever the encoding or the meaning of the bytecode changes.
+pub const FORMAT_VERSION: u16 = 3;
+
+/// Flag set when the chunk includes debug info.
+const FLAG_DEBUG_INFO: u16 = 1 << 0;
+
+impl Chunk {
+    /// Serialize the chunk into the binary chunk format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer { buf: vec![] };
+
+        w.buf.extend_from_slice(MAGIC);
+        w.u16(FORMAT_VERSION);
+        w.u16(if self.debug.is_some() { FLAG_DEBUG_INFO } else { 0 });
+
+        w.u32(self.code.len() as u32);
+        for instr in &self.code {
+            w.u8(instr.opcode.byte());
+            w.u8(instr.l);
+            w.u16(instr.a);
+        }
+
+        w.u32(self.constants.len() as u32);
+        for num in &self.constants {
+            w.buf.extend_from_slice(&num.to_le_bytes());
+        }
+
+        if let Some(debug) = &self.debug {
+            w.str(&debug.file);
+            w.u32(debug.lines.len() as u32);
+            for entry in &debug.lines {
+                w.u16(entry.pc);
+                w.u32(entry.line);
+                w.u32(entry.span.0);
+                w.u32(entry.span.1);
+            }
+            w.u32(debug.procs.len() as u32);
+            for proc in &debug.procs {
+                w.str(&proc.name);
+                w.u16(proc.addr);
+                w.u16(proc.end);
+                w.u8(proc.level);
+                w.u32(proc.vars.len() as u32);
+                for var in &proc.vars {
+                    w.str(&var.name);
+                    w.u16(var.offset);
+                }
+            }
+        }
+
+        w.buf
+    }
+
+    /// Load a chunk from the binary chunk format.
+    ///
+    /// The input is checked to be well formed, but the bytecode
+    /// itself isn't verified. See [`Chunk::verify`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Chunk> {
+        let mut r = Reader { bytes, pos: 0 };
+
+        if !bytes.starts_with(MAGIC) {
+            return error!(E0601, "not a compiled chunk, the magic bytes are missing").into();
+        }
+        r.pos = MAGIC.len();
+
+        let version = r.u16("version")?;
+        if version != FORMAT_VERSION {
+            return error!(E0602, "unsupported chunk format version {version}")
+                .with_note(format!("this build reads version {FORMAT_VERSION}"))
+                .into();
+        }
+
+        let flags = r.u16("flags")?;
+        if flags & !FLAG_DEBUG_INFO != 0 {
+            return error!(E0603, "unknown flags {flags:#06x}").into();
+        }
+
+        let code_len = r.u32("instruction count")? as usize;
+        if code_len > CODE_SIZE {
+            return error!(E0603, "chunk has too many instructions ({code_len})")
+                .with_note(format!("the limit is {CODE_SIZE}"))
+                .into();
+        }
+        let mut code = Vec::with_capacity(code_len);
+        for _ in 0..code_len {
+            let pos = r.pos;
+            let byte = r.u8("opcode")?;
+            let Some(opcode) = OpCode::from_byte(byte) else {
+                return error!(E0603, "unknown opcode {byte:#04x} at byte {pos}").into();
+            };
+            let l = r.u8("level")?;
+            let a = r.u16("argument")?;
+            code.push(Instr { opcode, l, a });
+        }
+
+        let mut constants: Vec<Num> = vec![];
+        for _ in 0..r.u32("constant count")? {
+            constants.push(r.u32("constant")? as Num);
+        }
+
+        for (pc, instr) in code.iter().enumerate() {
+            if instr.opcode == OpCode::Const && instr.a as usize >= constants.len() {
+                return error!(
+                    E0603,
+                    "instruction {pc:04} refers to constant #{}, but the pool has {} constants",
+                    instr.a,
+                    constants.len()
+                )
+                .into();
+            }
+        }
+
+        let debug = if flags & FLAG_DEBUG_INFO != 0 {
+            Some(r.debug_info()?)
+        } else {
+            None
+        };
+
+        if r.pos != bytes.len() {
+            return error!(
+                E0603,
+                "unexpected trailing data after byte {} of {}",
+                r.pos,
+                bytes.len()
+            )
+            .into();
+        }
+
+        let mut chunk = Chunk {
+            code,
+            constants,
+            debug,
+            stack: None,
+        };
+        // Stack usage isn't stored, since it can be recomputed from the code.
+        chunk.stack = chunk.analyze_stack().ok();
+
+        Ok(chunk)
+    }
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Take the next `n` bytes, failing if the input ends first.
+    fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8]> {
+        match self.bytes.get(self.pos..self.pos.saturating_add(n)) {
+            Some(slice) => {
+                self.pos += n;
+                Ok(slice)
+            }
+            None => error!(
+                E0603,
+                "chunk ends unexpectedly at byte {} while reading {what}",
+                self.bytes.len()
+            )
+            .into(),
+        }
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16> {
+        let bytes = self.take(2, what)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32> {
+        let bytes = self.take(4, what)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn str(&mut self, what: &str) -> Result<String> {
+        let len = self.u32(what)? as usize;
+        let pos = self.pos;
+        match std::str::from_utf8(self.take(len, what)?) {
+            Ok(text) => Ok(text.to_string()),
+            Err(_) => error!(E0603, "{what} at byte {pos} isn't valid UTF-8").into(),
+        }
+    }
+
+    fn debug_info(&mut self) -> Result<DebugInfo> {
+        let mut debug = DebugInfo::new(self.str("file name")?);
+
+        for _ in 0..self.u32("line count")? {
+            debug.lines.push(LineEntry {
+                pc: self.u16("line entry")?,
+                line: self.u32("line entry")?,
+                span: (self.u32("line entry")?, self.u32("line entry")?),
+            });
+        }
+
+        for _ in 0..self.u32("procedure count")? {
+            let mut proc = ProcInfo {
+                name: self.str("procedure name")?,
+                addr: self.u16("procedure")?,
+                end: self.u16("procedure")?,
+                level: self.u8("procedure")?,
+                vars: vec![],
+            };
+            for _ in 0..self.u32("variable count")? {
+                proc.vars.push(VarInfo {
+                    name: self.str("variable name")?,
+                    offset: self.u16("variable")?,
+                });
+            }
+            debug.procs.push(proc);
+        }
+
+        Ok(debug)
+    }
+}
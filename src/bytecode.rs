@@ -2,7 +2,7 @@
 #![allow(dead_code)]
 
 /// Bytecode instruction.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Instr {
     pub opcode: OpCode,
     /// Level.
@@ -25,6 +25,10 @@ pub enum OpCode {
     Store,
     /// Call a procedure.
     Call,
+    /// Call a procedure in place of the current one, reusing its frame.
+    ///
+    /// The callee returns straight to the current procedure's caller.
+    TailCall,
     /// Increase the stack top register by `a`.
     IncTop,
     Jump,
@@ -33,6 +37,12 @@ pub enum OpCode {
     // Extented operations not in original implementation.
     Write,
     Read,
+    /// Push a copy of the top of the stack.
+    Dup,
+    /// Push a number from the chunk's constant pool onto the top of the stack.
+    ///
+    /// Used for literals that don't fit in the `a` operand.
+    Const,
 }
 
 /// Arithmetic operator instruction types.
@@ -52,6 +62,162 @@ pub enum Math {
     LessEq,
 }
 
+/// Operation number of the `opr` instruction that returns from a procedure.
+pub const OPR_RETURN: u16 = 0;
+
+/// Operation number of the `opr` instruction that writes the top of the stack.
+pub const OPR_WRITE: u16 = 14;
+
+/// Operation number of the `opr` instruction that duplicates the top of the stack.
+pub const OPR_DUP: u16 = 15;
+
+/// Operation number of the `opr` instruction that reads a number onto the stack.
+pub const OPR_READ: u16 = 16;
+
+impl OpCode {
+    /// Mnemonic of the instruction in classic p-code.
+    ///
+    /// The return, arithmetic and IO operations are all variants of `opr`,
+    /// distinguished by their operation number. See [`OpCode::opr`].
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            OpCode::NoOp => "nop",
+            OpCode::Lit | OpCode::Const => "lit",
+            OpCode::Return | OpCode::Math(_) | OpCode::Write | OpCode::Read | OpCode::Dup => "opr",
+            OpCode::Load => "lod",
+            OpCode::Store => "sto",
+            OpCode::Call => "cal",
+            OpCode::TailCall => "tcl",
+            OpCode::IncTop => "int",
+            OpCode::Jump => "jmp",
+            OpCode::JumpIfZero => "jpc",
+        }
+    }
+
+    /// Operation number of an `opr` instruction, as in the original p-code.
+    pub fn opr(&self) -> Option<u16> {
+        match self {
+            OpCode::Return => Some(OPR_RETURN),
+            OpCode::Math(m) => Some(m.opr()),
+            OpCode::Write => Some(OPR_WRITE),
+            OpCode::Dup => Some(OPR_DUP),
+            OpCode::Read => Some(OPR_READ),
+            _ => None,
+        }
+    }
+
+    /// Decode the operation number of an `opr` instruction.
+    pub fn from_opr(opr: u16) -> Option<OpCode> {
+        match opr {
+            OPR_RETURN => Some(OpCode::Return),
+            OPR_WRITE => Some(OpCode::Write),
+            OPR_DUP => Some(OpCode::Dup),
+            OPR_READ => Some(OpCode::Read),
+            _ => Math::from_opr(opr).map(OpCode::Math),
+        }
+    }
+}
+
+impl OpCode {
+    /// Byte identifying the opcode in the binary chunk format.
+    ///
+    /// The `opr` family is encoded as `0x40` plus the operation number.
+    pub fn byte(&self) -> u8 {
+        match self {
+            OpCode::NoOp => 0x00,
+            OpCode::Lit => 0x01,
+            OpCode::Load => 0x03,
+            OpCode::Store => 0x04,
+            OpCode::Call => 0x05,
+            OpCode::IncTop => 0x06,
+            OpCode::Jump => 0x07,
+            OpCode::JumpIfZero => 0x08,
+            OpCode::Const => 0x09,
+            OpCode::TailCall => 0x0a,
+            OpCode::Return | OpCode::Math(_) | OpCode::Write | OpCode::Read | OpCode::Dup => {
+                0x40 + self.opr().unwrap_or_default() as u8
+            }
+        }
+    }
+
+    /// Decode an opcode from the binary chunk format.
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        match byte {
+            0x00 => Some(OpCode::NoOp),
+            0x01 => Some(OpCode::Lit),
+            0x03 => Some(OpCode::Load),
+            0x04 => Some(OpCode::Store),
+            0x05 => Some(OpCode::Call),
+            0x06 => Some(OpCode::IncTop),
+            0x07 => Some(OpCode::Jump),
+            0x08 => Some(OpCode::JumpIfZero),
+            0x09 => Some(OpCode::Const),
+            0x0a => Some(OpCode::TailCall),
+            0x40.. => OpCode::from_opr((byte - 0x40) as u16),
+            _ => None,
+        }
+    }
+}
+
+impl Math {
+    pub const ALL: [Math; 12] = [
+        Math::Neg,
+        Math::Add,
+        Math::Sub,
+        Math::Mul,
+        Math::Div,
+        Math::Odd,
+        Math::Eq,
+        Math::NotEq,
+        Math::Less,
+        Math::GreatEq,
+        Math::Great,
+        Math::LessEq,
+    ];
+
+    /// Operation number of the `opr` instruction, as in the original p-code.
+    #[rustfmt::skip]
+    pub fn opr(&self) -> u16 {
+        match self {
+            Math::Neg     => 1,
+            Math::Add     => 2,
+            Math::Sub     => 3,
+            Math::Mul     => 4,
+            Math::Div     => 5,
+            Math::Odd     => 6,
+            Math::Eq      => 8,
+            Math::NotEq   => 9,
+            Math::Less    => 10,
+            Math::GreatEq => 11,
+            Math::Great   => 12,
+            Math::LessEq  => 13,
+        }
+    }
+
+    pub fn from_opr(opr: u16) -> Option<Math> {
+        Math::ALL.iter().copied().find(|m| m.opr() == opr)
+    }
+
+    /// Short name of the operation.
+    #[rustfmt::skip]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Math::Neg     => "neg",
+            Math::Add     => "add",
+            Math::Sub     => "sub",
+            Math::Mul     => "mul",
+            Math::Div     => "div",
+            Math::Odd     => "odd",
+            Math::Eq      => "eq",
+            Math::NotEq   => "neq",
+            Math::Less    => "lt",
+            Math::GreatEq => "gte",
+            Math::Great   => "gt",
+            Math::LessEq  => "lte",
+        }
+    }
+}
+
 impl Default for Instr {
     fn default() -> Self {
         Self {
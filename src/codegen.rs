@@ -1,7 +1,56 @@
 //! Code generator interface.
 use crate::errors::Result;
+use crate::ir::{self, BinaryOp, BlockId, InstKind, ProcId, Term, UnaryOp};
+
+/// A position in the generated code that jumps can target.
+///
+/// Labels are handed out by [`CodeGen::new_label`], and bound to a
+/// position with [`CodeGen::bind_label`]. Jumps may refer to a label
+/// before it's bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(u32);
+
+impl Label {
+    pub fn new(index: usize) -> Self {
+        Label(index as u32)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
 
 pub trait CodeGen {
+    /// The generated program.
+    type Output;
+
+    /// Set the location of the source fragment that subsequently
+    /// emitted code is generated from.
+    fn set_span(&mut self, span: (u32, u32));
+
+    /// Start emitting a procedure, whose variables live at `level`.
+    ///
+    /// The procedures nested in it are emitted next, each one between its
+    /// own `begin_proc` and [`CodeGen::end_proc`], followed by the
+    /// procedure's body, which starts with [`CodeGen::begin_body`].
+    fn begin_proc(&mut self, id: ProcId, name: &str, level: u8) -> Result<()>;
+
+    /// Declare a variable in the stack frame of the current procedure.
+    fn declare_var(&mut self, name: &str, offset: u16);
+
+    /// Start emitting the current procedure's body, with a stack frame
+    /// of `frame` slots, including the block mark.
+    fn begin_body(&mut self, frame: u16) -> Result<()>;
+
+    /// Finish emitting the code of the current procedure.
+    fn end_proc(&mut self);
+
+    /// Create a label, to be bound later.
+    fn new_label(&mut self) -> Label;
+
+    /// Bind a label to the position of the next emitted code.
+    fn bind_label(&mut self, label: Label) -> Result<()>;
+
     fn emit_lit(&mut self, num: i32) -> Result<()>;
     fn emit_return(&mut self) -> Result<()>;
     fn emit_math_neg(&mut self) -> Result<()>;
@@ -16,16 +65,118 @@ pub trait CodeGen {
     fn emit_math_gte(&mut self) -> Result<()>;
     fn emit_math_gt(&mut self) -> Result<()>;
     fn emit_math_lte(&mut self) -> Result<()>;
-    fn emit_load(&mut self, level: u8, addr: u16) -> Result<()>;
-    fn emit_store(&mut self, level: u8, addr: u16) -> Result<()>;
-    fn emit_call(&mut self, level: u8, addr: u16) -> Result<()>;
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()>;
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()>;
+    /// Call a procedure, whose static link is `level` frames up from the caller's.
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()>;
+    /// Call a procedure in place of the current one, which must
+    /// return right after the call.
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()>;
     fn emit_write(&mut self) -> Result<()>;
     fn emit_read(&mut self) -> Result<()>;
-    fn emit_inc_top(&mut self, offset: u16) -> Result<()>;
-    fn emit_jump(&mut self, addr: u16) -> Result<()>;
-    fn reserve_jump(&mut self) -> Result<usize>;
-    fn reserve_jump_if_zero(&mut self) -> Result<usize>;
-    fn patch_jump(&mut self, index: usize, addr: u16) -> Result<()>;
+    fn emit_jump(&mut self, label: Label) -> Result<()>;
+    /// Pop the top of the stack, and jump if it's zero.
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()>;
+
+    /// Finish generating code, once every procedure has ended.
+    fn finish(self) -> Self::Output;
+}
+
+/// Generate code for a program through a code generator.
+pub(crate) fn emit_program<C: CodeGen>(program: &ir::Program, codegen: &mut C) -> Result<()> {
+    emit_proc(program, ProcId::MAIN, codegen)
+}
+
+fn emit_proc<C: CodeGen>(program: &ir::Program, id: ProcId, codegen: &mut C) -> Result<()> {
+    let proc = program.proc(id);
+    if let Some(span) = proc.span {
+        codegen.set_span(span);
+    }
+    codegen.begin_proc(id, &proc.name, proc.level)?;
+    for var in &proc.vars {
+        codegen.declare_var(&var.name, var.offset);
+    }
+    for child in program.children(id) {
+        emit_proc(program, child, codegen)?;
+    }
+
+    codegen.set_span(proc.body_span);
+    codegen.begin_body(proc.frame)?;
+    emit_blocks(proc, codegen)?;
+
+    codegen.end_proc();
+    Ok(())
+}
+
+/// Generate the code of a procedure's blocks, in order.
+///
+/// Jumps to the next block fall through instead. Temporaries are kept on
+/// the operand stack, which works because they're used in stack order.
+fn emit_blocks<C: CodeGen>(proc: &ir::Proc, codegen: &mut C) -> Result<()> {
+    let labels: Vec<Label> = proc.blocks.iter().map(|_| codegen.new_label()).collect();
+    let mut stack = vec![];
+
+    for (idx, block) in proc.blocks.iter().enumerate() {
+        codegen.bind_label(labels[idx])?;
+        for inst in &block.insts {
+            for temp in inst.kind.uses().into_iter().rev() {
+                debug_assert_eq!(stack.pop(), Some(temp), "temporaries are used in stack order");
+            }
+            stack.extend(inst.kind.dst());
+
+            codegen.set_span(inst.span);
+            match inst.kind {
+                InstKind::Const { value, .. } => codegen.emit_lit(value)?,
+                InstKind::Load { var, .. } => codegen.emit_load(var.level, var.offset)?,
+                InstKind::Store { var, .. } => codegen.emit_store(var.level, var.offset)?,
+                InstKind::Unary { op, .. } => match op {
+                    UnaryOp::Neg => codegen.emit_math_neg()?,
+                    UnaryOp::Odd => codegen.emit_math_odd()?,
+                },
+                InstKind::Binary { op, .. } => match op {
+                    BinaryOp::Add => codegen.emit_math_add()?,
+                    BinaryOp::Sub => codegen.emit_math_sub()?,
+                    BinaryOp::Mul => codegen.emit_math_mul()?,
+                    BinaryOp::Div => codegen.emit_math_div()?,
+                    BinaryOp::Eq => codegen.emit_math_eq()?,
+                    BinaryOp::NotEq => codegen.emit_math_noteq()?,
+                    BinaryOp::Less => codegen.emit_math_lt()?,
+                    BinaryOp::LessEq => codegen.emit_math_lte()?,
+                    BinaryOp::Great => codegen.emit_math_gt()?,
+                    BinaryOp::GreatEq => codegen.emit_math_gte()?,
+                },
+                InstKind::Call { level, proc } => codegen.emit_call(level, proc)?,
+                InstKind::Read { .. } => codegen.emit_read()?,
+                InstKind::Write { .. } => codegen.emit_write()?,
+            }
+        }
+
+        let next = BlockId::new(idx + 1);
+        match block.term {
+            Term::Jump(target) if target == next => {}
+            Term::Jump(target) => {
+                codegen.set_span(block.span);
+                codegen.emit_jump(labels[target.index()])?;
+            }
+            Term::Branch { cond, then, otherwise } => {
+                debug_assert_eq!(stack.pop(), Some(cond), "temporaries are used in stack order");
+                codegen.set_span(block.span);
+                codegen.emit_jump_if_zero(labels[otherwise.index()])?;
+                if then != next {
+                    codegen.emit_jump(labels[then.index()])?;
+                }
+            }
+            Term::Return => {
+                codegen.set_span(block.span);
+                codegen.emit_return()?;
+            }
+            Term::TailCall { level, proc } => {
+                codegen.set_span(block.span);
+                codegen.emit_tail_call(level, proc)?;
+            }
+        }
+        debug_assert!(stack.is_empty(), "temporaries don't outlive their block");
+    }
 
-    fn len(&self) -> usize;
+    Ok(())
 }
@@ -1,241 +1,388 @@
+use std::collections::HashMap;
+
 use crate::bytecode::{Instr, Math, OpCode};
-use crate::codegen::CodeGen;
+use crate::codegen::{CodeGen, Label};
+use crate::debug::{self, DebugInfo, ProcInfo, VarInfo};
 use crate::errors::Result;
-use crate::Chunk;
+use crate::ir::ProcId;
+use crate::limits::CODE_SIZE;
+use crate::{error, Chunk, Num};
 
 pub struct BytecodeGen {
     buf: Vec<Instr>,
+    /// Constant pool, for literals too large for an instruction operand.
+    constants: Vec<Num>,
+    /// Debug info being built, if requested.
+    debug: Option<DebugInfo>,
+    /// Byte offsets of line starts in the source text, for the line table.
+    line_starts: Vec<u32>,
+    /// Indices into the debug info's procedure table of the
+    /// procedures currently being emitted.
+    proc_stack: Vec<usize>,
+    /// Jumps over the nested procedures of the procedures currently
+    /// being emitted, patched when their body starts.
+    body_jumps: Vec<usize>,
+    /// Entry address of each procedure emitted so far.
+    procs: HashMap<ProcId, u16>,
+    /// Address each label is bound to, once it is.
+    labels: Vec<Option<u16>>,
+    /// Jumps to labels and calls to procedures that weren't emitted yet.
+    fixups: Vec<(usize, Target)>,
+    /// Source fragment being emitted, for errors.
+    span: (u32, u32),
+}
+
+/// What an instruction refers to before its address is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Label(Label),
+    Proc(ProcId),
 }
 
 impl BytecodeGen {
-    pub fn new() -> Self {
-        Self { buf: vec![] }
+    /// Create a generator that also builds a line table
+    /// mapping bytecode to the given source.
+    pub fn with_debug_info(file: &str, text: &str) -> Self {
+        Self {
+            buf: vec![],
+            constants: vec![],
+            debug: Some(DebugInfo::new(file)),
+            line_starts: debug::line_starts(text),
+            proc_stack: vec![],
+            body_jumps: vec![],
+            procs: HashMap::new(),
+            labels: vec![],
+            fixups: vec![],
+            span: (0, 0),
+        }
+    }
+
+    /// Entry address of a procedure, once it's emitted.
+    pub fn proc_addr(&self, id: ProcId) -> Option<u16> {
+        self.procs.get(&id).copied()
     }
 
     pub fn make_chunk(&mut self) -> Chunk {
+        assert!(self.fixups.is_empty(), "jump to a label that's never bound");
         Chunk {
             code: std::mem::take(&mut self.buf),
+            constants: std::mem::take(&mut self.constants),
+            debug: self.debug.take(),
+            stack: None,
         }
     }
 }
 
 impl CodeGen for BytecodeGen {
-    fn emit_lit(&mut self, num: i32) -> Result<()> {
-        self.buf.push(Instr {
-            opcode: OpCode::Lit,
+    type Output = Chunk;
+
+    fn set_span(&mut self, span: (u32, u32)) {
+        self.span = span;
+        let addr = self.addr();
+        if let Some(debug) = self.debug.as_mut() {
+            let line = debug::line_of(&self.line_starts, span.0);
+            debug.add_line(addr, line, span);
+        }
+    }
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, level: u8) -> Result<()> {
+        let addr = self.addr();
+        self.procs.insert(id, addr);
+        self.resolve(Target::Proc(id), addr);
+
+        if let Some(debug) = self.debug.as_mut() {
+            self.proc_stack.push(debug.procs.len());
+            debug.procs.push(ProcInfo {
+                name: name.to_string(),
+                addr,
+                end: addr,
+                level,
+                vars: vec![],
+            });
+        }
+
+        // The nested procedures come first, so the body has to be jumped to.
+        self.body_jumps.push(self.buf.len());
+        self.push(Instr {
+            opcode: OpCode::Jump,
             l: 0,
-            a: num as u16,
-        });
-        Ok(())
+            a: 0,
+        })
+    }
+
+    fn declare_var(&mut self, name: &str, offset: u16) {
+        if let (Some(debug), Some(idx)) = (self.debug.as_mut(), self.proc_stack.last()) {
+            debug.procs[*idx].vars.push(VarInfo {
+                name: name.to_string(),
+                offset,
+            });
+        }
+    }
+
+    fn begin_body(&mut self, frame: u16) -> Result<()> {
+        let jump = self.body_jumps.pop().expect("body of a procedure that was never begun");
+        self.buf[jump].a = self.addr();
+
+        // The stack space required by a procedure is encoded in this bytecode.
+        self.push(Instr {
+            opcode: OpCode::IncTop,
+            l: 0,
+            a: frame,
+        })
+    }
+
+    fn end_proc(&mut self) {
+        let addr = self.addr();
+        if let (Some(debug), Some(idx)) = (self.debug.as_mut(), self.proc_stack.pop()) {
+            debug.procs[idx].end = addr;
+        }
+    }
+
+    fn emit_lit(&mut self, num: i32) -> Result<()> {
+        let instr = match u16::try_from(num) {
+            Ok(a) => Instr {
+                opcode: OpCode::Lit,
+                l: 0,
+                a,
+            },
+            Err(_) => Instr {
+                opcode: OpCode::Const,
+                l: 0,
+                a: constant_index(&mut self.constants, num),
+            },
+        };
+        self.push(instr)
     }
 
     fn emit_return(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Return,
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_neg(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Neg),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_add(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Add),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_sub(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Sub),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_mul(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Mul),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_div(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Div),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_odd(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Odd),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_eq(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Eq),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_noteq(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::NotEq),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_lt(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Less),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_gte(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::GreatEq),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_gt(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::Great),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_math_lte(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Math(Math::LessEq),
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
-    fn emit_load(&mut self, level: u8, addr: u16) -> Result<()> {
-        self.buf.push(Instr {
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.push(Instr {
             opcode: OpCode::Load,
             l: level,
-            a: addr,
-        });
-        Ok(())
+            a: offset,
+        })
     }
 
-    fn emit_store(&mut self, level: u8, addr: u16) -> Result<()> {
-        self.buf.push(Instr {
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.push(Instr {
             opcode: OpCode::Store,
             l: level,
-            a: addr,
-        });
-        Ok(())
+            a: offset,
+        })
     }
 
-    fn emit_call(&mut self, level: u8, addr: u16) -> Result<()> {
-        self.buf.push(Instr {
-            opcode: OpCode::Call,
-            l: level,
-            a: addr,
-        });
-        Ok(())
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.emit_to(OpCode::Call, level, Target::Proc(proc))
+    }
+
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.emit_to(OpCode::TailCall, level, Target::Proc(proc))
     }
 
     fn emit_write(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Write,
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
     fn emit_read(&mut self) -> Result<()> {
-        self.buf.push(Instr {
+        self.push(Instr {
             opcode: OpCode::Read,
             l: 0,
             a: 0,
-        });
-        Ok(())
+        })
     }
 
-    fn emit_inc_top(&mut self, offset: u16) -> Result<()> {
-        self.buf.push(Instr {
-            opcode: OpCode::IncTop,
-            l: 0,
-            a: offset,
-        });
-        Ok(())
+    fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label::new(self.labels.len() - 1)
     }
 
-    fn emit_jump(&mut self, addr: u16) -> Result<()> {
-        self.buf.push(Instr {
-            opcode: OpCode::Jump,
-            l: 0,
-            a: addr,
-        });
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        let addr = self.addr();
+        self.labels[label.index()] = Some(addr);
+        self.resolve(Target::Label(label), addr);
         Ok(())
     }
 
-    fn reserve_jump(&mut self) -> Result<usize> {
-        let index = self.buf.len();
-        self.buf.push(Instr {
-            opcode: OpCode::Jump,
-            l: 0,
-            a: 0,
-        });
-        Ok(index)
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.emit_to(OpCode::Jump, 0, Target::Label(label))
     }
 
-    fn reserve_jump_if_zero(&mut self) -> Result<usize> {
-        let index = self.buf.len();
-        self.buf.push(Instr {
-            opcode: OpCode::JumpIfZero,
-            l: 0,
-            a: 0,
-        });
-        Ok(index)
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        self.emit_to(OpCode::JumpIfZero, 0, Target::Label(label))
     }
 
-    fn patch_jump(&mut self, index: usize, addr: u16) -> Result<()> {
-        assert!(matches!(self.buf[index].opcode, OpCode::Jump | OpCode::JumpIfZero));
-        self.buf[index].a = addr;
+    fn finish(mut self) -> Chunk {
+        self.make_chunk()
+    }
+}
+
+impl BytecodeGen {
+    /// Emit an instruction whose operand is the address of a label or procedure.
+    ///
+    /// The address is filled in later if it isn't known yet.
+    fn emit_to(&mut self, opcode: OpCode, level: u8, target: Target) -> Result<()> {
+        let addr = match target {
+            Target::Label(label) => self.labels[label.index()],
+            Target::Proc(proc) => self.procs.get(&proc).copied(),
+        };
+        if addr.is_none() {
+            self.fixups.push((self.buf.len(), target));
+        }
+        self.push(Instr {
+            opcode,
+            l: level,
+            a: addr.unwrap_or(0),
+        })
+    }
+
+    /// Append an instruction, if there's room for it in the VM's code.
+    ///
+    /// Keeping the code within [`CODE_SIZE`] also keeps every address
+    /// within an instruction operand.
+    fn push(&mut self, instr: Instr) -> Result<()> {
+        if self.buf.len() >= CODE_SIZE {
+            let file = self.debug.as_ref().map_or("", |debug| debug.file.as_str());
+            return error!(E0307, "program is too large")
+                .with_location(self.span, file)
+                .with_note(format!("the limit is {CODE_SIZE} instructions"))
+                .into();
+        }
+        self.buf.push(instr);
         Ok(())
     }
 
-    fn len(&self) -> usize {
-        self.buf.len()
+    /// Address of the next instruction.
+    fn addr(&self) -> u16 {
+        self.buf.len() as u16
     }
+
+    /// Fill in the address of a label or procedure that was just emitted.
+    fn resolve(&mut self, target: Target, addr: u16) {
+        let buf = &mut self.buf;
+        self.fixups.retain(|(index, fixup)| {
+            if *fixup == target {
+                buf[*index].a = addr;
+            }
+            *fixup != target
+        });
+    }
+}
+
+/// Index of the number in the constant pool, adding it if it isn't there yet.
+pub(crate) fn constant_index(constants: &mut Vec<Num>, num: Num) -> u16 {
+    let idx = match constants.iter().position(|constant| *constant == num) {
+        Some(idx) => idx,
+        None => {
+            constants.push(num);
+            constants.len() - 1
+        }
+    };
+    idx as u16
 }
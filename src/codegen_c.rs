@@ -0,0 +1,377 @@
+//! C source code generator.
+//!
+//! Every procedure becomes a C function taking its static link, the frame
+//! of the procedure it's nested in, as an explicit argument. Its variables
+//! live in an array in its own frame, and variables of enclosing
+//! procedures are reached by following the chain of static links, the
+//! same way the VM does.
+//!
+//! The operand stack is resolved at compile time: values are kept as C
+//! expressions until a statement consumes them. Arithmetic wraps around
+//! like in the VM, and division by zero exits with an error.
+//!
+//! Unlike the VM, there's no limit on the depth of recursion other than
+//! the native stack, and tail calls are only eliminated if the C compiler
+//! chooses to.
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::codegen::{CodeGen, Label};
+use crate::errors::Result;
+use crate::ir::ProcId;
+use crate::limits::DATA_OFFSET;
+use crate::Num;
+
+/// Support code included in every generated program.
+const RUNTIME: &str = r#"#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef struct pl0_frame pl0_frame;
+
+/* Activation record of a procedure. */
+struct pl0_frame {
+    /* Frame of the procedure this one is nested in. */
+    pl0_frame *sl;
+    int32_t *vars;
+};
+
+static inline int32_t pl0_add(int32_t a, int32_t b) { return (int32_t)((uint32_t)a + (uint32_t)b); }
+static inline int32_t pl0_sub(int32_t a, int32_t b) { return (int32_t)((uint32_t)a - (uint32_t)b); }
+static inline int32_t pl0_mul(int32_t a, int32_t b) { return (int32_t)((uint32_t)a * (uint32_t)b); }
+static inline int32_t pl0_neg(int32_t a) { return (int32_t)(0u - (uint32_t)a); }
+static inline int32_t pl0_odd(int32_t a) { return a % 2 != 0; }
+
+static inline int32_t pl0_div(int32_t a, int32_t b)
+{
+    if (b == 0) {
+        fflush(stdout);
+        fprintf(stderr, "error[E0401]: division by zero\n");
+        exit(1);
+    }
+    if (a == INT32_MIN && b == -1) {
+        return a;
+    }
+    return a / b;
+}
+
+static inline void pl0_write(int32_t n) { printf("%" PRId32 "\n", n); }
+
+static inline int32_t pl0_read(void)
+{
+    char line[64];
+    if (fgets(line, sizeof line, stdin) == NULL) {
+        return 0;
+    }
+    return (int32_t)strtol(line, NULL, 10);
+}
+"#;
+
+/// Generates a C program.
+pub struct CGen {
+    /// Finished functions by procedure id, with their prototypes.
+    funcs: Vec<(ProcId, String, String)>,
+    /// Functions being generated, innermost last.
+    stack: Vec<Func>,
+    /// C function name of each procedure begun so far.
+    names: HashMap<ProcId, String>,
+    labels: usize,
+    /// Labels that are jumped to. The others aren't written out.
+    targets: HashSet<usize>,
+}
+
+/// A function being generated.
+struct Func {
+    id: ProcId,
+    name: String,
+    /// Variables in the frame, with their offsets.
+    vars: Vec<(String, u16)>,
+    frame: u16,
+    body: String,
+    /// Whether the procedure's own variables are used.
+    uses_vars: bool,
+    /// Whether the frame is passed as a static link.
+    links: bool,
+    /// Operands waiting to be consumed, as C expressions.
+    operands: Vec<String>,
+}
+
+impl CGen {
+    pub fn new() -> Self {
+        Self {
+            funcs: vec![],
+            stack: vec![],
+            names: HashMap::new(),
+            labels: 0,
+            targets: HashSet::new(),
+        }
+    }
+
+    fn func(&mut self) -> &mut Func {
+        self.stack.last_mut().expect("no current procedure")
+    }
+
+    fn push(&mut self, operand: String) -> Result<()> {
+        self.func().operands.push(operand);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> String {
+        self.func().operands.pop().expect("operand stack underflow")
+    }
+
+    /// Add a statement to the current function.
+    fn stmt(&mut self, stmt: String) -> Result<()> {
+        let func = self.func();
+        debug_assert!(func.operands.is_empty(), "statement leaves operands behind");
+        let _ = writeln!(func.body, "    {stmt}");
+        Ok(())
+    }
+
+    fn unary(&mut self, func: &str) -> Result<()> {
+        let operand = self.pop();
+        self.push(format!("{func}({operand})"))
+    }
+
+    fn call(&mut self, func: &str) -> Result<()> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        self.push(format!("{func}({lhs}, {rhs})"))
+    }
+
+    fn compare(&mut self, op: &str) -> Result<()> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        self.push(format!("({lhs} {op} {rhs})"))
+    }
+
+    /// The frame `level` static links up from the current one.
+    fn frame(level: u8) -> String {
+        match level {
+            0 => "&frame".to_string(),
+            _ => format!("sl{}", "->sl".repeat(level as usize - 1)),
+        }
+    }
+
+    fn var(level: u8, offset: u16) -> String {
+        let idx = offset as usize - DATA_OFFSET;
+        match level {
+            0 => format!("vars[{idx}]"),
+            _ => format!("{}->vars[{idx}]", Self::frame(level)),
+        }
+    }
+
+    fn proc_name(&self, proc: ProcId) -> &str {
+        self.names
+            .get(&proc)
+            .expect("procedures are declared before they're called")
+    }
+}
+
+impl Default for CGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeGen for CGen {
+    type Output = String;
+
+    fn set_span(&mut self, _span: (u32, u32)) {}
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, _level: u8) -> Result<()> {
+        let name = if id == ProcId::MAIN {
+            format!("p{}_main", id.index())
+        } else {
+            format!("p{}_{name}", id.index())
+        };
+        self.names.insert(id, name.clone());
+        self.stack.push(Func {
+            id,
+            name,
+            vars: vec![],
+            frame: DATA_OFFSET as u16,
+            body: String::new(),
+            uses_vars: false,
+            links: false,
+            operands: vec![],
+        });
+        Ok(())
+    }
+
+    fn declare_var(&mut self, name: &str, offset: u16) {
+        self.func().vars.push((name.to_string(), offset));
+    }
+
+    fn begin_body(&mut self, frame: u16) -> Result<()> {
+        self.func().frame = frame;
+        Ok(())
+    }
+
+    fn end_proc(&mut self) {
+        let func = self.stack.pop().expect("no procedure to end");
+        let proto = format!("static void {}(pl0_frame *sl)", func.name);
+
+        let mut def = format!("{proto}\n{{\n");
+        // Unused variables are left out, which also avoids zero-length arrays.
+        let len = func.frame as usize - DATA_OFFSET;
+        let vars = len > 0 && (func.uses_vars || func.links);
+        if vars {
+            let _ = writeln!(def, "    int32_t vars[{len}] = {{0}};");
+            for (name, offset) in &func.vars {
+                let _ = writeln!(def, "    /* {name}: vars[{}] */", *offset as usize - DATA_OFFSET);
+            }
+        }
+        if func.links {
+            let vars = if vars { "vars" } else { "NULL" };
+            let _ = writeln!(def, "    pl0_frame frame = {{ sl, {vars} }};");
+        }
+        for line in func.body.lines() {
+            let unused = line
+                .strip_prefix('L')
+                .and_then(|line| line.strip_suffix(":;"))
+                .and_then(|label| label.parse().ok())
+                .is_some_and(|label| !self.targets.contains(&label));
+            if !unused {
+                def.push_str(line);
+                def.push('\n');
+            }
+        }
+        def.push_str("}\n");
+        self.funcs.push((func.id, proto, def));
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label::new(self.labels - 1)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        // A label has to be followed by a statement.
+        let func = self.func();
+        let _ = writeln!(func.body, "L{}:;", label.index());
+        Ok(())
+    }
+
+    fn emit_lit(&mut self, num: Num) -> Result<()> {
+        match num {
+            Num::MIN => self.push("INT32_MIN".to_string()),
+            _ => self.push(num.to_string()),
+        }
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        self.stmt("return;".to_string())
+    }
+
+    fn emit_math_neg(&mut self) -> Result<()> {
+        self.unary("pl0_neg")
+    }
+
+    fn emit_math_add(&mut self) -> Result<()> {
+        self.call("pl0_add")
+    }
+
+    fn emit_math_sub(&mut self) -> Result<()> {
+        self.call("pl0_sub")
+    }
+
+    fn emit_math_mul(&mut self) -> Result<()> {
+        self.call("pl0_mul")
+    }
+
+    fn emit_math_div(&mut self) -> Result<()> {
+        self.call("pl0_div")
+    }
+
+    fn emit_math_odd(&mut self) -> Result<()> {
+        self.unary("pl0_odd")
+    }
+
+    fn emit_math_eq(&mut self) -> Result<()> {
+        self.compare("==")
+    }
+
+    fn emit_math_noteq(&mut self) -> Result<()> {
+        self.compare("!=")
+    }
+
+    fn emit_math_lt(&mut self) -> Result<()> {
+        self.compare("<")
+    }
+
+    fn emit_math_gte(&mut self) -> Result<()> {
+        self.compare(">=")
+    }
+
+    fn emit_math_gt(&mut self) -> Result<()> {
+        self.compare(">")
+    }
+
+    fn emit_math_lte(&mut self) -> Result<()> {
+        self.compare("<=")
+    }
+
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.func().uses_vars |= level == 0;
+        self.push(Self::var(level, offset))
+    }
+
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.func().uses_vars |= level == 0;
+        let value = self.pop();
+        self.stmt(format!("{} = {value};", Self::var(level, offset)))
+    }
+
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.func().links |= level == 0;
+        let stmt = format!("{}({});", self.proc_name(proc), Self::frame(level));
+        self.stmt(stmt)
+    }
+
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.func().links |= level == 0;
+        let stmt = format!("{}({}); return;", self.proc_name(proc), Self::frame(level));
+        self.stmt(stmt)
+    }
+
+    fn emit_write(&mut self) -> Result<()> {
+        let value = self.pop();
+        self.stmt(format!("pl0_write({value});"))
+    }
+
+    fn emit_read(&mut self) -> Result<()> {
+        self.push("pl0_read()".to_string())
+    }
+
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.targets.insert(label.index());
+        self.stmt(format!("goto L{};", label.index()))
+    }
+
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        let cond = self.pop();
+        self.targets.insert(label.index());
+        self.stmt(format!("if ({cond} == 0) goto L{};", label.index()))
+    }
+
+    /// The complete C program.
+    fn finish(mut self) -> String {
+        assert!(self.stack.is_empty(), "procedure isn't finished");
+        self.funcs.sort_by_key(|(id, _, _)| *id);
+
+        let mut out = String::from(RUNTIME);
+        out.push('\n');
+        for (_, proto, _) in &self.funcs {
+            let _ = writeln!(out, "{proto};");
+        }
+        for (_, _, def) in &self.funcs {
+            out.push('\n');
+            out.push_str(def);
+        }
+        let main = &self.names[&ProcId::MAIN];
+        let _ = write!(out, "\nint main(void)\n{{\n    {main}(NULL);\n    return 0;\n}}\n");
+        out
+    }
+}
@@ -0,0 +1,114 @@
+use crate::codegen_c::CGen;
+use crate::ir_tests::emit;
+
+/// The body of a generated function, between its braces.
+fn function<'a>(c: &'a str, name: &str) -> &'a str {
+    let signature = format!("{name}(pl0_frame *sl)");
+    let start = c
+        .match_indices(&signature)
+        .map(|(at, _)| at + signature.len())
+        .find(|&end| c[end..].trim_start().starts_with('{'))
+        .expect(name);
+    let open = start + c[start..].find('{').unwrap();
+    let mut depth = 0;
+    for (at, ch) in c[open..].char_indices() {
+        match ch {
+            '{' => depth += 1,
+            '}' if depth == 1 => return &c[open + 1..open + at],
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    panic!("{name} doesn't end");
+}
+
+/// The variables a function's statements access, with the static links
+/// followed to reach them.
+fn accesses(body: &str) -> Vec<&str> {
+    let statements = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("int32_t") && !line.starts_with("/*"));
+    let mut accesses = vec![];
+    for line in statements {
+        for (at, _) in line.match_indices("vars[") {
+            let start = line[..at]
+                .rfind(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == '>'))
+                .map_or(0, |before| before + 1);
+            let end = at + line[at..].find(']').unwrap() + 1;
+            accesses.push(&line[start..end]);
+        }
+    }
+    accesses
+}
+
+/// The static links a function passes in its calls to `callee`.
+fn static_links<'a>(c: &'a str, caller: &str, callee: &str) -> Vec<&'a str> {
+    let body = function(c, caller);
+    body.match_indices(&format!("{callee}("))
+        .map(|(at, call)| {
+            let args = &body[at + call.len()..];
+            args[..args.find(')').unwrap()].trim()
+        })
+        .collect()
+}
+
+#[test]
+fn test_c_static_links() {
+    const SOURCE: &str = "var a;
+procedure p;
+    var b, c;
+    procedure q;
+        var d;
+    begin
+        d := a + b;
+        c := d * 2;
+        call p
+    end;
+begin
+    b := a + 1;
+    if b < 3 then call q
+end;
+begin
+    a := 1;
+    call p;
+    write a
+end.";
+    let c = emit::<CGen>(SOURCE);
+
+    // Every procedure is declared up front, since nested procedures can call outwards.
+    let declared = |name: &str| c.find(&format!("static void {name}(pl0_frame *sl);"));
+    assert!(
+        declared("p0_main") < declared("p1_p") && declared("p1_p") < declared("p2_q"),
+        "{c}"
+    );
+    assert!(declared("p0_main").is_some(), "{c}");
+
+    // Variables further out are reached through one static link per level.
+    assert_eq!(accesses(function(&c, "p0_main")), ["vars[0]", "vars[0]"]);
+    assert_eq!(accesses(function(&c, "p1_p")), ["vars[0]", "sl->vars[0]", "vars[0]"]);
+    assert_eq!(
+        accesses(function(&c, "p2_q")),
+        ["vars[0]", "sl->sl->vars[0]", "sl->vars[0]", "sl->vars[1]", "vars[0]"]
+    );
+
+    // Nested procedures get the caller's frame, and the others the
+    // frame of the procedure they're nested in.
+    assert_eq!(static_links(&c, "p0_main", "p1_p"), ["&frame"]);
+    assert_eq!(static_links(&c, "p1_p", "p2_q"), ["&frame"]);
+    assert_eq!(static_links(&c, "p2_q", "p1_p"), ["sl->sl"]);
+}
+
+#[test]
+fn test_c_expressions() {
+    // Arithmetic goes through the runtime's helpers, which wrap around
+    // like the VM, and conditions jump when they're false.
+    let c = emit::<CGen>("var x;\nbegin\n    read x;\n    write -x * (x / 2) - 7;\n    if odd x then write x\nend.");
+    let main: String = function(&c, "p0_main").split_whitespace().collect();
+    assert!(main.contains("vars[0]=pl0_read();"), "{c}");
+    assert!(
+        main.contains("pl0_write(pl0_sub(pl0_neg(pl0_mul(vars[0],pl0_div(vars[0],2))),7));"),
+        "{c}"
+    );
+    assert!(main.contains("if(pl0_odd(vars[0])==0)gotoL"), "{c}");
+}
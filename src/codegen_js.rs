@@ -0,0 +1,547 @@
+//! JavaScript code generator.
+//!
+//! Every procedure becomes a function nested in the function of the
+//! procedure it's declared in, so variables of enclosing procedures are
+//! plain closure variables and no static links are needed. Procedures and
+//! variables keep their names from the source, unless a name is reserved
+//! in JavaScript or already used further out.
+//!
+//! The program is a single `run(io)` function. Numbers are written with
+//! `io.write(num)`, which defaults to the console, and read with
+//! `io.read()`, which returns `undefined` at the end of the input.
+//! Arithmetic wraps around to 32 bits like in the VM, by way of `| 0` and
+//! `Math.imul`, and division by zero throws an error.
+//!
+//! Unlike the VM, the depth of recursion is only limited by the
+//! JavaScript engine's stack, and tail calls aren't eliminated.
+//!
+//! JavaScript has no `goto`, so a procedure with jumps runs its code in a
+//! loop, switching on the `$block` variable to the code following the
+//! label it jumps to.
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::codegen::{CodeGen, Label};
+use crate::errors::Result;
+use crate::ir::ProcId;
+use crate::Num;
+
+/// Code before the main program.
+const PROLOGUE: &str = r#""use strict";
+
+/**
+ * Run the program. Every number written is passed to `io.write`, and
+ * every number read is returned by `io.read`, which returns `undefined`
+ * once the input runs out.
+ */
+function run(io = {}) {
+    const $write = io.write ?? console.log;
+    const $read = io.read ?? (() => undefined);
+
+    /* Integer division, truncating towards zero. */
+    function $div(a, b) {
+        if (b === 0) {
+            throw new Error("error[E0401]: division by zero");
+        }
+        return (a / b) | 0;
+    }
+"#;
+
+/// Code after the main program.
+const EPILOGUE: &str = r#"
+if (typeof module !== "undefined") {
+    module.exports = run;
+}
+"#;
+
+/// Words that can't be used as names in JavaScript.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    // Used by the generated code.
+    "Math",
+    "console",
+    "io",
+    "module",
+    "run",
+];
+
+/// Generates a JavaScript program.
+pub struct JsGen {
+    /// The main program, once it's finished.
+    main: Option<String>,
+    /// Functions being generated, innermost last.
+    stack: Vec<Func>,
+    /// Function name of each procedure begun so far.
+    names: HashMap<ProcId, String>,
+    labels: usize,
+    /// Labels that are jumped to. The others don't start a new case.
+    targets: HashSet<usize>,
+}
+
+/// A function being generated.
+struct Func {
+    name: String,
+    /// Names declared in the function, its variables and nested functions.
+    scope: HashSet<String>,
+    /// Variables in the frame, by offset.
+    vars: Vec<(u16, String)>,
+    /// Nested functions, in the order they're declared.
+    nested: Vec<String>,
+    body: Vec<Line>,
+    /// Operands waiting to be consumed.
+    operands: Vec<Operand>,
+}
+
+/// A line of a function body.
+enum Line {
+    Stmt(String),
+    Label(usize),
+    /// Jump to a label, if the condition is true.
+    Jump(usize, Option<String>),
+}
+
+/// An expression on the operand stack.
+struct Operand {
+    text: String,
+    kind: Kind,
+    /// The opposite of a comparison.
+    negated: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A name, a literal or a call, which binds tighter than any operator.
+    Atom,
+    /// A number computed with operators.
+    Expr,
+    /// A comparison, whose result is a boolean rather than a number.
+    Compare,
+}
+
+impl Operand {
+    fn new(text: String, kind: Kind) -> Self {
+        Self {
+            text,
+            kind,
+            negated: None,
+        }
+    }
+
+    /// The operand as a number, to use as an operand of another operator.
+    fn atom(self) -> String {
+        match self.kind {
+            Kind::Atom => self.text,
+            Kind::Expr => format!("({})", self.text),
+            Kind::Compare => format!("({} ? 1 : 0)", self.text),
+        }
+    }
+
+    /// The operand as a number, on its own.
+    fn value(self) -> String {
+        match self.kind {
+            Kind::Atom | Kind::Expr => self.text,
+            Kind::Compare => format!("{} ? 1 : 0", self.text),
+        }
+    }
+}
+
+impl JsGen {
+    pub fn new() -> Self {
+        Self {
+            main: None,
+            stack: vec![],
+            names: HashMap::new(),
+            labels: 0,
+            targets: HashSet::new(),
+        }
+    }
+
+    fn func(&mut self) -> &mut Func {
+        self.stack.last_mut().expect("no current procedure")
+    }
+
+    /// A name for something declared in the current function, that's
+    /// neither reserved nor used by an enclosing function.
+    fn declare(&mut self, name: &str) -> String {
+        let taken = |name: &str| {
+            self.stack
+                .iter()
+                .any(|func| func.scope.contains(name) || func.name == name)
+        };
+        let mut unique = match RESERVED.contains(&name) {
+            true => format!("{name}$"),
+            false => name.to_string(),
+        };
+        let mut n = 0;
+        while taken(&unique) {
+            n += 1;
+            unique = format!("{name}${n}");
+        }
+        if let Some(func) = self.stack.last_mut() {
+            func.scope.insert(unique.clone());
+        }
+        unique
+    }
+
+    fn push(&mut self, text: String, kind: Kind) -> Result<()> {
+        self.func().operands.push(Operand::new(text, kind));
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Operand {
+        self.func().operands.pop().expect("operand stack underflow")
+    }
+
+    /// Add a statement to the current function.
+    fn stmt(&mut self, stmt: String) -> Result<()> {
+        let func = self.func();
+        debug_assert!(func.operands.is_empty(), "statement leaves operands behind");
+        func.body.push(Line::Stmt(stmt));
+        Ok(())
+    }
+
+    fn wrapping(&mut self, op: &str) -> Result<()> {
+        let rhs = self.pop().atom();
+        let lhs = self.pop().atom();
+        self.push(format!("({lhs} {op} {rhs}) | 0"), Kind::Expr)
+    }
+
+    fn call(&mut self, func: &str) -> Result<()> {
+        let rhs = self.pop().value();
+        let lhs = self.pop().value();
+        self.push(format!("{func}({lhs}, {rhs})"), Kind::Atom)
+    }
+
+    fn compare(&mut self, op: &str, negated: &str) -> Result<()> {
+        let rhs = self.pop().atom();
+        let lhs = self.pop().atom();
+        let mut operand = Operand::new(format!("{lhs} {op} {rhs}"), Kind::Compare);
+        operand.negated = Some(format!("{lhs} {negated} {rhs}"));
+        self.func().operands.push(operand);
+        Ok(())
+    }
+
+    /// Name of the variable `level` functions out from the current one.
+    fn var(&self, level: u8, offset: u16) -> String {
+        let func = &self.stack[self.stack.len() - 1 - level as usize];
+        func.vars
+            .iter()
+            .find(|(var, _)| *var == offset)
+            .map(|(_, name)| name.clone())
+            .expect("variables are declared before they're used")
+    }
+
+    fn proc_name(&self, proc: ProcId) -> &str {
+        self.names
+            .get(&proc)
+            .expect("procedures are declared before they're called")
+    }
+}
+
+impl Default for JsGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Indent every line that isn't empty by one level.
+fn indent(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        if !line.is_empty() {
+            out.push_str("    ");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+impl CodeGen for JsGen {
+    type Output = String;
+
+    fn set_span(&mut self, _span: (u32, u32)) {}
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, _level: u8) -> Result<()> {
+        let name = if id == ProcId::MAIN {
+            "main".to_string()
+        } else {
+            self.declare(name)
+        };
+        self.names.insert(id, name.clone());
+        self.stack.push(Func {
+            name,
+            scope: HashSet::new(),
+            vars: vec![],
+            nested: vec![],
+            body: vec![],
+            operands: vec![],
+        });
+        Ok(())
+    }
+
+    fn declare_var(&mut self, name: &str, offset: u16) {
+        let name = self.declare(name);
+        self.func().vars.push((offset, name));
+    }
+
+    fn begin_body(&mut self, _frame: u16) -> Result<()> {
+        Ok(())
+    }
+
+    fn end_proc(&mut self) {
+        let func = self.stack.pop().expect("no procedure to end");
+
+        let mut out = format!("function {}() {{\n", func.name);
+        let mut decls = String::new();
+        for (_, name) in &func.vars {
+            let _ = writeln!(decls, "let {name} = 0;");
+        }
+        for nested in &func.nested {
+            decls.push('\n');
+            decls.push_str(nested);
+        }
+
+        // Only labels that are jumped to start a case of the dispatch loop.
+        let cases: HashMap<usize, usize> = func
+            .body
+            .iter()
+            .filter_map(|line| match line {
+                Line::Label(label) if self.targets.contains(label) => Some(*label),
+                _ => None,
+            })
+            .enumerate()
+            .map(|(case, label)| (label, case + 1))
+            .collect();
+
+        let mut body = String::new();
+        for line in &func.body {
+            match line {
+                Line::Stmt(stmt) => {
+                    let _ = writeln!(body, "{stmt}");
+                }
+                Line::Label(label) => {
+                    if let Some(case) = cases.get(label) {
+                        let _ = writeln!(body, "case {case}:");
+                    }
+                }
+                Line::Jump(label, None) => {
+                    let _ = writeln!(body, "$block = {};\ncontinue;", cases[label]);
+                }
+                Line::Jump(label, Some(cond)) => {
+                    let _ = writeln!(body, "if ({cond}) {{ $block = {}; continue; }}", cases[label]);
+                }
+            }
+        }
+
+        if cases.is_empty() {
+            // The return at the end is implied.
+            let body = body.strip_suffix("return;\n").unwrap_or(&body);
+            if !decls.is_empty() && !body.is_empty() {
+                decls.push('\n');
+            }
+            out.push_str(&indent(&decls));
+            out.push_str(&indent(body));
+        } else {
+            decls.push_str(if decls.is_empty() { "" } else { "\n" });
+            decls.push_str("let $block = 0;\nfor (;;) {\n    switch ($block) {\n    case 0:\n");
+            // Statements are indented inside their case.
+            for line in body.lines() {
+                if line.starts_with("case ") {
+                    let _ = writeln!(decls, "    {line}");
+                } else {
+                    let _ = writeln!(decls, "        {line}");
+                }
+            }
+            decls.push_str("    }\n}\n");
+            out.push_str(&indent(&decls));
+        }
+        out.push_str("}\n");
+
+        match self.stack.last_mut() {
+            Some(parent) => parent.nested.push(out),
+            None => self.main = Some(out),
+        }
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label::new(self.labels - 1)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        self.func().body.push(Line::Label(label.index()));
+        Ok(())
+    }
+
+    fn emit_lit(&mut self, num: Num) -> Result<()> {
+        self.push(num.to_string(), Kind::Atom)
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        self.stmt("return;".to_string())
+    }
+
+    fn emit_math_neg(&mut self) -> Result<()> {
+        let operand = self.pop().atom();
+        // Avoid writing `--`, which is the decrement operator.
+        match operand.starts_with('-') {
+            true => self.push(format!("-({operand}) | 0"), Kind::Expr),
+            false => self.push(format!("-{operand} | 0"), Kind::Expr),
+        }
+    }
+
+    fn emit_math_add(&mut self) -> Result<()> {
+        self.wrapping("+")
+    }
+
+    fn emit_math_sub(&mut self) -> Result<()> {
+        self.wrapping("-")
+    }
+
+    fn emit_math_mul(&mut self) -> Result<()> {
+        self.call("Math.imul")
+    }
+
+    fn emit_math_div(&mut self) -> Result<()> {
+        self.call("$div")
+    }
+
+    fn emit_math_odd(&mut self) -> Result<()> {
+        let operand = self.pop().atom();
+        self.push(format!("{operand} & 1"), Kind::Expr)
+    }
+
+    fn emit_math_eq(&mut self) -> Result<()> {
+        self.compare("===", "!==")
+    }
+
+    fn emit_math_noteq(&mut self) -> Result<()> {
+        self.compare("!==", "===")
+    }
+
+    fn emit_math_lt(&mut self) -> Result<()> {
+        self.compare("<", ">=")
+    }
+
+    fn emit_math_gte(&mut self) -> Result<()> {
+        self.compare(">=", "<")
+    }
+
+    fn emit_math_gt(&mut self) -> Result<()> {
+        self.compare(">", "<=")
+    }
+
+    fn emit_math_lte(&mut self) -> Result<()> {
+        self.compare("<=", ">")
+    }
+
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        let var = self.var(level, offset);
+        self.push(var, Kind::Atom)
+    }
+
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        let value = self.pop().value();
+        let var = self.var(level, offset);
+        self.stmt(format!("{var} = {value};"))
+    }
+
+    fn emit_call(&mut self, _level: u8, proc: ProcId) -> Result<()> {
+        let stmt = format!("{}();", self.proc_name(proc));
+        self.stmt(stmt)
+    }
+
+    fn emit_tail_call(&mut self, _level: u8, proc: ProcId) -> Result<()> {
+        let stmt = format!("return {}();", self.proc_name(proc));
+        self.stmt(stmt)
+    }
+
+    fn emit_write(&mut self) -> Result<()> {
+        let value = self.pop().value();
+        self.stmt(format!("$write({value});"))
+    }
+
+    fn emit_read(&mut self) -> Result<()> {
+        // Anything that isn't a number, like `undefined`, reads as zero.
+        self.push("$read() | 0".to_string(), Kind::Expr)
+    }
+
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.targets.insert(label.index());
+        self.func().body.push(Line::Jump(label.index(), None));
+        Ok(())
+    }
+
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        let cond = self.pop();
+        let cond = match cond.negated {
+            Some(negated) => negated,
+            None => format!("{} === 0", cond.atom()),
+        };
+        self.targets.insert(label.index());
+        self.func().body.push(Line::Jump(label.index(), Some(cond)));
+        Ok(())
+    }
+
+    /// The complete JavaScript program.
+    fn finish(self) -> String {
+        assert!(self.stack.is_empty(), "procedure isn't finished");
+        let main = self.main.expect("no main program");
+
+        let mut out = String::from(PROLOGUE);
+        out.push('\n');
+        out.push_str(&indent(&main));
+        let _ = writeln!(out, "\n    {}();\n}}", self.names[&ProcId::MAIN]);
+        out.push_str(EPILOGUE);
+        out
+    }
+}
@@ -0,0 +1,90 @@
+use crate::codegen_js::JsGen;
+use crate::ir_tests::emit;
+
+const SOURCE: &str = "var x, new, x1;
+procedure outer;
+    var x;
+    procedure inner;
+    begin
+        new := x * 2;
+        call outer
+    end;
+begin
+    x := new - 1;
+    while x < 3 do
+    begin
+        if odd x then call inner;
+        x := x + 1
+    end
+end;
+begin
+    new := 0;
+    call outer;
+    write new / 2
+end.";
+
+/// The body of a generated function, between its braces.
+fn function<'a>(js: &'a str, name: &str) -> &'a str {
+    let start = js.find(&format!("function {name}(")).expect(name);
+    let open = start + js[start..].find('{').unwrap();
+    let mut depth = 0;
+    for (at, ch) in js[open..].char_indices() {
+        match ch {
+            '{' => depth += 1,
+            '}' if depth == 1 => return &js[open + 1..open + at],
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    panic!("{name} doesn't end");
+}
+
+/// The names declared with `let` and `function`, in order.
+fn declared(js: &str) -> Vec<&str> {
+    let mut words = js.split_whitespace();
+    let mut names = vec![];
+    while let Some(word) = words.next() {
+        if word == "let" || word == "function" {
+            let name = words.next().unwrap();
+            names.push(name.split('(').next().unwrap());
+        }
+    }
+    names
+}
+
+#[test]
+fn test_js_names() {
+    let js = emit::<JsGen>(SOURCE);
+
+    // Reserved words, and names used further out, are renamed.
+    let main = function(&js, "main");
+    assert_eq!(declared(main), ["x", "new$", "x1", "outer", "x$1", "inner", "$block"]);
+
+    // Procedures are nested in the functions they're declared in, so the
+    // variables further out are closure variables.
+    let inner = function(&js, "inner");
+    assert!(function(&js, "outer").contains(inner));
+    let inner: String = inner.split_whitespace().collect();
+    assert!(inner.contains("new$=Math.imul(x$1,2);"), "{js}");
+    // Tail calls return the call, since they aren't eliminated.
+    assert!(inner.contains("returnouter();"), "{js}");
+}
+
+#[test]
+fn test_js_jumps() {
+    let js = emit::<JsGen>(SOURCE);
+
+    // Only functions with jumps switch on `$block`.
+    assert!(!function(&js, "main")
+        .replace(function(&js, "outer"), "")
+        .contains("$block"));
+    assert!(!function(&js, "inner").contains("$block"));
+
+    // Comparisons are negated to jump when they're false, other conditions
+    // are compared with zero.
+    let outer: String = function(&js, "outer").split_whitespace().collect();
+    assert!(outer.contains("switch($block)"), "{js}");
+    assert!(outer.contains("if(x$1>=3){$block=3;continue;}"), "{js}");
+    assert!(outer.contains("if((x$1&1)===0){$block=2;continue;}"), "{js}");
+    assert!(outer.contains("$block=1;continue;"), "{js}");
+}
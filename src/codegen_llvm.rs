@@ -0,0 +1,423 @@
+//! LLVM IR code generator, in the text format.
+//!
+//! Every procedure is a function with its variables in a frame struct on
+//! the machine stack, `%frame.<proc>`, whose first field is the static
+//! link: a pointer to the frame of the procedure it's nested in. Variables
+//! of enclosing procedures are reached by following the chain of static
+//! links, so they stay in memory, but LLVM can promote the rest to
+//! registers.
+//!
+//! Arithmetic wraps around like in the VM, division by zero exits with an
+//! error, and tail calls are `musttail` calls. Numbers are written and
+//! read through the C library.
+//!
+//! The IR uses opaque pointers, the default since LLVM 15.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::codegen::{CodeGen, Label};
+use crate::errors::Result;
+use crate::ir::ProcId;
+use crate::limits::DATA_OFFSET;
+use crate::Num;
+
+/// Support code included in every module.
+const RUNTIME: &str = r#"
+@.write_format = private unnamed_addr constant [4 x i8] c"%d\0A\00"
+@.div_zero = private unnamed_addr constant [32 x i8] c"error[E0401]: division by zero\0A\00"
+@stdin = external global ptr
+@stderr = external global ptr
+
+declare i32 @printf(ptr, ...)
+declare ptr @fgets(ptr, i32, ptr)
+declare i64 @strtol(ptr, ptr, i32)
+declare i32 @fputs(ptr, ptr)
+declare void @exit(i32) noreturn
+
+define internal void @pl0_write(i32 %n) {
+  call i32 (ptr, ...) @printf(ptr @.write_format, i32 %n)
+  ret void
+}
+
+define internal i32 @pl0_read() {
+  %line = alloca [64 x i8]
+  %in = load ptr, ptr @stdin
+  %read = call ptr @fgets(ptr %line, i32 64, ptr %in)
+  %eof = icmp eq ptr %read, null
+  br i1 %eof, label %done, label %parse
+parse:
+  %long = call i64 @strtol(ptr %line, ptr null, i32 10)
+  %num = trunc i64 %long to i32
+  ret i32 %num
+done:
+  ret i32 0
+}
+
+; Divide, wrapping around like the VM.
+define internal i32 @pl0_div(i32 %a, i32 %b) {
+  switch i32 %b, label %divide [ i32 0, label %zero
+                                 i32 -1, label %negate ]
+zero:
+  %err = load ptr, ptr @stderr
+  call i32 @fputs(ptr @.div_zero, ptr %err)
+  call void @exit(i32 1)
+  unreachable
+negate:
+  %neg = sub i32 0, %a
+  ret i32 %neg
+divide:
+  %quot = sdiv i32 %a, %b
+  ret i32 %quot
+}
+
+define i32 @main() {
+  call void @p0_main(ptr null)
+  ret i32 0
+}
+"#;
+
+/// Generates an LLVM module.
+pub struct LlvmGen {
+    /// Finished functions by procedure id, with their frame types.
+    funcs: Vec<(ProcId, String, String)>,
+    /// Functions being generated, innermost last.
+    stack: Vec<Func>,
+    /// Function name of each procedure begun so far.
+    names: HashMap<ProcId, String>,
+    labels: usize,
+}
+
+/// A function being generated.
+struct Func {
+    id: ProcId,
+    name: String,
+    /// Variables in the frame, with their offsets.
+    vars: Vec<(String, u16)>,
+    frame: u16,
+    body: String,
+    /// Operands waiting to be consumed, as LLVM values.
+    operands: Vec<String>,
+    /// Values defined so far.
+    temps: usize,
+    /// Whether the current basic block has its terminator.
+    terminated: bool,
+}
+
+impl LlvmGen {
+    pub fn new() -> Self {
+        Self {
+            funcs: vec![],
+            stack: vec![],
+            names: HashMap::new(),
+            labels: 0,
+        }
+    }
+
+    fn func(&mut self) -> &mut Func {
+        self.stack.last_mut().expect("no current procedure")
+    }
+
+    /// Add an instruction to the current function, starting a new basic
+    /// block if the last one is finished.
+    fn inst(&mut self, inst: &str) {
+        if self.func().terminated {
+            let label = self.new_label();
+            let func = self.func();
+            let _ = writeln!(func.body, "L{}:", label.index());
+            func.terminated = false;
+        }
+        let _ = writeln!(self.func().body, "  {inst}");
+    }
+
+    /// Add an instruction defining a value, and return the value.
+    fn def(&mut self, inst: &str) -> String {
+        let func = self.func();
+        let temp = format!("%t{}", func.temps);
+        func.temps += 1;
+        self.inst(&format!("{temp} = {inst}"));
+        temp
+    }
+
+    /// Add an instruction ending the current basic block.
+    fn terminate(&mut self, inst: &str) {
+        self.inst(inst);
+        self.func().terminated = true;
+    }
+
+    fn push(&mut self, operand: String) -> Result<()> {
+        self.func().operands.push(operand);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> String {
+        self.func().operands.pop().expect("operand stack underflow")
+    }
+
+    fn binary(&mut self, op: &str) -> Result<()> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let value = self.def(&format!("{op} i32 {lhs}, {rhs}"));
+        self.push(value)
+    }
+
+    fn compare(&mut self, cond: &str) -> Result<()> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let flag = self.def(&format!("icmp {cond} i32 {lhs}, {rhs}"));
+        let value = self.def(&format!("zext i1 {flag} to i32"));
+        self.push(value)
+    }
+
+    /// The frame `level` static links up from the current one.
+    fn frame(&mut self, level: u8) -> String {
+        match level {
+            0 => "%frame".to_string(),
+            _ => {
+                let mut frame = "%sl".to_string();
+                for _ in 1..level {
+                    frame = self.def(&format!("load ptr, ptr {frame}"));
+                }
+                frame
+            }
+        }
+    }
+
+    /// Pointer to a variable.
+    fn var(&mut self, level: u8, offset: u16) -> String {
+        let frame = self.frame(level);
+        let owner = &self.stack[self.stack.len() - 1 - level as usize];
+        let ty = format!("%frame.{}", owner.name);
+        let idx = offset as usize - DATA_OFFSET;
+        self.def(&format!(
+            "getelementptr inbounds {ty}, ptr {frame}, i32 0, i32 1, i32 {idx}"
+        ))
+    }
+
+    fn call(&mut self, level: u8, proc: ProcId, tail: bool) -> Result<()> {
+        let frame = self.frame(level);
+        let name = self
+            .names
+            .get(&proc)
+            .expect("procedures are declared before they're called");
+        let call = format!("call void @{name}(ptr {frame})");
+        if tail {
+            self.inst(&format!("musttail {call}"));
+            self.terminate("ret void");
+        } else {
+            self.inst(&call);
+        }
+        Ok(())
+    }
+}
+
+impl Default for LlvmGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeGen for LlvmGen {
+    type Output = String;
+
+    fn set_span(&mut self, _span: (u32, u32)) {}
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, _level: u8) -> Result<()> {
+        let name = if id == ProcId::MAIN {
+            format!("p{}_main", id.index())
+        } else {
+            format!("p{}_{name}", id.index())
+        };
+        self.names.insert(id, name.clone());
+        self.stack.push(Func {
+            id,
+            name,
+            vars: vec![],
+            frame: DATA_OFFSET as u16,
+            body: String::new(),
+            operands: vec![],
+            temps: 0,
+            terminated: false,
+        });
+        Ok(())
+    }
+
+    fn declare_var(&mut self, name: &str, offset: u16) {
+        self.func().vars.push((name.to_string(), offset));
+    }
+
+    fn begin_body(&mut self, frame: u16) -> Result<()> {
+        self.func().frame = frame;
+        Ok(())
+    }
+
+    fn end_proc(&mut self) {
+        let func = self.stack.pop().expect("no procedure to end");
+        let len = func.frame as usize - DATA_OFFSET;
+        let ty = format!("%frame.{}", func.name);
+
+        let mut frame = String::new();
+        for (name, offset) in &func.vars {
+            let _ = writeln!(frame, "; {name}: variable {} of {ty}", *offset as usize - DATA_OFFSET);
+        }
+        let _ = writeln!(frame, "{ty} = type {{ ptr, [{len} x i32] }}");
+
+        let mut def = format!("define internal void @{}(ptr %sl) {{\n", func.name);
+        let _ = writeln!(def, "  %frame = alloca {ty}");
+        let _ = writeln!(def, "  store ptr %sl, ptr %frame");
+        let _ = writeln!(def, "  %vars = getelementptr inbounds {ty}, ptr %frame, i32 0, i32 1");
+        let _ = writeln!(def, "  store [{len} x i32] zeroinitializer, ptr %vars");
+        def.push_str(&func.body);
+        if !func.terminated {
+            def.push_str("  unreachable\n");
+        }
+        def.push_str("}\n");
+        self.funcs.push((func.id, frame, def));
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label::new(self.labels - 1)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        // Falling through to the label has to be explicit.
+        if !self.func().terminated {
+            self.terminate(&format!("br label %L{}", label.index()));
+        }
+        let func = self.func();
+        let _ = writeln!(func.body, "L{}:", label.index());
+        func.terminated = false;
+        Ok(())
+    }
+
+    fn emit_lit(&mut self, num: Num) -> Result<()> {
+        self.push(num.to_string())
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        self.terminate("ret void");
+        Ok(())
+    }
+
+    fn emit_math_neg(&mut self) -> Result<()> {
+        let operand = self.pop();
+        let value = self.def(&format!("sub i32 0, {operand}"));
+        self.push(value)
+    }
+
+    fn emit_math_add(&mut self) -> Result<()> {
+        self.binary("add")
+    }
+
+    fn emit_math_sub(&mut self) -> Result<()> {
+        self.binary("sub")
+    }
+
+    fn emit_math_mul(&mut self) -> Result<()> {
+        self.binary("mul")
+    }
+
+    fn emit_math_div(&mut self) -> Result<()> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let value = self.def(&format!("call i32 @pl0_div(i32 {lhs}, i32 {rhs})"));
+        self.push(value)
+    }
+
+    fn emit_math_odd(&mut self) -> Result<()> {
+        let operand = self.pop();
+        let value = self.def(&format!("and i32 {operand}, 1"));
+        self.push(value)
+    }
+
+    fn emit_math_eq(&mut self) -> Result<()> {
+        self.compare("eq")
+    }
+
+    fn emit_math_noteq(&mut self) -> Result<()> {
+        self.compare("ne")
+    }
+
+    fn emit_math_lt(&mut self) -> Result<()> {
+        self.compare("slt")
+    }
+
+    fn emit_math_gte(&mut self) -> Result<()> {
+        self.compare("sge")
+    }
+
+    fn emit_math_gt(&mut self) -> Result<()> {
+        self.compare("sgt")
+    }
+
+    fn emit_math_lte(&mut self) -> Result<()> {
+        self.compare("sle")
+    }
+
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        let var = self.var(level, offset);
+        let value = self.def(&format!("load i32, ptr {var}"));
+        self.push(value)
+    }
+
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        let value = self.pop();
+        let var = self.var(level, offset);
+        self.inst(&format!("store i32 {value}, ptr {var}"));
+        Ok(())
+    }
+
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.call(level, proc, false)
+    }
+
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        debug_assert!(level > 0, "the callee can't use the frame being popped");
+        self.call(level, proc, true)
+    }
+
+    fn emit_write(&mut self) -> Result<()> {
+        let value = self.pop();
+        self.inst(&format!("call void @pl0_write(i32 {value})"));
+        Ok(())
+    }
+
+    fn emit_read(&mut self) -> Result<()> {
+        let value = self.def("call i32 @pl0_read()");
+        self.push(value)
+    }
+
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.terminate(&format!("br label %L{}", label.index()));
+        Ok(())
+    }
+
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        let cond = self.pop();
+        let zero = self.def(&format!("icmp eq i32 {cond}, 0"));
+        let next = self.new_label();
+        self.terminate(&format!(
+            "br i1 {zero}, label %L{}, label %L{}",
+            label.index(),
+            next.index()
+        ));
+        self.bind_label(next)
+    }
+
+    /// The complete module.
+    fn finish(mut self) -> String {
+        assert!(self.stack.is_empty(), "procedure isn't finished");
+        self.funcs.sort_by_key(|(id, _, _)| *id);
+
+        let mut out = String::new();
+        for (_, frame, _) in &self.funcs {
+            out.push_str(frame);
+        }
+        for (_, _, def) in &self.funcs {
+            out.push('\n');
+            out.push_str(def);
+        }
+        out.push_str(RUNTIME);
+        out
+    }
+}
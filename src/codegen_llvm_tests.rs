@@ -0,0 +1,124 @@
+use crate::codegen_llvm::LlvmGen;
+use crate::ir_tests::emit;
+
+const SOURCE: &str = "var n, total;
+procedure sum;
+    var i;
+    procedure add;
+    begin
+        total := total + i
+    end;
+begin
+    i := n;
+    while i > 0 do
+    begin
+        if odd i then call add;
+        i := i - 1
+    end
+end;
+begin
+    read n;
+    call sum;
+    write total
+end.";
+
+/// The lines of the functions generated for procedures, by name.
+fn functions(ll: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut functions = vec![];
+    let mut lines = ll.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(name) = line.strip_prefix("define internal void @") else {
+            continue;
+        };
+        let name = &name[..name.find('(').unwrap()];
+        if name.starts_with("pl0_") {
+            continue;
+        }
+        functions.push((name, lines.by_ref().take_while(|line| *line != "}").collect()));
+    }
+    functions
+}
+
+/// Numbers of the temporaries in a line, in order.
+fn temporaries(line: &str) -> Vec<usize> {
+    line.match_indices("%t")
+        .filter_map(|(at, _)| {
+            let digits = &line[at + 2..];
+            let end = digits.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(digits.len());
+            digits[..end].parse().ok()
+        })
+        .collect()
+}
+
+#[test]
+fn test_llvm_ssa_names() {
+    let ll = emit::<LlvmGen>(SOURCE);
+    let functions = functions(&ll);
+    let names: Vec<&str> = functions.iter().map(|(name, _)| *name).collect();
+    assert_eq!(names, ["p0_main", "p1_sum", "p2_add"]);
+
+    for (name, lines) in &functions {
+        // Temporaries are numbered from zero in each function, assigned
+        // once each, and only used once they're assigned.
+        let mut assigned = 0;
+        for line in lines {
+            let (target, value) = match line.split_once(" = ") {
+                Some((target, value)) if target.starts_with("%t") => (Some(target), value),
+                _ => (None, *line),
+            };
+            for used in temporaries(value) {
+                assert!(
+                    used < assigned,
+                    "{name}: %t{used} used before it's assigned in {line:?}"
+                );
+            }
+            if let Some(target) = target {
+                assert_eq!(target, format!("%t{assigned}"), "{name}");
+                assigned += 1;
+            }
+        }
+
+        // Every label that's branched to is defined once.
+        for line in lines {
+            for (at, _) in line.match_indices("label %") {
+                let label = line[at + 7..].split([',', ' ']).next().unwrap();
+                let defined = lines.iter().filter(|line| **line == format!("{label}:")).count();
+                assert_eq!(defined, 1, "{name}: {label}");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_llvm_frames() {
+    let ll = emit::<LlvmGen>(SOURCE);
+
+    // A frame struct per procedure, with the static link and the variables.
+    let frames: Vec<&str> = ll.lines().filter(|line| line.starts_with("%frame.")).collect();
+    assert_eq!(
+        frames,
+        [
+            "%frame.p0_main = type { ptr, [2 x i32] }",
+            "%frame.p1_sum = type { ptr, [1 x i32] }",
+            "%frame.p2_add = type { ptr, [0 x i32] }",
+        ]
+    );
+
+    // Every function stores its static link in its frame, and nested
+    // procedures are called with the caller's frame.
+    for (name, lines) in functions(&ll) {
+        assert_eq!(lines[0], format!("%frame = alloca %frame.{name}"));
+        assert_eq!(lines[1], "store ptr %sl, ptr %frame");
+    }
+    let (_, sum) = &functions(&ll)[1];
+    assert!(sum.contains(&"call void @p2_add(ptr %frame)"), "{sum:#?}");
+
+    // `i` is one static link out from `add`, and `total` two.
+    let (_, add) = &functions(&ll)[2];
+    assert!(
+        add.iter()
+            .any(|line| line.ends_with("= getelementptr inbounds %frame.p1_sum, ptr %sl, i32 0, i32 1, i32 0")),
+        "{add:#?}"
+    );
+    assert!(add.iter().any(|line| line.ends_with("= load ptr, ptr %sl")), "{add:#?}");
+}
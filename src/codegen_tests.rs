@@ -0,0 +1,244 @@
+use crate::codegen::{CodeGen, Label};
+use crate::codegen_bytecode::BytecodeGen;
+use crate::errors::Result;
+use crate::ir::ProcId;
+use crate::ir_tests::emit;
+
+/// Code generator that writes down what it's asked to generate, without
+/// any notion of addresses.
+#[derive(Default)]
+struct Trace {
+    lines: Vec<String>,
+    labels: usize,
+}
+
+impl Trace {
+    fn push(&mut self, line: String) -> Result<()> {
+        self.lines.push(line);
+        Ok(())
+    }
+}
+
+impl CodeGen for Trace {
+    type Output = Vec<String>;
+
+    fn set_span(&mut self, _span: (u32, u32)) {}
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, level: u8) -> Result<()> {
+        self.lines.push(format!("proc {} {name} {level}", id.index()));
+        Ok(())
+    }
+
+    fn declare_var(&mut self, name: &str, offset: u16) {
+        self.lines.push(format!("var {name} {offset}"));
+    }
+
+    fn begin_body(&mut self, frame: u16) -> Result<()> {
+        self.push(format!("body {frame}"))
+    }
+
+    fn end_proc(&mut self) {
+        self.lines.push("end".to_string());
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label::new(self.labels - 1)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        self.push(format!("L{}:", label.index()))
+    }
+
+    fn emit_lit(&mut self, num: i32) -> Result<()> {
+        self.push(format!("lit {num}"))
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        self.push("ret".to_string())
+    }
+
+    fn emit_math_neg(&mut self) -> Result<()> {
+        self.push("neg".to_string())
+    }
+
+    fn emit_math_add(&mut self) -> Result<()> {
+        self.push("add".to_string())
+    }
+
+    fn emit_math_sub(&mut self) -> Result<()> {
+        self.push("sub".to_string())
+    }
+
+    fn emit_math_mul(&mut self) -> Result<()> {
+        self.push("mul".to_string())
+    }
+
+    fn emit_math_div(&mut self) -> Result<()> {
+        self.push("div".to_string())
+    }
+
+    fn emit_math_odd(&mut self) -> Result<()> {
+        self.push("odd".to_string())
+    }
+
+    fn emit_math_eq(&mut self) -> Result<()> {
+        self.push("eq".to_string())
+    }
+
+    fn emit_math_noteq(&mut self) -> Result<()> {
+        self.push("neq".to_string())
+    }
+
+    fn emit_math_lt(&mut self) -> Result<()> {
+        self.push("lt".to_string())
+    }
+
+    fn emit_math_gte(&mut self) -> Result<()> {
+        self.push("gte".to_string())
+    }
+
+    fn emit_math_gt(&mut self) -> Result<()> {
+        self.push("gt".to_string())
+    }
+
+    fn emit_math_lte(&mut self) -> Result<()> {
+        self.push("lte".to_string())
+    }
+
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.push(format!("load {level} {offset}"))
+    }
+
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.push(format!("store {level} {offset}"))
+    }
+
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.push(format!("call {level} {}", proc.index()))
+    }
+
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.push(format!("tail call {level} {}", proc.index()))
+    }
+
+    fn emit_write(&mut self) -> Result<()> {
+        self.push("write".to_string())
+    }
+
+    fn emit_read(&mut self) -> Result<()> {
+        self.push("read".to_string())
+    }
+
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.push(format!("jump L{}", label.index()))
+    }
+
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        self.push(format!("jump if zero L{}", label.index()))
+    }
+
+    fn finish(self) -> Vec<String> {
+        self.lines
+    }
+}
+
+#[test]
+fn test_codegen_labels() {
+    const SOURCE: &str = "var n;
+procedure down;
+    procedure show;
+    begin
+        write n
+    end;
+begin
+    while n > 0 do
+    begin
+        call show;
+        n := n - 1
+    end
+end;
+begin
+    n := 2;
+    call down
+end.";
+    assert_eq!(
+        emit::<Trace>(SOURCE),
+        [
+            "proc 0 <main> 0",
+            "var n 3",
+            "proc 1 down 1",
+            "proc 2 show 2",
+            "body 3",
+            "L0:",
+            "load 2 3",
+            "write",
+            "ret",
+            "end",
+            "body 3",
+            "L1:",
+            "L2:",
+            "load 1 3",
+            "lit 0",
+            "gt",
+            "jump if zero L4",
+            "L3:",
+            "call 0 2",
+            "load 1 3",
+            "lit 1",
+            "sub",
+            "store 1 3",
+            "jump L2",
+            "L4:",
+            "ret",
+            "end",
+            "body 4",
+            "L5:",
+            "lit 2",
+            "store 0 3",
+            "call 0 1",
+            "ret",
+            "end",
+        ]
+    );
+}
+
+#[test]
+fn test_bytecode_forward_refs() {
+    use crate::bytecode::OpCode;
+
+    // Jumps and calls can refer to what's emitted later.
+    let mut gen = BytecodeGen::with_debug_info("<test>", "");
+    let callee = ProcId::new(1);
+    gen.begin_proc(ProcId::MAIN, "<main>", 0).unwrap();
+    gen.begin_body(3).unwrap();
+    let end = gen.new_label();
+    gen.emit_lit(0).unwrap();
+    gen.emit_jump_if_zero(end).unwrap();
+    gen.emit_call(0, callee).unwrap();
+    gen.bind_label(end).unwrap();
+    gen.emit_return().unwrap();
+    gen.begin_proc(callee, "callee", 1).unwrap();
+    gen.begin_body(3).unwrap();
+    gen.emit_return().unwrap();
+    gen.end_proc();
+    gen.end_proc();
+
+    assert_eq!(gen.proc_addr(callee), Some(6));
+    let chunk = gen.make_chunk();
+    let operands: Vec<(OpCode, u16)> = chunk.code.iter().map(|instr| (instr.opcode, instr.a)).collect();
+    assert_eq!(
+        operands,
+        [
+            (OpCode::Jump, 1),
+            (OpCode::IncTop, 3),
+            (OpCode::Lit, 0),
+            (OpCode::JumpIfZero, 5),
+            (OpCode::Call, 6),
+            (OpCode::Return, 0),
+            (OpCode::Jump, 7),
+            (OpCode::IncTop, 3),
+            (OpCode::Return, 0),
+        ]
+    );
+}
@@ -0,0 +1,776 @@
+//! WebAssembly code generator.
+//!
+//! Frames live on a stack in linear memory, laid out like the VM's: the
+//! static link, two unused slots where the VM keeps the dynamic link and
+//! return address, then the variables. The `$sp` global points past the
+//! top frame. Every procedure is a function taking its static link, and
+//! reaches the variables of enclosing procedures by following the chain.
+//!
+//! WebAssembly only has structured control flow, so a procedure with
+//! jumps runs its code in a loop, branching on the `$pc` local to the
+//! code following the label it jumps to.
+//!
+//! Numbers are written and read by the imported functions `env.write` and
+//! `env.read`, and the program runs when the exported `main` function is
+//! called. Division by zero and running out of stack trap.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::codegen::{CodeGen, Label};
+use crate::errors::Result;
+use crate::ir::ProcId;
+use crate::limits::DATA_OFFSET;
+use crate::Num;
+
+/// Pages of linear memory, which is all stack.
+const MEMORY_PAGES: u32 = 16;
+const PAGE_SIZE: u32 = 0x1_0000;
+/// Bytes in a stack slot.
+const SLOT_SIZE: u32 = 4;
+
+/// Functions before the procedures.
+const WRITE: u32 = 0;
+const READ: u32 = 1;
+const DIV: u32 = 2;
+const MAIN: u32 = 3;
+/// Index of the first procedure's function.
+const FIRST_PROC: u32 = 4;
+/// Functions imported from the host.
+const IMPORTS: u32 = 2;
+
+/// Index of the `$sp` global.
+const SP: u32 = 0;
+
+/// Locals of a procedure's function.
+const SL: u32 = 0;
+const FP: u32 = 1;
+const PC: u32 = 2;
+const TMP: u32 = 3;
+
+/// Function signatures, in the order of the type section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    /// `(param i32)`, for `write` and procedures.
+    Link,
+    /// `(result i32)`, for `read`.
+    Read,
+    /// `(param i32 i32) (result i32)`, for `div`.
+    Binary,
+    /// No parameters or results, for `main`.
+    Main,
+}
+
+const TYPES: [Type; 4] = [Type::Link, Type::Read, Type::Binary, Type::Main];
+
+/// An instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    /// Branch to the block `$pc` levels out, or the outermost of `n`.
+    BrTable(u32),
+    Return,
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// Load from the address on the stack, plus a byte offset.
+    Load(u32),
+    /// Store to the address on the stack, plus a byte offset.
+    Store(u32),
+    Const(Num),
+    Eqz,
+    Eq,
+    Ne,
+    LtS,
+    GtS,
+    GtU,
+    LeS,
+    GeS,
+    Add,
+    Sub,
+    Mul,
+    DivS,
+    And,
+}
+
+/// An instruction, or a jump to a label that's resolved once all the
+/// labels of the procedure are bound.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Instr(Instr),
+    Jump(Label),
+    JumpIfZero(Label),
+}
+
+/// A function of the module.
+struct Function {
+    name: String,
+    ty: Type,
+    /// Names of the parameters and then the locals, all `i32`.
+    locals: &'static [&'static str],
+    body: Vec<Instr>,
+}
+
+/// A WebAssembly module, which can be written as text or binary.
+pub struct Module {
+    funcs: Vec<Function>,
+}
+
+/// Generates a WebAssembly module.
+pub struct WasmGen {
+    /// Finished procedures by id.
+    procs: Vec<(ProcId, Function)>,
+    /// Procedures being generated, innermost last.
+    stack: Vec<Proc>,
+    /// Function name of each procedure begun so far.
+    names: HashMap<ProcId, String>,
+    labels: usize,
+}
+
+/// A procedure being generated.
+struct Proc {
+    id: ProcId,
+    name: String,
+    /// Offsets of the variables in the frame.
+    vars: Vec<u16>,
+    frame: u16,
+    /// The code following each label, starting with the code before any.
+    segments: Vec<Vec<Op>>,
+    /// The segment each label starts.
+    labels: HashMap<usize, usize>,
+}
+
+impl WasmGen {
+    pub fn new() -> Self {
+        Self {
+            procs: vec![],
+            stack: vec![],
+            names: HashMap::new(),
+            labels: 0,
+        }
+    }
+
+    fn proc(&mut self) -> &mut Proc {
+        self.stack.last_mut().expect("no current procedure")
+    }
+
+    fn emit(&mut self, instrs: &[Instr]) -> Result<()> {
+        let segment = self.proc().segments.last_mut().expect("no segment");
+        segment.extend(instrs.iter().copied().map(Op::Instr));
+        Ok(())
+    }
+
+    /// Push the address of the frame `level` static links up from the current one.
+    fn frame(&mut self, level: u8) -> Result<()> {
+        self.emit(&[Instr::LocalGet(FP)])?;
+        for _ in 0..level {
+            self.emit(&[Instr::Load(0)])?;
+        }
+        Ok(())
+    }
+
+    /// Pop the procedure's frame and return.
+    fn leave(&mut self) -> Result<()> {
+        self.emit(&[Instr::LocalGet(FP), Instr::GlobalSet(SP), Instr::Return])
+    }
+
+    fn call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        debug_assert!(
+            self.names.contains_key(&proc),
+            "procedures are declared before they're called"
+        );
+        self.frame(level)?;
+        self.emit(&[Instr::Call(FIRST_PROC + proc.index() as u32)])
+    }
+}
+
+impl Default for WasmGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeGen for WasmGen {
+    type Output = Module;
+
+    fn set_span(&mut self, _span: (u32, u32)) {}
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, _level: u8) -> Result<()> {
+        let name = if id == ProcId::MAIN {
+            format!("p{}_main", id.index())
+        } else {
+            format!("p{}_{name}", id.index())
+        };
+        self.names.insert(id, name.clone());
+        self.stack.push(Proc {
+            id,
+            name,
+            vars: vec![],
+            frame: DATA_OFFSET as u16,
+            segments: vec![vec![]],
+            labels: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    fn declare_var(&mut self, _name: &str, offset: u16) {
+        self.proc().vars.push(offset);
+    }
+
+    fn begin_body(&mut self, frame: u16) -> Result<()> {
+        self.proc().frame = frame;
+        Ok(())
+    }
+
+    fn end_proc(&mut self) {
+        let proc = self.stack.pop().expect("no procedure to end");
+
+        // Push the frame, failing if the stack is full, and link it.
+        let mut body = vec![
+            Instr::GlobalGet(SP),
+            Instr::LocalTee(FP),
+            Instr::Const((proc.frame as u32 * SLOT_SIZE) as Num),
+            Instr::Add,
+            Instr::GlobalSet(SP),
+            Instr::GlobalGet(SP),
+            Instr::Const((MEMORY_PAGES * PAGE_SIZE) as Num),
+            Instr::GtU,
+            Instr::If,
+            Instr::Unreachable,
+            Instr::End,
+            Instr::LocalGet(FP),
+            Instr::LocalGet(SL),
+            Instr::Store(0),
+        ];
+        // The memory is reused by every frame, so variables start out cleared.
+        for offset in &proc.vars {
+            body.extend([
+                Instr::LocalGet(FP),
+                Instr::Const(0),
+                Instr::Store(*offset as u32 * SLOT_SIZE),
+            ]);
+        }
+
+        let jumps = proc.segments.iter().flatten().any(|op| !matches!(op, Op::Instr(_)));
+        if !jumps {
+            body.extend(proc.segments.iter().flatten().map(|op| match op {
+                Op::Instr(instr) => *instr,
+                _ => unreachable!(),
+            }));
+        } else {
+            // Segment `k` follows the end of block `k`, nested in the blocks
+            // of the segments after it, and in the loop.
+            let count = proc.segments.len() as u32;
+            body.push(Instr::Loop);
+            body.extend((0..count).map(|_| Instr::Block));
+            body.extend([Instr::LocalGet(PC), Instr::BrTable(count)]);
+            for (k, segment) in proc.segments.iter().enumerate() {
+                body.push(Instr::End);
+                let depth = count - 1 - k as u32;
+                for op in segment {
+                    match *op {
+                        Op::Instr(instr) => body.push(instr),
+                        Op::Jump(label) => {
+                            body.extend([
+                                Instr::Const(proc.labels[&label.index()] as Num),
+                                Instr::LocalSet(PC),
+                                Instr::Br(depth),
+                            ]);
+                        }
+                        Op::JumpIfZero(label) => {
+                            body.extend([
+                                Instr::Eqz,
+                                Instr::If,
+                                Instr::Const(proc.labels[&label.index()] as Num),
+                                Instr::LocalSet(PC),
+                                Instr::Br(depth + 1),
+                                Instr::End,
+                            ]);
+                        }
+                    }
+                }
+            }
+            body.push(Instr::End);
+        }
+
+        let func = Function {
+            name: proc.name,
+            ty: Type::Link,
+            locals: &["sl", "fp", "pc", "tmp"],
+            body,
+        };
+        self.procs.push((proc.id, func));
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label::new(self.labels - 1)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        let proc = self.proc();
+        // Labels bound at the same place share a segment.
+        if !proc.segments.last().expect("no segment").is_empty() {
+            proc.segments.push(vec![]);
+        }
+        let segment = proc.segments.len() - 1;
+        proc.labels.insert(label.index(), segment);
+        Ok(())
+    }
+
+    fn emit_lit(&mut self, num: Num) -> Result<()> {
+        self.emit(&[Instr::Const(num)])
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        self.leave()
+    }
+
+    fn emit_math_neg(&mut self) -> Result<()> {
+        self.emit(&[Instr::Const(-1), Instr::Mul])
+    }
+
+    fn emit_math_add(&mut self) -> Result<()> {
+        self.emit(&[Instr::Add])
+    }
+
+    fn emit_math_sub(&mut self) -> Result<()> {
+        self.emit(&[Instr::Sub])
+    }
+
+    fn emit_math_mul(&mut self) -> Result<()> {
+        self.emit(&[Instr::Mul])
+    }
+
+    fn emit_math_div(&mut self) -> Result<()> {
+        self.emit(&[Instr::Call(DIV)])
+    }
+
+    fn emit_math_odd(&mut self) -> Result<()> {
+        self.emit(&[Instr::Const(1), Instr::And])
+    }
+
+    fn emit_math_eq(&mut self) -> Result<()> {
+        self.emit(&[Instr::Eq])
+    }
+
+    fn emit_math_noteq(&mut self) -> Result<()> {
+        self.emit(&[Instr::Ne])
+    }
+
+    fn emit_math_lt(&mut self) -> Result<()> {
+        self.emit(&[Instr::LtS])
+    }
+
+    fn emit_math_gte(&mut self) -> Result<()> {
+        self.emit(&[Instr::GeS])
+    }
+
+    fn emit_math_gt(&mut self) -> Result<()> {
+        self.emit(&[Instr::GtS])
+    }
+
+    fn emit_math_lte(&mut self) -> Result<()> {
+        self.emit(&[Instr::LeS])
+    }
+
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.frame(level)?;
+        self.emit(&[Instr::Load(offset as u32 * SLOT_SIZE)])
+    }
+
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        // The address goes below the value.
+        self.emit(&[Instr::LocalSet(TMP)])?;
+        self.frame(level)?;
+        self.emit(&[Instr::LocalGet(TMP), Instr::Store(offset as u32 * SLOT_SIZE)])
+    }
+
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.call(level, proc)
+    }
+
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.call(level, proc)?;
+        self.leave()
+    }
+
+    fn emit_write(&mut self) -> Result<()> {
+        self.emit(&[Instr::Call(WRITE)])
+    }
+
+    fn emit_read(&mut self) -> Result<()> {
+        self.emit(&[Instr::Call(READ)])
+    }
+
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.proc()
+            .segments
+            .last_mut()
+            .expect("no segment")
+            .push(Op::Jump(label));
+        Ok(())
+    }
+
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        self.proc()
+            .segments
+            .last_mut()
+            .expect("no segment")
+            .push(Op::JumpIfZero(label));
+        Ok(())
+    }
+
+    /// The complete module.
+    fn finish(mut self) -> Module {
+        assert!(self.stack.is_empty(), "procedure isn't finished");
+        self.procs.sort_by_key(|(id, _)| *id);
+
+        let div = Function {
+            name: "div".to_string(),
+            ty: Type::Binary,
+            locals: &["a", "b"],
+            body: vec![
+                // Dividing the smallest number by -1 wraps around, like in the VM,
+                // instead of trapping.
+                Instr::LocalGet(1),
+                Instr::Const(-1),
+                Instr::Eq,
+                Instr::If,
+                Instr::Const(0),
+                Instr::LocalGet(0),
+                Instr::Sub,
+                Instr::Return,
+                Instr::End,
+                Instr::LocalGet(0),
+                Instr::LocalGet(1),
+                Instr::DivS,
+            ],
+        };
+        let main = Function {
+            name: "main".to_string(),
+            ty: Type::Main,
+            locals: &[],
+            body: vec![
+                Instr::Const(0),
+                Instr::GlobalSet(SP),
+                Instr::Const(0),
+                Instr::Call(FIRST_PROC + ProcId::MAIN.index() as u32),
+            ],
+        };
+
+        let mut funcs = vec![div, main];
+        funcs.extend(self.procs.into_iter().map(|(_, func)| func));
+        Module { funcs }
+    }
+}
+
+impl Type {
+    fn params(self) -> usize {
+        match self {
+            Type::Link => 1,
+            Type::Read | Type::Main => 0,
+            Type::Binary => 2,
+        }
+    }
+
+    fn results(self) -> usize {
+        match self {
+            Type::Read | Type::Binary => 1,
+            Type::Link | Type::Main => 0,
+        }
+    }
+
+    fn index(self) -> u32 {
+        TYPES.iter().position(|ty| *ty == self).expect("type") as u32
+    }
+}
+
+impl Module {
+    /// Name of a function by index, including the imported ones.
+    fn func_name(&self, idx: u32) -> &str {
+        match idx {
+            WRITE => "write",
+            READ => "read",
+            _ => &self.funcs[(idx - IMPORTS) as usize].name,
+        }
+    }
+
+    /// The module in the WebAssembly text format.
+    pub fn to_wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        let _ = writeln!(out, "  (import \"env\" \"write\" (func $write (param i32)))");
+        let _ = writeln!(out, "  (import \"env\" \"read\" (func $read (result i32)))");
+        let _ = writeln!(out, "  (memory (export \"memory\") {MEMORY_PAGES})");
+        let _ = writeln!(out, "  (global $sp (mut i32) (i32.const 0))");
+        let _ = writeln!(out, "  (export \"main\" (func $main))");
+
+        for func in &self.funcs {
+            let _ = write!(out, "  (func ${}", func.name);
+            let params = func.ty.params();
+            for (idx, local) in func.locals.iter().enumerate() {
+                let kind = if idx < params { "param" } else { "local" };
+                let _ = write!(out, " ({kind} ${local} i32)");
+            }
+            if func.ty.results() > 0 {
+                out.push_str(" (result i32)");
+            }
+            out.push('\n');
+
+            let mut depth = 2;
+            for instr in &func.body {
+                if *instr == Instr::End {
+                    depth -= 1;
+                }
+                let _ = writeln!(out, "{:depth$}{}", "", self.instr_text(func, instr), depth = depth * 2);
+                if matches!(instr, Instr::Block | Instr::Loop | Instr::If) {
+                    depth += 1;
+                }
+            }
+            out.push_str("  )\n");
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    fn instr_text(&self, func: &Function, instr: &Instr) -> String {
+        match *instr {
+            Instr::Unreachable => "unreachable".to_string(),
+            Instr::Block => "block".to_string(),
+            Instr::Loop => "loop".to_string(),
+            Instr::If => "if".to_string(),
+            Instr::End => "end".to_string(),
+            Instr::Br(depth) => format!("br {depth}"),
+            Instr::BrTable(count) => {
+                let targets: Vec<String> = (0..count).map(|depth| depth.to_string()).collect();
+                format!("br_table {}", targets.join(" "))
+            }
+            Instr::Return => "return".to_string(),
+            Instr::Call(idx) => format!("call ${}", self.func_name(idx)),
+            Instr::LocalGet(idx) => format!("local.get ${}", func.locals[idx as usize]),
+            Instr::LocalSet(idx) => format!("local.set ${}", func.locals[idx as usize]),
+            Instr::LocalTee(idx) => format!("local.tee ${}", func.locals[idx as usize]),
+            Instr::GlobalGet(_) => "global.get $sp".to_string(),
+            Instr::GlobalSet(_) => "global.set $sp".to_string(),
+            Instr::Load(0) => "i32.load".to_string(),
+            Instr::Load(offset) => format!("i32.load offset={offset}"),
+            Instr::Store(0) => "i32.store".to_string(),
+            Instr::Store(offset) => format!("i32.store offset={offset}"),
+            Instr::Const(num) => format!("i32.const {num}"),
+            Instr::Eqz => "i32.eqz".to_string(),
+            Instr::Eq => "i32.eq".to_string(),
+            Instr::Ne => "i32.ne".to_string(),
+            Instr::LtS => "i32.lt_s".to_string(),
+            Instr::GtS => "i32.gt_s".to_string(),
+            Instr::GtU => "i32.gt_u".to_string(),
+            Instr::LeS => "i32.le_s".to_string(),
+            Instr::GeS => "i32.ge_s".to_string(),
+            Instr::Add => "i32.add".to_string(),
+            Instr::Sub => "i32.sub".to_string(),
+            Instr::Mul => "i32.mul".to_string(),
+            Instr::DivS => "i32.div_s".to_string(),
+            Instr::And => "i32.and".to_string(),
+        }
+    }
+
+    /// The module in the WebAssembly binary format.
+    pub fn to_wasm(&self) -> Vec<u8> {
+        const I32: u8 = 0x7f;
+        const FUNC: u8 = 0x00;
+        const MEMORY: u8 = 0x02;
+
+        let mut out = b"\0asm".to_vec();
+        out.extend(1u32.to_le_bytes());
+
+        let mut types = vec![];
+        leb_u32(&mut types, TYPES.len() as u32);
+        for ty in TYPES {
+            types.push(0x60);
+            leb_u32(&mut types, ty.params() as u32);
+            types.extend(std::iter::repeat_n(I32, ty.params()));
+            leb_u32(&mut types, ty.results() as u32);
+            types.extend(std::iter::repeat_n(I32, ty.results()));
+        }
+        section(&mut out, 1, &types);
+
+        let mut imports = vec![];
+        leb_u32(&mut imports, IMPORTS);
+        for (name, ty) in [("write", Type::Link), ("read", Type::Read)] {
+            name_bytes(&mut imports, "env");
+            name_bytes(&mut imports, name);
+            imports.push(FUNC);
+            leb_u32(&mut imports, ty.index());
+        }
+        section(&mut out, 2, &imports);
+
+        let mut funcs = vec![];
+        leb_u32(&mut funcs, self.funcs.len() as u32);
+        for func in &self.funcs {
+            leb_u32(&mut funcs, func.ty.index());
+        }
+        section(&mut out, 3, &funcs);
+
+        // One memory with a minimum size and no maximum.
+        let mut memory = vec![1, 0x00];
+        leb_u32(&mut memory, MEMORY_PAGES);
+        section(&mut out, 5, &memory);
+
+        // `$sp`, a mutable `i32` starting at 0.
+        section(&mut out, 6, &[1, I32, 0x01, 0x41, 0x00, 0x0b]);
+
+        let mut exports = vec![];
+        leb_u32(&mut exports, 2);
+        name_bytes(&mut exports, "memory");
+        exports.push(MEMORY);
+        leb_u32(&mut exports, 0);
+        name_bytes(&mut exports, "main");
+        exports.push(FUNC);
+        leb_u32(&mut exports, MAIN);
+        section(&mut out, 7, &exports);
+
+        let mut code = vec![];
+        leb_u32(&mut code, self.funcs.len() as u32);
+        for func in &self.funcs {
+            let mut body = vec![];
+            let locals = (func.locals.len() - func.ty.params()) as u32;
+            if locals > 0 {
+                body.push(1);
+                leb_u32(&mut body, locals);
+                body.push(I32);
+            } else {
+                body.push(0);
+            }
+            for instr in &func.body {
+                encode(&mut body, instr);
+            }
+            body.push(0x0b);
+            leb_u32(&mut code, body.len() as u32);
+            code.extend(body);
+        }
+        section(&mut out, 10, &code);
+
+        out
+    }
+}
+
+fn encode(out: &mut Vec<u8>, instr: &Instr) {
+    /// Empty block type.
+    const EMPTY: u8 = 0x40;
+    /// Alignment of memory accesses, as a power of two.
+    const ALIGN: u32 = 2;
+
+    match *instr {
+        Instr::Unreachable => out.push(0x00),
+        Instr::Block => out.extend([0x02, EMPTY]),
+        Instr::Loop => out.extend([0x03, EMPTY]),
+        Instr::If => out.extend([0x04, EMPTY]),
+        Instr::End => out.push(0x0b),
+        Instr::Br(depth) => {
+            out.push(0x0c);
+            leb_u32(out, depth);
+        }
+        Instr::BrTable(count) => {
+            out.push(0x0e);
+            leb_u32(out, count - 1);
+            for depth in 0..count - 1 {
+                leb_u32(out, depth);
+            }
+            leb_u32(out, count - 1);
+        }
+        Instr::Return => out.push(0x0f),
+        Instr::Call(idx) => {
+            out.push(0x10);
+            leb_u32(out, idx);
+        }
+        Instr::LocalGet(idx) => {
+            out.push(0x20);
+            leb_u32(out, idx);
+        }
+        Instr::LocalSet(idx) => {
+            out.push(0x21);
+            leb_u32(out, idx);
+        }
+        Instr::LocalTee(idx) => {
+            out.push(0x22);
+            leb_u32(out, idx);
+        }
+        Instr::GlobalGet(idx) => {
+            out.push(0x23);
+            leb_u32(out, idx);
+        }
+        Instr::GlobalSet(idx) => {
+            out.push(0x24);
+            leb_u32(out, idx);
+        }
+        Instr::Load(offset) => {
+            out.push(0x28);
+            leb_u32(out, ALIGN);
+            leb_u32(out, offset);
+        }
+        Instr::Store(offset) => {
+            out.push(0x36);
+            leb_u32(out, ALIGN);
+            leb_u32(out, offset);
+        }
+        Instr::Const(num) => {
+            out.push(0x41);
+            leb_i32(out, num);
+        }
+        Instr::Eqz => out.push(0x45),
+        Instr::Eq => out.push(0x46),
+        Instr::Ne => out.push(0x47),
+        Instr::LtS => out.push(0x48),
+        Instr::GtS => out.push(0x4a),
+        Instr::GtU => out.push(0x4b),
+        Instr::LeS => out.push(0x4c),
+        Instr::GeS => out.push(0x4e),
+        Instr::Add => out.push(0x6a),
+        Instr::Sub => out.push(0x6b),
+        Instr::Mul => out.push(0x6c),
+        Instr::DivS => out.push(0x6d),
+        Instr::And => out.push(0x71),
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    leb_u32(out, contents.len() as u32);
+    out.extend(contents);
+}
+
+fn name_bytes(out: &mut Vec<u8>, name: &str) {
+    leb_u32(out, name.len() as u32);
+    out.extend(name.as_bytes());
+}
+
+fn leb_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn leb_i32(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::codegen_wasm::WasmGen;
+use crate::ir_tests::emit;
+
+const SOURCE: &str = "var x;
+procedure outer;
+    var y;
+    procedure inner;
+    begin
+        x := y + 1;
+        call outer
+    end;
+begin
+    y := x;
+    if y < 3 then call inner
+end;
+begin
+    read x;
+    call outer;
+    write x / 2
+end.";
+
+/// Read an unsigned LEB128 number.
+fn leb(bytes: &[u8], pos: &mut usize) -> u32 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[test]
+fn test_wasm_text() {
+    let wat = emit::<WasmGen>(SOURCE).to_wat();
+    assert!(wat.starts_with("(module\n"), "{wat}");
+    assert!(
+        wat.contains("  (import \"env\" \"write\" (func $write (param i32)))\n"),
+        "{wat}"
+    );
+    assert!(
+        wat.contains("  (import \"env\" \"read\" (func $read (result i32)))\n"),
+        "{wat}"
+    );
+    assert!(wat.contains("  (export \"main\" (func $main))\n"), "{wat}");
+    assert!(wat.contains("  (func $p2_inner (param $sl i32) (local $fp i32) (local $pc i32) (local $tmp i32)\n"));
+
+    // `x` is two static links out from `inner`, and `outer` gets the main program's frame.
+    assert!(
+        wat.contains("    local.get $fp\n    i32.load\n    i32.load\n    local.get $tmp\n    i32.store offset=12\n")
+    );
+    assert!(wat.contains("    local.get $fp\n    i32.load\n    i32.load\n    call $p1_outer\n"));
+
+    // Jumps go through the loop dispatching on `$pc`.
+    assert!(
+        wat.contains("            local.get $pc\n            br_table 0 1 2\n"),
+        "{wat}"
+    );
+    assert!(wat.contains("    call $div\n    call $write\n"), "{wat}");
+}
+
+#[test]
+fn test_wasm_binary() {
+    let wasm = emit::<WasmGen>(SOURCE).to_wasm();
+    assert_eq!(&wasm[..8], b"\0asm\x01\0\0\0");
+
+    let mut pos = 8;
+    let mut sections = vec![];
+    let mut funcs = 0;
+    let mut bodies = 0;
+    let mut exports = vec![];
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let len = leb(&wasm, &mut pos) as usize;
+        let contents = &wasm[pos..pos + len];
+        let mut at = 0;
+        match id {
+            3 => funcs = leb(contents, &mut at),
+            7 => {
+                for _ in 0..leb(contents, &mut at) {
+                    let len = leb(contents, &mut at) as usize;
+                    exports.push(String::from_utf8(contents[at..at + len].to_vec()).unwrap());
+                    at += len + 1;
+                    leb(contents, &mut at);
+                }
+            }
+            10 => {
+                bodies = leb(contents, &mut at);
+                for _ in 0..bodies {
+                    let len = leb(contents, &mut at) as usize;
+                    at += len;
+                    assert_eq!(contents[at - 1], 0x0b, "function body doesn't end with `end`");
+                }
+                assert_eq!(at, len);
+            }
+            _ => {}
+        }
+        sections.push(id);
+        pos += len;
+    }
+
+    assert_eq!(pos, wasm.len());
+    assert_eq!(sections, [1, 2, 3, 5, 6, 7, 10]);
+    // `div` and `main`, and the three procedures.
+    assert_eq!(funcs, 5);
+    assert_eq!(bodies, funcs);
+    assert_eq!(exports, ["memory", "main"]);
+}
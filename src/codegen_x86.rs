@@ -0,0 +1,397 @@
+//! x86-64 assembly code generator, for the GNU assembler on Linux.
+//!
+//! Every procedure is a function with a frame on the machine stack that
+//! starts with the same block mark as the VM's, in the order the machine
+//! pushes it: the return address at `8(%rbp)`, the dynamic link (the
+//! caller's `%rbp`) at `0(%rbp)`, and the static link, passed in `%rdi`,
+//! at `-8(%rbp)`. The variables follow in 8 byte slots, of which only the
+//! low 32 bits are used.
+//!
+//! Expressions are evaluated on the machine stack too, the same way the
+//! VM does. A tail call pops the frame before jumping to the callee, so it
+//! runs in the frame its caller had.
+//!
+//! A small runtime written against the C library implements `write`,
+//! `read` and division, and the program is linked with the C compiler:
+//! `cc -o program program.s`.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::codegen::{CodeGen, Label};
+use crate::errors::Result;
+use crate::ir::ProcId;
+use crate::limits::DATA_OFFSET;
+use crate::Num;
+
+/// Most stack space a program can use, in bytes, before it fails with a
+/// stack overflow instead of crashing.
+const STACK_SIZE: u32 = 4 << 20;
+
+/// Support code included in every program.
+const RUNTIME: &str = r#"
+# Runtime
+
+        .section .rodata
+.Lwrite_format:
+        .string "%d\n"
+.Ldiv_zero:
+        .string "error[E0401]: division by zero\n"
+.Lstack_overflow:
+        .string "error[E0402]: stack overflow\n"
+
+        .bss
+        .align 8
+# Lowest address the stack may grow to.
+pl0_stack_limit:
+        .zero 8
+
+        .text
+# Write the number in %edi.
+pl0_write:
+        pushq %rbp
+        movq %rsp, %rbp
+        andq $-16, %rsp
+        movl %edi, %esi
+        leaq .Lwrite_format(%rip), %rdi
+        xorl %eax, %eax
+        call printf@PLT
+        leave
+        ret
+
+# Read a number into %eax, or 0 at the end of the input.
+pl0_read:
+        pushq %rbp
+        movq %rsp, %rbp
+        subq $64, %rsp
+        andq $-16, %rsp
+        movq %rsp, %rdi
+        movl $64, %esi
+        movq stdin@GOTPCREL(%rip), %rax
+        movq (%rax), %rdx
+        call fgets@PLT
+        testq %rax, %rax
+        jz 1f
+        movq %rsp, %rdi
+        xorl %esi, %esi
+        movl $10, %edx
+        call strtol@PLT
+        jmp 2f
+1:      xorl %eax, %eax
+2:      leave
+        ret
+
+# Divide %eax by %ecx, wrapping around like the VM.
+pl0_div:
+        testl %ecx, %ecx
+        jz pl0_div_zero
+        cmpl $-1, %ecx
+        je 1f
+        cltd
+        idivl %ecx
+        ret
+1:      negl %eax
+        ret
+
+pl0_div_zero:
+        leaq .Ldiv_zero(%rip), %rdi
+        jmp pl0_fail
+
+pl0_stack_overflow:
+        leaq .Lstack_overflow(%rip), %rdi
+
+# Print the message in %rdi and exit.
+pl0_fail:
+        andq $-16, %rsp
+        movq stderr@GOTPCREL(%rip), %rax
+        movq (%rax), %rsi
+        call fputs@PLT
+        movl $1, %edi
+        call exit@PLT
+
+        .section .note.GNU-stack,"",@progbits
+"#;
+
+/// Generates an x86-64 assembly program.
+pub struct X86Gen {
+    /// Finished functions by procedure id.
+    funcs: Vec<(ProcId, String)>,
+    /// Functions being generated, innermost last.
+    stack: Vec<Func>,
+    /// Symbol of each procedure begun so far.
+    names: HashMap<ProcId, String>,
+    labels: usize,
+}
+
+/// A function being generated.
+struct Func {
+    id: ProcId,
+    name: String,
+    /// Variables in the frame, with their offsets.
+    vars: Vec<(String, u16)>,
+    frame: u16,
+    body: String,
+}
+
+impl X86Gen {
+    pub fn new() -> Self {
+        Self {
+            funcs: vec![],
+            stack: vec![],
+            names: HashMap::new(),
+            labels: 0,
+        }
+    }
+
+    fn func(&mut self) -> &mut Func {
+        self.stack.last_mut().expect("no current procedure")
+    }
+
+    /// Add instructions to the current function, one per line.
+    fn emit(&mut self, instrs: &str) -> Result<()> {
+        let func = self.func();
+        for instr in instrs.lines() {
+            let _ = writeln!(func.body, "        {instr}");
+        }
+        Ok(())
+    }
+
+    /// Address of a variable, loading the frame it's in into `%rax` unless
+    /// it's the current one.
+    fn var(&mut self, level: u8, offset: u16) -> Result<String> {
+        let disp = Self::disp(offset);
+        if level == 0 {
+            return Ok(format!("{disp}(%rbp)"));
+        }
+        self.frame("%rax", level)?;
+        Ok(format!("{disp}(%rax)"))
+    }
+
+    /// Displacement of a variable from the frame pointer.
+    fn disp(offset: u16) -> i64 {
+        -8 * (offset as i64 - DATA_OFFSET as i64 + 2)
+    }
+
+    /// Load the frame `level` static links up from the current one into `reg`.
+    fn frame(&mut self, reg: &str, level: u8) -> Result<()> {
+        self.emit(&format!("movq %rbp, {reg}"))?;
+        for _ in 0..level {
+            self.emit(&format!("movq -8({reg}), {reg}"))?;
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, instr: &str) -> Result<()> {
+        self.emit(&format!("popq %rcx\npopq %rax\n{instr} %ecx, %eax\npushq %rax"))
+    }
+
+    fn compare(&mut self, set: &str) -> Result<()> {
+        self.emit(&format!(
+            "popq %rcx\npopq %rax\ncmpl %ecx, %eax\n{set} %al\nmovzbl %al, %eax\npushq %rax"
+        ))
+    }
+
+    fn proc_name(&self, proc: ProcId) -> &str {
+        self.names
+            .get(&proc)
+            .expect("procedures are declared before they're called")
+    }
+}
+
+impl Default for X86Gen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeGen for X86Gen {
+    type Output = String;
+
+    fn set_span(&mut self, _span: (u32, u32)) {}
+
+    fn begin_proc(&mut self, id: ProcId, name: &str, _level: u8) -> Result<()> {
+        let name = if id == ProcId::MAIN {
+            format!("p{}_main", id.index())
+        } else {
+            format!("p{}_{name}", id.index())
+        };
+        self.names.insert(id, name.clone());
+        self.stack.push(Func {
+            id,
+            name,
+            vars: vec![],
+            frame: DATA_OFFSET as u16,
+            body: String::new(),
+        });
+        Ok(())
+    }
+
+    fn declare_var(&mut self, name: &str, offset: u16) {
+        self.func().vars.push((name.to_string(), offset));
+    }
+
+    fn begin_body(&mut self, frame: u16) -> Result<()> {
+        self.func().frame = frame;
+        Ok(())
+    }
+
+    fn end_proc(&mut self) {
+        let func = self.stack.pop().expect("no procedure to end");
+
+        let mut out = String::new();
+        for (name, offset) in &func.vars {
+            let _ = writeln!(out, "# {name} at {}(%rbp)", Self::disp(*offset));
+        }
+        let _ = write!(
+            out,
+            "{}:
+        pushq %rbp
+        movq %rsp, %rbp
+        pushq %rdi
+        cmpq pl0_stack_limit(%rip), %rsp
+        jb pl0_stack_overflow
+",
+            func.name
+        );
+        // The variables start out cleared.
+        for _ in DATA_OFFSET as u16..func.frame {
+            out.push_str("        pushq $0\n");
+        }
+        out.push_str(&func.body);
+        self.funcs.push((func.id, out));
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label::new(self.labels - 1)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<()> {
+        let func = self.func();
+        let _ = writeln!(func.body, ".L{}:", label.index());
+        Ok(())
+    }
+
+    fn emit_lit(&mut self, num: Num) -> Result<()> {
+        self.emit(&format!("pushq ${num}"))
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        self.emit("leave\nret")
+    }
+
+    fn emit_math_neg(&mut self) -> Result<()> {
+        self.emit("popq %rax\nnegl %eax\npushq %rax")
+    }
+
+    fn emit_math_add(&mut self) -> Result<()> {
+        self.binary("addl")
+    }
+
+    fn emit_math_sub(&mut self) -> Result<()> {
+        self.binary("subl")
+    }
+
+    fn emit_math_mul(&mut self) -> Result<()> {
+        self.binary("imull")
+    }
+
+    fn emit_math_div(&mut self) -> Result<()> {
+        self.emit("popq %rcx\npopq %rax\ncall pl0_div\npushq %rax")
+    }
+
+    fn emit_math_odd(&mut self) -> Result<()> {
+        self.emit("popq %rax\nandl $1, %eax\npushq %rax")
+    }
+
+    fn emit_math_eq(&mut self) -> Result<()> {
+        self.compare("sete")
+    }
+
+    fn emit_math_noteq(&mut self) -> Result<()> {
+        self.compare("setne")
+    }
+
+    fn emit_math_lt(&mut self) -> Result<()> {
+        self.compare("setl")
+    }
+
+    fn emit_math_gte(&mut self) -> Result<()> {
+        self.compare("setge")
+    }
+
+    fn emit_math_gt(&mut self) -> Result<()> {
+        self.compare("setg")
+    }
+
+    fn emit_math_lte(&mut self) -> Result<()> {
+        self.compare("setle")
+    }
+
+    fn emit_load(&mut self, level: u8, offset: u16) -> Result<()> {
+        let var = self.var(level, offset)?;
+        self.emit(&format!("pushq {var}"))
+    }
+
+    fn emit_store(&mut self, level: u8, offset: u16) -> Result<()> {
+        self.emit("popq %rcx")?;
+        let var = self.var(level, offset)?;
+        self.emit(&format!("movl %ecx, {var}"))
+    }
+
+    fn emit_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        self.frame("%rdi", level)?;
+        let name = self.proc_name(proc).to_string();
+        self.emit(&format!("call {name}"))
+    }
+
+    fn emit_tail_call(&mut self, level: u8, proc: ProcId) -> Result<()> {
+        debug_assert!(level > 0, "the frame being popped can't be the static link");
+        self.frame("%rdi", level)?;
+        let name = self.proc_name(proc).to_string();
+        self.emit(&format!("leave\njmp {name}"))
+    }
+
+    fn emit_write(&mut self) -> Result<()> {
+        self.emit("popq %rdi\ncall pl0_write")
+    }
+
+    fn emit_read(&mut self) -> Result<()> {
+        self.emit("call pl0_read\npushq %rax")
+    }
+
+    fn emit_jump(&mut self, label: Label) -> Result<()> {
+        self.emit(&format!("jmp .L{}", label.index()))
+    }
+
+    fn emit_jump_if_zero(&mut self, label: Label) -> Result<()> {
+        self.emit(&format!("popq %rax\ntestl %eax, %eax\njz .L{}", label.index()))
+    }
+
+    /// The complete assembly program.
+    fn finish(mut self) -> String {
+        assert!(self.stack.is_empty(), "procedure isn't finished");
+        self.funcs.sort_by_key(|(id, _)| *id);
+
+        let main = &self.names[&ProcId::MAIN];
+        let mut out = String::from("        .text\n        .globl main\nmain:\n");
+        let _ = write!(
+            out,
+            "        pushq %rbp
+        movq %rsp, %rbp
+        leaq -{STACK_SIZE}(%rsp), %rax
+        movq %rax, pl0_stack_limit(%rip)
+        xorl %edi, %edi
+        call {main}
+        xorl %eax, %eax
+        popq %rbp
+        ret
+"
+        );
+        for (_, func) in &self.funcs {
+            out.push('\n');
+            out.push_str(func);
+        }
+        out.push_str(RUNTIME);
+        out
+    }
+}
@@ -0,0 +1,102 @@
+use crate::codegen_x86::X86Gen;
+use crate::ir_tests::emit;
+
+/// The instructions of a function, without labels or indentation.
+fn instructions<'a>(asm: &'a str, name: &str) -> Vec<&'a str> {
+    let start = asm.find(&format!("\n{name}:")).expect(name) + 1;
+    asm[start..]
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.ends_with(':') && !line.starts_with('#'))
+        .collect()
+}
+
+/// The variables, and their offsets from the frame's base.
+fn variables(asm: &str) -> Vec<(&str, i32)> {
+    asm.lines()
+        .filter_map(|line| {
+            let (name, at) = line.strip_prefix("# ")?.split_once(" at ")?;
+            Some((name, at.strip_suffix("(%rbp)")?.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Whether the instructions contain `expected` in a row.
+fn runs(instructions: &[&str], expected: &[&str]) -> bool {
+    instructions.windows(expected.len()).any(|window| window == expected)
+}
+
+#[test]
+fn test_x86_frames() {
+    const SOURCE: &str = "var a;
+procedure p;
+    var b, c, d;
+    procedure q;
+    begin
+        a := c;
+        call p
+    end;
+begin
+    b := a;
+    if b < 3 then call q
+end;
+begin
+    a := 1;
+    call p;
+    write a
+end.";
+    let asm = emit::<X86Gen>(SOURCE);
+    assert!(asm.contains(".globl main\nmain:\n"), "{asm}");
+
+    // The block mark holds the caller's %rbp, and the static link below
+    // it. The variables follow.
+    assert_eq!(variables(&asm), [("a", -16), ("b", -16), ("c", -24), ("d", -32)]);
+    let p = instructions(&asm, "p1_p");
+    assert_eq!(p[..3], ["pushq %rbp", "movq %rsp, %rbp", "pushq %rdi"]);
+    // Once the stack is checked, a slot is reserved for each variable.
+    let reserved = p
+        .iter()
+        .skip_while(|instr| !instr.starts_with("jb "))
+        .skip(1)
+        .take_while(|instr| **instr == "pushq $0")
+        .count();
+    assert_eq!(reserved, 3, "{p:#?}");
+    // `q` is nested in `p`, so it gets `p`'s frame.
+    assert!(runs(&p, &["movq %rbp, %rdi", "call p2_q"]), "{p:#?}");
+
+    // Following one static link per level out.
+    let q = instructions(&asm, "p2_q");
+    assert!(
+        runs(&q, &["movq %rbp, %rax", "movq -8(%rax), %rax", "pushq -24(%rax)"]),
+        "{q:#?}"
+    );
+    assert!(
+        runs(
+            &q,
+            &[
+                "movq %rbp, %rax",
+                "movq -8(%rax), %rax",
+                "movq -8(%rax), %rax",
+                "movl %ecx, -16(%rax)"
+            ]
+        ),
+        "{q:#?}"
+    );
+    // The tail call passes `p` the main program's frame, pops its own
+    // frame and jumps.
+    assert!(
+        runs(
+            &q,
+            &[
+                "movq %rbp, %rdi",
+                "movq -8(%rdi), %rdi",
+                "movq -8(%rdi), %rdi",
+                "leave",
+                "jmp p1_p"
+            ]
+        ),
+        "{q:#?}"
+    );
+}
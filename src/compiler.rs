@@ -1,60 +1,108 @@
-use crate::codegen::CodeGen;
+use std::collections::HashMap;
+
+use crate::debug::MAIN_PROC;
+use crate::env::{Env, Scope, ScopeId, Symbol, SymbolId, SymbolKind};
 use crate::errors::Result;
+use crate::ir::{self, BinaryOp, BlockId, ProcId, UnaryOp};
 use crate::limits::*;
 use crate::{ast::*, error, Num};
 
-pub struct Compiler<'a, C> {
-    codegen: &'a mut C,
-    table: Vec<Entry>,
+/// Lowers the AST into the [intermediate representation](crate::ir).
+pub struct Compiler {
+    builder: ir::Builder,
+    /// File where the source text is from.
+    file: String,
+    /// Every symbol declared in the program.
+    env: Env,
+    /// Symbols currently in scope, in declaration order.
+    table: Vec<SymbolId>,
+    /// The lowered procedure of each procedure symbol.
+    procs: HashMap<SymbolId, ProcId>,
+    /// The scope declarations are currently entered into.
+    scope: ScopeId,
     level: u8,
     /// The local relative stack offset where the procedure's data starts
     data_offset: u16,
 }
 
-/// Entry in the identifier table.
-enum Entry {
-    Const { name: String, value: Num },
-    Var { name: String, level: u8, offset: u16 },
-    Proc { name: String, level: u8, addr: u16 },
+/// An expression with its constant parts evaluated, by
+/// [`Compiler::fold_expr`].
+enum Folded<'a> {
+    Num(Num),
+    Neg(Box<Folded<'a>>),
+    Binary(BinOp, Box<Folded<'a>>, Box<Folded<'a>>),
+    /// A name that isn't a constant.
+    Name(&'a Ident),
+    Err,
 }
 
-impl<'a, C: CodeGen> Compiler<'a, C> {
-    pub fn new(codegen: &'a mut C) -> Self {
+impl Compiler {
+    pub fn new(file: impl ToString) -> Self {
         Self {
-            codegen,
+            builder: ir::Builder::new(),
+            file: file.to_string(),
+            env: Env::new(),
             table: vec![],
+            procs: HashMap::new(),
+            scope: ScopeId::default(),
             level: 0,
             data_offset: DATA_OFFSET as u16,
         }
     }
 
-    pub fn compile(&mut self, program: &Program) -> Result<()> {
+    pub fn compile(&mut self, program: &Program) -> Result<ir::Program> {
         self.compile_program(program)?;
 
-        Ok(())
+        let builder = std::mem::replace(&mut self.builder, ir::Builder::new());
+        Ok(builder.finish())
     }
 
-    fn find_ident(&self, query: &str) -> Option<&Entry> {
+    /// The symbol table of the compiled program.
+    pub fn into_env(self) -> Env {
+        self.env
+    }
+
+    fn find_ident(&self, query: &str) -> Option<&Symbol> {
+        self.find_ident_id(query).map(|id| self.env.symbol(id))
+    }
+
+    fn find_ident_id(&self, query: &str) -> Option<SymbolId> {
         // Search backwards, crawling up lexical scope.
-        self.table.iter().rev().find(|entry| match entry {
-            Entry::Const { name, .. } => name == query,
-            Entry::Var { name, .. } => name == query,
-            Entry::Proc { name, .. } => name == query,
-        })
+        self.table
+            .iter()
+            .rev()
+            .copied()
+            .find(|id| self.env.symbol(*id).name == query)
+    }
+
+    /// Enter a symbol into the current scope.
+    fn declare(&mut self, ident: &Ident, kind: SymbolKind) -> SymbolId {
+        let id = self.env.push_symbol(Symbol {
+            name: ident.name.clone(),
+            kind,
+            scope: self.scope,
+            level: self.level,
+            span: ident.span,
+        });
+        self.table.push(id);
+        id
     }
 
-    fn with_scope<F>(&mut self, f: F) -> Result<()>
+    fn with_scope<F>(&mut self, scope: ScopeId, f: F) -> Result<()>
     where
         F: FnOnce(&mut Self) -> Result<()>,
     {
         let table_len = self.table.len();
         let data_offset = self.data_offset;
+        let parent = self.scope;
         self.data_offset = DATA_OFFSET as u16;
+        self.scope = scope;
         self.level += 1;
 
         let result = f(self);
 
         self.level -= 1;
+        self.scope = parent;
         self.data_offset = data_offset;
         // Identifiers are now out of scope.
         self.table.truncate(table_len);
@@ -63,51 +111,44 @@ impl<'a, C: CodeGen> Compiler<'a, C> {
     }
 }
 
-impl<'a, C: CodeGen> Compiler<'a, C> {
+impl Compiler {
     fn compile_program(&mut self, program: &Program) -> Result<()> {
-        self.compile_block(&program.block)
+        self.builder.begin_proc(MAIN_PROC, self.level, None);
+        self.compile_block(&program.block)?;
+        self.builder.end_proc();
+        Ok(())
     }
 
     fn compile_block(&mut self, block: &Block) -> Result<()> {
-        // Interpreter has to jump over all the generated procedure
-        // bodies to get to this block's statement.
-        let jump_index = self.codegen.reserve_jump()?;
-
         self.compile_consts(&block.consts)?;
         self.compile_vars(&block.vars)?;
         self.compile_procs(&block.procs)?;
 
-        // Patch the starting address of this block's bytecode
-        // into the jump instruction emitted at the beginning.
-        let begin_addr = self.codegen.len();
-        self.codegen.patch_jump(jump_index, begin_addr as u16)?;
-
-        // The stack space required by a procedure is encoded
-        // in this bytecode.
-        self.codegen.emit_inc_top(self.data_offset)?;
-        self.compile_stmt(&block.stmt)?;
-        self.codegen.emit_return()?;
+        // The frame is complete once all the variables are declared.
+        self.builder.set_span(block.stmt.span());
+        self.builder.enter(self.data_offset);
+        self.compile_tail_stmt(&block.stmt)?;
+        self.builder.ret();
 
         Ok(())
     }
 
     fn compile_consts(&mut self, consts: &[Const]) -> Result<()> {
         for const_ in consts {
-            self.table.push(Entry::Const {
-                name: const_.ident.name.clone(),
-                value: const_.value,
-            });
+            self.declare(&const_.ident, SymbolKind::Const { value: const_.value });
         }
         Ok(())
     }
 
     fn compile_vars(&mut self, vars: &[Var]) -> Result<()> {
         for var in vars {
-            self.table.push(Entry::Var {
-                name: var.ident.name.clone(),
-                level: self.level,
-                offset: self.data_offset,
-            });
+            self.declare(
+                &var.ident,
+                SymbolKind::Var {
+                    offset: self.data_offset,
+                },
+            );
+            self.builder.declare_var(&var.ident.name, self.data_offset);
             self.data_offset += 1;
         }
         Ok(())
@@ -115,65 +156,121 @@ impl<'a, C: CodeGen> Compiler<'a, C> {
 
     fn compile_procs(&mut self, procs: &[Proc]) -> Result<()> {
         for proc in procs {
-            // Enter identifier so procedures can call themselves recursively.
-            let name = proc.name.name.clone();
-            let addr = self.codegen.len() as u16;
-            self.table.push(Entry::Proc {
-                name,
-                level: self.level,
-                addr,
+            let body = self.env.push_scope(Scope {
+                parent: Some(self.scope),
+                proc: None,
+                level: self.level + 1,
             });
 
-            self.with_scope(|compiler| compiler.compile_block(&proc.body))?
+            // Procedure prologue is attributed to its declaration.
+            self.builder.set_span(proc.name.span);
+
+            // Enter identifier so procedures can call themselves recursively.
+            // The address is only known once code is generated.
+            let id = self.declare(&proc.name, SymbolKind::Proc { addr: None, body });
+            self.env.scope_mut(body).proc = Some(id);
+
+            self.with_scope(body, |compiler| {
+                let proc_id = compiler.builder.begin_proc(&proc.name.name, compiler.level, Some(id));
+                compiler.procs.insert(id, proc_id);
+                compiler.compile_block(&proc.body)?;
+                compiler.builder.end_proc();
+                Ok(())
+            })?
         }
 
         Ok(())
     }
 
     fn compile_stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        self.builder.set_span(stmt.span());
+
         match stmt {
             Stmt::Assign(assign) => self.compile_assign(assign),
-            Stmt::Call(call) => self.compile_call(call),
+            Stmt::Call(call) => self.compile_call(call, false),
             Stmt::Read(read) => self.compile_read(read),
             Stmt::Write(write) => self.compile_write(write),
             Stmt::SubBlock(sub_block) => self.compile_sub_block(sub_block),
-            Stmt::If(if_stmt) => self.compile_if(if_stmt),
+            Stmt::If(if_stmt) => self.compile_if(if_stmt, false),
             Stmt::While(while_stmt) => self.compile_while(while_stmt),
         }
     }
 
+    /// Compile a statement in tail position, where the procedure
+    /// returns as soon as the statement is done.
+    fn compile_tail_stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Call(call) => {
+                self.builder.set_span(call.span);
+                self.compile_call(call, true)
+            }
+            Stmt::SubBlock(sub_block) => {
+                let Some((last, stmts)) = sub_block.stmts.split_last() else {
+                    return Ok(());
+                };
+                for stmt in stmts {
+                    self.compile_stmt(stmt)?;
+                }
+                self.compile_tail_stmt(last)
+            }
+            Stmt::If(if_stmt) => {
+                self.builder.set_span(if_stmt.span);
+                self.compile_if(if_stmt, true)
+            }
+            _ => self.compile_stmt(stmt),
+        }
+    }
+
     fn compile_assign(&mut self, assign: &AssignStmt) -> Result<()> {
         self.compile_expr(&assign.rhs)?;
-        self.compile_var_store(&assign.lhs.name)
+        self.compile_var_store(&assign.lhs)
     }
 
-    fn compile_call(&mut self, call: &CallStmt) -> Result<()> {
-        match self.find_ident(call.name.name.as_str()) {
-            Some(entry) => match entry {
-                Entry::Const { .. } => error!(
-                    "compiler",
-                    "expected '{}' to be procedure; found constant", call.name.name
-                )
-                .into(),
-                Entry::Var { .. } => error!(
-                    "compiler",
-                    "expected '{}' to be procedure; found variable", call.name.name
-                )
-                .into(),
-                Entry::Proc { level, addr, .. } => self.codegen.emit_call(self.level - level, *addr),
+    /// Compile a call, as a tail call if it's in tail position.
+    ///
+    /// A tail call reuses the caller's frame, so recursion that ends in a
+    /// call doesn't grow the stack. A procedure declared in the caller's own
+    /// scope has a static link to the caller's frame, and would see its
+    /// variables overwritten, so it's always called normally.
+    fn compile_call(&mut self, call: &CallStmt, tail: bool) -> Result<()> {
+        match self.find_ident_id(call.name.name.as_str()) {
+            Some(id) => match self.env.symbol(id).kind {
+                SymbolKind::Const { .. } => {
+                    error!(E0302, "expected '{}' to be procedure; found constant", call.name.name)
+                        .with_location(call.name.span, &self.file)
+                        .into()
+                }
+                SymbolKind::Var { .. } => {
+                    error!(E0302, "expected '{}' to be procedure; found variable", call.name.name)
+                        .with_location(call.name.span, &self.file)
+                        .into()
+                }
+                SymbolKind::Proc { .. } => {
+                    let level = self.level - self.env.symbol(id).level;
+                    let proc = self.procs[&id];
+                    if tail && level > 0 {
+                        self.builder.tail_call(level, proc);
+                    } else {
+                        self.builder.call(level, proc);
+                    }
+                    Ok(())
+                }
             },
-            None => error!("compiler", "unresolved indentifier: {}", call.name.name).into(),
+            None => error!(E0301, "unresolved indentifier: {}", call.name.name)
+                .with_location(call.name.span, &self.file)
+                .into(),
         }
     }
 
     fn compile_write(&mut self, write: &WriteStmt) -> Result<()> {
         self.compile_expr(&write.expr)?;
-        self.codegen.emit_write()
+        self.builder.write();
+        Ok(())
     }
 
     fn compile_read(&mut self, read: &ReadStmt) -> Result<()> {
-        self.codegen.emit_read()?;
-        self.compile_var_store(&read.name.name)
+        self.builder.read();
+        self.compile_var_store(&read.name)
     }
 
     fn compile_sub_block(&mut self, sub_block: &SubBlock) -> Result<()> {
@@ -183,29 +280,59 @@ impl<'a, C: CodeGen> Compiler<'a, C> {
         Ok(())
     }
 
-    fn compile_if(&mut self, if_stmt: &IfStmt) -> Result<()> {
-        self.compile_cond(&if_stmt.head)?;
-        let jump_index = self.codegen.reserve_jump_if_zero()?;
-
-        self.compile_stmt(&if_stmt.body)?;
+    fn compile_if(&mut self, if_stmt: &IfStmt, tail: bool) -> Result<()> {
+        let end = self.builder.new_block();
+        self.compile_branch(&if_stmt.head, end)?;
 
-        let end = self.codegen.len();
-        self.codegen.patch_jump(jump_index, end as u16)?;
+        if tail {
+            self.compile_tail_stmt(&if_stmt.body)?;
+        } else {
+            self.compile_stmt(&if_stmt.body)?;
+        }
 
+        self.builder.jump(end);
+        self.builder.switch_to(end);
         Ok(())
     }
 
     fn compile_while(&mut self, while_stmt: &WhileStmt) -> Result<()> {
-        let head_addr = self.codegen.len() as u16;
-        self.compile_cond(&while_stmt.head)?;
-        let jump_index = self.codegen.reserve_jump_if_zero()?;
+        let head = self.builder.new_block();
+        let end = self.builder.new_block();
+        self.builder.jump(head);
+        self.builder.switch_to(head);
+        self.compile_branch(&while_stmt.head, end)?;
 
         self.compile_stmt(&while_stmt.body)?;
-        self.codegen.emit_jump(head_addr)?;
+        self.builder.set_span(while_stmt.span);
+        self.builder.jump(head);
+        self.builder.switch_to(end);
 
-        let end = self.codegen.len();
-        self.codegen.patch_jump(jump_index, end as u16)?;
+        Ok(())
+    }
 
+    /// Go to `otherwise` when the condition is false, and continue in a
+    /// new block when it's true.
+    ///
+    /// Constant conditions don't need to be tested at runtime. When the
+    /// condition is always true there's no branch, and when it's always
+    /// false the jump is unconditional. The statements it skips are still
+    /// compiled, so they're checked for errors, and left for dead code
+    /// elimination to remove.
+    fn compile_branch(&mut self, cond: &Cond, otherwise: BlockId) -> Result<()> {
+        match self.fold_cond(cond)? {
+            Some(true) => {}
+            Some(false) => {
+                let then = self.builder.new_block();
+                self.builder.jump(otherwise);
+                self.builder.switch_to(then);
+            }
+            None => {
+                self.compile_cond(cond)?;
+                let then = self.builder.new_block();
+                self.builder.branch(then, otherwise);
+                self.builder.switch_to(then);
+            }
+        }
         Ok(())
     }
 
@@ -213,74 +340,174 @@ impl<'a, C: CodeGen> Compiler<'a, C> {
         match cond {
             Cond::Odd(odd_cond) => {
                 self.compile_expr(&odd_cond.expr)?;
-                self.codegen.emit_math_odd()
+                self.builder.unary(UnaryOp::Odd);
             }
             Cond::Bin(bin_cond) => {
                 self.compile_expr(&bin_cond.lhs)?;
                 self.compile_expr(&bin_cond.rhs)?;
-                self.compile_cond_op(bin_cond.op)
+                self.builder.binary(match bin_cond.op {
+                    CondOp::Eq => BinaryOp::Eq,
+                    CondOp::NotEq => BinaryOp::NotEq,
+                    CondOp::Less => BinaryOp::Less,
+                    CondOp::LessEq => BinaryOp::LessEq,
+                    CondOp::Great => BinaryOp::Great,
+                    CondOp::GreatEq => BinaryOp::GreatEq,
+                });
             }
         }
+        Ok(())
     }
 
-    fn compile_cond_op(&mut self, op: CondOp) -> Result<()> {
-        match op {
-            CondOp::Eq => self.codegen.emit_math_eq(),
-            CondOp::NotEq => self.codegen.emit_math_noteq(),
-            CondOp::Less => self.codegen.emit_math_lt(),
-            CondOp::LessEq => self.codegen.emit_math_lte(),
-            CondOp::Great => self.codegen.emit_math_gt(),
-            CondOp::GreatEq => self.codegen.emit_math_gte(),
-        }
+    fn compile_expr(&mut self, expr: &Expr) -> Result<()> {
+        let folded = self.fold_expr(expr)?;
+        self.compile_folded(&folded)
     }
 
-    fn compile_expr(&mut self, expr: &Expr) -> Result<()> {
+    fn compile_folded(&mut self, expr: &Folded) -> Result<()> {
         match expr {
             // Push number literal onto the stack.
-            Expr::Num(num) => self.codegen.emit_lit(*num),
-            Expr::Unary(expr) => {
-                self.compile_expr(&expr.expr)?;
-                if expr.op == UnOp::Neg {
-                    self.codegen.emit_math_neg()?;
-                }
+            Folded::Num(num) => {
+                self.builder.lit(*num);
                 Ok(())
             }
-            Expr::Binary(bin_expr) => {
-                self.compile_expr(&bin_expr.lhs)?;
-                self.compile_expr(&bin_expr.rhs)?;
-                match bin_expr.op {
-                    BinOp::Add => self.codegen.emit_math_add(),
-                    BinOp::Sub => self.codegen.emit_math_sub(),
-                    BinOp::Mul => self.codegen.emit_math_mul(),
-                    BinOp::Div => self.codegen.emit_math_div(),
-                }
+            Folded::Neg(expr) => {
+                self.compile_folded(expr)?;
+                self.builder.unary(UnaryOp::Neg);
+                Ok(())
             }
+            Folded::Binary(op, lhs, rhs) => {
+                self.compile_folded(lhs)?;
+                self.compile_folded(rhs)?;
+                self.builder.binary(match op {
+                    BinOp::Add => BinaryOp::Add,
+                    BinOp::Sub => BinaryOp::Sub,
+                    BinOp::Mul => BinaryOp::Mul,
+                    BinOp::Div => BinaryOp::Div,
+                });
+                Ok(())
+            }
+            Folded::Name(name) => match self.find_ident(name.name.as_str()) {
+                Some(symbol) => match symbol.kind {
+                    SymbolKind::Const { value } => {
+                        self.builder.lit(value);
+                        Ok(())
+                    }
+                    SymbolKind::Var { offset } => {
+                        self.builder.load(self.level - symbol.level, offset);
+                        Ok(())
+                    }
+                    SymbolKind::Proc { .. } => error!(E0303, "procedure call not allowed in expression")
+                        .with_location(name.span, &self.file)
+                        .into(),
+                },
+                None => error!(E0301, "unresolved indentifier: {}", name.name)
+                    .with_location(name.span, &self.file)
+                    .into(),
+            },
+            Folded::Err => panic!("abstract-syntax-tree contains an error node"),
+        }
+    }
+
+    /// Evaluate the parts of an expression that consist of only literals
+    /// and constants at compile time.
+    ///
+    /// Each part is evaluated once, so that [`Compiler::compile_folded`]
+    /// doesn't have to look for constants again at every level.
+    /// Undeclared names are left for it to report.
+    fn fold_expr<'a>(&self, expr: &'a Expr) -> Result<Folded<'a>> {
+        match expr {
+            Expr::Num(num) => Ok(Folded::Num(*num)),
             Expr::Name(name) => match self.find_ident(name.name.as_str()) {
-                Some(entry) => match entry {
-                    Entry::Const { value, .. } => self.codegen.emit_lit(*value),
-                    Entry::Var {
-                        level, offset: addr, ..
-                    } => self.codegen.emit_load(self.level - level, *addr),
-                    Entry::Proc { .. } => error!("compiler", "procedure call not allowed in expression").into(),
+                Some(Symbol {
+                    kind: SymbolKind::Const { value },
+                    ..
+                }) => Ok(Folded::Num(*value)),
+                _ => Ok(Folded::Name(name)),
+            },
+            Expr::Unary(un_expr) => match (un_expr.op, self.fold_expr(&un_expr.expr)?) {
+                (UnOp::Pos, folded) => Ok(folded),
+                (UnOp::Neg, Folded::Num(num)) => match num.checked_neg() {
+                    Some(num) => Ok(Folded::Num(num)),
+                    None => error!(E0305, "negating {num} overflows")
+                        .with_location(un_expr.span, &self.file)
+                        .into(),
                 },
-                None => error!("compiler", "unresolved indentifier: {}", name.name).into(),
+                (UnOp::Neg, folded) => Ok(Folded::Neg(Box::new(folded))),
             },
-            Expr::Err() => panic!("abstract-syntax-tree contains an error node"),
+            Expr::Binary(bin_expr) => {
+                let lhs = self.fold_expr(&bin_expr.lhs)?;
+                let rhs = self.fold_expr(&bin_expr.rhs)?;
+                let (&Folded::Num(lhs), &Folded::Num(rhs)) = (&lhs, &rhs) else {
+                    return Ok(Folded::Binary(bin_expr.op, Box::new(lhs), Box::new(rhs)));
+                };
+                let (result, op) = match bin_expr.op {
+                    BinOp::Add => (lhs.checked_add(rhs), "+"),
+                    BinOp::Sub => (lhs.checked_sub(rhs), "-"),
+                    BinOp::Mul => (lhs.checked_mul(rhs), "*"),
+                    BinOp::Div if rhs == 0 => {
+                        return error!(E0306, "constant expression divides {lhs} by zero")
+                            .with_location(bin_expr.span, &self.file)
+                            .into()
+                    }
+                    BinOp::Div => (lhs.checked_div(rhs), "/"),
+                };
+                match result {
+                    Some(num) => Ok(Folded::Num(num)),
+                    None => error!(E0305, "constant expression {lhs} {op} {rhs} overflows")
+                        .with_location(bin_expr.span, &self.file)
+                        .into(),
+                }
+            }
+            Expr::Err() => Ok(Folded::Err),
         }
     }
 
-    fn compile_var_store(&mut self, var_name: &str) -> Result<()> {
-        match self.find_ident(var_name) {
-            Some(entry) => match entry {
-                Entry::Const { .. } => {
-                    error!("compiler", "expected '{}' to be variable; found constant", var_name).into()
+    /// Evaluate a condition at compile time, if both sides are constant.
+    fn fold_cond(&self, cond: &Cond) -> Result<Option<bool>> {
+        match cond {
+            Cond::Odd(odd_cond) => match self.fold_expr(&odd_cond.expr)? {
+                Folded::Num(num) => Ok(Some(num % 2 != 0)),
+                _ => Ok(None),
+            },
+            Cond::Bin(bin_cond) => {
+                let lhs = self.fold_expr(&bin_cond.lhs)?;
+                let rhs = self.fold_expr(&bin_cond.rhs)?;
+                let (Folded::Num(lhs), Folded::Num(rhs)) = (lhs, rhs) else {
+                    return Ok(None);
+                };
+                Ok(Some(match bin_cond.op {
+                    CondOp::Eq => lhs == rhs,
+                    CondOp::NotEq => lhs != rhs,
+                    CondOp::Less => lhs < rhs,
+                    CondOp::LessEq => lhs <= rhs,
+                    CondOp::Great => lhs > rhs,
+                    CondOp::GreatEq => lhs >= rhs,
+                }))
+            }
+        }
+    }
+
+    fn compile_var_store(&mut self, var_name: &Ident) -> Result<()> {
+        match self.find_ident(var_name.name.as_str()) {
+            Some(symbol) => match symbol.kind {
+                SymbolKind::Const { .. } => {
+                    error!(E0304, "expected '{}' to be variable; found constant", var_name.name)
+                        .with_location(var_name.span, &self.file)
+                        .into()
                 }
-                Entry::Var { level, offset, .. } => self.codegen.emit_store(self.level - *level, *offset),
-                Entry::Proc { .. } => {
-                    error!("compiler", "expected '{}' to be variable; found procedure", var_name).into()
+                SymbolKind::Var { offset } => {
+                    self.builder.store(self.level - symbol.level, offset);
+                    Ok(())
+                }
+                SymbolKind::Proc { .. } => {
+                    error!(E0304, "expected '{}' to be variable; found procedure", var_name.name)
+                        .with_location(var_name.span, &self.file)
+                        .into()
                 }
             },
-            None => error!("compiler", "unresolved indentifier: {}", var_name).into(),
+            None => error!(E0301, "unresolved indentifier: {}", var_name.name)
+                .with_location(var_name.span, &self.file)
+                .into(),
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::compile;
+use crate::{compile, compile_with_env, ErrorCode, SymbolKind};
 
 #[test]
 fn test_scope() {
@@ -19,3 +19,209 @@ end.
     chunk.dump();
     // assert!(false);
 }
+
+#[test]
+fn test_env() {
+    const SOURCE: &str = r"
+const answer = 42;
+var x;
+
+procedure outer;
+var y;
+    procedure inner;
+    begin
+        y := answer
+    end;
+begin
+    call inner
+end;
+
+begin
+    call outer
+end.
+    ";
+    let (_, env) = compile_with_env("<test>", SOURCE).expect("failed to compile");
+
+    let answer = env.symbol(env.lookup(env.root(), "answer").unwrap());
+    assert_eq!(answer.kind, SymbolKind::Const { value: 42 });
+    assert_eq!(answer.level, 0);
+    assert_eq!(answer.span, (7, 6));
+
+    let x = env.symbol(env.lookup(env.root(), "x").unwrap());
+    assert_eq!(x.kind, SymbolKind::Var { offset: 3 });
+
+    let outer_id = env.find_proc("outer").unwrap();
+    let SymbolKind::Proc { body: outer_scope, .. } = env.symbol(outer_id).kind else {
+        panic!("outer isn't a procedure");
+    };
+    assert_eq!(env.scope(outer_scope).parent, Some(env.root()));
+    assert_eq!(env.scope(outer_scope).proc, Some(outer_id));
+    assert_eq!(env.scope(outer_scope).level, 1);
+
+    let inner = env.symbol(env.find_proc("inner").unwrap());
+    assert_eq!(inner.scope, outer_scope);
+    assert_eq!(inner.level, 1);
+    let SymbolKind::Proc { body: inner_scope, .. } = inner.kind else {
+        panic!("inner isn't a procedure");
+    };
+
+    // Names resolve through enclosing scopes.
+    let y = env.symbol(env.lookup(inner_scope, "y").unwrap());
+    assert_eq!(y.kind, SymbolKind::Var { offset: 3 });
+    assert_eq!(y.level, 1);
+    assert_eq!(env.lookup(inner_scope, "answer"), env.lookup(env.root(), "answer"));
+    assert_eq!(env.lookup(env.root(), "y"), None);
+}
+
+#[test]
+fn test_env_lookup_order() {
+    // Procedures only see the names declared before them in the
+    // enclosing scopes, like the compiler resolves them.
+    const SOURCE: &str = r"
+var x;
+procedure first;
+begin
+    write x;
+    call first
+end;
+procedure x;
+begin
+    call first
+end;
+begin
+    call x
+end.
+    ";
+    let (_, env) = compile_with_env("<test>", SOURCE).expect("failed to compile");
+    let body = |name| match env.symbol(env.find_proc(name).unwrap()).kind {
+        SymbolKind::Proc { body, .. } => body,
+        _ => panic!("{name} isn't a procedure"),
+    };
+
+    let x = env.lookup(body("first"), "x").map(|id| env.symbol(id).kind);
+    assert_eq!(x, Some(SymbolKind::Var { offset: 3 }));
+    assert_eq!(env.lookup(body("first"), "first"), env.find_proc("first"));
+    assert_eq!(env.lookup(body("x"), "first"), env.find_proc("first"));
+
+    // The main program comes after every declaration.
+    let x = env.lookup(env.root(), "x").map(|id| env.symbol(id).kind);
+    assert!(matches!(x, Some(SymbolKind::Proc { .. })), "{x:?}");
+}
+
+#[test]
+fn test_constant_folding() {
+    use crate::bytecode::{Math, OpCode};
+
+    let chunk = compile("<test>", include_str!("../tests/expressions.pas")).expect("failed to compile");
+    let lits: Vec<_> = chunk
+        .code
+        .iter()
+        .filter_map(|instr| match instr.opcode {
+            OpCode::Lit => Some(instr.a as i32),
+            OpCode::Const => Some(chunk.constants[instr.a as usize]),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lits, vec![-1, -2, 26]);
+    assert!(!chunk.code.iter().any(|instr| matches!(instr.opcode, OpCode::Math(_))));
+
+    // Only the constant operand of a variable expression is folded.
+    const SOURCE: &str = "const k = 3; var x; begin x := x + k * 2 end.";
+    let chunk = compile("<test>", SOURCE).expect("failed to compile");
+    let ops: Vec<_> = chunk.code.iter().map(|instr| (instr.opcode, instr.a)).collect();
+    assert!(ops
+        .windows(3)
+        .any(|ops| ops == [(OpCode::Load, 3), (OpCode::Lit, 6), (OpCode::Math(Math::Add), 0)]));
+}
+
+#[test]
+fn test_constant_folding_errors() {
+    let cases = [
+        (
+            "const big = 2000000000; begin write big + big end.",
+            ErrorCode::E0305,
+            (36, 9),
+        ),
+        (
+            "begin write (0 - 2147483647 - 1) * (0 - 1) end.",
+            ErrorCode::E0305,
+            (12, 30),
+        ),
+        ("begin write -(0 - 2147483647 - 1) end.", ErrorCode::E0305, (12, 21)),
+        (
+            "const step = 0; begin write 100 / step end.",
+            ErrorCode::E0306,
+            (28, 10),
+        ),
+        // Division by a variable is left to the runtime.
+        ("var step; begin write 100 / step end.", ErrorCode::E0401, (0, 0)),
+    ];
+    for (source, code, span) in cases {
+        match compile("<test>", source) {
+            Ok(chunk) => {
+                let err = crate::Vm::new().eval(&chunk).expect_err("unexpected success");
+                assert_eq!(err.code(), code, "{source}");
+            }
+            Err(err) => {
+                assert_eq!(err.code(), code, "{source}");
+                assert_eq!(err.guest_loc.map(|loc| loc.span), Some(span), "{source}");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_program_too_large() {
+    // Four instructions a statement don't fit 300 times.
+    let source = format!("var x; begin {} end.", vec!["x := x + 1"; 300].join("; "));
+    let err = compile("<test>", &source).expect_err("compiled too many instructions");
+    assert_eq!(err.code(), ErrorCode::E0307);
+    assert!(err.guest_loc.is_some());
+
+    let source = format!("var x; begin {} end.", vec!["x := x + 1"; 200].join("; "));
+    compile("<test>", &source).expect("failed to compile");
+}
+
+#[test]
+fn test_tail_position() {
+    use crate::bytecode::OpCode;
+
+    // Calls the procedure returns right after, through blocks and `if`.
+    const SOURCE: &str = "var n;
+procedure p;
+begin
+    n := n - 1;
+    if n > 0 then begin write n; call p end
+end;
+procedure q;
+begin
+    call p;
+    while n > 0 do call q;
+    if n = 0 then call p
+end;
+begin
+    n := 3;
+    call q
+end.";
+    let chunk = compile("<test>", SOURCE).expect("failed to compile");
+    let calls: Vec<_> = chunk
+        .code
+        .iter()
+        .filter(|instr| matches!(instr.opcode, OpCode::Call | OpCode::TailCall))
+        .map(|instr| instr.opcode)
+        .collect();
+    assert_eq!(
+        calls,
+        vec![
+            // Last statement of `p`.
+            OpCode::TailCall,
+            // Followed by more statements in `q`.
+            OpCode::Call,
+            // Loops back to the condition.
+            OpCode::Call,
+            OpCode::TailCall,
+            // The main program has no caller to return to.
+            OpCode::Call,
+        ]
+    );
+}
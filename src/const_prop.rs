@@ -0,0 +1,137 @@
+//! Constant propagation.
+//!
+//! Tracks which variables hold a known constant at each point of a
+//! procedure, replaces loads of them with the constant, and folds the
+//! operations whose operands are all constant. A branch on a constant
+//! condition becomes a jump, leaving the statements it skips for dead
+//! code elimination to remove.
+//!
+//! Any call can change any variable the callee can see, which includes
+//! the caller's own variables when the callee is nested in it, so
+//! nothing is known about variables after a call.
+use std::collections::HashMap;
+
+use crate::ir::{Block, BlockId, Inst, InstKind, Proc, Program, Temp, Term, Var};
+use crate::Num;
+
+/// Variables known to hold a constant.
+type Consts = HashMap<Var, Num>;
+
+/// Propagate constants through every procedure of the program.
+pub(crate) fn propagate(program: &mut Program) {
+    for proc in program.procs.iter_mut() {
+        propagate_proc(proc);
+    }
+}
+
+fn propagate_proc(proc: &mut Proc) {
+    // The constants known on entry to each block, or `None` while no
+    // path to the block has been found. Only the branches that can be
+    // taken with what's known are followed, and what's known only
+    // shrinks as more paths are found, until nothing changes.
+    let mut entry: Vec<Option<Consts>> = vec![None; proc.blocks.len()];
+    entry[BlockId::ENTRY.index()] = Some(Consts::new());
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (idx, block) in proc.blocks.iter().enumerate() {
+            let Some(mut consts) = entry[idx].clone() else {
+                continue;
+            };
+            let folded = fold_block(block, &mut consts);
+            for succ in folded.term.successors() {
+                changed |= merge(&mut entry[succ.index()], &consts);
+            }
+        }
+    }
+
+    for (block, consts) in proc.blocks.iter_mut().zip(entry) {
+        // Blocks that can't be reached are left alone.
+        if let Some(mut consts) = consts {
+            *block = fold_block(block, &mut consts);
+        }
+    }
+}
+
+/// Combine what's known on one more path into a block.
+///
+/// Returns whether anything changed.
+fn merge(entry: &mut Option<Consts>, consts: &Consts) -> bool {
+    match entry {
+        None => {
+            *entry = Some(consts.clone());
+            true
+        }
+        Some(entry) => {
+            let len = entry.len();
+            entry.retain(|var, value| consts.get(var) == Some(value));
+            entry.len() != len
+        }
+    }
+}
+
+/// Rewrite a block with the constants known on entry, updating them to
+/// the constants known at its end.
+fn fold_block(block: &Block, consts: &mut Consts) -> Block {
+    let mut insts: Vec<Option<Inst>> = Vec::with_capacity(block.insts.len());
+    // Temporaries holding a constant, and the index of the instruction defining them.
+    let mut temps: HashMap<Temp, (Num, usize)> = HashMap::new();
+
+    for inst in &block.insts {
+        let folded = match inst.kind {
+            InstKind::Load { dst, var } => consts.get(&var).map(|value| (dst, *value, vec![])),
+            InstKind::Unary { dst, op, src } => temps.get(&src).map(|(value, def)| (dst, op.eval(*value), vec![*def])),
+            InstKind::Binary { dst, op, lhs, rhs } => match (temps.get(&lhs), temps.get(&rhs)) {
+                (Some((lhs, lhs_def)), Some((rhs, rhs_def))) => {
+                    op.eval(*lhs, *rhs).map(|value| (dst, value, vec![*lhs_def, *rhs_def]))
+                }
+                _ => None,
+            },
+            InstKind::Store { var, src } => {
+                match temps.get(&src) {
+                    Some((value, _)) => consts.insert(var, *value),
+                    None => consts.remove(&var),
+                };
+                None
+            }
+            InstKind::Call { .. } => {
+                consts.clear();
+                None
+            }
+            InstKind::Const { .. } | InstKind::Read { .. } | InstKind::Write { .. } => None,
+        };
+
+        let kind = match folded {
+            Some((dst, value, defs)) => {
+                // The operands were only used here.
+                for def in defs {
+                    insts[def] = None;
+                }
+                InstKind::Const { dst, value }
+            }
+            None => inst.kind,
+        };
+        if let InstKind::Const { dst, value } = kind {
+            temps.insert(dst, (value, insts.len()));
+        }
+        insts.push(Some(Inst { kind, span: inst.span }));
+    }
+
+    let term = match block.term {
+        Term::Branch { cond, then, otherwise } => match temps.get(&cond) {
+            Some((value, def)) => {
+                insts[*def] = None;
+                Term::Jump(if *value != 0 { then } else { otherwise })
+            }
+            None => block.term,
+        },
+        term => term,
+    };
+
+    Block {
+        insts: insts.into_iter().flatten().collect(),
+        term,
+        span: block.span,
+    }
+}
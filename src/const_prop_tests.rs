@@ -0,0 +1,108 @@
+use crate::const_prop::propagate;
+use crate::ir::{InstKind, Program, Term};
+use crate::ir_tests::lower;
+
+fn propagated(source: &str) -> Program {
+    let mut program = lower(source);
+    propagate(&mut program);
+    program
+}
+
+/// The instructions of a procedure's blocks, one line per block.
+fn main_blocks(program: &Program) -> Vec<String> {
+    program.procs[0]
+        .blocks
+        .iter()
+        .map(|block| {
+            block
+                .insts
+                .iter()
+                .map(|inst| format!("{:?}", inst.kind))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .collect()
+}
+
+fn writes_const(program: &Program, expected: i32) -> bool {
+    program.procs.iter().flat_map(|proc| &proc.blocks).any(|block| {
+        block.insts.windows(2).any(|pair| {
+            matches!(
+                (pair[0].kind, pair[1].kind),
+                (InstKind::Const { value, .. }, InstKind::Write { .. }) if value == expected
+            )
+        })
+    })
+}
+
+#[test]
+fn test_propagate_straight_line() {
+    let program = propagated("var x, y;\nbegin\n    x := 6;\n    y := x * 7;\n    write y - x\nend.");
+    assert_eq!(
+        program.to_string(),
+        "\
+proc <main> (level 0, frame 5)
+    ; var x at 3
+    ; var y at 4
+b0:
+    t0 = const 6
+    store 0 3, t0
+    t3 = const 42
+    store 0 4, t3
+    t6 = const 36
+    write t6
+    return
+"
+    );
+}
+
+#[test]
+fn test_propagate_paths() {
+    // Known on every path.
+    let program =
+        propagated("var x, y;\nbegin\n    x := 1;\n    read y;\n    if y > 0 then y := 0;\n    write x\nend.");
+    assert!(writes_const(&program, 1));
+
+    // Different on different paths.
+    let program =
+        propagated("var x, y;\nbegin\n    x := 1;\n    read y;\n    if y > 0 then x := 2;\n    write x\nend.");
+    assert!(!writes_const(&program, 1));
+    assert!(!writes_const(&program, 2));
+
+    // Changed by the loop it's tested in.
+    let program = propagated("var x;\nbegin\n    x := 0;\n    while x < 3 do x := x + 1;\n    write x\nend.");
+    assert!(main_blocks(&program).iter().any(|block| block.contains("Less")));
+}
+
+#[test]
+fn test_propagate_calls() {
+    // The procedure can change the main program's variables.
+    const SOURCE: &str = "var x;
+procedure p;
+begin
+    x := x + 1
+end;
+begin
+    x := 1;
+    call p;
+    write x
+end.";
+    let program = propagated(SOURCE);
+    assert!(!writes_const(&program, 1));
+    assert!(main_blocks(&program)[0].contains("Load"));
+}
+
+#[test]
+fn test_propagate_branches() {
+    let program = propagated("var x;\nbegin\n    x := 0;\n    if x = 1 then write 1;\n    write 2\nend.");
+    let main = &program.procs[0];
+    assert!(matches!(main.blocks[0].term, Term::Jump(_)));
+    assert!(main.blocks[0]
+        .insts
+        .iter()
+        .all(|inst| !matches!(inst.kind, InstKind::Load { .. } | InstKind::Binary { .. })));
+
+    // Division by zero is left to fail at runtime.
+    let program = propagated("var x;\nbegin\n    x := 0;\n    write 1 / x\nend.");
+    assert!(main_blocks(&program)[0].contains("Div"));
+}
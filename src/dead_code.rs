@@ -0,0 +1,137 @@
+//! Dead code elimination.
+//!
+//! Removes procedures that are never called, and statements that can
+//! never run, such as the body of an `if` whose condition is always false.
+//!
+//! The compiler turns constant conditions into unconditional jumps, so
+//! it's enough to follow every path through the bytecode from the main
+//! program, like the [verifier](crate::Chunk::verify) does, and delete
+//! the instructions that were never reached.
+use std::fmt;
+
+use crate::errors::Result;
+use crate::peephole::{self, AddrMap};
+use crate::Chunk;
+
+/// Code removed by dead code elimination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removed {
+    pub kind: RemovedKind,
+    /// 1-based line number where the removed code starts.
+    pub line: u32,
+    /// Byte span of the source fragment the removed code starts with.
+    pub span: (u32, u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemovedKind {
+    /// A procedure that is never called, including the procedures nested in it.
+    Proc { name: String },
+    /// A procedure that was [inlined](crate::CompileOptions::inline_threshold) at every call.
+    Inlined { name: String },
+    /// Statements that can never run.
+    Unreachable,
+}
+
+/// Result of eliminating dead code from a chunk.
+pub(crate) struct Eliminated {
+    /// Mapping from the original addresses to the new ones.
+    pub(crate) addrs: AddrMap,
+    /// Whether each instruction, by its address from before the removal,
+    /// was removed.
+    removed: Vec<bool>,
+    /// What was removed, in order of address.
+    ///
+    /// Only filled in when the chunk has debug info.
+    pub(crate) report: Vec<Removed>,
+}
+
+impl Eliminated {
+    /// New address of a procedure, or `None` if it was removed.
+    ///
+    /// Procedures are only entered by calls, so one whose entry was
+    /// never reached is never called, and was removed whole.
+    pub(crate) fn relocate_proc(&self, addr: u16) -> Option<u16> {
+        if self.removed.get(addr as usize) == Some(&true) {
+            None
+        } else {
+            Some(self.addrs.get(addr))
+        }
+    }
+}
+
+/// Remove every instruction that can't be reached from the main program.
+///
+/// The procedures at the `inlined` addresses are reported as inlined
+/// rather than never called.
+pub(crate) fn eliminate(chunk: &mut Chunk, inlined: &[u16]) -> Result<Eliminated> {
+    let flow = chunk.flow()?;
+    let remove: Vec<bool> = flow.procs.iter().map(Option::is_none).collect();
+    let report = report(chunk, &remove, inlined);
+
+    let addrs = peephole::remove_instrs(chunk, &remove);
+    Ok(Eliminated {
+        addrs,
+        removed: remove,
+        report,
+    })
+}
+
+/// Describe the code about to be removed.
+fn report(chunk: &Chunk, remove: &[bool], inlined: &[u16]) -> Vec<Removed> {
+    let Some(debug) = &chunk.debug else {
+        return vec![];
+    };
+    let removed = |pc: usize, kind: RemovedKind| {
+        debug.line_entry(pc).map(|entry| Removed {
+            kind,
+            line: entry.line,
+            span: entry.span,
+        })
+    };
+
+    let mut report = vec![];
+    let mut pc = 0;
+    while pc < remove.len() {
+        if !remove[pc] {
+            pc += 1;
+            continue;
+        }
+
+        // A removed procedure also takes the procedures nested in it along.
+        if let Some(proc) = debug.procs.iter().find(|proc| proc.addr as usize == pc) {
+            let nested = debug
+                .procs
+                .iter()
+                .filter(|nested| nested.addr >= proc.addr && nested.end <= proc.end);
+            for nested in nested {
+                let name = nested.name.clone();
+                let kind = if inlined.contains(&nested.addr) {
+                    RemovedKind::Inlined { name }
+                } else {
+                    RemovedKind::Proc { name }
+                };
+                report.extend(removed(nested.addr as usize, kind));
+            }
+            pc = proc.end as usize;
+            continue;
+        }
+
+        report.extend(removed(pc, RemovedKind::Unreachable));
+        while pc < remove.len() && remove[pc] && debug.proc_by_addr(pc as u16).is_none() {
+            pc += 1;
+        }
+    }
+
+    report
+}
+
+impl fmt::Display for Removed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            RemovedKind::Proc { name } => write!(f, "procedure '{name}' is never called"),
+            RemovedKind::Inlined { name } => write!(f, "procedure '{name}' is inlined at every call"),
+            RemovedKind::Unreachable => write!(f, "statement can never run"),
+        }
+    }
+}
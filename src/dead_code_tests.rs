@@ -0,0 +1,148 @@
+use crate::dead_code::eliminate;
+use crate::{assemble, compile_with_report, CompileOptions, Removed, RemovedKind, SymbolKind};
+
+/// Dead code elimination, without inlining procedures away.
+const OPTIONS: CompileOptions = CompileOptions {
+    opt_level: 2,
+    inline_threshold: 0,
+};
+
+/// Compile a program with dead code elimination, and return the removed
+/// code as pairs of line number and description.
+fn removed(source: &str) -> Vec<(u32, String)> {
+    let (chunk, _, removed) = compile_with_report("<test>", source, &OPTIONS).expect("failed to compile");
+    chunk.verify().expect("failed to verify");
+    removed
+        .iter()
+        .map(|removed| (removed.line, removed.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_dead_code_procs() {
+    const SOURCE: &str = r"var x;
+procedure unused;
+    procedure inner;
+    begin
+        write 1
+    end;
+begin
+    call inner
+end;
+procedure recursive;
+begin
+    call recursive
+end;
+procedure caller;
+begin
+    call recursive
+end;
+procedure used;
+begin
+    write x
+end;
+begin
+    x := 5;
+    call used
+end.
+";
+    let (chunk, env, removed) = compile_with_report("<test>", SOURCE, &OPTIONS).expect("failed to compile");
+    assert_eq!(
+        removed,
+        vec![
+            Removed {
+                kind: RemovedKind::Proc {
+                    name: "unused".to_string()
+                },
+                line: 2,
+                span: (17, 6),
+            },
+            Removed {
+                kind: RemovedKind::Proc {
+                    name: "inner".to_string()
+                },
+                line: 3,
+                span: (39, 5),
+            },
+            Removed {
+                kind: RemovedKind::Proc {
+                    name: "recursive".to_string()
+                },
+                line: 10,
+                span: (117, 9),
+            },
+            Removed {
+                kind: RemovedKind::Proc {
+                    name: "caller".to_string()
+                },
+                line: 14,
+                span: (168, 6),
+            },
+        ]
+    );
+
+    // Only the procedure that is called is left.
+    let debug = chunk.debug_info().expect("debug info");
+    let names: Vec<&str> = debug.procs.iter().map(|proc| proc.name.as_str()).collect();
+    assert_eq!(names, ["<main>", "used"]);
+
+    for (_, symbol) in env.symbols() {
+        if let SymbolKind::Proc { addr, .. } = symbol.kind {
+            let expected = debug.procs.iter().find(|proc| proc.name == symbol.name);
+            assert_eq!(addr, expected.map(|proc| proc.addr), "procedure '{}'", symbol.name);
+        }
+    }
+}
+
+#[test]
+fn test_dead_code_branches() {
+    // Constant conditions that are always false.
+    assert_eq!(
+        removed("const debug = 0;\nbegin\n    if debug = 1 then write 1;\n    write 2\nend."),
+        vec![(3, "statement can never run".to_string())]
+    );
+    assert_eq!(
+        removed("var x;\nbegin\n    x := 1;\n    while 1 > 2 do\n        write x\nend."),
+        vec![(5, "statement can never run".to_string())]
+    );
+    // Always true conditions keep their statements.
+    assert_eq!(removed("begin\n    if odd 3 then write 1\nend."), vec![]);
+    // Code after a loop that never ends can't run either.
+    assert_eq!(
+        removed("begin\n    while 0 = 0 do write 1;\n    write 2\nend."),
+        vec![(3, "statement can never run".to_string())]
+    );
+    // Conditions on variables are kept.
+    assert_eq!(removed("var x;\nbegin\n    if x = 1 then write 1\nend."), vec![]);
+}
+
+#[test]
+fn test_dead_code_opt_level() {
+    const SOURCE: &str = "procedure p;\nbegin\n    write 1\nend;\nbegin\n    if 1 = 0 then call p\nend.";
+    let (plain, _, removed) = compile_with_report("<test>", SOURCE, &CompileOptions::new()).expect("failed to compile");
+    assert!(removed.is_empty());
+
+    let (chunk, _, removed) = compile_with_report("<test>", SOURCE, &OPTIONS).expect("failed to compile");
+    assert_eq!(removed.len(), 2);
+    assert!(chunk.len() < plain.len());
+    assert_eq!(chunk.disassemble().matches("cal").count(), 0);
+}
+
+#[test]
+fn test_dead_code_without_debug_info() {
+    // Removed procedures are found from the code alone, without the debug
+    // info that names them.
+    let mut chunk = assemble(
+        "<test>",
+        "jmp 0 main\nunused: int 0 3\nopr 0 0\nused: int 0 3\nopr 0 0\nmain: int 0 3\ncal 0 used\nopr 0 0",
+    )
+    .expect("failed to assemble");
+    assert!(chunk.debug_info().is_none());
+
+    let eliminated = eliminate(&mut chunk, &[]).expect("failed to eliminate dead code");
+    assert!(eliminated.report.is_empty());
+    assert_eq!(eliminated.relocate_proc(1), None);
+    assert_eq!(eliminated.relocate_proc(3), Some(1));
+    assert_eq!(eliminated.relocate_proc(5), Some(3));
+    chunk.verify().expect("failed to verify");
+}
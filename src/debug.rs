@@ -0,0 +1,119 @@
+//! Debug information mapping bytecode back to the PL/0 source.
+
+/// Optional information attached to a [`Chunk`](crate::Chunk) that
+/// relates the executable bytecode to the source it was compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// File the program was compiled from.
+    pub file: String,
+    /// Line table, sorted by bytecode address.
+    ///
+    /// Each entry covers the instructions from its address up to
+    /// the address of the next entry.
+    pub lines: Vec<LineEntry>,
+    /// Procedures in the program, sorted by entry address.
+    ///
+    /// The main program is included as a procedure named [`MAIN_PROC`] at level zero.
+    pub procs: Vec<ProcInfo>,
+}
+
+/// Name given to the main program in the procedure table.
+pub const MAIN_PROC: &str = "<main>";
+
+/// Entry in the line table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    /// Address of the first instruction covered by the entry.
+    pub pc: u16,
+    /// 1-based line number in the source text.
+    pub line: u32,
+    /// Byte span of the source fragment that generated the instructions.
+    pub span: (u32, u32),
+}
+
+/// Layout of a procedure's code and stack frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcInfo {
+    pub name: String,
+    /// Address the procedure is called at.
+    pub addr: u16,
+    /// Address one past the procedure's last instruction.
+    ///
+    /// The range `addr..end` includes the code of nested procedures.
+    pub end: u16,
+    /// Lexical level of the procedure's variables.
+    pub level: u8,
+    /// Variables in the procedure's stack frame, in declaration order.
+    pub vars: Vec<VarInfo>,
+}
+
+/// A variable in a procedure's stack frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarInfo {
+    pub name: String,
+    /// Stack offset relative to the base of the frame.
+    pub offset: u16,
+}
+
+impl DebugInfo {
+    pub fn new(file: impl ToString) -> Self {
+        Self {
+            file: file.to_string(),
+            lines: vec![],
+            procs: vec![],
+        }
+    }
+
+    /// Find the innermost procedure containing the given bytecode address.
+    pub fn proc_at(&self, pc: usize) -> Option<&ProcInfo> {
+        // Nested procedures start after their parent, so the
+        // last match in address order is the innermost.
+        self.procs
+            .iter()
+            .rev()
+            .find(|proc| (proc.addr as usize..proc.end as usize).contains(&pc))
+    }
+
+    /// Find the procedure with the given entry address.
+    pub fn proc_by_addr(&self, addr: u16) -> Option<&ProcInfo> {
+        self.procs.iter().find(|proc| proc.addr == addr)
+    }
+
+    /// Find the line table entry covering the given bytecode address.
+    pub fn line_entry(&self, pc: usize) -> Option<&LineEntry> {
+        let idx = self.lines.partition_point(|entry| entry.pc as usize <= pc);
+        idx.checked_sub(1).map(|idx| &self.lines[idx])
+    }
+
+    /// Source location of the given bytecode address, formatted as `file:line`.
+    pub fn location(&self, pc: usize) -> Option<String> {
+        self.line_entry(pc).map(|entry| format!("{}:{}", self.file, entry.line))
+    }
+
+    /// Record that instructions starting at `pc` were generated by
+    /// the source fragment at `span`.
+    pub(crate) fn add_line(&mut self, pc: u16, line: u32, span: (u32, u32)) {
+        match self.lines.last_mut() {
+            // Same fragment continues.
+            Some(last) if last.span == span => {}
+            // Previous fragment didn't generate any instructions.
+            Some(last) if last.pc == pc => {
+                last.line = line;
+                last.span = span;
+            }
+            _ => self.lines.push(LineEntry { pc, line, span }),
+        }
+    }
+}
+
+/// Byte offsets where each line in the text starts.
+pub(crate) fn line_starts(text: &str) -> Vec<u32> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(idx, _)| idx as u32 + 1))
+        .collect()
+}
+
+/// 1-based line number of the given byte offset.
+pub(crate) fn line_of(line_starts: &[u32], offset: u32) -> u32 {
+    line_starts.partition_point(|start| *start <= offset) as u32
+}
@@ -0,0 +1,179 @@
+//! Symbolic disassembler.
+//!
+//! Prints a chunk in classic p-code mnemonics, with jump targets
+//! replaced by labels.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::bytecode::{Instr, OpCode};
+use crate::debug::{DebugInfo, MAIN_PROC};
+use crate::{Chunk, Num};
+
+/// Column where the trailing comment of an instruction line starts.
+const COMMENT_COLUMN: usize = 32;
+
+/// Label of the main program's entry point.
+const MAIN_LABEL: &str = "main";
+
+impl Chunk {
+    /// Disassemble the chunk into textual p-code.
+    ///
+    /// When the chunk has debug info, procedure entry points are
+    /// labelled by name and source locations are shown as comments.
+    pub fn disassemble(&self) -> String {
+        disassemble(self, None)
+    }
+
+    /// Disassemble the chunk, quoting the lines of the given source
+    /// text that the instructions were compiled from.
+    pub fn disassemble_with_source(&self, text: &str) -> String {
+        disassemble(self, Some(text))
+    }
+}
+
+fn disassemble(chunk: &Chunk, text: Option<&str>) -> String {
+    let debug = chunk.debug.as_ref();
+    let labels = make_labels(chunk);
+    let source_lines: Vec<&str> = text.map(|text| text.lines().collect()).unwrap_or_default();
+    let mut out = String::new();
+
+    if let Some(debug) = debug {
+        writeln!(out, "; {}", debug.file).unwrap();
+    }
+
+    for (idx, instr) in chunk.code.iter().enumerate() {
+        if let Some(debug) = debug {
+            if let Some(proc) = debug.proc_by_addr(idx as u16) {
+                if proc.name == MAIN_PROC {
+                    writeln!(out, "\n; program").unwrap();
+                } else {
+                    writeln!(out, "\n; procedure {}, level {}", proc.name, proc.level).unwrap();
+                }
+                for var in &proc.vars {
+                    writeln!(out, ";   var {} at {}", var.name, var.offset).unwrap();
+                }
+            }
+
+            if let Some(entry) = debug.line_entry(idx).filter(|entry| entry.pc as usize == idx) {
+                match source_lines.get(entry.line as usize - 1) {
+                    Some(line) => writeln!(out, "; {}:{}: {}", debug.file, entry.line, line.trim()).unwrap(),
+                    None => writeln!(out, "; {}:{}", debug.file, entry.line).unwrap(),
+                }
+            }
+        }
+
+        if let Some(label) = labels.get(&idx) {
+            writeln!(out, "{label}:").unwrap();
+        }
+
+        let line = format!("        {}", format_instr(instr, &chunk.constants, &labels));
+        write!(out, "{line:<COMMENT_COLUMN$}; {idx:04}").unwrap();
+        if let Some(comment) = instr_comment(idx, instr, debug) {
+            write!(out, " {comment}").unwrap();
+        }
+        out.push('\n');
+    }
+
+    // Jumps may target the address just past the end.
+    if let Some(label) = labels.get(&chunk.code.len()) {
+        writeln!(out, "{label}:").unwrap();
+    }
+
+    out
+}
+
+/// Assign labels to procedure entry points and jump targets.
+fn make_labels(chunk: &Chunk) -> HashMap<usize, String> {
+    let mut labels = HashMap::new();
+
+    // Procedure names aren't necessarily unique, since procedures
+    // in different scopes can share a name.
+    if let Some(debug) = chunk.debug.as_ref() {
+        let mut taken: Vec<String> = vec![];
+        for proc in &debug.procs {
+            let name = if proc.name == MAIN_PROC {
+                MAIN_LABEL
+            } else {
+                proc.name.as_str()
+            };
+            let mut label = name.to_string();
+            let mut suffix = 1;
+            while taken.contains(&label) {
+                suffix += 1;
+                label = format!("{name}_{suffix}");
+            }
+            taken.push(label.clone());
+            labels.insert(proc.addr as usize, label);
+        }
+    }
+
+    // Generated labels start with a period, which can't
+    // clash with PL/0 identifiers.
+    for instr in &chunk.code {
+        if matches!(
+            instr.opcode,
+            OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall
+        ) {
+            let addr = instr.a as usize;
+            if addr <= chunk.code.len() {
+                labels.entry(addr).or_insert_with(|| format!(".L{addr:04}"));
+            }
+        }
+    }
+
+    labels
+}
+
+fn format_instr(instr: &Instr, constants: &[Num], labels: &HashMap<usize, String>) -> String {
+    let Instr { opcode, l, a } = *instr;
+    let mnemonic = opcode.mnemonic();
+
+    match opcode {
+        OpCode::NoOp => mnemonic.to_string(),
+        OpCode::Return | OpCode::Math(_) | OpCode::Write | OpCode::Read | OpCode::Dup => {
+            format!("{mnemonic} {l} {}", opcode.opr().unwrap_or_default())
+        }
+        OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall => match labels.get(&(a as usize)) {
+            Some(label) => format!("{mnemonic} {l} {label}"),
+            None => format!("{mnemonic} {l} {a}"),
+        },
+        // Pooled constants are printed by value. The assembler moves
+        // literals that don't fit the operand back into the pool.
+        OpCode::Const => match constants.get(a as usize) {
+            Some(num) => format!("{mnemonic} {l} {num}"),
+            None => format!("{mnemonic} {l} #{a}"),
+        },
+        OpCode::Lit | OpCode::Load | OpCode::Store | OpCode::IncTop => format!("{mnemonic} {l} {a}"),
+    }
+}
+
+/// Explanatory comment for an instruction, like the name
+/// of an operation or variable.
+fn instr_comment(idx: usize, instr: &Instr, debug: Option<&DebugInfo>) -> Option<String> {
+    match instr.opcode {
+        OpCode::Return => Some("ret".to_string()),
+        OpCode::Math(m) => Some(m.name().to_string()),
+        OpCode::Write => Some("write".to_string()),
+        OpCode::Dup => Some("dup".to_string()),
+        OpCode::Read => Some("read".to_string()),
+        OpCode::Const => Some(format!("const #{}", instr.a)),
+        OpCode::TailCall => Some("tail call".to_string()),
+        OpCode::Load | OpCode::Store => {
+            let debug = debug?;
+            let current = debug.proc_at(idx)?;
+            let level = current.level.checked_sub(instr.l)?;
+            // The variable belongs to the enclosing procedure at the target level.
+            let owner = debug
+                .procs
+                .iter()
+                .filter(|proc| (proc.addr as usize..proc.end as usize).contains(&idx))
+                .find(|proc| proc.level == level)?;
+            owner
+                .vars
+                .iter()
+                .find(|var| var.offset == instr.a)
+                .map(|var| var.name.clone())
+        }
+        _ => None,
+    }
+}
@@ -1,4 +1,205 @@
+//! Symbol table.
+use crate::Num;
+
 /// Global environment holding all the symbols used in the program.
 ///
 /// In the original PL/0 implementation this is called the `table`.
-pub struct _Env {}
+///
+/// The compiler fills in the environment as it encounters declarations.
+/// Unlike the compiler's working table, symbols are never removed
+/// when they go out of scope, so the environment describes every
+/// declaration in the program once compilation is done.
+#[derive(Debug, Clone)]
+pub struct Env {
+    symbols: Vec<Symbol>,
+    scopes: Vec<Scope>,
+}
+
+/// Index of a symbol in its [`Env`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+/// Index of a scope in its [`Env`].
+///
+/// The default is the main program's scope.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopeId(u32);
+
+/// A declared constant, variable or procedure.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Scope the symbol is declared in.
+    pub scope: ScopeId,
+    /// Lexical level of the declaring scope.
+    ///
+    /// The main program is level zero, and each nested procedure
+    /// is one level deeper than its parent.
+    pub level: u8,
+    /// Location of the declaring identifier in the source text.
+    pub span: (u32, u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Const {
+        value: Num,
+    },
+    Var {
+        /// Stack offset relative to the base of the declaring procedure's frame.
+        offset: u16,
+    },
+    Proc {
+        /// Bytecode address the procedure is called at, or `None` when
+        /// the procedure was removed because nothing calls it.
+        addr: Option<u16>,
+        /// The scope holding the procedure's own declarations.
+        body: ScopeId,
+    },
+}
+
+/// A lexical scope, introduced by the main program or a procedure.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    /// Enclosing scope, or `None` for the main program.
+    pub parent: Option<ScopeId>,
+    /// The procedure that introduced the scope, or `None` for the main program.
+    pub proc: Option<SymbolId>,
+    /// Lexical level of the declarations in this scope.
+    pub level: u8,
+}
+
+impl SymbolId {
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl ScopeId {
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Env {
+    /// Create an environment containing only the main program's scope.
+    pub fn new() -> Self {
+        Self {
+            symbols: vec![],
+            scopes: vec![Scope {
+                parent: None,
+                proc: None,
+                level: 0,
+            }],
+        }
+    }
+
+    /// The main program's scope.
+    pub fn root(&self) -> ScopeId {
+        ScopeId::default()
+    }
+
+    pub fn symbol(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.index()]
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.index()]
+    }
+
+    /// All symbols, in declaration order.
+    pub fn symbols(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .map(|(idx, symbol)| (SymbolId(idx as u32), symbol))
+    }
+
+    /// All scopes, in the order they were entered.
+    pub fn scopes(&self) -> impl Iterator<Item = (ScopeId, &Scope)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .map(|(idx, scope)| (ScopeId(idx as u32), scope))
+    }
+
+    /// Symbols declared directly in the given scope, in declaration order.
+    pub fn symbols_in(&self, scope: ScopeId) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols().filter(move |(_, symbol)| symbol.scope == scope)
+    }
+
+    /// Resolve a name as seen from the statements of the given scope,
+    /// crawling up through the enclosing scopes, the same way the
+    /// compiler does.
+    ///
+    /// The statements come after every declaration of their own scope,
+    /// but in an enclosing scope only the names declared up to the
+    /// procedure are visible. When a name is declared more than once in a
+    /// scope, the last visible declaration wins.
+    pub fn lookup(&self, scope: ScopeId, name: &str) -> Option<SymbolId> {
+        let mut current = Some(scope);
+        // The procedure whose scope was just searched.
+        let mut proc: Option<SymbolId> = None;
+        while let Some(scope) = current {
+            let found = self
+                .symbols_in(scope)
+                .filter(|(id, symbol)| symbol.name == name && proc.is_none_or(|proc| *id <= proc))
+                .last()
+                .map(|(id, _)| id);
+            if found.is_some() {
+                return found;
+            }
+            proc = self.scope(scope).proc;
+            current = self.scope(scope).parent;
+        }
+        None
+    }
+
+    /// Find a procedure's symbol by its name, in any scope.
+    pub fn find_proc(&self, name: &str) -> Option<SymbolId> {
+        self.symbols()
+            .find(|(_, symbol)| symbol.name == name && matches!(symbol.kind, SymbolKind::Proc { .. }))
+            .map(|(id, _)| id)
+    }
+
+    pub(crate) fn push_symbol(&mut self, symbol: Symbol) -> SymbolId {
+        let id = SymbolId(self.symbols.len() as u32);
+        self.symbols.push(symbol);
+        id
+    }
+
+    pub(crate) fn push_scope(&mut self, scope: Scope) -> ScopeId {
+        let id = ScopeId(self.scopes.len() as u32);
+        self.scopes.push(scope);
+        id
+    }
+
+    pub(crate) fn scope_mut(&mut self, id: ScopeId) -> &mut Scope {
+        &mut self.scopes[id.index()]
+    }
+
+    /// Record the address a procedure's code was generated at.
+    pub(crate) fn set_proc_addr(&mut self, id: SymbolId, proc_addr: u16) {
+        if let SymbolKind::Proc { addr, .. } = &mut self.symbols[id.index()].kind {
+            *addr = Some(proc_addr);
+        }
+    }
+
+    /// Update the addresses of procedures after their code has moved.
+    ///
+    /// Procedures that `relocate` maps to `None` were removed.
+    pub(crate) fn relocate_procs(&mut self, relocate: impl Fn(u16) -> Option<u16>) {
+        for symbol in self.symbols.iter_mut() {
+            if let SymbolKind::Proc { addr, .. } = &mut symbol.kind {
+                *addr = addr.and_then(&relocate);
+            }
+        }
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
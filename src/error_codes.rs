@@ -0,0 +1,754 @@
+//! Stable diagnostic codes.
+//!
+//! Every error the toolchain can report carries one of these codes. The
+//! codes are stable and may be linked to from external material, so a
+//! code must never be reused for a different diagnostic once published.
+//!
+//! The hundreds digit identifies the stage that reports the error:
+//!
+//! - `E01xx` lexer
+//! - `E02xx` parser
+//! - `E03xx` compiler
+//! - `E04xx` runtime
+//! - `E05xx` assembler
+//! - `E06xx` loader
+//! - `E07xx` verifier
+use std::fmt::{self, Formatter};
+
+use crate::errors::Stage;
+
+macro_rules! error_codes {
+    ($($code:ident => $stage:ident, $summary:expr, $explain:expr;)*) => {
+        /// Stable identifier of a diagnostic.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum ErrorCode {
+            $(
+                #[doc = $summary]
+                $code,
+            )*
+        }
+
+        impl ErrorCode {
+            /// All known error codes, in ascending order.
+            pub const ALL: &'static [ErrorCode] = &[$(ErrorCode::$code,)*];
+
+            /// The code as it is displayed, for example `"E0102"`.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(ErrorCode::$code => stringify!($code),)*
+                }
+            }
+
+            /// The stage that reports this diagnostic.
+            pub fn stage(&self) -> Stage {
+                match self {
+                    $(ErrorCode::$code => Stage::$stage,)*
+                }
+            }
+
+            /// One line summary of the diagnostic.
+            pub fn summary(&self) -> &'static str {
+                match self {
+                    $(ErrorCode::$code => $summary,)*
+                }
+            }
+
+            /// Long-form explanation of the diagnostic, with an example.
+            pub fn explain(&self) -> &'static str {
+                match self {
+                    $(ErrorCode::$code => $explain,)*
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    E0101 => Lexer, "unexpected character",
+r"The source text contains a character that is not part of the PL/0 language.
+
+Identifiers start with a letter, followed by letters, digits or underscores.
+Numbers are sequences of decimal digits. Everything else must be one of the
+operators or punctuation marks of the language.
+
+Erroneous example:
+
+    var x;
+    begin
+        x := 5 % 2
+    end.
+
+The `%` character has no meaning in PL/0. Remainders can be calculated using
+division and multiplication:
+
+    var x;
+    begin
+        x := 5 - (5 / 2) * 2
+    end.
+";
+
+    E0102 => Lexer, "incomplete assignment operator",
+r"A colon `:` was found that isn't followed by an equal sign `=`.
+
+The only use of the colon in PL/0 is the assignment operator `:=`, and the two
+characters may not be separated by whitespace.
+
+Erroneous example:
+
+    var x;
+    begin
+        x : = 1
+    end.
+
+Write the assignment operator as a single token:
+
+    var x;
+    begin
+        x := 1
+    end.
+";
+
+    E0201 => Parser, "unexpected token",
+r"The parser expected a specific token, like a semicolon or keyword, but found
+something else.
+
+Erroneous example:
+
+    var x
+    begin
+        x := 1
+    end.
+
+The variable declaration must be terminated with a semicolon:
+
+    var x;
+    begin
+        x := 1
+    end.
+";
+
+    E0202 => Parser, "unexpected end-of-file",
+r"The source text ended before the program was complete.
+
+A program is a block followed by a period. Blocks, procedures and `begin..end`
+statements must all be closed before the end of the file.
+
+Erroneous example:
+
+    begin
+        write 1;
+
+Close the statement and terminate the program:
+
+    begin
+        write 1
+    end.
+";
+
+    E0203 => Parser, "malformed declaration list",
+r"A `const` or `var` declaration list contains something other than a comma
+separating the declarations, or a semicolon ending them.
+
+Erroneous example:
+
+    var x y;
+    begin
+        x := 1
+    end.
+
+Separate declarations with commas and end the list with a semicolon:
+
+    var x, y;
+    begin
+        x := 1
+    end.
+";
+
+    E0204 => Parser, "malformed statement list",
+r"The statements inside a `begin..end` statement must be separated by
+semicolons, and the list must be closed with `end`.
+
+Erroneous example:
+
+    begin
+        write 1
+        write 2
+    end.
+
+Separate the statements with a semicolon:
+
+    begin
+        write 1;
+        write 2
+    end.
+
+Note that the semicolon is a separator, and not a terminator. A final
+semicolon before `end` is not allowed.
+";
+
+    E0205 => Parser, "statement expected",
+r"The parser expected the start of a statement.
+
+A statement is an assignment, or begins with one of the keywords `call`,
+`read`, `write`, `begin`, `if` or `while`. A block must contain exactly one
+statement.
+
+Erroneous example:
+
+    procedure foobar;
+    begin write 1 end;
+
+    begin
+        foobar
+    end.
+
+Procedures are invoked with the `call` statement:
+
+    procedure foobar;
+    begin write 1 end;
+
+    begin
+        call foobar
+    end.
+";
+
+    E0206 => Parser, "conditional operator expected",
+r"A condition compares two expressions with one of the operators `=`, `#`, `<`,
+`<=`, `>` or `>=`. The parser found something else between the expressions.
+
+Erroneous example:
+
+    var x;
+    begin
+        x := 1;
+        if x != 2 then write x
+    end.
+
+PL/0 uses `#` for inequality:
+
+    var x;
+    begin
+        x := 1;
+        if x # 2 then write x
+    end.
+";
+
+    E0207 => Parser, "expression expected",
+r"The parser expected an identifier, a number or a parenthesised expression.
+
+Erroneous example:
+
+    begin
+        write 1 + * 2
+    end.
+
+Provide an operand for every operator:
+
+    begin
+        write 1 + 2 * 2
+    end.
+";
+
+    E0208 => Parser, "identifier expected",
+r"The parser expected a name, for example in a declaration, assignment or
+`call` statement.
+
+Identifiers start with a letter. Keywords can't be used as identifiers.
+
+Erroneous example:
+
+    var begin;
+    begin
+        begin := 1
+    end.
+
+Choose a name that isn't a keyword:
+
+    var start;
+    begin
+        start := 1
+    end.
+";
+
+    E0209 => Parser, "invalid number literal",
+r"A number literal could not be converted to a number.
+
+Numbers are 32-bit signed integers, so literals larger than 2147483647 can't
+be represented.
+
+Erroneous example:
+
+    begin
+        write 9999999999
+    end.
+
+Use a value that fits into the number type:
+
+    begin
+        write 999999999
+    end.
+";
+
+    E0301 => Compiler, "unresolved identifier",
+r"A name is used that hasn't been declared in the current scope, or in any of
+the enclosing scopes.
+
+Erroneous example:
+
+    begin
+        x := 1
+    end.
+
+Declare the variable before using it:
+
+    var x;
+    begin
+        x := 1
+    end.
+
+Names declared inside a procedure are not visible outside of it.
+";
+
+    E0302 => Compiler, "called identifier is not a procedure",
+r"The `call` statement was given the name of a constant or variable.
+
+Erroneous example:
+
+    var x;
+    begin
+        call x
+    end.
+
+Only procedures can be called:
+
+    var x;
+    procedure setx;
+    begin x := 1 end;
+    begin
+        call setx
+    end.
+";
+
+    E0303 => Compiler, "procedure used in expression",
+r"A procedure name was used as a value in an expression.
+
+PL/0 procedures don't return values, so they can't be used in expressions.
+Values are passed out of a procedure using variables.
+
+Erroneous example:
+
+    procedure one;
+    begin write 1 end;
+    begin
+        write one + 1
+    end.
+
+Assign the result to a variable instead:
+
+    var result;
+    procedure one;
+    begin result := 1 end;
+    begin
+        call one;
+        write result + 1
+    end.
+";
+
+    E0304 => Compiler, "assignment to non-variable",
+r"An assignment or `read` statement targets a constant or a procedure.
+
+Constants can't be changed after they are declared, and procedures aren't
+values.
+
+Erroneous example:
+
+    const limit = 10;
+    begin
+        limit := 20
+    end.
+
+Declare a variable to hold values that change:
+
+    var limit;
+    begin
+        limit := 20
+    end.
+";
+
+    E0305 => Compiler, "arithmetic overflow in constant expression",
+r"An expression made up of only numbers and constants is calculated while the
+program is compiled, and its result doesn't fit in the number type.
+
+Numbers are 32-bit signed integers, between -2147483648 and 2147483647.
+
+Erroneous example:
+
+    const big = 2000000000;
+    begin
+        write big + big
+    end.
+
+Keep intermediate results within range:
+
+    const big = 2000000000;
+    begin
+        write big / 2 + big / 2
+    end.
+";
+
+    E0306 => Compiler, "division by zero in constant expression",
+r"An expression made up of only numbers and constants divides by zero.
+
+Such an expression is calculated while the program is compiled, and would
+always fail when the program runs.
+
+Erroneous example:
+
+    const step = 0;
+    begin
+        write 100 / step
+    end.
+
+Make sure the divisor is never zero:
+
+    const step = 5;
+    begin
+        write 100 / step
+    end.
+";
+
+    E0307 => Compiler, "program too large",
+r"The compiled program has more instructions than the virtual machine can hold.
+
+The virtual machine has room for 1024 instructions. Every statement and
+expression takes a few, and each procedure a few more.
+
+Move statements that are repeated into a procedure, and call it instead.
+";
+
+    E0401 => Runtime, "division by zero",
+r"The right-hand side of a division evaluated to zero while the program was
+running.
+
+Erroneous example:
+
+    var x;
+    begin
+        x := 0;
+        write 10 / x
+    end.
+
+Guard the division with a condition:
+
+    var x;
+    begin
+        x := 0;
+        if x # 0 then write 10 / x
+    end.
+";
+
+    E0402 => Runtime, "stack overflow",
+r"The program ran out of stack space.
+
+Every procedure call reserves space on the stack for its block mark and
+variables. This is usually caused by a recursive procedure that never stops
+calling itself.
+
+Erroneous example:
+
+    procedure forever;
+    begin
+        call forever
+    end;
+    begin
+        call forever
+    end.
+
+Make sure recursion has a terminating condition:
+
+    var n;
+    procedure countdown;
+    begin
+        if n > 0 then
+        begin
+            n := n - 1;
+            call countdown
+        end
+    end;
+    begin
+        n := 10;
+        call countdown
+    end.
+";
+
+    E0501 => Assembler, "unknown mnemonic",
+r"An assembly line starts with a word that isn't a p-code instruction.
+
+The instructions are `lit`, `opr`, `lod`, `sto`, `cal`, `int`, `jmp`, `jpc`
+and `nop`. Labels are written on their own, followed by a colon.
+
+Erroneous example:
+
+    main:
+            push 0 7
+            opr 0 14
+            opr 0 0
+
+Literals are pushed with `lit`:
+
+    main:
+            lit 0 7
+            opr 0 14
+            opr 0 0
+";
+
+    E0502 => Assembler, "undefined label",
+r"A jump or call refers to a label that isn't defined anywhere in the program.
+
+Erroneous example:
+
+    main:
+            jmp 0 done
+            lit 0 1
+            opr 0 14
+            opr 0 0
+
+Define the label in front of the instruction the jump should land on:
+
+    main:
+            jmp 0 done
+            lit 0 1
+            opr 0 14
+    done:
+            opr 0 0
+";
+
+    E0503 => Assembler, "invalid operand",
+r"An instruction has a missing, extra or malformed operand.
+
+Every instruction except `nop` takes a level `l` and an argument `a`. The level
+must fit in 8 bits and the argument in 16 bits, except for `lit` which takes
+any number. The argument of `opr` must be a known operation number, and the
+argument of `jmp`, `jpc` and `cal` may be a label instead of an address.
+
+Erroneous example:
+
+    main:
+            lit 7
+            opr 0 14
+            opr 0 0
+
+Provide both operands:
+
+    main:
+            lit 0 7
+            opr 0 14
+            opr 0 0
+";
+
+    E0504 => Assembler, "duplicate label",
+r"The same label is defined more than once, so jumps to it are ambiguous.
+
+Erroneous example:
+
+    main:
+            jmp 0 loop
+    loop:
+            opr 0 0
+    loop:
+            opr 0 0
+
+Give every label a unique name:
+
+    main:
+            jmp 0 loop
+    loop:
+            opr 0 0
+    exit:
+            opr 0 0
+";
+
+    E0505 => Assembler, "program too large",
+r"The assembly text has more instructions than the virtual machine can hold.
+
+The virtual machine has room for 1024 instructions, at addresses 0 to 1023.
+Labels may still be defined just past the last instruction.
+";
+
+    E0601 => Loader, "not a compiled chunk",
+r"The file doesn't start with the magic bytes of a compiled PL/0 chunk.
+
+Compiled chunks are produced by `pl0 build`, and start with the bytes `PL0C`.
+Source files must be run by name, with an extension other than `.pl0c`.
+
+Erroneous example:
+
+    $ cp program.pl0 program.pl0c
+    $ pl0 program.pl0c
+
+Compile the program first:
+
+    $ pl0 build program.pl0 -o program.pl0c
+    $ pl0 program.pl0c
+";
+
+    E0602 => Loader, "unsupported chunk version",
+r"The chunk was written in a version of the binary format that this build of
+the toolchain can't read.
+
+The format version is bumped whenever the bytecode changes incompatibly. Chunks
+must be rebuilt from source with the toolchain that will run them:
+
+    $ pl0 build program.pl0 -o program.pl0c
+";
+
+    E0603 => Loader, "malformed chunk",
+r"The chunk file has a valid header, but its contents are damaged.
+
+This happens when a file is truncated or altered after it was built, for
+example by a failed download or a text mode transfer that rewrote line
+endings. The error message describes the first problem that was found.
+
+Rebuild the chunk from source:
+
+    $ pl0 build program.pl0 -o program.pl0c
+";
+
+    E0701 => Verifier, "control flow leaves the code",
+r"A jump or call targets an address past the end of the bytecode, or execution
+can run off the end without returning.
+
+Every path through a procedure must end with a return (`opr 0 0`) or a jump.
+Jumping to address zero halts the machine.
+
+Erroneous example:
+
+    main:
+            int 0 3
+            lit 0 1
+            opr 0 14
+
+End the program with a return:
+
+    main:
+            int 0 3
+            lit 0 1
+            opr 0 14
+            opr 0 0
+";
+
+    E0702 => Verifier, "invalid instruction operand",
+r"An instruction has an operand that is out of range.
+
+Variables are addressed by a level, which must not be deeper than the chain of
+enclosing procedures, and an offset, which must be inside the target frame and
+past its block mark. Operands that an instruction doesn't use must be zero,
+and the first `int` of a procedure must reserve room for the block mark.
+
+Erroneous example:
+
+    main:
+            int 0 4
+            lod 1 3
+            opr 0 14
+            opr 0 0
+
+The main program has no enclosing procedure, so its variables are at level 0:
+
+    main:
+            int 0 4
+            lod 0 3
+            opr 0 14
+            opr 0 0
+";
+
+    E0703 => Verifier, "stack underflow",
+r"An instruction takes more values off the stack than the current frame has
+pushed, or uses the frame before it has been reserved with `int`.
+
+Erroneous example:
+
+    main:
+            int 0 3
+            opr 0 2
+            opr 0 0
+
+Push both operands before an arithmetic operation:
+
+    main:
+            int 0 3
+            lit 0 1
+            lit 0 2
+            opr 0 2
+            opr 0 14
+            opr 0 0
+";
+
+    E0704 => Verifier, "inconsistent stack height",
+r"An instruction can be reached along paths that leave different numbers of
+values on the stack, or from different procedures.
+
+Bytecode compiled from PL/0 always has a balanced stack at the start of each
+statement. This error indicates hand-written or damaged bytecode.
+
+Erroneous example:
+
+    main:
+            int 0 3
+            lit 0 1
+            jpc 0 done
+            lit 0 2
+    done:
+            opr 0 0
+
+Both paths must arrive at `done` with the same stack height:
+
+    main:
+            int 0 3
+            lit 0 1
+            jpc 0 done
+            lit 0 2
+            opr 0 14
+    done:
+            opr 0 0
+";
+
+    E0705 => Verifier, "stack too small",
+r"The program needs more stack space than the virtual machine provides.
+
+For programs without recursion, the deepest chain of procedure calls is known
+ahead of time. If the frames along that chain don't fit on the stack, the
+program would fail with a stack overflow when it runs.
+
+Reduce the number of variables declared in nested procedures, or the depth
+of nested procedure calls.
+";
+
+    E0706 => Verifier, "program too large",
+r"The chunk has more instructions than the virtual machine can hold.
+
+The virtual machine has room for 1024 instructions. Chunks from the compiler
+and the assembler never have more, so this error indicates a chunk that was
+put together by hand.
+";
+}
+
+impl ErrorCode {
+    /// Look up an error code by its display string, for example `"E0102"`.
+    ///
+    /// The leading `E` may be omitted, and the match is case-insensitive.
+    pub fn lookup(code: &str) -> Option<ErrorCode> {
+        let code = code.trim();
+        let digits = code.strip_prefix(['E', 'e']).unwrap_or(code);
+        ErrorCode::ALL
+            .iter()
+            .copied()
+            .find(|error_code| &error_code.as_str()[1..] == digits)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
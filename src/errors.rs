@@ -1,14 +1,19 @@
 use std::fmt::{self, Formatter};
+use std::num::NonZeroU32;
+
+use crate::debug::LineEntry;
+use crate::error_codes::ErrorCode;
 
 #[macro_export]
 macro_rules! error {
-    ($stage:expr, $($arg:tt)*) => {
+    ($code:ident, $($arg:tt)*) => {
         $crate::errors::Error {
             message: format!($($arg)*),
-            stage: $crate::errors::Stage::try_from($stage).unwrap_or_else(|e| panic!("{e}")),
+            code: $crate::error_codes::ErrorCode::$code,
+            stage: $crate::error_codes::ErrorCode::$code.stage(),
             guest_loc: None,
             location: $crate::errors::HostLoc { line: line!(), file: file!() },
-            note: None,
+            notes: vec![],
         }
     };
 }
@@ -24,20 +29,14 @@ impl<T> ResultExt for Result<T> {
     #[inline(always)]
     fn with_note<R: ToString>(self, f: impl FnOnce() -> R) -> Self {
         self.map_err(|mut err| {
-            err.note = Some(f().to_string());
+            err.notes.push(f().to_string());
             err
         })
     }
 
     #[inline(always)]
     fn with_location(self, span: (u32, u32), file: impl ToString) -> Self {
-        self.map_err(|mut err| {
-            err.guest_loc = Some(GuestLoc {
-                span,
-                file: file.to_string(),
-            });
-            err
-        })
+        self.map_err(|err| err.with_location(span, file))
     }
 }
 
@@ -50,17 +49,38 @@ impl<T> From<self::Error> for self::Result<T> {
 #[derive(Debug, Clone)]
 pub struct Error {
     pub(crate) message: String,
+    pub(crate) code: ErrorCode,
     pub(crate) stage: Stage,
     pub(crate) guest_loc: Option<GuestLoc>,
     pub(crate) location: HostLoc,
-    pub(crate) note: Option<String>,
+    pub(crate) notes: Vec<String>,
 }
 
 /// Code location in PL/0 script.
 #[derive(Debug, Clone)]
 pub(crate) struct GuestLoc {
     pub(crate) span: (u32, u32),
-    pub(crate) file: String,
+    pub(crate) file: Box<str>,
+    /// Line of the span, as recorded in a chunk's line table.
+    ///
+    /// Used when the source text doesn't match the span, like when a
+    /// chunk is run without its source, or after the source changed.
+    pub(crate) line: Option<NonZeroU32>,
+}
+
+impl GuestLoc {
+    /// Line and column of the start and end of the span in `text`, if
+    /// the text still matches it.
+    fn position(&self, text: &str) -> Option<((u32, u32), (u32, u32))> {
+        let (start, length) = self.span;
+        let begin = line_col(text, start)?;
+        // Spans at the end of the file may reach past it.
+        let end = line_col(text, (start + length).min(text.len() as u32)).unwrap_or(begin);
+        match self.line {
+            Some(line) if line.get() != begin.0 => None,
+            _ => Some((begin, end)),
+        }
+    }
 }
 
 /// Code lcoation in Rust code.
@@ -71,9 +91,54 @@ pub(crate) struct HostLoc {
 }
 
 impl Error {
+    /// Set the location in the PL/0 source where the error occurred.
+    pub(crate) fn with_location(mut self, span: (u32, u32), file: impl ToString) -> Self {
+        self.guest_loc = Some(GuestLoc {
+            span,
+            file: file.to_string().into(),
+            line: None,
+        });
+        self
+    }
+
+    /// Set the location from an entry of a chunk's line table, which
+    /// also knows the line without the source text.
+    pub(crate) fn with_line_entry(mut self, entry: &LineEntry, file: impl ToString) -> Self {
+        self.guest_loc = Some(GuestLoc {
+            span: entry.span,
+            file: file.to_string().into(),
+            line: NonZeroU32::new(entry.line),
+        });
+        self
+    }
+
+    /// Attach an explanatory note to the error.
+    pub(crate) fn with_note(mut self, note: impl ToString) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Stable code identifying the kind of diagnostic.
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+
     pub fn pretty<'a, 'b>(&'a self, text: &'b str) -> ErrorPretty<'a, 'b> {
         ErrorPretty { err: self, text }
     }
+
+    /// Format the error as a single line JSON object, for consumption by tools.
+    pub fn json<'a, 'b>(&'a self, text: &'b str) -> ErrorJson<'a, 'b> {
+        ErrorJson { err: self, text }
+    }
 }
 
 impl std::error::Error for self::Error {}
@@ -83,14 +148,17 @@ impl fmt::Display for Error {
         if cfg!(debug_assertions) {
             let Self {
                 message,
+                code,
                 stage,
                 location: HostLoc { line, file },
                 ..
             } = self;
-            write!(f, "{stage} error [{file}:{line}]: {message}")
+            write!(f, "{stage} error[{code}] [{file}:{line}]: {message}")
         } else {
-            let Self { message, stage, .. } = self;
-            write!(f, "{stage} error: {message}")
+            let Self {
+                message, code, stage, ..
+            } = self;
+            write!(f, "{stage} error[{code}]: {message}")
         }
     }
 }
@@ -101,6 +169,9 @@ pub enum Stage {
     Parser,
     Compiler,
     Runtime,
+    Assembler,
+    Loader,
+    Verifier,
 }
 
 impl fmt::Display for Stage {
@@ -110,40 +181,14 @@ impl fmt::Display for Stage {
             Stage::Parser => "parser",
             Stage::Compiler => "compiler",
             Stage::Runtime => "runtime",
+            Stage::Assembler => "assembler",
+            Stage::Loader => "loader",
+            Stage::Verifier => "verifier",
         };
         fmt::Display::fmt(name, f)
     }
 }
 
-#[derive(Debug)]
-pub struct StageParseError {
-    /// Original value that should ahve been a stage enum.
-    pub input: String,
-}
-
-impl fmt::Display for StageParseError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let Self { input } = self;
-        write!(f, "failed to parse error stage: {input:?}")
-    }
-}
-
-impl TryFrom<&str> for Stage {
-    type Error = StageParseError;
-
-    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
-        match value {
-            "lexer" => Ok(Stage::Lexer),
-            "parser" => Ok(Stage::Parser),
-            "compiler" => Ok(Stage::Compiler),
-            "runtime" => Ok(Stage::Runtime),
-            _ => Err(StageParseError {
-                input: value.to_string(),
-            }),
-        }
-    }
-}
-
 pub struct ErrorPretty<'a, 'b> {
     err: &'a self::Error,
     text: &'b str,
@@ -171,35 +216,47 @@ impl<'a, 'b> fmt::Display for ErrorPretty<'a, 'b> {
         // error: could not compile `pl0` (lib) due to 1 previous error
         let Error {
             stage,
+            code,
             message,
             guest_loc,
             location,
-            note,
+            notes,
             ..
         } = self.err;
 
         // Error Message
-        writeln!(f, "{stage} error: {message}")?;
+        writeln!(f, "{stage} error[{code}]: {message}")?;
 
         // Block Header (Optional)
         // procedure foobar;
 
         // Text Fragment
         if let Some(guest_loc) = guest_loc {
-            writeln!(f, "file: {}", guest_loc.file)?;
-
             let (start, length) = guest_loc.span;
-            let fragment = &self.text[start as usize..(start + length) as usize];
-            writeln!(f, "     |")?;
-            writeln!(f, "     | {fragment}")?;
-            writeln!(f, "     |")?;
-            writeln!(f, "     |")?;
+            match (guest_loc.position(self.text), guest_loc.line) {
+                (Some(((line, column), _)), _) => {
+                    writeln!(f, "file: {}:{line}:{column}", guest_loc.file)?;
+                    if let Some(fragment) = self.text.get(start as usize..(start + length) as usize) {
+                        writeln!(f, "     |")?;
+                        writeln!(f, "     | {fragment}")?;
+                        writeln!(f, "     |")?;
+                        writeln!(f, "     |")?;
+                    }
+                }
+                // The text may be unavailable or out of date, like when
+                // running a compiled chunk.
+                (None, Some(line)) => writeln!(f, "file: {}:{line}", guest_loc.file)?,
+                (None, None) => writeln!(f, "file: {}", guest_loc.file)?,
+            }
         };
 
         // Notes (Optional)
-        if let Some(note) = note {
+        for note in notes {
             writeln!(f, "note: {note}")?
-        };
+        }
+
+        // Explanation Hint
+        writeln!(f, "help: run `pl0 explain {code}` for more information")?;
 
         // Rust Location
         write!(f, "rust: {}:{}", location.file, location.line)?;
@@ -207,3 +264,96 @@ impl<'a, 'b> fmt::Display for ErrorPretty<'a, 'b> {
         Ok(())
     }
 }
+
+pub struct ErrorJson<'a, 'b> {
+    err: &'a self::Error,
+    text: &'b str,
+}
+
+impl<'a, 'b> fmt::Display for ErrorJson<'a, 'b> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // {"severity":"error","code":"E0201","stage":"parser","message":"...",
+        //  "file":"foo.pl0","span":{"start":30,"end":33,"line":6,"column":5,
+        //  "end_line":6,"end_column":8},"notes":["..."]}
+        let Error {
+            stage,
+            code,
+            message,
+            guest_loc,
+            notes,
+            ..
+        } = self.err;
+
+        write!(
+            f,
+            "{{\"severity\":\"error\",\"code\":\"{code}\",\"stage\":\"{stage}\",\"message\":"
+        )?;
+        write_json_str(f, message)?;
+
+        match guest_loc {
+            Some(guest_loc) => {
+                let (start, length) = guest_loc.span;
+                let end = start + length;
+
+                write!(f, ",\"file\":")?;
+                write_json_str(f, &guest_loc.file)?;
+                write!(f, ",\"span\":{{\"start\":{start},\"end\":{end},")?;
+                match (guest_loc.position(self.text), guest_loc.line) {
+                    (Some(((line, column), (end_line, end_column))), _) => write!(
+                        f,
+                        "\"line\":{line},\"column\":{column},\"end_line\":{end_line},\"end_column\":{end_column}}}"
+                    )?,
+                    // Without the text, only the line is known, if that.
+                    (None, line) => {
+                        let line = line.map_or("null".to_string(), |line| line.to_string());
+                        write!(
+                            f,
+                            "\"line\":{line},\"column\":null,\"end_line\":null,\"end_column\":null}}"
+                        )?
+                    }
+                }
+            }
+            None => write!(f, ",\"file\":null,\"span\":null")?,
+        }
+
+        write!(f, ",\"notes\":[")?;
+        for (idx, note) in notes.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            write_json_str(f, note)?;
+        }
+        write!(f, "]}}")
+    }
+}
+
+/// Write a string as a quoted and escaped JSON string literal.
+fn write_json_str(f: &mut Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{ch}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Convert a byte offset into the source text to a 1-based line
+/// and column number.
+///
+/// Columns are counted in characters, not bytes. Returns `None` for
+/// offsets past the end of the text or inside a character, which happen
+/// when the text isn't the one the offset was taken from.
+pub(crate) fn line_col(text: &str, offset: u32) -> Option<(u32, u32)> {
+    let before = text.get(..offset as usize)?;
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    Some((line as u32, column as u32))
+}
@@ -0,0 +1,409 @@
+//! Differential fuzzing.
+//!
+//! Runs generated programs with every engine at hand, and checks that
+//! they write the same numbers and finish the same way as the
+//! tree-walking interpreter. The interpreter runs the generated syntax
+//! tree directly, while the other engines get it printed as source text,
+//! so the printer and the parser are checked too.
+//!
+//! A program that makes an engine disagree is shrunk down to a small one
+//! that still does, with [`Fuzzer::minimize`].
+//!
+//! Stack overflows are left out of the comparison, since every engine
+//! lays out its stack differently.
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ast::Program;
+use crate::{fuzz_gen, fuzz_reduce, CompileOptions, ErrorCode, Interpreter, Num, Result, Stage, Vm};
+
+pub use crate::fuzz_runner::{
+    build, installed, io_config, llvm_tool, run_process, Exit, Io, JS_RUNNER, TIMEOUT, WASM_RUNNER,
+};
+
+/// File name that programs are compiled under.
+const FILENAME: &str = "fuzz.pl0";
+
+/// A generated program, and the numbers it reads.
+#[derive(Debug, Clone)]
+pub struct FuzzCase {
+    program: Program,
+    input: Vec<Num>,
+}
+
+impl FuzzCase {
+    /// Generate a case from a seed. The same seed always gives the same case.
+    pub fn generate(seed: u64) -> Self {
+        let (program, input) = fuzz_gen::generate(seed);
+        Self { program, input }
+    }
+
+    /// The program's source text.
+    pub fn source(&self) -> String {
+        self.program.to_string()
+    }
+
+    /// The numbers the program reads, in order. Reading past them reads zero.
+    pub fn input(&self) -> &[Num] {
+        &self.input
+    }
+
+    /// Shrink the case while `keep` still holds for it.
+    pub fn minimize(&self, mut keep: impl FnMut(&FuzzCase) -> bool) -> FuzzCase {
+        let (program, input) = fuzz_reduce::minimize(&self.program, &self.input, |program, input| {
+            keep(&FuzzCase {
+                program: program.clone(),
+                input: input.to_vec(),
+            })
+        });
+        FuzzCase { program, input }
+    }
+}
+
+/// A way of running programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// The tree-walking interpreter, which the others are compared to.
+    Interpreter,
+    /// The bytecode VM, at an optimization level.
+    Vm(u8),
+    /// The bytecode VM running native code.
+    #[cfg(feature = "jit")]
+    Jit(u8),
+    /// Translated to C, and built with the system C compiler.
+    C(u8),
+    /// Translated to JavaScript, and run with Node.js.
+    Js(u8),
+    /// Compiled to WebAssembly, and run with Node.js.
+    Wasm(u8),
+    /// Compiled to x86-64 assembly, and linked with the system C compiler.
+    X86(u8),
+    /// Compiled to LLVM IR, and run with `lli`.
+    Llvm(u8),
+}
+
+impl Engine {
+    /// Every engine that runs here. The backends that need other tools
+    /// are only included when the tools are installed.
+    pub fn available() -> Vec<Engine> {
+        let mut engines = vec![Engine::Interpreter];
+        for opt_level in 0..=2 {
+            engines.push(Engine::Vm(opt_level));
+            #[cfg(feature = "jit")]
+            engines.push(Engine::Jit(opt_level));
+        }
+
+        let (cc, node, lli) = (installed("cc"), installed("node"), llvm_tool("lli").is_some());
+        let x86 = cc && cfg!(all(target_os = "linux", target_arch = "x86_64"));
+        // The backends translate the IR, which the peephole optimizer at
+        // -O1 leaves alone, so only -O0 and -O2 give them different input.
+        for opt_level in [0, 2] {
+            let backends = [
+                (cc, Engine::C(opt_level)),
+                (node, Engine::Js(opt_level)),
+                (node, Engine::Wasm(opt_level)),
+                (x86, Engine::X86(opt_level)),
+                (lli, Engine::Llvm(opt_level)),
+            ];
+            engines.extend(
+                backends
+                    .into_iter()
+                    .filter(|(found, _)| *found)
+                    .map(|(_, engine)| engine),
+            );
+        }
+        engines
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, opt_level) = match *self {
+            Engine::Interpreter => return f.write_str("interpreter"),
+            Engine::Vm(opt_level) => ("vm", opt_level),
+            #[cfg(feature = "jit")]
+            Engine::Jit(opt_level) => ("jit", opt_level),
+            Engine::C(opt_level) => ("c", opt_level),
+            Engine::Js(opt_level) => ("js", opt_level),
+            Engine::Wasm(opt_level) => ("wasm", opt_level),
+            Engine::X86(opt_level) => ("x86", opt_level),
+            Engine::Llvm(opt_level) => ("llvm", opt_level),
+        };
+        write!(f, "{name} -O{opt_level}")
+    }
+}
+
+/// What a program did when it ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// Numbers written, up to where it stopped.
+    pub written: Vec<Num>,
+    pub status: Status,
+}
+
+/// How a program stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Finished,
+    /// Stopped with an error. Programs running in other processes only
+    /// tell that they failed, not why.
+    Failed(Option<ErrorCode>),
+    /// The translated program didn't build.
+    BuildFailed,
+    /// Ran for longer than [`TIMEOUT`], and was stopped.
+    TimedOut,
+    /// Wrote something other than numbers.
+    Garbled,
+}
+
+impl Outcome {
+    fn from_result(written: Vec<Num>, result: Result<()>) -> Self {
+        let status = match result {
+            Ok(()) => Status::Finished,
+            Err(err) => Status::Failed(Some(err.code())),
+        };
+        Self { written, status }
+    }
+
+    /// Whether two engines did the same, as far as both can tell.
+    fn agrees(&self, other: &Outcome) -> bool {
+        let status = match (self.status, other.status) {
+            (Status::Finished, Status::Finished) => true,
+            (Status::Failed(Some(code)), Status::Failed(Some(other))) => code == other,
+            // Only engines running in other processes fail without a
+            // code, which any error agrees with.
+            (Status::Failed(None), Status::Failed(_)) | (Status::Failed(_), Status::Failed(None)) => true,
+            _ => false,
+        };
+        status && self.written == other.written
+    }
+
+    fn overflowed(&self) -> bool {
+        self.status == Status::Failed(Some(ErrorCode::E0402))
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "wrote {:?} and ", self.written)?;
+        match self.status {
+            Status::Finished => f.write_str("finished"),
+            Status::Failed(Some(code)) => write!(f, "failed with {code}"),
+            Status::Failed(None) => f.write_str("failed"),
+            Status::BuildFailed => f.write_str("didn't build"),
+            Status::TimedOut => write!(f, "ran for over {}s", TIMEOUT.as_secs()),
+            Status::Garbled => f.write_str("wrote something other than numbers"),
+        }
+    }
+}
+
+/// An engine that did something else than the interpreter.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub engine: Engine,
+    /// What the interpreter did.
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+/// Runs programs with several engines, and compares what they do.
+pub struct Fuzzer {
+    engines: Vec<Engine>,
+    /// Where compiled programs are written to be run.
+    dir: PathBuf,
+}
+
+impl Fuzzer {
+    /// A fuzzer comparing every engine that's available.
+    pub fn new() -> Self {
+        Self::with_engines(Engine::available())
+    }
+
+    /// A fuzzer comparing the given engines to the interpreter.
+    pub fn with_engines(engines: Vec<Engine>) -> Self {
+        // Fuzzers in the same process get directories of their own.
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("pl0-fuzz-{}-{count}", std::process::id()));
+        Self { engines, dir }
+    }
+
+    pub fn engines(&self) -> &[Engine] {
+        &self.engines
+    }
+
+    /// Run a case with every engine, and return the first one that
+    /// disagrees with the interpreter.
+    ///
+    /// Cases the compiler rejects, and engines running out of stack,
+    /// are skipped.
+    pub fn check(&self, case: &FuzzCase) -> Option<Mismatch> {
+        let expected = self.reference(case)?;
+        let source = case.source();
+        self.engines
+            .iter()
+            .filter(|engine| **engine != Engine::Interpreter)
+            .find_map(|&engine| self.compare(engine, case, &source, &expected))
+    }
+
+    /// Run a case with one engine, and compare it to the interpreter.
+    pub fn check_engine(&self, case: &FuzzCase, engine: Engine) -> Option<Mismatch> {
+        let expected = self.reference(case)?;
+        self.compare(engine, case, &case.source(), &expected)
+    }
+
+    /// Shrink a case while `engine` still disagrees with the interpreter.
+    pub fn minimize(&self, case: &FuzzCase, engine: Engine) -> FuzzCase {
+        case.minimize(|case| self.check_engine(case, engine).is_some())
+    }
+
+    /// Run a case with the interpreter, unless the outcome can't be compared.
+    fn reference(&self, case: &FuzzCase) -> Option<Outcome> {
+        let expected = self.run(Engine::Interpreter, case, "");
+        match expected.status {
+            _ if expected.overflowed() => None,
+            Status::Failed(Some(code)) if code.stage() != Stage::Runtime => None,
+            _ => Some(expected),
+        }
+    }
+
+    fn compare(&self, engine: Engine, case: &FuzzCase, source: &str, expected: &Outcome) -> Option<Mismatch> {
+        let actual = self.run(engine, case, source);
+        (!actual.overflowed() && !actual.agrees(expected)).then(|| Mismatch {
+            engine,
+            expected: expected.clone(),
+            actual,
+        })
+    }
+
+    fn run(&self, engine: Engine, case: &FuzzCase, source: &str) -> Outcome {
+        let options = |opt_level| CompileOptions {
+            opt_level,
+            ..CompileOptions::new()
+        };
+        let input = case.input();
+        let name = engine.to_string().replace(' ', "");
+        let compiled = match engine {
+            Engine::Interpreter => {
+                let (config, io) = io_config(input);
+                let result = Interpreter::from_config(config).run_program(FILENAME, &case.program);
+                let written = io.borrow().1.clone();
+                return Outcome::from_result(written, result);
+            }
+            Engine::Vm(opt_level) => return run_vm(source, input, &options(opt_level), false),
+            #[cfg(feature = "jit")]
+            Engine::Jit(opt_level) => return run_vm(source, input, &options(opt_level), true),
+            Engine::C(opt_level) => crate::compile_to_c(FILENAME, source, &options(opt_level)).map(|c| {
+                let (source, exe) = (self.path(&name, "c"), self.path(&name, ""));
+                self.write(&source, c.as_bytes());
+                let mut cc = Command::new("cc");
+                cc.args(["-std=c99", "-O2", "-w", "-o"]).arg(&exe).arg(&source);
+                self.build_and_run(cc, Command::new(&exe), input, &[source, exe])
+            }),
+            Engine::Js(opt_level) => crate::compile_to_js(FILENAME, source, &options(opt_level)).map(|js| {
+                let program = self.path(&name, "js");
+                self.write(&program, js.as_bytes());
+                let mut node = Command::new("node");
+                node.args(["-e", JS_RUNNER]).arg(&program);
+                self.run_process(node, input, &[program])
+            }),
+            Engine::Wasm(opt_level) => crate::compile_to_wasm(FILENAME, source, &options(opt_level)).map(|wasm| {
+                let module = self.path(&name, "wasm");
+                self.write(&module, &wasm);
+                let mut node = Command::new("node");
+                node.args(["-e", WASM_RUNNER]).arg(&module);
+                self.run_process(node, input, &[module])
+            }),
+            Engine::X86(opt_level) => crate::compile_to_x86(FILENAME, source, &options(opt_level)).map(|asm| {
+                let (source, exe) = (self.path(&name, "s"), self.path(&name, ""));
+                self.write(&source, asm.as_bytes());
+                let mut cc = Command::new("cc");
+                cc.arg("-o").arg(&exe).arg(&source);
+                self.build_and_run(cc, Command::new(&exe), input, &[source, exe])
+            }),
+            Engine::Llvm(opt_level) => crate::compile_to_llvm(FILENAME, source, &options(opt_level)).map(|ll| {
+                let module = self.path(&name, "ll");
+                self.write(&module, ll.as_bytes());
+                let mut lli = llvm_tool("lli").expect("lli is installed");
+                lli.arg(&module);
+                self.run_process(lli, input, &[module])
+            }),
+        };
+        compiled.unwrap_or_else(|err| Outcome::from_result(vec![], Err(err)))
+    }
+
+    fn path(&self, name: &str, extension: &str) -> PathBuf {
+        self.dir.join(name).with_extension(extension)
+    }
+
+    fn write(&self, path: &PathBuf, contents: &[u8]) {
+        fs::create_dir_all(&self.dir).expect("create fuzzing directory");
+        fs::write(path, contents).expect("write program");
+    }
+
+    /// Build a program with `compile`, then run it.
+    fn build_and_run(&self, compile: Command, run: Command, input: &[Num], files: &[PathBuf]) -> Outcome {
+        if build(compile).is_err() {
+            self.remove(files);
+            return Outcome {
+                written: vec![],
+                status: Status::BuildFailed,
+            };
+        }
+        self.run_process(run, input, files)
+    }
+
+    /// Run a program in a process of its own, then remove its files.
+    fn run_process(&self, command: Command, input: &[Num], files: &[PathBuf]) -> Outcome {
+        let (written, exit) = run_process(command, input);
+        self.remove(files);
+        let status = match exit {
+            Exit::Success => Status::Finished,
+            Exit::Failure => Status::Failed(None),
+            Exit::TimedOut => Status::TimedOut,
+        };
+        match written {
+            Some(written) => Outcome { written, status },
+            None => Outcome {
+                written: vec![],
+                status: Status::Garbled,
+            },
+        }
+    }
+
+    fn remove(&self, files: &[PathBuf]) {
+        for file in files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+impl Default for Fuzzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Fuzzer {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Compile a program to bytecode, and run it in the VM.
+fn run_vm(source: &str, input: &[Num], options: &CompileOptions, jit: bool) -> Outcome {
+    let (config, io) = io_config(input);
+    let result = crate::compile_with_options(FILENAME, source, options).and_then(|(chunk, _)| {
+        let mut vm = Vm::from_config(config);
+        #[cfg(feature = "jit")]
+        vm.set_jit(jit);
+        #[cfg(not(feature = "jit"))]
+        let _ = jit;
+        vm.eval(&chunk).map(|_| ())
+    });
+    let written = io.borrow().1.clone();
+    Outcome::from_result(written, result)
+}
@@ -0,0 +1,495 @@
+//! Random program generator.
+//!
+//! Builds syntax trees of programs that compile, and that finish in a
+//! bounded number of steps without running out of stack:
+//!
+//! - Loops count a variable of their own up to a small number.
+//! - A procedure only calls the procedures declared in it, and the ones
+//!   declared before it or before the procedures it's nested in, which
+//!   never lead back to itself. Procedures may also call themselves,
+//!   guarded by a depth counter declared next to them.
+//! - Calls and loops are only generated while the statements they'd run
+//!   stay within a budget.
+//!
+//! The counters are named apart from the other variables (see
+//! [`is_counter`]), never assigned anything else, and never read into,
+//! which the minimizer relies on to keep programs finishing.
+use crate::ast::*;
+use crate::compiler::Compiler;
+use crate::Num;
+
+/// Deepest nesting of procedures.
+const MAX_PROC_DEPTH: usize = 2;
+/// Most procedures in a program.
+const MAX_PROCS: usize = 6;
+/// Deepest nesting of statements.
+const MAX_STMT_DEPTH: usize = 3;
+/// Deepest nesting of expressions.
+const MAX_EXPR_DEPTH: usize = 3;
+/// Most statements in a `begin ... end` block.
+const MAX_STMTS: usize = 4;
+/// Most times a loop runs, or a procedure calls itself.
+const MAX_REPEAT: Num = 4;
+/// Most statements a single statement may run, roughly.
+const BUDGET: u64 = 5000;
+
+/// Names of constants and variables. They're few, so that declarations in
+/// nested procedures shadow the outer ones.
+const NAMES: &[&str] = &["a", "b", "c", "x", "y", "z"];
+
+/// Numbers around the edges of the encodings the backends use.
+const LITERALS: &[Num] = &[
+    0,
+    1,
+    2,
+    3,
+    7,
+    10,
+    100,
+    255,
+    256,
+    32767,
+    32768,
+    65535,
+    65536,
+    65537,
+    1 << 24,
+    Num::MAX,
+];
+
+/// Whether a variable is a loop or depth counter.
+pub(crate) fn is_counter(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some('i' | 'd')) && !chars.as_str().is_empty() && chars.all(|c| c.is_ascii_digit())
+}
+
+/// Generate a program, and the input it reads, from a seed.
+///
+/// Constant expressions that overflow or divide by zero don't compile, so
+/// programs that have them are thrown away and drawn again.
+pub(crate) fn generate(seed: u64) -> (Program, Vec<Num>) {
+    let mut rng = Rng::new(seed);
+    loop {
+        let mut gen = Generator {
+            rng,
+            scope: vec![],
+            procs: 0,
+            counters: 0,
+        };
+        let (block, _) = gen.block(0, None);
+        let program = Program { block };
+        if Compiler::new("<fuzz>").compile(&program).is_ok() {
+            let input = (0..gen.rng.below(4)).map(|_| gen.number()).collect();
+            return (program, input);
+        }
+        rng = gen.rng;
+    }
+}
+
+/// SplitMix64, which is plenty random for picking syntax.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from `0` up to, but not including, `n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// A declaration in scope.
+struct Name {
+    name: String,
+    kind: Kind,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Const,
+    Var,
+    /// Loop or depth counter, only read.
+    Counter,
+    /// A procedure, with the statements a call runs, roughly. Procedures
+    /// being generated can't be called yet.
+    Proc(Option<u64>),
+}
+
+/// A procedure that calls itself.
+struct Recursion {
+    name: String,
+    /// Counter of how deep the calls go.
+    depth: String,
+    limit: Num,
+}
+
+struct Generator {
+    rng: Rng,
+    /// Declarations in scope, the innermost last.
+    scope: Vec<Name>,
+    /// Procedures generated so far.
+    procs: usize,
+    /// Counters generated so far.
+    counters: usize,
+}
+
+impl Generator {
+    /// Generate a block, and the statements it runs, roughly.
+    fn block(&mut self, depth: usize, recursion: Option<&Recursion>) -> (Block, u64) {
+        let mark = self.scope.len();
+
+        // Names can only be declared once per block.
+        let mut taken: Vec<&str> = vec![];
+        let mut consts = vec![];
+        for _ in 0..self.rng.below(3) {
+            let name = *self.rng.pick(NAMES);
+            if !taken.contains(&name) {
+                taken.push(name);
+                consts.push(Const {
+                    ident: ident(name),
+                    value: self.literal(),
+                });
+                self.declare(name, Kind::Const);
+            }
+        }
+        let mut vars = vec![];
+        for _ in 0..self.rng.below(4) {
+            let name = *self.rng.pick(NAMES);
+            if !taken.contains(&name) {
+                taken.push(name);
+                vars.push(Var { ident: ident(name) });
+                self.declare(name, Kind::Var);
+            }
+        }
+
+        let mut procs = vec![];
+        if depth < MAX_PROC_DEPTH {
+            for _ in 0..self.rng.below(4 - depth) {
+                if self.procs == MAX_PROCS {
+                    break;
+                }
+                self.procs += 1;
+                let name = format!("p{}", self.procs);
+                let recursion = self.rng.chance(30).then(|| Recursion {
+                    name: name.clone(),
+                    depth: self.counter(&mut vars, 'd'),
+                    limit: 1 + self.rng.below(MAX_REPEAT as usize) as Num,
+                });
+
+                self.declare(&name, Kind::Proc(None));
+                let index = self.scope.len() - 1;
+                let (body, cost) = self.block(depth + 1, recursion.as_ref());
+                self.scope[index].kind = Kind::Proc(Some(cost));
+                procs.push(Proc {
+                    name: ident(&name),
+                    body,
+                });
+            }
+        }
+
+        // Calls in a procedure that calls itself run once per call.
+        let repeat = recursion.map_or(1, |recursion| 1 + recursion.limit as u64);
+        let stmt = match recursion {
+            Some(recursion) => self.recursive_body(recursion, &mut vars, repeat),
+            None => self.stmt(0, 1, &mut vars),
+        };
+
+        let cost = repeat * self.cost(&stmt);
+        self.scope.truncate(mark);
+        let block = Block {
+            consts,
+            vars,
+            procs,
+            stmt,
+        };
+        (block, cost)
+    }
+
+    /// Body of a procedure that calls itself, while its depth counter is
+    /// below the limit.
+    fn recursive_body(&mut self, recursion: &Recursion, vars: &mut Vec<Var>, repeat: u64) -> Stmt {
+        let mut stmts = vec![assign(
+            &recursion.depth,
+            binary(BinOp::Add, name(&recursion.depth), Expr::Num(1)),
+        )];
+        stmts.extend(self.stmts(1, repeat, vars));
+        let call = Stmt::Call(Box::new(CallStmt {
+            name: ident(&recursion.name),
+            span: (0, 0),
+        }));
+        // Sometimes it's a tail call.
+        let at = if self.rng.chance(50) {
+            stmts.len()
+        } else {
+            1 + self.rng.below(stmts.len())
+        };
+        stmts.insert(at, call);
+
+        let guard = Stmt::If(Box::new(IfStmt {
+            head: Cond::Bin(BinaryCond {
+                op: CondOp::Less,
+                lhs: name(&recursion.depth),
+                rhs: Expr::Num(recursion.limit),
+            }),
+            body: sub_block(stmts),
+            span: (0, 0),
+        }));
+        if self.rng.chance(50) {
+            return guard;
+        }
+        let mut stmts = self.stmts(1, repeat, vars);
+        let at = self.rng.below(stmts.len() + 1);
+        stmts.insert(at, guard);
+        sub_block(stmts)
+    }
+
+    /// Generate a statement nested `depth` deep, which runs `repeat` times
+    /// as often as the procedure it's in.
+    fn stmt(&mut self, depth: usize, repeat: u64, vars: &mut Vec<Var>) -> Stmt {
+        let nested = depth < MAX_STMT_DEPTH;
+        match self.rng.below(10) {
+            0..=1 if nested => sub_block(self.stmts(depth + 1, repeat, vars)),
+            2 if nested => Stmt::If(Box::new(IfStmt {
+                head: self.cond(),
+                body: self.stmt(depth + 1, repeat, vars),
+                span: (0, 0),
+            })),
+            3 if nested => match self.rng.below(MAX_REPEAT as usize) as Num + 1 {
+                limit if repeat * limit as u64 <= BUDGET => self.count_loop(depth, repeat, limit, vars),
+                _ => self.write(),
+            },
+            4..=5 => self.call(repeat).unwrap_or_else(|| self.write()),
+            6 => match self.assignable() {
+                Some(target) => Stmt::Read(Box::new(ReadStmt {
+                    name: ident(&target),
+                    span: (0, 0),
+                })),
+                None => self.write(),
+            },
+            7..=8 => match self.assignable() {
+                Some(target) => assign(&target, self.expr(0)),
+                None => self.write(),
+            },
+            _ => self.write(),
+        }
+    }
+
+    fn stmts(&mut self, depth: usize, repeat: u64, vars: &mut Vec<Var>) -> Vec<Stmt> {
+        (0..1 + self.rng.below(MAX_STMTS))
+            .map(|_| self.stmt(depth, repeat, vars))
+            .collect()
+    }
+
+    /// `i := 0; while i < limit do begin ...; i := i + 1 end`
+    fn count_loop(&mut self, depth: usize, repeat: u64, limit: Num, vars: &mut Vec<Var>) -> Stmt {
+        let counter = self.counter(vars, 'i');
+        let mut body = self.stmts(depth + 2, repeat * limit as u64, vars);
+        body.push(assign(&counter, binary(BinOp::Add, name(&counter), Expr::Num(1))));
+        let while_stmt = Stmt::While(Box::new(WhileStmt {
+            head: Cond::Bin(BinaryCond {
+                op: CondOp::Less,
+                lhs: name(&counter),
+                rhs: Expr::Num(limit),
+            }),
+            body: sub_block(body),
+            span: (0, 0),
+        }));
+        sub_block(vec![assign(&counter, Expr::Num(0)), while_stmt])
+    }
+
+    /// Call a procedure that won't run over the budget, if there's one.
+    fn call(&mut self, repeat: u64) -> Option<Stmt> {
+        let callees = self.visible(|kind| matches!(kind, Kind::Proc(Some(cost)) if repeat * cost <= BUDGET));
+        if callees.is_empty() {
+            return None;
+        }
+        Some(Stmt::Call(Box::new(CallStmt {
+            name: ident(self.rng.pick(&callees).as_str()),
+            span: (0, 0),
+        })))
+    }
+
+    fn write(&mut self) -> Stmt {
+        Stmt::Write(WriteStmt {
+            expr: self.expr(0),
+            span: (0, 0),
+        })
+    }
+
+    fn cond(&mut self) -> Cond {
+        if self.rng.chance(20) {
+            return Cond::Odd(OddCond { expr: self.expr(0) });
+        }
+        let op = *self.rng.pick(&[
+            CondOp::Eq,
+            CondOp::NotEq,
+            CondOp::Less,
+            CondOp::LessEq,
+            CondOp::Great,
+            CondOp::GreatEq,
+        ]);
+        Cond::Bin(BinaryCond {
+            op,
+            lhs: self.expr(0),
+            rhs: self.expr(0),
+        })
+    }
+
+    fn expr(&mut self, depth: usize) -> Expr {
+        if depth == MAX_EXPR_DEPTH || self.rng.chance(35) {
+            return self.leaf();
+        }
+        match self.rng.below(5) {
+            0 => Expr::Unary(Box::new(UnExpr {
+                op: if self.rng.chance(80) { UnOp::Neg } else { UnOp::Pos },
+                expr: self.expr(depth + 1),
+                span: (0, 0),
+            })),
+            _ => {
+                let op = *self.rng.pick(&[BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div]);
+                let lhs = self.expr(depth + 1);
+                // Mostly divide by something that isn't zero, so that
+                // programs get further than their first division.
+                let rhs = match op {
+                    BinOp::Div if self.rng.chance(70) => Expr::Num(*self.rng.pick(&LITERALS[1..])),
+                    _ => self.expr(depth + 1),
+                };
+                binary(op, lhs, rhs)
+            }
+        }
+    }
+
+    fn leaf(&mut self) -> Expr {
+        let names = self.visible(|kind| matches!(kind, Kind::Const | Kind::Var | Kind::Counter));
+        if names.is_empty() || self.rng.chance(40) {
+            Expr::Num(self.literal())
+        } else {
+            name(self.rng.pick(&names).as_str())
+        }
+    }
+
+    /// A number that can be written as a literal.
+    fn literal(&mut self) -> Num {
+        self.number().checked_abs().unwrap_or(Num::MAX)
+    }
+
+    fn number(&mut self) -> Num {
+        let num = match self.rng.below(3) {
+            0 => self.rng.next() as Num,
+            _ => *self.rng.pick(LITERALS),
+        };
+        if self.rng.chance(20) {
+            num.wrapping_neg()
+        } else {
+            num
+        }
+    }
+
+    /// A variable that can be assigned, if there's one.
+    fn assignable(&mut self) -> Option<String> {
+        let vars = self.visible(|kind| matches!(kind, Kind::Var));
+        (!vars.is_empty()).then(|| self.rng.pick(&vars).clone())
+    }
+
+    /// Names in scope whose innermost declaration is of a kind.
+    fn visible(&self, pred: impl Fn(Kind) -> bool) -> Vec<String> {
+        self.scope
+            .iter()
+            .enumerate()
+            .filter(|(idx, decl)| pred(decl.kind) && !self.scope[idx + 1..].iter().any(|inner| inner.name == decl.name))
+            .map(|(_, decl)| decl.name.clone())
+            .collect()
+    }
+
+    fn declare(&mut self, name: &str, kind: Kind) {
+        self.scope.push(Name {
+            name: name.to_string(),
+            kind,
+        });
+    }
+
+    /// Declare a new counter in a block.
+    fn counter(&mut self, vars: &mut Vec<Var>, prefix: char) -> String {
+        self.counters += 1;
+        let name = format!("{prefix}{}", self.counters);
+        vars.push(Var { ident: ident(&name) });
+        self.declare(&name, Kind::Counter);
+        name
+    }
+
+    /// Statements a statement runs, roughly, with the callees in scope.
+    fn cost(&self, stmt: &Stmt) -> u64 {
+        match stmt {
+            Stmt::Call(call) => self
+                .scope
+                .iter()
+                .rev()
+                .find(|decl| decl.name == call.name.name)
+                .and_then(|decl| match decl.kind {
+                    Kind::Proc(cost) => cost,
+                    _ => None,
+                })
+                // Only the procedure itself isn't known yet.
+                .unwrap_or(1),
+            Stmt::SubBlock(sub_block) => sub_block.stmts.iter().map(|stmt| self.cost(stmt)).sum(),
+            Stmt::If(if_stmt) => 1 + self.cost(&if_stmt.body),
+            Stmt::While(while_stmt) => match &while_stmt.head {
+                Cond::Bin(BinaryCond {
+                    rhs: Expr::Num(limit), ..
+                }) => 1 + *limit as u64 * (1 + self.cost(&while_stmt.body)),
+                _ => unreachable!("loops count up to a number"),
+            },
+            _ => 1,
+        }
+    }
+}
+
+fn ident(name: &str) -> Ident {
+    Ident {
+        name: name.to_string(),
+        span: (0, 0),
+    }
+}
+
+fn name(name: &str) -> Expr {
+    Expr::Name(ident(name))
+}
+
+fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary(Box::new(BinExpr {
+        op,
+        lhs,
+        rhs,
+        span: (0, 0),
+    }))
+}
+
+fn assign(target: &str, rhs: Expr) -> Stmt {
+    Stmt::Assign(Box::new(AssignStmt {
+        lhs: ident(target),
+        rhs,
+        span: (0, 0),
+    }))
+}
+
+fn sub_block(stmts: Vec<Stmt>) -> Stmt {
+    Stmt::SubBlock(SubBlock { stmts, span: (0, 0) })
+}
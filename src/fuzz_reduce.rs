@@ -0,0 +1,256 @@
+//! Test case minimizer.
+//!
+//! Shrinks a program one small step at a time, keeping each step that
+//! still fails, until no step does. The steps remove declarations,
+//! statements and input, replace statements with their bodies, and
+//! replace expressions with their operands or with small numbers.
+//!
+//! Loop and depth counters are left alone inside the loops and guards
+//! that test them, except for removing those whole, so that smaller
+//! programs still finish.
+use std::mem;
+
+use crate::ast::*;
+use crate::fuzz_gen::is_counter;
+use crate::Num;
+
+/// Shrink a program and its input while `fails` holds for them.
+pub(crate) fn minimize(
+    program: &Program,
+    input: &[Num],
+    mut fails: impl FnMut(&Program, &[Num]) -> bool,
+) -> (Program, Vec<Num>) {
+    let mut program = program.clone();
+    let mut input = input.to_vec();
+    loop {
+        let mut progress = false;
+        let mut target = 0;
+        loop {
+            let mut reducer = Reducer {
+                target,
+                seen: 0,
+                guards: vec![],
+            };
+            let mut smaller = program.clone();
+            let mut smaller_input = input.clone();
+            if !reducer.input(&mut smaller_input) && !reducer.block(&mut smaller.block) {
+                break;
+            }
+            if fails(&smaller, &smaller_input) {
+                // The next step now has the same index as this one.
+                program = smaller;
+                input = smaller_input;
+                progress = true;
+            } else {
+                target += 1;
+            }
+        }
+        if !progress {
+            return (program, input);
+        }
+    }
+}
+
+/// Applies the `target`th of the steps that shrink a program, counting
+/// the steps from the outside in.
+struct Reducer {
+    target: usize,
+    seen: usize,
+    /// Counters tested by the loops and guards around the statement.
+    guards: Vec<String>,
+}
+
+impl Reducer {
+    /// Whether the next step is the one to take.
+    fn hit(&mut self) -> bool {
+        self.seen += 1;
+        self.seen - 1 == self.target
+    }
+
+    fn input(&mut self, input: &mut Vec<Num>) -> bool {
+        for idx in 0..input.len() {
+            if self.hit() {
+                input.remove(idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn block(&mut self, block: &mut Block) -> bool {
+        // Declarations that are still used make the program fail to
+        // compile, so removing them doesn't stick.
+        for idx in 0..block.procs.len() {
+            if self.hit() {
+                block.procs.remove(idx);
+                return true;
+            }
+        }
+        for idx in 0..block.vars.len() {
+            if self.hit() {
+                block.vars.remove(idx);
+                return true;
+            }
+        }
+        for idx in 0..block.consts.len() {
+            if self.hit() {
+                block.consts.remove(idx);
+                return true;
+            }
+        }
+        for proc in &mut block.procs {
+            if self.block(&mut proc.body) {
+                return true;
+            }
+        }
+        self.stmt(&mut block.stmt)
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) -> bool {
+        match stmt {
+            Stmt::SubBlock(sub_block) => {
+                if sub_block.stmts.len() == 1 && self.hit() {
+                    *stmt = sub_block.stmts.remove(0);
+                    return true;
+                }
+                let stmts = &mut sub_block.stmts;
+                for idx in 0..stmts.len() {
+                    if stmts.len() > 1 && !self.is_guarded(&stmts[idx]) && self.hit() {
+                        stmts.remove(idx);
+                        return true;
+                    }
+                }
+                // Splice nested blocks into this one.
+                for idx in 0..stmts.len() {
+                    if matches!(stmts[idx], Stmt::SubBlock(_)) && self.hit() {
+                        let Stmt::SubBlock(inner) = stmts.remove(idx) else {
+                            unreachable!()
+                        };
+                        stmts.splice(idx..idx, inner.stmts);
+                        return true;
+                    }
+                }
+                stmts.iter_mut().any(|stmt| self.stmt(stmt))
+            }
+            Stmt::If(if_stmt) => {
+                // The guard of a procedure calling itself stays.
+                let counters = counters(&if_stmt.head);
+                if !counters.is_empty() {
+                    return self.guarded(counters, &mut if_stmt.body);
+                }
+                if self.hit() {
+                    *stmt = take_body(&mut if_stmt.body);
+                    return true;
+                }
+                self.cond(&mut if_stmt.head) || self.stmt(&mut if_stmt.body)
+            }
+            Stmt::While(while_stmt) => {
+                // Loops run their body at least once, so their counters
+                // still count up.
+                if self.hit() {
+                    *stmt = take_body(&mut while_stmt.body);
+                    return true;
+                }
+                self.guarded(counters(&while_stmt.head), &mut while_stmt.body)
+            }
+            Stmt::Assign(assign) if is_counter(&assign.lhs.name) => false,
+            Stmt::Assign(assign) => self.expr(&mut assign.rhs),
+            Stmt::Write(write) => self.expr(&mut write.expr),
+            Stmt::Read(_) | Stmt::Call(_) => false,
+        }
+    }
+
+    /// Shrink the body of a loop or guard that tests some counters.
+    fn guarded(&mut self, counters: Vec<String>, body: &mut Stmt) -> bool {
+        let len = self.guards.len();
+        self.guards.extend(counters);
+        let hit = self.stmt(body);
+        self.guards.truncate(len);
+        hit
+    }
+
+    /// Whether a statement counts up a counter that a loop or guard
+    /// around it tests.
+    fn is_guarded(&self, stmt: &Stmt) -> bool {
+        matches!(stmt, Stmt::Assign(assign)
+            if self.guards.contains(&assign.lhs.name) && !matches!(assign.rhs, Expr::Num(_)))
+    }
+
+    fn cond(&mut self, cond: &mut Cond) -> bool {
+        match cond {
+            Cond::Odd(odd) => self.expr(&mut odd.expr),
+            Cond::Bin(bin) => self.expr(&mut bin.lhs) || self.expr(&mut bin.rhs),
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> bool {
+        // Numbers get smaller, everything else turns into a number first.
+        let small: &[Num] = match expr {
+            Expr::Num(0) => &[],
+            Expr::Num(1) => &[0],
+            _ => &[0, 1],
+        };
+        for &num in small {
+            if self.hit() {
+                *expr = Expr::Num(num);
+                return true;
+            }
+        }
+
+        match expr {
+            Expr::Unary(unary) => {
+                if self.hit() {
+                    *expr = mem::replace(&mut unary.expr, Expr::Num(0));
+                    return true;
+                }
+                self.expr(&mut unary.expr)
+            }
+            Expr::Binary(binary) => {
+                if self.hit() {
+                    *expr = mem::replace(&mut binary.lhs, Expr::Num(0));
+                    return true;
+                }
+                if self.hit() {
+                    *expr = mem::replace(&mut binary.rhs, Expr::Num(0));
+                    return true;
+                }
+                self.expr(&mut binary.lhs) || self.expr(&mut binary.rhs)
+            }
+            Expr::Num(_) | Expr::Name(_) | Expr::Err() => false,
+        }
+    }
+}
+
+/// The loop and depth counters a condition tests.
+fn counters(cond: &Cond) -> Vec<String> {
+    fn collect(expr: &Expr, counters: &mut Vec<String>) {
+        match expr {
+            Expr::Name(name) if is_counter(&name.name) => counters.push(name.name.clone()),
+            Expr::Unary(unary) => collect(&unary.expr, counters),
+            Expr::Binary(binary) => {
+                collect(&binary.lhs, counters);
+                collect(&binary.rhs, counters);
+            }
+            Expr::Name(_) | Expr::Num(_) | Expr::Err() => {}
+        }
+    }
+    let mut counters = vec![];
+    match cond {
+        Cond::Odd(odd) => collect(&odd.expr, &mut counters),
+        Cond::Bin(bin) => {
+            collect(&bin.lhs, &mut counters);
+            collect(&bin.rhs, &mut counters);
+        }
+    }
+    counters
+}
+
+fn take_body(body: &mut Stmt) -> Stmt {
+    mem::replace(
+        body,
+        Stmt::SubBlock(SubBlock {
+            stmts: vec![],
+            span: (0, 0),
+        }),
+    )
+}
@@ -0,0 +1,136 @@
+//! Running programs and their translations, in this process or in
+//! processes of their own.
+//!
+//! The [fuzzer](crate::fuzz::Fuzzer) runs every engine this way, and the
+//! integration tests run the backends' output the same way.
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Num, Pl0Config};
+
+/// Longest a program may run in a process of its own before it's taken to
+/// be stuck.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs a JavaScript program, given as the first argument, with Node.js.
+pub const JS_RUNNER: &str = "
+const input = require('fs').readFileSync(0, 'utf-8').split('\\n').filter(Boolean).map(Number);
+const run = require(process.argv[1]);
+try {
+    run({ write: (num) => console.log(num), read: () => input.shift() });
+} catch {
+    process.exit(1);
+}
+";
+
+/// Runs a WebAssembly module, given as the first argument, with Node.js.
+pub const WASM_RUNNER: &str = "
+const input = require('fs').readFileSync(0, 'utf-8').split('\\n').filter(Boolean).map(Number);
+const env = { write: (num) => console.log(num), read: () => input.shift() ?? 0 };
+WebAssembly.instantiate(require('fs').readFileSync(process.argv[1]), { env })
+    .then(({ instance }) => instance.exports.main())
+    .catch(() => process.exit(1));
+";
+
+/// Input still to be read, and the numbers written so far.
+pub type Io = RefCell<(Vec<Num>, Vec<Num>)>;
+
+/// A config that reads from `input` and collects what's written.
+pub fn io_config(input: &[Num]) -> (Pl0Config, Rc<Io>) {
+    let io: Rc<Io> = Rc::new(RefCell::new((input.iter().rev().copied().collect(), vec![])));
+    let mut config = Pl0Config::new();
+    config.write = |user_data, num| {
+        let io = user_data.and_then(|data| data.downcast_ref::<Rc<Io>>()).unwrap();
+        io.borrow_mut().1.push(num);
+    };
+    config.read = |user_data| {
+        let io = user_data.and_then(|data| data.downcast_ref::<Rc<Io>>()).unwrap();
+        io.borrow_mut().0.pop()
+    };
+    config.user_data = Some(Box::new(io.clone()));
+    (config, io)
+}
+
+/// Whether a program is installed.
+pub fn installed(program: &str) -> bool {
+    Command::new(program).arg("--version").output().is_ok()
+}
+
+/// An LLVM tool, if it's installed, set up to read IR with opaque
+/// pointers, which older versions need to be asked for.
+pub fn llvm_tool(name: &str) -> Option<Command> {
+    let version = Command::new(name).arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&version.stdout);
+    let major: u32 = version
+        .split("LLVM version ")
+        .nth(1)
+        .and_then(|version| version.split('.').next())
+        .and_then(|major| major.parse().ok())?;
+    let mut command = Command::new(name);
+    if major < 15 {
+        command.arg("-opaque-pointers");
+    }
+    Some(command)
+}
+
+/// Run a build step, returning what it printed to stderr if it fails.
+pub fn build(mut command: Command) -> Result<(), String> {
+    let output = command.stdin(Stdio::null()).output().map_err(|err| err.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+/// How a program run with [`run_process`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Success,
+    Failure,
+    /// Ran for longer than [`TIMEOUT`], and was stopped.
+    TimedOut,
+}
+
+/// Run a program in a process of its own, feeding it the input one number
+/// per line, and reading one number per line back. The numbers are
+/// `None` if it wrote anything else.
+pub fn run_process(mut command: Command, input: &[Num]) -> (Option<Vec<Num>>, Exit) {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("run program");
+    let input: String = input.iter().map(|num| format!("{num}\n")).collect();
+    // The program may be done before it reads everything.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+
+    // Read on another thread, so that a program writing a lot doesn't
+    // block while it's waited on.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        output
+    });
+    let deadline = Instant::now() + TIMEOUT;
+    let exit = loop {
+        if let Some(status) = child.try_wait().expect("wait for program") {
+            break if status.success() { Exit::Success } else { Exit::Failure };
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            break Exit::TimedOut;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    let output = reader.join().expect("read program output");
+    let written = output.lines().map(|line| line.parse().ok()).collect();
+    (written, exit)
+}
@@ -0,0 +1,78 @@
+use crate::ast::Program;
+use crate::compiler::Compiler;
+use crate::fuzz_gen::generate;
+use crate::fuzz_reduce::minimize;
+use crate::fuzz_runner::io_config;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::{ErrorCode, Interpreter, Num, Result};
+
+/// Run a program with the interpreter, and return what it writes.
+fn run(program: &Program, input: &[Num]) -> (Vec<Num>, Result<()>) {
+    let (config, io) = io_config(input);
+    let result = Interpreter::from_config(config).run_program("<test>", program);
+    let written = io.borrow().1.clone();
+    (written, result)
+}
+
+#[test]
+fn test_fuzz_generate() {
+    for seed in 0..500 {
+        let (program, input) = generate(seed);
+        let source = program.to_string();
+        assert_eq!(generate(seed).0.to_string(), source, "seed {seed} isn't reproducible");
+
+        // The source parses back into the same program.
+        let parsed = Parser::new(Lexer::new(&source, "<test>"))
+            .parse_program()
+            .unwrap_or_else(|err| panic!("seed {seed}: {err}\n{source}"));
+        assert_eq!(parsed.to_string(), source, "seed {seed}");
+
+        // It runs to the end, or divides by zero, without running out of
+        // stack.
+        if let (_, Err(err)) = run(&program, &input) {
+            assert_eq!(err.code(), ErrorCode::E0401, "seed {seed}: {err}\n{source}");
+        }
+    }
+}
+
+#[test]
+fn test_fuzz_minimize() {
+    // Find a program that writes something, and shrink it down to the
+    // smallest program that still does.
+    let (program, input) = (0..)
+        .map(generate)
+        .find(|(program, input)| matches!(run(program, input), (written, Ok(())) if written.len() > 3))
+        .unwrap();
+    let (smaller, smaller_input) = minimize(
+        &program,
+        &input,
+        |program, input| matches!(run(program, input), (written, Ok(())) if !written.is_empty()),
+    );
+    assert_eq!(smaller.to_string(), "write 0.\n");
+    assert!(smaller_input.is_empty());
+}
+
+#[test]
+fn test_fuzz_minimize_loops() {
+    // Shrinking keeps loops and recursion bounded, so that every step
+    // still finishes. Steps may divide by zero, but don't recurse without
+    // end.
+    for seed in 0..50 {
+        let (program, input) = generate(seed);
+        if !matches!(run(&program, &input), (_, Ok(()))) {
+            continue;
+        }
+        let mut steps = 0;
+        minimize(&program, &input, |program, input| {
+            steps += 1;
+            if Compiler::new("<test>").compile(program).is_err() {
+                return false;
+            }
+            if let (_, Err(err)) = run(program, input) {
+                assert_eq!(err.code(), ErrorCode::E0401, "seed {seed}: {err}\n{program}");
+            }
+            steps < 200
+        });
+    }
+}
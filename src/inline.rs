@@ -0,0 +1,306 @@
+//! Inline expansion of small leaf procedures.
+//!
+//! A call to a procedure that makes no calls of its own, and whose body
+//! is short, is replaced by a copy of the body. That saves the block mark,
+//! the call and the return.
+//!
+//! The copy runs in the caller's frame. The callee's variables move to
+//! extra slots at the end of it, and the variables of the procedures
+//! enclosing the callee are reached through the caller's static chain.
+use std::collections::{HashMap, HashSet};
+
+use crate::bytecode::{Instr, OpCode};
+use crate::debug::VarInfo;
+use crate::errors::Result;
+use crate::limits::{CODE_SIZE, DATA_OFFSET};
+use crate::peephole::AddrMap;
+use crate::Chunk;
+
+/// The body of a procedure that can be inlined.
+struct Body {
+    /// Size of the procedure's frame.
+    frame: usize,
+    /// Address of the first instruction after the frame is reserved.
+    start: usize,
+    /// Address of the procedure's return.
+    end: usize,
+}
+
+/// A call that is replaced by the body of the procedure.
+struct Site {
+    callee: u16,
+    /// Level of the call, which is where the callee's static link points.
+    level: u8,
+    /// Offset in the caller's frame where the callee's variables start.
+    offset: u16,
+}
+
+/// Result of inlining procedures into a chunk.
+pub(crate) struct Inlined {
+    /// Mapping from the original addresses to the new ones.
+    pub(crate) addrs: AddrMap,
+    /// Entry addresses of the procedures that were inlined at every call.
+    ///
+    /// These are never called anymore, and are left for dead code
+    /// elimination to remove.
+    pub(crate) procs: Vec<u16>,
+}
+
+/// Inline the procedures whose bodies are at most `threshold` instructions long.
+///
+/// Repeats until nothing changes, since a procedure whose calls were all
+/// inlined may have become a leaf itself.
+pub(crate) fn inline(chunk: &mut Chunk, threshold: usize) -> Result<Inlined> {
+    let mut total = Inlined {
+        addrs: AddrMap::new((0..=chunk.code.len() as u16).collect()),
+        procs: vec![],
+    };
+
+    while let Some(inlined) = inline_leaves(chunk, threshold)? {
+        total.addrs = total.addrs.then(&inlined.addrs);
+        for proc in total.procs.iter_mut() {
+            *proc = inlined.addrs.get(*proc);
+        }
+        total.procs.extend(inlined.procs);
+    }
+
+    total.procs.sort_unstable();
+    total.procs.dedup();
+    Ok(total)
+}
+
+/// Inline the calls to procedures that are currently leaves.
+///
+/// Returns `None` when there was nothing to inline.
+fn inline_leaves(chunk: &mut Chunk, threshold: usize) -> Result<Option<Inlined>> {
+    let flow = chunk.flow()?;
+    let code = &chunk.code;
+
+    let mut bodies: HashMap<u16, Option<Body>> = HashMap::new();
+    let mut sites = HashMap::new();
+    // Slots added to each caller's frame, and where each callee's variables start in it.
+    let mut grown: HashMap<u16, usize> = HashMap::new();
+    let mut slots: HashMap<(u16, u16), u16> = HashMap::new();
+    // Procedures that are still called somewhere.
+    let mut called = HashSet::new();
+
+    for (pc, instr) in code.iter().enumerate() {
+        let inlinable = match instr.opcode {
+            OpCode::Call => true,
+            // The caller returns right after a tail call, so the body can
+            // fall through to that return instead.
+            OpCode::TailCall => code.get(pc + 1).map(|next| next.opcode) == Some(OpCode::Return),
+            _ => continue,
+        };
+        let callee = instr.a;
+        let body = bodies
+            .entry(callee)
+            .or_insert_with(|| leaf_body(code, callee, threshold));
+        let caller = flow.procs[pc];
+        let frame = caller.and_then(|caller| flow.frames.get(&caller));
+        let (Some(caller), Some(frame), Some(body), true) = (caller, frame, body, inlinable) else {
+            called.insert(callee);
+            continue;
+        };
+
+        let offset = *slots.entry((caller, callee)).or_insert_with(|| {
+            let grown = grown.entry(caller).or_default();
+            let offset = frame + *grown;
+            *grown += body.frame - DATA_OFFSET;
+            offset as u16
+        });
+        let site = Site {
+            callee,
+            level: instr.l,
+            offset,
+        };
+        sites.insert(pc, site);
+    }
+
+    let size = code.len()
+        + sites
+            .values()
+            .filter_map(|site| bodies[&site.callee].as_ref())
+            .map(|body| 2 * body.read_vars(code).len() + body.end - body.start)
+            .sum::<usize>()
+        - sites.len();
+    if sites.is_empty() || size > CODE_SIZE {
+        return Ok(None);
+    }
+
+    let mut old = std::mem::take(&mut chunk.code);
+    for (caller, grown) in &grown {
+        old[flow.reserves[caller]].a += *grown as u16;
+    }
+
+    let mut addrs = Vec::with_capacity(old.len() + 1);
+    let mut fixups = vec![];
+    for (pc, instr) in old.iter().enumerate() {
+        addrs.push(chunk.code.len() as u16);
+        match sites.get(&pc) {
+            Some(site) => {
+                let body = bodies[&site.callee].as_ref().expect("inlined procedure has a body");
+                // The callee's variables start out as zero on every call,
+                // which matters for the ones it reads first.
+                for var in body.read_vars(&old) {
+                    chunk.code.push(Instr {
+                        opcode: OpCode::Lit,
+                        l: 0,
+                        a: 0,
+                    });
+                    chunk.code.push(Instr {
+                        opcode: OpCode::Store,
+                        l: 0,
+                        a: site.offset + var - DATA_OFFSET as u16,
+                    });
+                }
+                let base = chunk.code.len();
+                for instr in &old[body.start..body.end] {
+                    chunk.code.push(relocate(instr, site, body, base));
+                }
+            }
+            None => {
+                if matches!(
+                    instr.opcode,
+                    OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall
+                ) {
+                    fixups.push(chunk.code.len());
+                }
+                chunk.code.push(*instr);
+            }
+        }
+    }
+    addrs.push(chunk.code.len() as u16);
+    let map = AddrMap::new(addrs);
+    for idx in fixups {
+        chunk.code[idx].a = map.get(chunk.code[idx].a);
+    }
+
+    if let Some(debug) = chunk.debug.as_mut() {
+        // Name the variables that moved into the caller's frame after the callee.
+        for ((caller, callee), offset) in &slots {
+            let Some(callee) = debug.proc_by_addr(*callee) else {
+                continue;
+            };
+            let vars: Vec<VarInfo> = callee
+                .vars
+                .iter()
+                .map(|var| VarInfo {
+                    name: format!("{}.{}", callee.name, var.name),
+                    offset: offset + var.offset - DATA_OFFSET as u16,
+                })
+                .collect();
+            if let Some(caller) = debug.procs.iter_mut().find(|proc| proc.addr == *caller) {
+                caller.vars.extend(vars);
+            }
+        }
+
+        for entry in debug.lines.iter_mut() {
+            entry.pc = map.get(entry.pc);
+        }
+        for proc in debug.procs.iter_mut() {
+            proc.addr = map.get(proc.addr);
+            proc.end = map.get(proc.end);
+        }
+    }
+
+    let procs = sites
+        .values()
+        .map(|site| site.callee)
+        .filter(|callee| !called.contains(callee))
+        .map(|callee| map.get(callee))
+        .collect();
+
+    Ok(Some(Inlined { addrs: map, procs }))
+}
+
+/// Find the body of a procedure that makes no calls, if it's at most `threshold` instructions long.
+fn leaf_body(code: &[Instr], entry: u16, threshold: usize) -> Option<Body> {
+    // Skip the jump over the procedure's nested procedures.
+    let mut pc = entry as usize;
+    for _ in 0..code.len() {
+        match code.get(pc)? {
+            Instr {
+                opcode: OpCode::Jump,
+                a,
+                ..
+            } if *a != 0 => pc = *a as usize,
+            _ => break,
+        }
+    }
+    let reserve = code.get(pc)?;
+    if reserve.opcode != OpCode::IncTop {
+        return None;
+    }
+
+    // The body must run from the frame reservation to the return without
+    // jumping out of that range, so it can be copied as a whole.
+    let start = pc + 1;
+    let end = start + code[start..].iter().position(|instr| instr.opcode == OpCode::Return)?;
+    if end - start > threshold {
+        return None;
+    }
+    for instr in &code[start..end] {
+        match instr.opcode {
+            OpCode::Call | OpCode::TailCall | OpCode::IncTop => return None,
+            OpCode::Jump | OpCode::JumpIfZero if !(start..=end).contains(&(instr.a as usize)) => return None,
+            _ => {}
+        }
+    }
+
+    Some(Body {
+        frame: reserve.a as usize,
+        start,
+        end,
+    })
+}
+
+impl Body {
+    /// Offsets of the procedure's own variables that its body may read
+    /// before it writes them. Bodies with jumps are taken to read every
+    /// variable they load.
+    fn read_vars(&self, code: &[Instr]) -> Vec<u16> {
+        let body = &code[self.start..self.end];
+        let jumps = body
+            .iter()
+            .any(|instr| matches!(instr.opcode, OpCode::Jump | OpCode::JumpIfZero));
+        let mut written = vec![];
+        let mut read = vec![];
+        for instr in body.iter().filter(|instr| instr.l == 0) {
+            match instr.opcode {
+                OpCode::Load if !written.contains(&instr.a) && !read.contains(&instr.a) => read.push(instr.a),
+                OpCode::Store if !jumps => written.push(instr.a),
+                _ => {}
+            }
+        }
+        read.sort_unstable();
+        read
+    }
+}
+
+/// Adjust an instruction of the callee's body to run in the caller's frame,
+/// with the copy of the body starting at `base`.
+fn relocate(instr: &Instr, site: &Site, body: &Body, base: usize) -> Instr {
+    let Instr { opcode, l, a } = *instr;
+    match opcode {
+        // The callee's own variables are in the caller's frame now.
+        OpCode::Load | OpCode::Store if l == 0 => Instr {
+            opcode,
+            l: 0,
+            a: site.offset + a - DATA_OFFSET as u16,
+        },
+        // The callee's static link is `site.level` levels up from the
+        // caller, and the rest of its chain follows from there.
+        OpCode::Load | OpCode::Store => Instr {
+            opcode,
+            l: site.level + l - 1,
+            a,
+        },
+        OpCode::Jump | OpCode::JumpIfZero => Instr {
+            opcode,
+            l,
+            a: (base + a as usize - body.start) as u16,
+        },
+        _ => *instr,
+    }
+}
@@ -0,0 +1,97 @@
+use crate::bytecode::OpCode;
+use crate::{compile_with_report, CompileOptions, RemovedKind};
+
+const NESTED: &str = "var g;
+procedure outer;
+    var a;
+    procedure leaf;
+        var t;
+    begin
+        t := a * 2;
+        g := g + t
+    end;
+begin
+    a := 5;
+    call leaf;
+    call leaf;
+    write g
+end;
+begin
+    g := 1;
+    call outer;
+    write g
+end.";
+
+fn options(inline_threshold: usize) -> CompileOptions {
+    CompileOptions {
+        opt_level: 2,
+        inline_threshold,
+    }
+}
+
+#[test]
+fn test_inline_levels() {
+    let (chunk, _, removed) = compile_with_report("<test>", NESTED, &options(8)).expect("failed to compile");
+    chunk.verify().expect("failed to verify");
+
+    // The leaf's variable moved into its caller's frame, after the caller's own.
+    let debug = chunk.debug_info().expect("debug info");
+    let outer = debug.procs.iter().find(|proc| proc.name == "outer").expect("outer");
+    let vars: Vec<_> = outer.vars.iter().map(|var| (var.name.as_str(), var.offset)).collect();
+    assert_eq!(vars, [("a", 3), ("leaf.t", 4)]);
+    let reserve = chunk.code[outer.addr as usize..]
+        .iter()
+        .find(|instr| instr.opcode == OpCode::IncTop)
+        .expect("frame reservation");
+    assert_eq!(reserve.a, 5);
+
+    // Its own variable is now at level zero, and the main program's one level closer.
+    let accesses: Vec<_> = chunk.code[outer.addr as usize..outer.end as usize]
+        .iter()
+        .filter(|instr| matches!(instr.opcode, OpCode::Load | OpCode::Store))
+        .map(|instr| (instr.l, instr.a))
+        .collect();
+    assert!(accesses.contains(&(0, 4)), "{accesses:?}");
+    assert!(accesses.contains(&(1, 3)), "{accesses:?}");
+
+    assert!(removed.iter().any(|removed| removed.kind
+        == RemovedKind::Inlined {
+            name: "leaf".to_string()
+        }));
+}
+
+#[test]
+fn test_inline_threshold() {
+    // The leaf's body is eight instructions long.
+    let (chunk, _, removed) = compile_with_report("<test>", NESTED, &options(7)).expect("failed to compile");
+    assert!(removed.is_empty());
+    assert_eq!(
+        chunk.code.iter().filter(|instr| instr.opcode == OpCode::Call).count(),
+        3
+    );
+
+    // Only the call to the leaf goes, the one to its caller is still too long.
+    let (chunk, _, _) = compile_with_report("<test>", NESTED, &options(8)).expect("failed to compile");
+    assert_eq!(
+        chunk.code.iter().filter(|instr| instr.opcode == OpCode::Call).count(),
+        1
+    );
+}
+
+#[test]
+fn test_inline_recursion() {
+    // Procedures that call anything, including themselves, are left alone.
+    const SOURCE: &str = "var n;
+procedure down;
+begin
+    if n > 0 then begin n := n - 1; call down end
+end;
+begin
+    n := 3;
+    call down
+end.";
+    let (chunk, _, removed) = compile_with_report("<test>", SOURCE, &options(100)).expect("failed to compile");
+    assert!(removed.is_empty());
+    assert!(chunk.code.iter().any(|instr| instr.opcode == OpCode::Call));
+    assert!(chunk.code.iter().any(|instr| instr.opcode == OpCode::TailCall));
+}
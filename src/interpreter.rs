@@ -0,0 +1,328 @@
+//! Tree-walking interpreter.
+//!
+//! Runs a program straight from its syntax tree, without compiling it to
+//! bytecode. It's written to be simple rather than fast, and serves as the
+//! reference for what a program means: the compiler and the VM are tested
+//! against it.
+//!
+//! Every procedure call gets a [`Frame`] with the procedure's variables,
+//! and a link to the frame of the procedure it's declared in. Names are
+//! looked up through that chain of frames at runtime, the same way the
+//! compiler resolves them up front: the innermost declaration wins, and a
+//! procedure can see itself and the procedures declared before it, but not
+//! the ones after.
+//!
+//! A call that's the last thing a procedure does replaces the caller's
+//! frame when the compiler would make it a tail call, and the frames are
+//! held to the same stack budget as the VM's, so deep recursion fails
+//! with a stack overflow at about the same depth. The budget only counts
+//! frames, not temporary values, so the depth isn't exactly the same.
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::compiler::Compiler;
+use crate::errors::Result;
+use crate::lexer::Lexer;
+use crate::limits::{DATA_OFFSET, STACK_SIZE};
+use crate::parser::Parser;
+use crate::{error, Num, Pl0Config};
+
+/// Runs programs from their syntax tree.
+pub struct Interpreter {
+    /// User injected callbacks and data.
+    config: Pl0Config,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::from_config(Pl0Config::new())
+    }
+
+    pub fn from_config(config: Pl0Config) -> Self {
+        Self { config }
+    }
+
+    /// Parse and run a program.
+    ///
+    /// The program is checked by the compiler first, so it's rejected with
+    /// the same errors as when it's compiled.
+    pub fn run(&mut self, filename: &str, text: &str) -> Result<()> {
+        let program = Parser::new(Lexer::new(text, filename)).parse_program()?;
+        self.run_program(filename, &program)
+    }
+
+    /// Run a program that's already parsed.
+    pub(crate) fn run_program(&mut self, filename: &str, program: &Program) -> Result<()> {
+        Compiler::new(filename).compile(program)?;
+
+        let mut walker = Walker {
+            config: &self.config,
+            file: filename,
+            slots: 0,
+        };
+        let main = walker.enter(&program.block, None, 0)?;
+        walker.exec(&program.block.stmt, &main)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Activation of a procedure, or of the main program.
+struct Frame<'a> {
+    /// Declarations and body of the procedure.
+    block: &'a Block,
+    /// Frame of the procedure this one is declared in.
+    parent: Option<Rc<Frame<'a>>>,
+    /// Number of procedures in the parent's block visible from this one,
+    /// which are the ones declared up to this procedure.
+    visible_procs: usize,
+    vars: Vec<Cell<Num>>,
+}
+
+/// What a name refers to.
+enum Binding<'a> {
+    Const(Num),
+    /// A variable, by its index in the frame's block.
+    Var(Rc<Frame<'a>>, usize),
+    Proc(Callee<'a>),
+}
+
+/// A procedure, together with the frame it's declared in.
+struct Callee<'a> {
+    proc: &'a Proc,
+    parent: Rc<Frame<'a>>,
+    /// Index of the procedure in its parent's block.
+    index: usize,
+}
+
+/// How a statement finished.
+enum Flow<'a> {
+    /// Carry on with the next statement.
+    Next,
+    /// Return from the procedure, and call this one in its place.
+    TailCall(Callee<'a>),
+}
+
+/// Find what a name refers to, from the innermost frame out.
+fn lookup<'a>(frame: &Rc<Frame<'a>>, name: &str) -> Option<Binding<'a>> {
+    let mut frame = frame.clone();
+    let mut visible_procs = frame.block.procs.len();
+    loop {
+        // Later declarations shadow earlier ones.
+        let block = frame.block;
+        if let Some(index) = block.procs[..visible_procs]
+            .iter()
+            .rposition(|proc| proc.name.name == name)
+        {
+            let proc = &block.procs[index];
+            return Some(Binding::Proc(Callee {
+                proc,
+                parent: frame,
+                index,
+            }));
+        }
+        if let Some(index) = block.vars.iter().rposition(|var| var.ident.name == name) {
+            return Some(Binding::Var(frame, index));
+        }
+        if let Some(const_) = block.consts.iter().rev().find(|const_| const_.ident.name == name) {
+            return Some(Binding::Const(const_.value));
+        }
+
+        visible_procs = frame.visible_procs;
+        frame = frame.parent.clone()?;
+    }
+}
+
+/// Walks the syntax tree of a running program.
+struct Walker<'c> {
+    config: &'c Pl0Config,
+    file: &'c str,
+    /// Stack slots used by the frames, as counted by the VM.
+    slots: usize,
+}
+
+impl<'c> Walker<'c> {
+    /// Make a frame for a block, if there's room on the stack.
+    fn enter<'a>(
+        &mut self,
+        block: &'a Block,
+        parent: Option<Rc<Frame<'a>>>,
+        visible_procs: usize,
+    ) -> Result<Rc<Frame<'a>>> {
+        let size = DATA_OFFSET + block.vars.len();
+        if self.slots + size >= STACK_SIZE {
+            return error!(E0402, "stack overflow")
+                .with_location(block.stmt.span(), self.file)
+                .into();
+        }
+        self.slots += size;
+
+        Ok(Rc::new(Frame {
+            block,
+            parent,
+            visible_procs,
+            vars: block.vars.iter().map(|_| Cell::new(0)).collect(),
+        }))
+    }
+
+    fn leave(&mut self, frame: &Frame) {
+        self.slots -= DATA_OFFSET + frame.vars.len();
+    }
+
+    /// Run a procedure, and then the procedures it tail calls, if any.
+    fn call<'a>(&mut self, mut callee: Callee<'a>) -> Result<()> {
+        loop {
+            let body = &callee.proc.body;
+            let frame = self.enter(body, Some(callee.parent), callee.index + 1)?;
+            let flow = self.exec_tail(&body.stmt, &frame);
+            self.leave(&frame);
+            match flow? {
+                Flow::Next => return Ok(()),
+                Flow::TailCall(next) => callee = next,
+            }
+        }
+    }
+
+    /// Run a statement that ends the procedure, where a call is a tail call.
+    ///
+    /// Procedures declared in the current one are called normally, like
+    /// the compiler does, since their frame links to the current frame.
+    fn exec_tail<'a>(&mut self, stmt: &'a Stmt, frame: &Rc<Frame<'a>>) -> Result<Flow<'a>> {
+        match stmt {
+            Stmt::Call(call) => {
+                let callee = self.resolve_proc(&call.name, frame);
+                if Rc::ptr_eq(&callee.parent, frame) {
+                    self.call(callee)?;
+                    return Ok(Flow::Next);
+                }
+                Ok(Flow::TailCall(callee))
+            }
+            Stmt::SubBlock(sub_block) => {
+                let Some((last, stmts)) = sub_block.stmts.split_last() else {
+                    return Ok(Flow::Next);
+                };
+                for stmt in stmts {
+                    self.exec(stmt, frame)?;
+                }
+                self.exec_tail(last, frame)
+            }
+            Stmt::If(if_stmt) => match self.cond(&if_stmt.head, frame, if_stmt.span)? {
+                true => self.exec_tail(&if_stmt.body, frame),
+                false => Ok(Flow::Next),
+            },
+            _ => {
+                self.exec(stmt, frame)?;
+                Ok(Flow::Next)
+            }
+        }
+    }
+
+    fn exec<'a>(&mut self, stmt: &'a Stmt, frame: &Rc<Frame<'a>>) -> Result<()> {
+        match stmt {
+            Stmt::Assign(assign) => {
+                let value = self.eval(&assign.rhs, frame, assign.span)?;
+                self.store(&assign.lhs, frame, value);
+            }
+            Stmt::Call(call) => {
+                let callee = self.resolve_proc(&call.name, frame);
+                self.call(callee)?;
+            }
+            Stmt::Read(read) => {
+                let value = (self.config.read)(self.config.user_data.as_deref()).unwrap_or_default();
+                self.store(&read.name, frame, value);
+            }
+            Stmt::Write(write) => {
+                let value = self.eval(&write.expr, frame, write.span)?;
+                (self.config.write)(self.config.user_data.as_deref(), value);
+            }
+            Stmt::SubBlock(sub_block) => {
+                for stmt in &sub_block.stmts {
+                    self.exec(stmt, frame)?;
+                }
+            }
+            Stmt::If(if_stmt) => {
+                if self.cond(&if_stmt.head, frame, if_stmt.span)? {
+                    self.exec(&if_stmt.body, frame)?;
+                }
+            }
+            Stmt::While(while_stmt) => {
+                while self.cond(&while_stmt.head, frame, while_stmt.span)? {
+                    self.exec(&while_stmt.body, frame)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate a condition of the statement at `span`.
+    fn cond<'a>(&self, cond: &'a Cond, frame: &Rc<Frame<'a>>, span: (u32, u32)) -> Result<bool> {
+        match cond {
+            Cond::Odd(odd) => Ok(self.eval(&odd.expr, frame, span)? % 2 != 0),
+            Cond::Bin(bin) => {
+                let lhs = self.eval(&bin.lhs, frame, span)?;
+                let rhs = self.eval(&bin.rhs, frame, span)?;
+                Ok(match bin.op {
+                    CondOp::Eq => lhs == rhs,
+                    CondOp::NotEq => lhs != rhs,
+                    CondOp::Less => lhs < rhs,
+                    CondOp::LessEq => lhs <= rhs,
+                    CondOp::Great => lhs > rhs,
+                    CondOp::GreatEq => lhs >= rhs,
+                })
+            }
+        }
+    }
+
+    /// Evaluate an expression of the statement at `span`. Arithmetic wraps around.
+    fn eval<'a>(&self, expr: &'a Expr, frame: &Rc<Frame<'a>>, span: (u32, u32)) -> Result<Num> {
+        match expr {
+            Expr::Num(num) => Ok(*num),
+            Expr::Name(name) => match lookup(frame, &name.name) {
+                Some(Binding::Const(value)) => Ok(value),
+                Some(Binding::Var(frame, index)) => Ok(frame.vars[index].get()),
+                _ => panic!(
+                    "'{}' isn't a constant or variable, which the compiler checks",
+                    name.name
+                ),
+            },
+            Expr::Unary(unary) => {
+                let value = self.eval(&unary.expr, frame, span)?;
+                Ok(match unary.op {
+                    UnOp::Pos => value,
+                    UnOp::Neg => value.wrapping_neg(),
+                })
+            }
+            Expr::Binary(binary) => {
+                let lhs = self.eval(&binary.lhs, frame, span)?;
+                let rhs = self.eval(&binary.rhs, frame, span)?;
+                match binary.op {
+                    BinOp::Add => Ok(lhs.wrapping_add(rhs)),
+                    BinOp::Sub => Ok(lhs.wrapping_sub(rhs)),
+                    BinOp::Mul => Ok(lhs.wrapping_mul(rhs)),
+                    BinOp::Div if rhs == 0 => error!(E0401, "division by zero").with_location(span, self.file).into(),
+                    BinOp::Div => Ok(lhs.wrapping_div(rhs)),
+                }
+            }
+            Expr::Err() => panic!("abstract-syntax-tree contains an error node"),
+        }
+    }
+
+    fn store<'a>(&self, name: &Ident, frame: &Rc<Frame<'a>>, value: Num) {
+        match lookup(frame, &name.name) {
+            Some(Binding::Var(frame, index)) => frame.vars[index].set(value),
+            _ => panic!("'{}' isn't a variable, which the compiler checks", name.name),
+        }
+    }
+
+    fn resolve_proc<'a>(&self, name: &Ident, frame: &Rc<Frame<'a>>) -> Callee<'a> {
+        match lookup(frame, &name.name) {
+            Some(Binding::Proc(callee)) => callee,
+            _ => panic!("'{}' isn't a procedure, which the compiler checks", name.name),
+        }
+    }
+}
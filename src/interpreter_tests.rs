@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ErrorCode, Interpreter, Num, Pl0Config, Result};
+
+type Io = RefCell<(Vec<Num>, Vec<Num>)>;
+
+/// Run a program with the given input, and return what it writes.
+fn run(source: &str, input: &[Num]) -> (Vec<Num>, Result<()>) {
+    let io: Rc<Io> = Rc::new(RefCell::new((input.iter().rev().copied().collect(), vec![])));
+    let mut config = Pl0Config::new();
+    config.write = |user_data, num| {
+        let io = user_data.and_then(|data| data.downcast_ref::<Rc<Io>>()).unwrap();
+        io.borrow_mut().1.push(num);
+    };
+    config.read = |user_data| {
+        let io = user_data.and_then(|data| data.downcast_ref::<Rc<Io>>()).unwrap();
+        io.borrow_mut().0.pop()
+    };
+    config.user_data = Some(Box::new(io.clone()));
+
+    let result = Interpreter::from_config(config).run("<test>", source);
+    let written = io.borrow().1.clone();
+    (written, result)
+}
+
+#[test]
+fn test_interpreter_frames() {
+    // Every call has its own variables, and nested procedures reach the
+    // variables of the call they're declared in.
+    const SOURCE: &str = "var result;
+procedure fact;
+    var n;
+    procedure step;
+    begin
+        result := result * n
+    end;
+begin
+    read n;
+    if n > 1 then
+    begin
+        call fact;
+        call step
+    end
+end;
+begin
+    result := 1;
+    call fact;
+    write result
+end.";
+    let (written, result) = run(SOURCE, &[5, 4, 3, 2, 1]);
+    result.expect("failed to run");
+    assert_eq!(written, vec![120]);
+}
+
+#[test]
+fn test_interpreter_names() {
+    // Inner declarations shadow outer ones, and a procedure sees the
+    // procedures declared before it, including itself.
+    const SOURCE: &str = "const x = 1;
+var y;
+procedure first;
+    var x;
+begin
+    x := 2;
+    y := y + x
+end;
+procedure second;
+    const y = 10;
+begin
+    write y + x;
+    call first
+end;
+begin
+    y := 0;
+    call second;
+    write y;
+    write x
+end.";
+    let (written, result) = run(SOURCE, &[]);
+    result.expect("failed to run");
+    assert_eq!(written, vec![11, 2, 1]);
+
+    // A procedure can't see the procedures declared after it.
+    const LATER: &str = "procedure first;
+begin
+    call second
+end;
+procedure second;
+begin
+    write 1
+end;
+call first.";
+    let (_, result) = run(LATER, &[]);
+    assert_eq!(result.unwrap_err().code(), ErrorCode::E0301);
+}
+
+#[test]
+fn test_interpreter_arithmetic() {
+    const SOURCE: &str = "var x;
+begin
+    read x;
+    write x - 1;
+    write -x;
+    write x / (0 - 1);
+    write 7 / (0 - 2);
+    if odd (0 - 3) then write 1;
+    write x / (x - x)
+end.";
+    let (written, result) = run(SOURCE, &[Num::MIN]);
+    assert_eq!(written, vec![Num::MAX, Num::MIN, Num::MIN, -3, 1]);
+    let err = result.unwrap_err();
+    assert_eq!(err.code(), ErrorCode::E0401);
+    let json = err.json(SOURCE).to_string();
+    assert!(json.contains(r#""line":9"#), "{json}");
+
+    // Reading past the end of the input reads zero.
+    let (written, result) = run("var x; begin read x; write x end.", &[]);
+    result.expect("failed to run");
+    assert_eq!(written, vec![0]);
+}
+
+#[test]
+fn test_interpreter_recursion() {
+    // Tail calls run in constant space, other recursion runs out of stack.
+    const SOURCE: &str = "var n;
+procedure down;
+begin
+    if n > 0 then
+    begin
+        n := n - 1;
+        call down
+    end
+end;
+procedure forever;
+begin
+    call forever;
+    write 1
+end;
+begin
+    read n;
+    call down;
+    write n;
+    call forever
+end.";
+    let (written, result) = run(SOURCE, &[100_000]);
+    assert_eq!(written, vec![0]);
+    assert_eq!(result.unwrap_err().code(), ErrorCode::E0402);
+}
@@ -0,0 +1,646 @@
+//! Intermediate representation between the AST and the code generators.
+//!
+//! The compiler lowers each procedure into a control flow graph of basic
+//! blocks, and the code generators lower the graph back out. Analyses and
+//! optimizations work on the graph, so they're written once rather than
+//! per code generator.
+//!
+//! Values live in temporaries. Expressions are lowered in evaluation
+//! order, so each temporary is used exactly once, by a later instruction
+//! in the same block, and temporaries are used in the reverse order they
+//! were defined. That's the discipline of an operand stack, which the
+//! bytecode generator relies on and passes must preserve.
+use std::fmt;
+
+use crate::debug::VarInfo;
+use crate::env::SymbolId;
+use crate::Num;
+
+/// A program lowered to basic blocks.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Program {
+    /// Every procedure in declaration order, starting with the main program.
+    ///
+    /// A procedure always comes after the procedure it's nested in.
+    pub(crate) procs: Vec<Proc>,
+}
+
+/// Index of a procedure in its [`Program`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct ProcId(u32);
+
+/// Index of a basic block in its [`Proc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct BlockId(u32);
+
+/// A temporary value, numbered per procedure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct Temp(u32);
+
+#[derive(Debug, Clone)]
+pub(crate) struct Proc {
+    pub(crate) name: String,
+    /// Enclosing procedure, or `None` for the main program.
+    pub(crate) parent: Option<ProcId>,
+    /// Lexical level of the procedure's variables.
+    pub(crate) level: u8,
+    /// The procedure's symbol, or `None` for the main program.
+    pub(crate) symbol: Option<SymbolId>,
+    /// Location of the procedure's declaration, or `None` for the main program.
+    pub(crate) span: Option<(u32, u32)>,
+    /// Variables in the procedure's stack frame, in declaration order.
+    pub(crate) vars: Vec<VarInfo>,
+    /// Size of the stack frame, including the block mark.
+    pub(crate) frame: u16,
+    /// Location of the procedure's body statement.
+    pub(crate) body_span: (u32, u32),
+    /// Basic blocks in code order, starting with the entry block.
+    pub(crate) blocks: Vec<Block>,
+}
+
+/// Straight-line code ending in a transfer of control.
+#[derive(Debug, Clone)]
+pub(crate) struct Block {
+    pub(crate) insts: Vec<Inst>,
+    pub(crate) term: Term,
+    /// Location of the source fragment the terminator was lowered from.
+    pub(crate) span: (u32, u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Inst {
+    pub(crate) kind: InstKind,
+    /// Location of the source fragment the instruction was lowered from.
+    pub(crate) span: (u32, u32),
+}
+
+/// A variable, addressed relative to the current procedure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct Var {
+    /// Number of static links followed to reach the declaring procedure's frame.
+    pub(crate) level: u8,
+    /// Stack offset relative to the base of that frame.
+    pub(crate) offset: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InstKind {
+    Const {
+        dst: Temp,
+        value: Num,
+    },
+    Load {
+        dst: Temp,
+        var: Var,
+    },
+    Store {
+        var: Var,
+        src: Temp,
+    },
+    Unary {
+        dst: Temp,
+        op: UnaryOp,
+        src: Temp,
+    },
+    Binary {
+        dst: Temp,
+        op: BinaryOp,
+        lhs: Temp,
+        rhs: Temp,
+    },
+    /// Call a procedure, whose static link is `level` frames up from the caller's.
+    Call {
+        level: u8,
+        proc: ProcId,
+    },
+    Read {
+        dst: Temp,
+    },
+    Write {
+        src: Temp,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnaryOp {
+    Neg,
+    /// One if the operand is odd, zero otherwise.
+    Odd,
+}
+
+/// Arithmetic and comparison operators.
+///
+/// Comparisons result in one when true and zero when false.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    NotEq,
+    Less,
+    LessEq,
+    Great,
+    GreatEq,
+}
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Term {
+    Jump(BlockId),
+    /// Go to `then` when `cond` isn't zero, and to `otherwise` when it is.
+    Branch {
+        cond: Temp,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return,
+    /// Call a procedure in place of the current one, which returns
+    /// straight to the current procedure's caller.
+    TailCall {
+        level: u8,
+        proc: ProcId,
+    },
+}
+
+impl ProcId {
+    pub(crate) const MAIN: ProcId = ProcId(0);
+
+    pub(crate) fn new(index: usize) -> Self {
+        ProcId(index as u32)
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl BlockId {
+    pub(crate) const ENTRY: BlockId = BlockId(0);
+
+    pub(crate) fn new(index: usize) -> Self {
+        BlockId(index as u32)
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Program {
+    pub(crate) fn proc(&self, id: ProcId) -> &Proc {
+        &self.procs[id.index()]
+    }
+
+    /// The procedures declared directly in the given one, in declaration order.
+    pub(crate) fn children(&self, id: ProcId) -> impl Iterator<Item = ProcId> + '_ {
+        self.procs
+            .iter()
+            .enumerate()
+            .filter(move |(_, proc)| proc.parent == Some(id))
+            .map(|(idx, _)| ProcId::new(idx))
+    }
+}
+
+impl InstKind {
+    /// The temporary defined by the instruction, if any.
+    pub(crate) fn dst(&self) -> Option<Temp> {
+        match *self {
+            InstKind::Const { dst, .. }
+            | InstKind::Load { dst, .. }
+            | InstKind::Unary { dst, .. }
+            | InstKind::Binary { dst, .. }
+            | InstKind::Read { dst } => Some(dst),
+            InstKind::Store { .. } | InstKind::Call { .. } | InstKind::Write { .. } => None,
+        }
+    }
+
+    /// The temporaries used by the instruction, in the order they were defined.
+    pub(crate) fn uses(&self) -> Vec<Temp> {
+        match *self {
+            InstKind::Store { src, .. } | InstKind::Unary { src, .. } | InstKind::Write { src } => vec![src],
+            InstKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            InstKind::Const { .. } | InstKind::Load { .. } | InstKind::Call { .. } | InstKind::Read { .. } => vec![],
+        }
+    }
+
+    /// Whether the instruction can be removed when its result isn't used.
+    ///
+    /// Division can fail at runtime, so it has to stay.
+    pub(crate) fn is_pure(&self) -> bool {
+        match self {
+            InstKind::Const { .. } | InstKind::Load { .. } | InstKind::Unary { .. } => true,
+            InstKind::Binary { op, .. } => *op != BinaryOp::Div,
+            InstKind::Store { .. } | InstKind::Call { .. } | InstKind::Read { .. } | InstKind::Write { .. } => false,
+        }
+    }
+}
+
+impl UnaryOp {
+    /// Apply the operator the way the VM does.
+    pub(crate) fn eval(&self, num: Num) -> Num {
+        match self {
+            UnaryOp::Neg => num.wrapping_neg(),
+            UnaryOp::Odd => (num % 2 != 0) as Num,
+        }
+    }
+}
+
+impl BinaryOp {
+    /// Apply the operator the way the VM does.
+    ///
+    /// Returns `None` for division by zero, which is a runtime error.
+    pub(crate) fn eval(&self, lhs: Num, rhs: Num) -> Option<Num> {
+        Some(match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            BinaryOp::Div if rhs == 0 => return None,
+            BinaryOp::Div => lhs.wrapping_div(rhs),
+            BinaryOp::Eq => (lhs == rhs) as Num,
+            BinaryOp::NotEq => (lhs != rhs) as Num,
+            BinaryOp::Less => (lhs < rhs) as Num,
+            BinaryOp::LessEq => (lhs <= rhs) as Num,
+            BinaryOp::Great => (lhs > rhs) as Num,
+            BinaryOp::GreatEq => (lhs >= rhs) as Num,
+        })
+    }
+}
+
+impl Term {
+    /// The blocks control can go to next, or none when it leaves the procedure.
+    pub(crate) fn successors(&self) -> Vec<BlockId> {
+        match *self {
+            Term::Jump(target) => vec![target],
+            Term::Branch { then, otherwise, .. } => vec![then, otherwise],
+            Term::Return | Term::TailCall { .. } => vec![],
+        }
+    }
+}
+
+/// Builds a [`Program`] one instruction at a time.
+///
+/// Instructions take their operands from, and push their results onto,
+/// an operand stack of temporaries, the same way bytecode is generated.
+/// That keeps the temporaries in stack order.
+pub(crate) struct Builder {
+    program: Program,
+    /// Procedures being built, innermost last.
+    procs: Vec<ProcBuilder>,
+    /// Location of the source fragment being lowered.
+    span: (u32, u32),
+}
+
+struct ProcBuilder {
+    id: ProcId,
+    /// Blocks in the order they were created.
+    blocks: Vec<PendingBlock>,
+    /// Blocks in the order they were switched to, which is code order.
+    order: Vec<BlockId>,
+    current: BlockId,
+    temps: u32,
+    /// Temporaries defined but not yet used.
+    stack: Vec<Temp>,
+}
+
+#[derive(Default)]
+struct PendingBlock {
+    insts: Vec<Inst>,
+    term: Option<(Term, (u32, u32))>,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Self {
+        Self {
+            program: Program::default(),
+            procs: vec![],
+            span: (0, 0),
+        }
+    }
+
+    /// The finished program.
+    pub(crate) fn finish(self) -> Program {
+        assert!(self.procs.is_empty(), "procedure isn't finished");
+        self.program
+    }
+
+    /// Set the location of the source fragment that subsequently
+    /// added instructions are lowered from.
+    pub(crate) fn set_span(&mut self, span: (u32, u32)) {
+        self.span = span;
+    }
+
+    /// Start building a procedure nested in the current one, or the main
+    /// program when there's no current one.
+    ///
+    /// A nested procedure is declared at the current span. Its entry block
+    /// is current until the procedure is finished with [`Builder::end_proc`].
+    /// Nested procedures have to be built before any code is added to
+    /// their parent.
+    pub(crate) fn begin_proc(&mut self, name: &str, level: u8, symbol: Option<SymbolId>) -> ProcId {
+        let id = ProcId(self.program.procs.len() as u32);
+        let parent = self.procs.last().map(|proc| proc.id);
+        self.program.procs.push(Proc {
+            name: name.to_string(),
+            parent,
+            level,
+            symbol,
+            span: parent.map(|_| self.span),
+            vars: vec![],
+            frame: 0,
+            body_span: self.span,
+            blocks: vec![],
+        });
+        self.procs.push(ProcBuilder {
+            id,
+            blocks: vec![PendingBlock::default()],
+            order: vec![BlockId::ENTRY],
+            current: BlockId::ENTRY,
+            temps: 0,
+            stack: vec![],
+        });
+        id
+    }
+
+    /// Declare a variable in the stack frame of the current procedure.
+    pub(crate) fn declare_var(&mut self, name: &str, offset: u16) {
+        let proc = self.current_proc_mut();
+        proc.vars.push(VarInfo {
+            name: name.to_string(),
+            offset,
+        });
+    }
+
+    /// Set the size of the current procedure's stack frame, at the start of its body.
+    pub(crate) fn enter(&mut self, frame: u16) {
+        let span = self.span;
+        let proc = self.current_proc_mut();
+        proc.frame = frame;
+        proc.body_span = span;
+    }
+
+    /// Finish building the current procedure.
+    ///
+    /// Every block must have been switched to and terminated.
+    pub(crate) fn end_proc(&mut self) {
+        let builder = self.procs.pop().expect("no procedure to end");
+        let mut renumber = vec![BlockId(0); builder.blocks.len()];
+        for (idx, id) in builder.order.iter().enumerate() {
+            renumber[id.index()] = BlockId(idx as u32);
+        }
+
+        let mut blocks: Vec<Option<PendingBlock>> = builder.blocks.into_iter().map(Some).collect();
+        self.program.procs[builder.id.index()].blocks = builder
+            .order
+            .iter()
+            .map(|id| {
+                let block = blocks[id.index()].take().expect("block is only placed once");
+                let (term, span) = block.term.expect("block is terminated");
+                let term = match term {
+                    Term::Jump(target) => Term::Jump(renumber[target.index()]),
+                    Term::Branch { cond, then, otherwise } => Term::Branch {
+                        cond,
+                        then: renumber[then.index()],
+                        otherwise: renumber[otherwise.index()],
+                    },
+                    Term::Return | Term::TailCall { .. } => term,
+                };
+                Block {
+                    insts: block.insts,
+                    term,
+                    span,
+                }
+            })
+            .collect();
+        assert!(blocks.iter().all(Option::is_none), "block was never switched to");
+    }
+
+    /// Create a block, to be switched to later.
+    pub(crate) fn new_block(&mut self) -> BlockId {
+        let proc = self.proc_builder();
+        proc.blocks.push(PendingBlock::default());
+        BlockId(proc.blocks.len() as u32 - 1)
+    }
+
+    /// Continue adding code to the given block, which is placed after the
+    /// blocks switched to before it.
+    ///
+    /// The current block must be terminated first.
+    pub(crate) fn switch_to(&mut self, block: BlockId) {
+        let proc = self.proc_builder();
+        assert!(
+            proc.blocks[proc.current.index()].term.is_some(),
+            "block isn't terminated"
+        );
+        assert!(!proc.order.contains(&block), "block was already switched to");
+        proc.order.push(block);
+        proc.current = block;
+    }
+
+    pub(crate) fn lit(&mut self, value: Num) {
+        let dst = self.push_temp();
+        self.add(InstKind::Const { dst, value });
+    }
+
+    pub(crate) fn load(&mut self, level: u8, offset: u16) {
+        let dst = self.push_temp();
+        self.add(InstKind::Load {
+            dst,
+            var: Var { level, offset },
+        });
+    }
+
+    pub(crate) fn store(&mut self, level: u8, offset: u16) {
+        let src = self.pop_temp();
+        self.add(InstKind::Store {
+            var: Var { level, offset },
+            src,
+        });
+    }
+
+    pub(crate) fn unary(&mut self, op: UnaryOp) {
+        let src = self.pop_temp();
+        let dst = self.push_temp();
+        self.add(InstKind::Unary { dst, op, src });
+    }
+
+    pub(crate) fn binary(&mut self, op: BinaryOp) {
+        let rhs = self.pop_temp();
+        let lhs = self.pop_temp();
+        let dst = self.push_temp();
+        self.add(InstKind::Binary { dst, op, lhs, rhs });
+    }
+
+    pub(crate) fn call(&mut self, level: u8, proc: ProcId) {
+        self.add(InstKind::Call { level, proc });
+    }
+
+    pub(crate) fn read(&mut self) {
+        let dst = self.push_temp();
+        self.add(InstKind::Read { dst });
+    }
+
+    pub(crate) fn write(&mut self) {
+        let src = self.pop_temp();
+        self.add(InstKind::Write { src });
+    }
+
+    pub(crate) fn jump(&mut self, target: BlockId) {
+        self.terminate(Term::Jump(target));
+    }
+
+    /// Branch on the value on top of the operand stack.
+    pub(crate) fn branch(&mut self, then: BlockId, otherwise: BlockId) {
+        let cond = self.pop_temp();
+        self.terminate(Term::Branch { cond, then, otherwise });
+    }
+
+    pub(crate) fn ret(&mut self) {
+        self.terminate(Term::Return);
+    }
+
+    /// Call a procedure in place of the current one.
+    ///
+    /// Code added after the call can't be reached, and goes into a new block.
+    pub(crate) fn tail_call(&mut self, level: u8, proc: ProcId) {
+        self.terminate(Term::TailCall { level, proc });
+        let block = self.new_block();
+        self.switch_to(block);
+    }
+
+    fn current_proc_mut(&mut self) -> &mut Proc {
+        let id = self.procs.last().expect("no current procedure").id;
+        &mut self.program.procs[id.index()]
+    }
+
+    fn proc_builder(&mut self) -> &mut ProcBuilder {
+        self.procs.last_mut().expect("no current procedure")
+    }
+
+    fn current_block(&mut self) -> &mut PendingBlock {
+        let proc = self.proc_builder();
+        let block = &mut proc.blocks[proc.current.index()];
+        assert!(block.term.is_none(), "block is already terminated");
+        block
+    }
+
+    fn add(&mut self, kind: InstKind) {
+        let span = self.span;
+        self.current_block().insts.push(Inst { kind, span });
+    }
+
+    fn terminate(&mut self, term: Term) {
+        let span = self.span;
+        assert!(self.proc_builder().stack.is_empty(), "operand stack isn't empty");
+        self.current_block().term = Some((term, span));
+    }
+
+    fn push_temp(&mut self) -> Temp {
+        let proc = self.proc_builder();
+        let temp = Temp(proc.temps);
+        proc.temps += 1;
+        proc.stack.push(temp);
+        temp
+    }
+
+    fn pop_temp(&mut self) -> Temp {
+        self.proc_builder().stack.pop().expect("operand stack underflow")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, proc) in self.procs.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "proc {} (level {}, frame {})", proc.name, proc.level, proc.frame)?;
+            for var in &proc.vars {
+                writeln!(f, "    ; var {} at {}", var.name, var.offset)?;
+            }
+            for (idx, block) in proc.blocks.iter().enumerate() {
+                writeln!(f, "b{idx}:")?;
+                for inst in &block.insts {
+                    write!(f, "    ")?;
+                    self.fmt_inst(f, &inst.kind)?;
+                    writeln!(f)?;
+                }
+                write!(f, "    ")?;
+                self.fmt_term(f, &block.term)?;
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Program {
+    fn fmt_inst(&self, f: &mut fmt::Formatter<'_>, kind: &InstKind) -> fmt::Result {
+        match *kind {
+            InstKind::Const { dst, value } => write!(f, "{dst} = const {value}"),
+            InstKind::Load { dst, var } => write!(f, "{dst} = load {var}"),
+            InstKind::Store { var, src } => write!(f, "store {var}, {src}"),
+            InstKind::Unary { dst, op, src } => write!(f, "{dst} = {op} {src}"),
+            InstKind::Binary { dst, op, lhs, rhs } => write!(f, "{dst} = {op} {lhs}, {rhs}"),
+            InstKind::Call { level, proc } => write!(f, "call {level} {}", self.proc(proc).name),
+            InstKind::Read { dst } => write!(f, "{dst} = read"),
+            InstKind::Write { src } => write!(f, "write {src}"),
+        }
+    }
+
+    fn fmt_term(&self, f: &mut fmt::Formatter<'_>, term: &Term) -> fmt::Result {
+        match *term {
+            Term::Jump(target) => write!(f, "jump {target}"),
+            Term::Branch { cond, then, otherwise } => write!(f, "branch {cond}, {then}, {otherwise}"),
+            Term::Return => write!(f, "return"),
+            Term::TailCall { level, proc } => write!(f, "tail call {level} {}", self.proc(proc).name),
+        }
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.level, self.offset)
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            UnaryOp::Neg => "neg",
+            UnaryOp::Odd => "odd",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Eq => "eq",
+            BinaryOp::NotEq => "neq",
+            BinaryOp::Less => "lt",
+            BinaryOp::LessEq => "lte",
+            BinaryOp::Great => "gt",
+            BinaryOp::GreatEq => "gte",
+        };
+        f.write_str(name)
+    }
+}
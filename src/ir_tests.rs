@@ -0,0 +1,125 @@
+use crate::codegen::{emit_program, CodeGen};
+use crate::compiler::Compiler;
+use crate::ir::{BlockId, ProcId, Program, Term};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+
+/// Lower a program to the intermediate representation.
+pub(crate) fn lower(source: &str) -> Program {
+    let lex = Lexer::new(source, "<test>");
+    let program = Parser::new(lex).parse_program().expect("failed to parse");
+    Compiler::new("<test>").compile(&program).expect("failed to compile")
+}
+
+/// Lower a program, and generate code for it with a new `G`.
+pub(crate) fn emit<G: CodeGen + Default>(source: &str) -> G::Output {
+    let mut gen = G::default();
+    emit_program(&lower(source), &mut gen).expect("failed to generate code");
+    gen.finish()
+}
+
+#[test]
+fn test_ir_blocks() {
+    const SOURCE: &str = "var x;
+procedure count;
+    var n;
+begin
+    n := x;
+    while n > 0 do
+    begin
+        if odd n then write n;
+        n := n - 1
+    end;
+    call count
+end;
+begin
+    read x;
+    call count
+end.";
+    let program = lower(SOURCE);
+    assert_eq!(
+        program.to_string(),
+        "\
+proc <main> (level 0, frame 4)
+    ; var x at 3
+b0:
+    t0 = read
+    store 0 3, t0
+    call 0 count
+    return
+
+proc count (level 1, frame 4)
+    ; var n at 3
+b0:
+    t0 = load 1 3
+    store 0 3, t0
+    jump b1
+b1:
+    t1 = load 0 3
+    t2 = const 0
+    t3 = gt t1, t2
+    branch t3, b2, b5
+b2:
+    t4 = load 0 3
+    t5 = odd t4
+    branch t5, b3, b4
+b3:
+    t6 = load 0 3
+    write t6
+    jump b4
+b4:
+    t7 = load 0 3
+    t8 = const 1
+    t9 = sub t7, t8
+    store 0 3, t9
+    jump b1
+b5:
+    tail call 1 count
+b6:
+    return
+"
+    );
+}
+
+#[test]
+fn test_ir_procs() {
+    const SOURCE: &str = "procedure a;
+    procedure b;
+    begin
+        write 1
+    end;
+begin
+    call b
+end;
+procedure c;
+begin
+    call a
+end;
+begin
+    call c
+end.";
+    let program = lower(SOURCE);
+    let names: Vec<&str> = program.procs.iter().map(|proc| proc.name.as_str()).collect();
+    assert_eq!(names, ["<main>", "a", "b", "c"]);
+
+    // Nested procedures are listed under their parent, in declaration order.
+    let children =
+        |id: ProcId| -> Vec<&str> { program.children(id).map(|id| program.proc(id).name.as_str()).collect() };
+    assert_eq!(children(ProcId::MAIN), ["a", "c"]);
+    let a = program.children(ProcId::MAIN).next().unwrap();
+    assert_eq!(children(a), ["b"]);
+    assert_eq!(program.proc(a).level, 1);
+    assert_eq!(program.procs[2].level, 2);
+}
+
+#[test]
+fn test_ir_constant_branch() {
+    // The statement skipped by a condition that's always false is still
+    // lowered, into a block nothing jumps to.
+    let program = lower("begin\n    if 0 = 1 then write 1;\n    write 2\nend.");
+    let main = program.proc(ProcId::MAIN);
+    assert_eq!(main.blocks.len(), 3);
+    assert_eq!(main.blocks[0].term, Term::Jump(BlockId::new(2)));
+    assert_eq!(main.blocks[1].term, Term::Jump(BlockId::new(2)));
+    assert_eq!(main.blocks[2].term, Term::Return);
+}
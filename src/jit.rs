@@ -0,0 +1,617 @@
+//! Native code generation for chunks, with Cranelift.
+//!
+//! A whole chunk is translated into one native function that runs on the
+//! VM's own stack, one basic block per bytecode instruction. The base and
+//! top registers live in machine registers, and are written back to the VM
+//! when the function stops: when the program halts, when it fails with a
+//! runtime error, or when it reaches something the translation doesn't
+//! cover, in which case the interpreter carries on from the same state.
+//!
+//! Only chunks that pass [`Chunk::verify`] are translated, since the
+//! native code doesn't check stack accesses the verifier already rules out.
+//! The stack limit is still checked at runtime, where the interpreter does.
+use std::any::Any;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, JumpTableData, MemFlags, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::bytecode::{Instr, Math, OpCode};
+use crate::errors::Error;
+use crate::limits::*;
+use crate::{error, Chunk, Pl0Config};
+
+/// Exit status of the native code: the program halted.
+const HALTED: i32 = 0;
+/// Exit status of the native code: division by zero.
+const DIV_ZERO: i32 = 1;
+/// Exit status of the native code: stack overflow.
+const STACK_OVERFLOW: i32 = 2;
+/// Exit status of the native code: a `write` or `read` callback panicked.
+const PANICKED: i32 = 3;
+/// Exit status of the native code: the interpreter has to take over.
+const INTERPRET: i32 = 4;
+
+/// Result of a failed `read` callback, outside the range of numbers.
+const READ_PANICKED: i64 = i64::MIN;
+
+/// Registers of the VM, passed in and out of the native code.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Registers {
+    pub pc: usize,
+    pub base: usize,
+    pub top: usize,
+}
+
+/// How the native code stopped.
+pub(crate) enum Exit {
+    /// The program ran to the end.
+    Halted,
+    /// The program failed with a runtime error.
+    Failed(Error),
+    /// The interpreter has to carry on from the registers.
+    Interpret,
+}
+
+/// State shared with the native code and the callbacks it makes.
+#[repr(C)]
+struct Context<'a> {
+    stack: *mut i32,
+    len: usize,
+    pc: usize,
+    base: usize,
+    top: usize,
+    config: &'a Pl0Config,
+    /// Payload of a callback that panicked, to resume once the native code returned.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// A chunk compiled to native code.
+pub(crate) struct NativeCode {
+    /// Owns the memory the code is in.
+    module: Option<JITModule>,
+    entry: unsafe extern "C" fn(*mut Context) -> i32,
+}
+
+impl NativeCode {
+    /// Compile a chunk, if it's verified and the host is supported.
+    pub(crate) fn compile(chunk: &Chunk) -> Option<Self> {
+        if chunk.code.is_empty() || chunk.verify().is_err() {
+            return None;
+        }
+
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").ok()?;
+        let isa = cranelift_native::builder()
+            .ok()?
+            .finish(settings::Flags::new(flags))
+            .ok()?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("pl0_write", write as *const u8);
+        builder.symbol("pl0_read", read as *const u8);
+        let mut module = JITModule::new(builder);
+
+        match translate(&mut module, chunk) {
+            Some(entry) => Some(Self {
+                module: Some(module),
+                // SAFETY: the function was built with this signature.
+                entry: unsafe { mem::transmute::<*const u8, unsafe extern "C" fn(*mut Context) -> i32>(entry) },
+            }),
+            None => {
+                // SAFETY: nothing was finalized, so no code is in use.
+                unsafe { module.free_memory() };
+                None
+            }
+        }
+    }
+
+    /// Run the program from its first instruction until it stops, and
+    /// leave the registers where it stopped.
+    ///
+    /// `stack` must be the stack the chunk was sized for, and the base and
+    /// top registers must be where the main program starts.
+    pub(crate) fn run(&self, stack: &mut [i32], config: &Pl0Config, regs: &mut Registers) -> Exit {
+        let mut ctx = Context {
+            stack: stack.as_mut_ptr(),
+            len: stack.len(),
+            pc: 0,
+            base: regs.base,
+            top: regs.top,
+            config,
+            panic: None,
+        };
+        // SAFETY: the code only accesses the stack within the bounds the
+        // verifier and the runtime stack checks guarantee.
+        let status = unsafe { (self.entry)(&mut ctx) };
+        if let Some(payload) = ctx.panic {
+            panic::resume_unwind(payload);
+        }
+
+        *regs = Registers {
+            pc: ctx.pc,
+            base: ctx.base,
+            top: ctx.top,
+        };
+        match status {
+            HALTED => Exit::Halted,
+            DIV_ZERO => Exit::Failed(error!(E0401, "division by zero")),
+            STACK_OVERFLOW => Exit::Failed(error!(E0402, "stack overflow")),
+            _ => Exit::Interpret,
+        }
+    }
+}
+
+impl Drop for NativeCode {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the code can't be called any more.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// `write` callback made by the native code. Returns whether it panicked.
+extern "C" fn write(ctx: *mut Context, arg: i32) -> i32 {
+    // SAFETY: the native code passes the context it was called with.
+    let ctx = unsafe { &mut *ctx };
+    let config = ctx.config;
+    match panic::catch_unwind(AssertUnwindSafe(|| (config.write)(config.user_data.as_deref(), arg))) {
+        Ok(()) => 0,
+        Err(payload) => {
+            ctx.panic = Some(payload);
+            1
+        }
+    }
+}
+
+/// `read` callback made by the native code. Returns [`READ_PANICKED`] if it panicked.
+extern "C" fn read(ctx: *mut Context) -> i64 {
+    // SAFETY: the native code passes the context it was called with.
+    let ctx = unsafe { &mut *ctx };
+    let config = ctx.config;
+    match panic::catch_unwind(AssertUnwindSafe(|| (config.read)(config.user_data.as_deref()))) {
+        Ok(num) => num.unwrap_or_default() as i64,
+        Err(payload) => {
+            ctx.panic = Some(payload);
+            READ_PANICKED
+        }
+    }
+}
+
+/// Build the native function for a chunk, and return its address.
+fn translate(module: &mut JITModule, chunk: &Chunk) -> Option<*const u8> {
+    let ptr = module.target_config().pointer_type();
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(ptr));
+    sig.returns.push(AbiParam::new(types::I32));
+    let func_id = module.declare_function("pl0_chunk", Linkage::Local, &sig).ok()?;
+
+    let mut write_sig = module.make_signature();
+    write_sig.params.push(AbiParam::new(ptr));
+    write_sig.params.push(AbiParam::new(types::I32));
+    write_sig.returns.push(AbiParam::new(types::I32));
+    let write_id = module.declare_function("pl0_write", Linkage::Import, &write_sig).ok()?;
+
+    let mut read_sig = module.make_signature();
+    read_sig.params.push(AbiParam::new(ptr));
+    read_sig.returns.push(AbiParam::new(types::I64));
+    let read_id = module.declare_function("pl0_read", Linkage::Import, &read_sig).ok()?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let write = module.declare_func_in_func(write_id, builder.func);
+        let read = module.declare_func_in_func(read_id, builder.func);
+        Translator::new(builder, chunk, ptr, write, read).run();
+    }
+
+    module.define_function(func_id, &mut ctx).ok()?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().ok()?;
+    Some(module.get_finalized_function(func_id))
+}
+
+/// Translates the instructions of a chunk into a function.
+struct Translator<'a, 'c> {
+    builder: FunctionBuilder<'a>,
+    chunk: &'c Chunk,
+    /// Type of addresses and stack indices.
+    ptr: Type,
+    ctx: Value,
+    /// Address of the bottom of the stack.
+    stack: Value,
+    /// Number of slots in the stack.
+    len: Value,
+    base: Variable,
+    top: Variable,
+    /// Block of each instruction.
+    blocks: Vec<Block>,
+    /// Returns from the function once the program halted.
+    halt: Block,
+    /// Stores the registers and returns from the function, given the exit
+    /// status and the program counter.
+    exit: Block,
+    /// Continues after a procedure returns, given the return address. Only
+    /// addresses following a call are translated, the others are left to
+    /// the interpreter.
+    dispatch: Block,
+    write: FuncRef,
+    read: FuncRef,
+}
+
+impl<'a, 'c> Translator<'a, 'c> {
+    fn new(mut builder: FunctionBuilder<'a>, chunk: &'c Chunk, ptr: Type, write: FuncRef, read: FuncRef) -> Self {
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let ctx = builder.block_params(entry)[0];
+
+        let flags = MemFlags::trusted();
+        let stack = builder
+            .ins()
+            .load(ptr, flags, ctx, mem::offset_of!(Context, stack) as i32);
+        let len = builder
+            .ins()
+            .load(ptr, flags, ctx, mem::offset_of!(Context, len) as i32);
+
+        let (base, top) = (Variable::from_u32(0), Variable::from_u32(1));
+        builder.declare_var(base, ptr);
+        builder.declare_var(top, ptr);
+        let value = builder
+            .ins()
+            .load(ptr, flags, ctx, mem::offset_of!(Context, base) as i32);
+        builder.def_var(base, value);
+        let value = builder
+            .ins()
+            .load(ptr, flags, ctx, mem::offset_of!(Context, top) as i32);
+        builder.def_var(top, value);
+
+        let blocks = chunk.code.iter().map(|_| builder.create_block()).collect();
+        let halt = builder.create_block();
+        let exit = builder.create_block();
+        builder.append_block_param(exit, types::I32);
+        builder.append_block_param(exit, ptr);
+        let dispatch = builder.create_block();
+        builder.append_block_param(dispatch, types::I32);
+
+        Self {
+            builder,
+            chunk,
+            ptr,
+            ctx,
+            stack,
+            len,
+            base,
+            top,
+            blocks,
+            halt,
+            exit,
+            dispatch,
+            write,
+            read,
+        }
+    }
+
+    fn run(mut self) {
+        self.builder.ins().jump(self.blocks[0], &[]);
+
+        for (addr, instr) in self.chunk.code.iter().enumerate() {
+            self.builder.switch_to_block(self.blocks[addr]);
+            self.instr(addr, *instr);
+        }
+
+        self.builder.switch_to_block(self.halt);
+        let status = self.builder.ins().iconst(types::I32, HALTED as i64);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.switch_to_block(self.exit);
+        let (status, pc) = (
+            self.builder.block_params(self.exit)[0],
+            self.builder.block_params(self.exit)[1],
+        );
+        let flags = MemFlags::trusted();
+        self.builder
+            .ins()
+            .store(flags, pc, self.ctx, mem::offset_of!(Context, pc) as i32);
+        let base = self.builder.use_var(self.base);
+        self.builder
+            .ins()
+            .store(flags, base, self.ctx, mem::offset_of!(Context, base) as i32);
+        let top = self.builder.use_var(self.top);
+        self.builder
+            .ins()
+            .store(flags, top, self.ctx, mem::offset_of!(Context, top) as i32);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.switch_to_block(self.dispatch);
+        let addr = self.builder.block_params(self.dispatch)[0];
+        let pc = self.builder.ins().sextend(self.ptr, addr);
+        let interpret = self.builder.ins().iconst(types::I32, INTERPRET as i64);
+        let default = self.builder.func.dfg.block_call(self.exit, &[interpret, pc]);
+        // Jumping to address zero halts the machine.
+        let mut table = vec![self.builder.func.dfg.block_call(self.halt, &[])];
+        for addr in 1..self.chunk.code.len() {
+            // Every entry needs its own arguments, they can't be shared.
+            let call = match self.chunk.code[addr - 1].opcode {
+                OpCode::Call => self.builder.func.dfg.block_call(self.blocks[addr], &[]),
+                _ => self.builder.func.dfg.block_call(self.exit, &[interpret, pc]),
+            };
+            table.push(call);
+        }
+        let table = self.builder.create_jump_table(JumpTableData::new(default, &table));
+        self.builder.ins().br_table(addr, table);
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn instr(&mut self, addr: usize, Instr { opcode, l, a }: Instr) {
+        let next_pc = (addr + 1) & (CODE_SIZE - 1);
+        match opcode {
+            OpCode::NoOp => {}
+            OpCode::Lit => {
+                let num = self.builder.ins().iconst(types::I32, a as i64);
+                self.check_stack(1, next_pc);
+                self.push(num);
+            }
+            OpCode::Const => match self.chunk.constants.get(a as usize) {
+                Some(num) => {
+                    let num = self.builder.ins().iconst(types::I32, *num as i64);
+                    self.check_stack(1, next_pc);
+                    self.push(num);
+                }
+                // Not reached in a verified chunk, but the interpreter knows what to do.
+                None => return self.exit_with(INTERPRET, addr),
+            },
+            OpCode::Return => {
+                let base = self.builder.use_var(self.base);
+                let top = self.builder.ins().iadd_imm(base, -1);
+                self.builder.def_var(self.top, top);
+                let return_addr = self.load_at(base, 2);
+                let dynamic_link = self.load_at(base, 1);
+                let base = self.builder.ins().sextend(self.ptr, dynamic_link);
+                self.builder.def_var(self.base, base);
+                self.builder.ins().jump(self.dispatch, &[return_addr]);
+                return;
+            }
+            OpCode::Math(Math::Neg) => {
+                let value = self.peek();
+                let value = self.builder.ins().ineg(value);
+                self.replace(value);
+            }
+            OpCode::Math(Math::Odd) => {
+                let value = self.peek();
+                let value = self.builder.ins().band_imm(value, 1);
+                self.replace(value);
+            }
+            OpCode::Math(Math::Div) => {
+                let (lhs, rhs) = self.pop_operands();
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+                self.exit_if(zero, DIV_ZERO, next_pc);
+                // Dividing the lowest number by -1 overflows, and traps.
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let one = self.builder.ins().iconst(types::I32, 1);
+                let divisor = self.builder.ins().select(minus_one, one, rhs);
+                let quotient = self.builder.ins().sdiv(lhs, divisor);
+                let negated = self.builder.ins().ineg(lhs);
+                let value = self.builder.ins().select(minus_one, negated, quotient);
+                self.replace(value);
+            }
+            OpCode::Math(m) => {
+                let (lhs, rhs) = self.pop_operands();
+                let ins = self.builder.ins();
+                let value = match m {
+                    Math::Add => ins.iadd(lhs, rhs),
+                    Math::Sub => ins.isub(lhs, rhs),
+                    Math::Mul => ins.imul(lhs, rhs),
+                    _ => {
+                        let cond = match m {
+                            Math::Eq => IntCC::Equal,
+                            Math::NotEq => IntCC::NotEqual,
+                            Math::Less => IntCC::SignedLessThan,
+                            Math::GreatEq => IntCC::SignedGreaterThanOrEqual,
+                            Math::Great => IntCC::SignedGreaterThan,
+                            _ => IntCC::SignedLessThanOrEqual,
+                        };
+                        let flag = ins.icmp(cond, lhs, rhs);
+                        self.builder.ins().uextend(types::I32, flag)
+                    }
+                };
+                self.replace(value);
+            }
+            OpCode::Load => {
+                let base = self.find_base(l);
+                let value = self.load_at(base, a as i64);
+                self.push(value);
+            }
+            OpCode::Store => {
+                let base = self.find_base(l);
+                let value = self.peek();
+                self.store_at(base, a as i64, value);
+                self.adjust_top(-1);
+            }
+            OpCode::Call => {
+                self.check_stack(DATA_OFFSET, next_pc);
+                let static_link = self.find_base(l);
+                let static_link = self.builder.ins().ireduce(types::I32, static_link);
+                let base = self.builder.use_var(self.base);
+                let dynamic_link = self.builder.ins().ireduce(types::I32, base);
+                let return_addr = self.builder.ins().iconst(types::I32, next_pc as i64);
+                let top = self.builder.use_var(self.top);
+                self.store_at(top, 1, static_link);
+                self.store_at(top, 2, dynamic_link);
+                self.store_at(top, 3, return_addr);
+                let base = self.builder.ins().iadd_imm(top, 1);
+                self.builder.def_var(self.base, base);
+                return self.jump_to(a as usize);
+            }
+            OpCode::TailCall => {
+                let static_link = self.find_base(l);
+                let static_link = self.builder.ins().ireduce(types::I32, static_link);
+                let base = self.builder.use_var(self.base);
+                self.store_at(base, 0, static_link);
+                let top = self.builder.ins().iadd_imm(base, -1);
+                self.builder.def_var(self.top, top);
+                return self.jump_to(a as usize);
+            }
+            OpCode::IncTop => {
+                self.check_stack(a as usize, next_pc);
+                // Variables start out as zero, and the block mark stays.
+                let top = self.builder.use_var(self.top);
+                let base = self.builder.use_var(self.base);
+                let vars = self.builder.ins().iadd_imm(base, DATA_OFFSET as i64);
+                let zero = self.builder.ins().iconst(types::I32, 0);
+                for offset in 1..=a as i64 {
+                    let index = self.builder.ins().iadd_imm(top, offset);
+                    let is_var = self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, index, vars);
+                    let old = self.load_at(top, offset);
+                    let value = self.builder.ins().select(is_var, zero, old);
+                    self.store_at(top, offset, value);
+                }
+                self.adjust_top(a as i64);
+            }
+            OpCode::Jump => return self.jump_to(a as usize),
+            OpCode::JumpIfZero => {
+                let value = self.peek();
+                self.adjust_top(-1);
+                let target = self.target(a as usize);
+                let next = self.target(next_pc);
+                self.builder.ins().brif(value, next, &[], target, &[]);
+                return;
+            }
+            OpCode::Write => {
+                let value = self.peek();
+                let call = self.builder.ins().call(self.write, &[self.ctx, value]);
+                let panicked = self.builder.inst_results(call)[0];
+                self.exit_if(panicked, PANICKED, next_pc);
+                self.adjust_top(-1);
+            }
+            OpCode::Dup => {
+                let value = self.peek();
+                self.push(value);
+            }
+            OpCode::Read => {
+                self.check_stack(1, next_pc);
+                let call = self.builder.ins().call(self.read, &[self.ctx]);
+                let result = self.builder.inst_results(call)[0];
+                let panicked = self.builder.ins().icmp_imm(IntCC::Equal, result, READ_PANICKED);
+                self.exit_if(panicked, PANICKED, next_pc);
+                let value = self.builder.ins().ireduce(types::I32, result);
+                self.push(value);
+            }
+        }
+        self.jump_to(next_pc);
+    }
+
+    /// Block to continue at for a new program counter.
+    fn target(&self, pc: usize) -> Block {
+        // Jumping to address zero halts the machine.
+        match pc {
+            0 => self.halt,
+            _ => self.blocks.get(pc).copied().unwrap_or(self.halt),
+        }
+    }
+
+    fn jump_to(&mut self, pc: usize) {
+        let target = self.target(pc);
+        self.builder.ins().jump(target, &[]);
+    }
+
+    /// Leave the function with a status, unconditionally.
+    fn exit_with(&mut self, status: i32, pc: usize) {
+        let status = self.builder.ins().iconst(types::I32, status as i64);
+        let pc = self.builder.ins().iconst(self.ptr, pc as i64);
+        self.builder.ins().jump(self.exit, &[status, pc]);
+    }
+
+    /// Leave the function with a status if `cond` is true.
+    fn exit_if(&mut self, cond: Value, status: i32, pc: usize) {
+        let status = self.builder.ins().iconst(types::I32, status as i64);
+        let pc = self.builder.ins().iconst(self.ptr, pc as i64);
+        let next = self.builder.create_block();
+        self.builder.ins().brif(cond, self.exit, &[status, pc], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Fail with a stack overflow unless there's room for `n` more values above the top.
+    fn check_stack(&mut self, n: usize, next_pc: usize) {
+        let top = self.builder.use_var(self.top);
+        let end = self.builder.ins().iadd_imm(top, n as i64);
+        let full = self
+            .builder
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, end, self.len);
+        self.exit_if(full, STACK_OVERFLOW, next_pc);
+    }
+
+    /// Index of the frame `level` static links up from the current one.
+    fn find_base(&mut self, level: u8) -> Value {
+        let mut base = self.builder.use_var(self.base);
+        for _ in 0..level {
+            let static_link = self.load_at(base, 0);
+            base = self.builder.ins().sextend(self.ptr, static_link);
+        }
+        base
+    }
+
+    /// Address of the stack slot at `index`.
+    fn slot(&mut self, index: Value) -> Value {
+        let bytes = self.builder.ins().ishl_imm(index, 2);
+        self.builder.ins().iadd(self.stack, bytes)
+    }
+
+    fn load_at(&mut self, index: Value, offset: i64) -> Value {
+        let slot = self.slot(index);
+        self.builder
+            .ins()
+            .load(types::I32, MemFlags::trusted(), slot, (offset * 4) as i32)
+    }
+
+    fn store_at(&mut self, index: Value, offset: i64, value: Value) {
+        let slot = self.slot(index);
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, slot, (offset * 4) as i32);
+    }
+
+    fn adjust_top(&mut self, delta: i64) {
+        let top = self.builder.use_var(self.top);
+        let top = self.builder.ins().iadd_imm(top, delta);
+        self.builder.def_var(self.top, top);
+    }
+
+    /// The value on top of the stack.
+    fn peek(&mut self) -> Value {
+        let top = self.builder.use_var(self.top);
+        self.load_at(top, 0)
+    }
+
+    /// Replace the value on top of the stack.
+    fn replace(&mut self, value: Value) {
+        let top = self.builder.use_var(self.top);
+        self.store_at(top, 0, value);
+    }
+
+    /// Pop the right operand of a binary operation, and read the left one,
+    /// whose slot takes the result.
+    fn pop_operands(&mut self) -> (Value, Value) {
+        let rhs = self.peek();
+        self.adjust_top(-1);
+        let lhs = self.peek();
+        (lhs, rhs)
+    }
+
+    /// Push a value, once the stack was checked for room.
+    fn push(&mut self, value: Value) {
+        self.adjust_top(1);
+        self.replace(value);
+    }
+}
@@ -0,0 +1,93 @@
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use crate::jit::NativeCode;
+use crate::{assemble, compile, Chunk, Num, Pl0Config, Vm};
+
+/// Run a chunk on a VM, and return what it writes.
+fn run(chunk: &Chunk, jit: bool) -> Vec<Num> {
+    let written: Rc<RefCell<Vec<Num>>> = Rc::default();
+    let mut config = Pl0Config::new();
+    config.write = |user_data, num| {
+        let written = user_data
+            .and_then(|data| data.downcast_ref::<Rc<RefCell<Vec<Num>>>>())
+            .unwrap();
+        written.borrow_mut().push(num);
+    };
+    config.user_data = Some(Box::new(written.clone()));
+
+    let mut vm = Vm::from_config(config);
+    vm.set_jit(jit);
+    vm.eval(chunk).expect("failed to run");
+    let written = written.borrow().clone();
+    written
+}
+
+#[test]
+fn test_jit_compile() {
+    let sources = [
+        include_str!("../tests/conditionals.pas"),
+        include_str!("../tests/expressions.pas"),
+        include_str!("../tests/fibonacci.pas"),
+        include_str!("../tests/hello_world.pas"),
+        include_str!("../tests/procedures.pas"),
+        include_str!("../tests/read.pas"),
+    ];
+    for source in sources {
+        let chunk = compile("<test>", source).expect("failed to compile");
+        assert!(NativeCode::compile(&chunk).is_some(), "failed to translate:\n{source}");
+    }
+
+    // Chunks the verifier rejects are left to the interpreter,
+    // even if they would run.
+    let chunk = assemble("<test>", "int 0 3\nlit 1 7\nopr 0 14\nopr 0 0").expect("failed to assemble");
+    assert!(chunk.verify().is_err());
+    assert!(NativeCode::compile(&chunk).is_none());
+    assert_eq!(run(&chunk, true), vec![7]);
+}
+
+#[test]
+fn test_jit_arithmetic() {
+    // Every operation, including the ones that wrap around.
+    const SOURCE: &str = "var x, y;
+begin
+    x := 0 - 2147483647;
+    x := x - 1;
+    y := 0 - 1;
+    write x / y;
+    write x * y;
+    write x - 1;
+    write 0 - x;
+    write -x;
+    write 7 / (0 - 2);
+    write (0 - 7) / 2;
+    if odd (0 - 3) then write 1;
+    if odd 4 then write 0;
+    if x < y then write 2;
+    if x >= y then write 0;
+    if x > y then write 0;
+    if x <= y then write 3;
+    if x = y then write 0;
+    if x # y then write 4
+end.";
+    let chunk = compile("<test>", SOURCE).expect("failed to compile");
+    let expected = vec![Num::MIN, Num::MIN, Num::MAX, Num::MIN, Num::MIN, -3, -3, 1, 2, 3, 4];
+    assert_eq!(run(&chunk, false), expected);
+    assert_eq!(run(&chunk, true), expected);
+}
+
+#[test]
+fn test_jit_callback_panic() {
+    let chunk = compile("<test>", "begin write 1 end.").expect("failed to compile");
+    let mut config = Pl0Config::new();
+    config.write = |_, num| panic!("can't write {num}");
+    let mut vm = Vm::from_config(config);
+
+    // The panic unwinds out of the VM as it would from the interpreter.
+    let payload = panic::catch_unwind(AssertUnwindSafe(|| vm.eval(&chunk))).expect_err("didn't panic");
+    assert_eq!(
+        payload.downcast_ref::<String>().map(String::as_str),
+        Some("can't write 1")
+    );
+}
@@ -59,7 +59,9 @@ impl<'a> Lexer<'a> {
                         self.bump();
                         self.make_token(TokenKind::Assign)
                     } else {
-                        return error!("lexer", "unexpected character: {ch:?}").into();
+                        return error!(E0102, "expected '=' after ':'")
+                            .with_location(self.span, &self.file)
+                            .into();
                     }
                 }
                 '(' => self.make_token(TokenKind::ParenLeft),
@@ -82,7 +84,11 @@ impl<'a> Lexer<'a> {
                 }
                 '!' => self.make_token(TokenKind::Keyword(Keyword::Write)),
                 '?' => self.make_token(TokenKind::Keyword(Keyword::Read)),
-                _ => return error!("lexer", "unexpected character {ch:?}").into(),
+                _ => {
+                    return error!(E0101, "unexpected character {ch:?}")
+                        .with_location(self.span, &self.file)
+                        .into()
+                }
             },
             // End-of-file
             None => self.make_token(TokenKind::Eof),
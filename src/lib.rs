@@ -1,30 +1,97 @@
 //! PL/0 programming language.
 use std::any::Any;
 
+use self::codegen::CodeGen;
+
+mod asm;
 mod ast;
+mod binary;
 mod bytecode;
 mod codegen;
 mod codegen_bytecode;
+mod codegen_c;
+#[cfg(test)]
+mod codegen_c_tests;
+mod codegen_js;
+#[cfg(test)]
+mod codegen_js_tests;
+mod codegen_llvm;
+#[cfg(test)]
+mod codegen_llvm_tests;
+#[cfg(test)]
+mod codegen_tests;
+mod codegen_wasm;
+#[cfg(test)]
+mod codegen_wasm_tests;
+mod codegen_x86;
+#[cfg(test)]
+mod codegen_x86_tests;
 mod compiler;
 #[cfg(test)]
 mod compiler_tests;
+mod const_prop;
+#[cfg(test)]
+mod const_prop_tests;
+mod dead_code;
+#[cfg(test)]
+mod dead_code_tests;
+mod debug;
+mod disasm;
 mod env;
+mod error_codes;
 mod errors;
+pub mod fuzz;
+mod fuzz_gen;
+mod fuzz_reduce;
+mod fuzz_runner;
+#[cfg(test)]
+mod fuzz_tests;
+mod inline;
+#[cfg(test)]
+mod inline_tests;
+mod interpreter;
+#[cfg(test)]
+mod interpreter_tests;
+mod ir;
+#[cfg(test)]
+mod ir_tests;
+#[cfg(feature = "jit")]
+mod jit;
+#[cfg(all(test, feature = "jit"))]
+mod jit_tests;
 mod lexer;
 #[cfg(test)]
 mod lexer_tests;
 mod limits;
+mod liveness;
+#[cfg(test)]
+mod liveness_tests;
 mod parser;
 #[cfg(test)]
 mod parser_tests;
+mod peephole;
+#[cfg(test)]
+mod peephole_tests;
+mod stack;
 mod tokens;
+mod verify;
+#[cfg(test)]
+mod verify_tests;
 mod vm;
 
-pub use self::vm::Vm;
+pub use self::asm::assemble;
+pub use self::binary::{FORMAT_VERSION, MAGIC};
+pub use self::dead_code::{Removed, RemovedKind};
+pub use self::debug::{DebugInfo, LineEntry, ProcInfo, VarInfo, MAIN_PROC};
+pub use self::env::{Env, Scope, ScopeId, Symbol, SymbolId, SymbolKind};
+pub use self::interpreter::Interpreter;
+pub use self::stack::{FrameUsage, StackUsage};
+pub use self::vm::{CallFrame, Vm};
 
 pub mod prelude {}
 
-pub use self::errors::{Error, Result};
+pub use self::error_codes::ErrorCode;
+pub use self::errors::{Error, Result, Stage};
 
 /// Engine configuration.
 pub struct Pl0Config {
@@ -36,27 +103,172 @@ pub struct Pl0Config {
     pub user_data: Option<Box<dyn Any>>,
 }
 
+/// Compiler configuration.
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// How much effort is spent optimizing the program.
+    ///
+    /// - `0` folds constant expressions.
+    /// - `1` also runs the peephole optimizer over the bytecode.
+    /// - `2` also propagates constants through variables, removes stores
+    ///   that are never read, inlines small procedures, and removes
+    ///   procedures that are never called and statements that can never run.
+    pub opt_level: u8,
+    /// Longest procedure body, in instructions, that is inlined at its
+    /// call sites. Only procedures that make no calls are inlined.
+    pub inline_threshold: usize,
+}
+
 /// The number type.
 ///
 /// PL/0 is a tiny language and only has the one value type.
 pub type Num = i32;
 
 /// A chunk holds an executable program.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
     pub(crate) code: Vec<bytecode::Instr>,
+    /// Numbers too large to be encoded in an instruction.
+    pub(crate) constants: Vec<Num>,
+    /// Optional mapping from bytecode to source.
+    pub(crate) debug: Option<DebugInfo>,
+    /// Stack usage, if the bytecode could be analyzed.
+    pub(crate) stack: Option<StackUsage>,
 }
 
 pub fn compile(filename: &str, text: &str) -> Result<Chunk> {
+    compile_with_env(filename, text).map(|(chunk, _)| chunk)
+}
+
+/// Compile a program, and also return the symbol table describing
+/// every constant, variable and procedure declared in it.
+pub fn compile_with_env(filename: &str, text: &str) -> Result<(Chunk, Env)> {
+    compile_with_options(filename, text, &CompileOptions::new())
+}
+
+/// Compile a program with the given options, returning the chunk and symbol table.
+pub fn compile_with_options(filename: &str, text: &str, options: &CompileOptions) -> Result<(Chunk, Env)> {
+    compile_with_report(filename, text, options).map(|(chunk, env, _)| (chunk, env))
+}
+
+/// Compile a program with the given options, and also report the
+/// code removed by dead code elimination.
+pub fn compile_with_report(filename: &str, text: &str, options: &CompileOptions) -> Result<(Chunk, Env, Vec<Removed>)> {
+    let (ir, mut env) = lower(filename, text, options)?;
+
+    let mut gen = codegen_bytecode::BytecodeGen::with_debug_info(filename, text);
+    codegen::emit_program(&ir, &mut gen)?;
+    for (idx, proc) in ir.procs.iter().enumerate() {
+        if let (Some(symbol), Some(addr)) = (proc.symbol, gen.proc_addr(ir::ProcId::new(idx))) {
+            env.set_proc_addr(symbol, addr);
+        }
+    }
+
+    let mut chunk = gen.make_chunk();
+    let mut removed = vec![];
+    if options.opt_level >= 2 {
+        let inlined = inline::inline(&mut chunk, options.inline_threshold)?;
+        env.relocate_procs(|addr| Some(inlined.addrs.get(addr)));
+        let eliminated = dead_code::eliminate(&mut chunk, &inlined.procs)?;
+        env.relocate_procs(|addr| eliminated.relocate_proc(addr));
+        removed = eliminated.report;
+    }
+    if options.opt_level >= 1 {
+        let addrs = peephole::optimize(&mut chunk);
+        env.relocate_procs(|addr| Some(addrs.get(addr)));
+    }
+    chunk.stack = Some(chunk.analyze_stack()?);
+
+    Ok((chunk, env, removed))
+}
+
+/// Compile a program into a standalone C program.
+///
+/// Only the optimizations done before code generation apply, the C
+/// compiler is left to do the rest.
+pub fn compile_to_c(filename: &str, text: &str, options: &CompileOptions) -> Result<String> {
+    let (ir, _) = lower(filename, text, options)?;
+    let mut gen = codegen_c::CGen::new();
+    codegen::emit_program(&ir, &mut gen)?;
+    Ok(gen.finish())
+}
+
+/// Compile a program into a self-contained JavaScript program.
+///
+/// The program defines a `run(io)` function, which runs it with `io.write`
+/// and `io.read` standing in for `write` and `read`.
+pub fn compile_to_js(filename: &str, text: &str, options: &CompileOptions) -> Result<String> {
+    let (ir, _) = lower(filename, text, options)?;
+    let mut gen = codegen_js::JsGen::new();
+    codegen::emit_program(&ir, &mut gen)?;
+    Ok(gen.finish())
+}
+
+/// Compile a program into x86-64 assembly for the GNU assembler, to be
+/// linked with the C library on Linux.
+pub fn compile_to_x86(filename: &str, text: &str, options: &CompileOptions) -> Result<String> {
+    let (ir, _) = lower(filename, text, options)?;
+    let mut gen = codegen_x86::X86Gen::new();
+    codegen::emit_program(&ir, &mut gen)?;
+    Ok(gen.finish())
+}
+
+/// Compile a program into an LLVM module, in the text format.
+pub fn compile_to_llvm(filename: &str, text: &str, options: &CompileOptions) -> Result<String> {
+    let (ir, _) = lower(filename, text, options)?;
+    let mut gen = codegen_llvm::LlvmGen::new();
+    codegen::emit_program(&ir, &mut gen)?;
+    Ok(gen.finish())
+}
+
+/// Compile a program into a WebAssembly module, in the text format.
+///
+/// The module imports `env.write` and `env.read`, and exports `main`.
+pub fn compile_to_wat(filename: &str, text: &str, options: &CompileOptions) -> Result<String> {
+    compile_to_wasm_module(filename, text, options).map(|module| module.to_wat())
+}
+
+/// Compile a program into a WebAssembly module, in the binary format.
+pub fn compile_to_wasm(filename: &str, text: &str, options: &CompileOptions) -> Result<Vec<u8>> {
+    compile_to_wasm_module(filename, text, options).map(|module| module.to_wasm())
+}
+
+fn compile_to_wasm_module(filename: &str, text: &str, options: &CompileOptions) -> Result<codegen_wasm::Module> {
+    let (ir, _) = lower(filename, text, options)?;
+    let mut gen = codegen_wasm::WasmGen::new();
+    codegen::emit_program(&ir, &mut gen)?;
+    Ok(gen.finish())
+}
+
+/// Parse a program and lower it into optimized IR, shared by every backend.
+fn lower(filename: &str, text: &str, options: &CompileOptions) -> Result<(ir::Program, Env)> {
     let lex = lexer::Lexer::new(text, filename);
     let mut par = parser::Parser::new(lex);
     let program = par.parse_program()?;
 
-    let mut gen = codegen_bytecode::BytecodeGen::new();
-    let mut compiler = compiler::Compiler::new(&mut gen);
-    compiler.compile(&program)?;
-    drop(compiler);
+    let mut compiler = compiler::Compiler::new(filename);
+    let mut ir = compiler.compile(&program)?;
+    let env = compiler.into_env();
+    if options.opt_level >= 2 {
+        const_prop::propagate(&mut ir);
+        liveness::eliminate_dead_stores(&mut ir);
+    }
+    Ok((ir, env))
+}
 
-    Ok(gen.make_chunk())
+impl CompileOptions {
+    pub fn new() -> Self {
+        Self {
+            opt_level: 0,
+            inline_threshold: 8,
+        }
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pl0Config {
@@ -76,11 +288,45 @@ impl Default for Pl0Config {
 }
 
 impl Chunk {
+    /// Number of instructions in the chunk.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// The chunk's constant pool.
+    pub fn constants(&self) -> &[Num] {
+        &self.constants
+    }
+
+    /// How much stack the program needs.
+    ///
+    /// Only missing for assembled or loaded chunks that fail verification.
+    pub fn stack_usage(&self) -> Option<&StackUsage> {
+        self.stack.as_ref()
+    }
+
+    /// Debug information, if the chunk was compiled with it.
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug.as_ref()
+    }
+
+    /// Source location of the instruction at `pc`, formatted as `file:line`.
+    pub fn location(&self, pc: usize) -> Option<String> {
+        self.debug.as_ref().and_then(|debug| debug.location(pc))
+    }
+
+    /// Remove the debug information from the chunk.
+    pub fn strip_debug_info(&mut self) {
+        self.debug = None;
+    }
+
     #[doc(hidden)]
     #[allow(dead_code)]
     pub fn dump(&self) {
-        for (idx, instr) in self.code.iter().enumerate() {
-            println!(" {idx:04} {instr:?}");
-        }
+        print!("{}", self.disassemble());
     }
 }
@@ -0,0 +1,122 @@
+//! Liveness of a procedure's own variables, and removal of dead stores.
+//!
+//! A variable is live when its current value may still be read. Only the
+//! variables in the procedure's own frame are tracked: they're gone once
+//! the procedure returns, while the variables of enclosing procedures
+//! outlive it. A call may read any of them, since procedures nested in
+//! this one can see its frame.
+use std::collections::BTreeSet;
+
+use crate::ir::{Block, InstKind, Proc, Program, Var};
+
+/// Offsets of the live variables in a procedure's own frame.
+pub(crate) type Live = BTreeSet<u16>;
+
+/// The variables live on entry to each block of a procedure.
+pub(crate) fn live_vars(proc: &Proc) -> Vec<Live> {
+    let all: Live = proc.vars.iter().map(|var| var.offset).collect();
+    let mut live = vec![Live::new(); proc.blocks.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // Liveness flows backwards, so visit the blocks last to first.
+        for (idx, block) in proc.blocks.iter().enumerate().rev() {
+            let mut entry = live_out(block, &live);
+            for inst in block.insts.iter().rev() {
+                transfer(&inst.kind, &mut entry, &all);
+            }
+            if entry != live[idx] {
+                live[idx] = entry;
+                changed = true;
+            }
+        }
+    }
+
+    live
+}
+
+/// Remove the stores to variables that are never read afterwards, in
+/// every procedure of the program.
+///
+/// The computation of the stored value goes with the store, unless it
+/// has an effect of its own, like reading input or failing at runtime.
+pub(crate) fn eliminate_dead_stores(program: &mut Program) {
+    for proc in program.procs.iter_mut() {
+        let all: Live = proc.vars.iter().map(|var| var.offset).collect();
+        let live = live_vars(proc);
+        for idx in 0..proc.blocks.len() {
+            let mut entry = live_out(&proc.blocks[idx], &live);
+            let block = &mut proc.blocks[idx];
+
+            let mut end = block.insts.len();
+            while end > 0 {
+                let idx = end - 1;
+                if let InstKind::Store {
+                    var: Var { level: 0, offset },
+                    ..
+                } = block.insts[idx].kind
+                {
+                    if !entry.contains(&offset) {
+                        if let Some(start) = removable_value(block, idx) {
+                            block.insts.drain(start..=idx);
+                            end = start;
+                            continue;
+                        }
+                    }
+                }
+                transfer(&block.insts[idx].kind, &mut entry, &all);
+                end = idx;
+            }
+        }
+    }
+}
+
+/// The variables live at the end of a block.
+fn live_out(block: &Block, live: &[Live]) -> Live {
+    block
+        .term
+        .successors()
+        .iter()
+        .flat_map(|succ| live[succ.index()].iter().copied())
+        .collect()
+}
+
+/// Update the live variables from after an instruction to before it.
+fn transfer(kind: &InstKind, live: &mut Live, all: &Live) {
+    match *kind {
+        InstKind::Load {
+            var: Var { level: 0, offset },
+            ..
+        } => {
+            live.insert(offset);
+        }
+        InstKind::Store {
+            var: Var { level: 0, offset },
+            ..
+        } => {
+            live.remove(&offset);
+        }
+        InstKind::Call { .. } => live.extend(all),
+        _ => {}
+    }
+}
+
+/// Find where the computation of the value stored at `store` starts, if
+/// it can be removed along with the store.
+///
+/// Temporaries are used in stack order, so the computation is the run
+/// of instructions right before the store.
+fn removable_value(block: &Block, store: usize) -> Option<usize> {
+    let mut needed = 1;
+    let mut idx = store;
+    while needed > 0 {
+        idx = idx.checked_sub(1)?;
+        let kind = &block.insts[idx].kind;
+        if !kind.is_pure() {
+            return None;
+        }
+        needed = needed - 1 + kind.uses().len();
+    }
+    Some(idx)
+}
@@ -0,0 +1,84 @@
+use crate::ir::{InstKind, Program, Var};
+use crate::ir_tests::lower;
+use crate::liveness::{eliminate_dead_stores, live_vars, Live};
+
+/// The variables stored to in every procedure, by procedure name.
+fn stores(program: &Program) -> Vec<(&str, Var)> {
+    program
+        .procs
+        .iter()
+        .flat_map(|proc| {
+            proc.blocks
+                .iter()
+                .flat_map(|block| &block.insts)
+                .filter_map(move |inst| match inst.kind {
+                    InstKind::Store { var, .. } => Some((proc.name.as_str(), var)),
+                    _ => None,
+                })
+        })
+        .collect()
+}
+
+#[test]
+fn test_live_vars() {
+    let program = lower("var x, y;\nbegin\n    read x;\n    y := 0;\n    while x > 0 do\n    begin\n        y := y + x;\n        x := x - 1\n    end;\n    write y\nend.");
+    let live = live_vars(&program.procs[0]);
+    // Nothing is live on entry, both variables are live around the loop,
+    // and only the result is live after it.
+    assert_eq!(live[0], Live::new());
+    assert_eq!(live[1], Live::from([3, 4]));
+    assert_eq!(live[3], Live::from([4]));
+}
+
+#[test]
+fn test_dead_stores() {
+    const SOURCE: &str = "var g;
+procedure p;
+    var a, b, c;
+begin
+    a := 1;
+    a := 2;
+    read b;
+    c := 10 / a;
+    g := 2
+end;
+procedure q;
+    var d;
+    procedure r;
+    begin
+        write d
+    end;
+begin
+    d := 3;
+    call r
+end;
+begin
+    call p;
+    call q
+end.";
+    let mut program = lower(SOURCE);
+    eliminate_dead_stores(&mut program);
+
+    let var = |offset| Var { level: 0, offset };
+    assert_eq!(
+        stores(&program),
+        [
+            // Only the first store to `a` is overwritten before it's read.
+            ("p", var(3)),
+            // The input has to be read, and the division may fail.
+            ("p", var(4)),
+            ("p", var(5)),
+            // The main program's variables outlive the procedure.
+            ("p", Var { level: 1, offset: 3 }),
+            // Procedures nested in this one can read its variables.
+            ("q", var(3)),
+        ]
+    );
+
+    // The store's computation goes with it.
+    let p = &program.procs[1];
+    assert!(!p.blocks[0]
+        .insts
+        .iter()
+        .any(|inst| matches!(inst.kind, InstKind::Const { value: 1, .. })));
+}
@@ -17,6 +17,8 @@ pub struct Parser<'a> {
     lexer: Lexer<'a>,
     /// The next token, or a lexical error.
     token: Option<Result<Token>>,
+    /// Span of the most recently consumed token.
+    prev_span: (u32, u32),
     /// Indicates whether the parser has encountered an error.
     has_error: bool,
     errors: Vec<Error>,
@@ -27,16 +29,21 @@ impl<'a> Parser<'a> {
         Self {
             lexer,
             token: None,
+            prev_span: (0, 0),
             has_error: false,
             errors: vec![],
         }
     }
 
     fn next_token(&mut self) -> Result<Token> {
-        match self.token.take() {
+        let token = match self.token.take() {
             Some(maybe_token) => maybe_token,
             None => self.lexer.next_token(),
+        };
+        if let Ok(token) = &token {
+            self.prev_span = token.span;
         }
+        token
     }
 
     fn peek(&mut self) -> Result<TK> {
@@ -56,13 +63,30 @@ impl<'a> Parser<'a> {
         if kind == token_kind {
             self.next_token()
         } else {
-            let err: Result<Token> = error!("parser", "{token_kind} expected; found {kind}").into();
-            if let Some(token_result) = self.token.as_ref() {
-                if let Ok(token) = token_result {
-                    return err.with_location(token.span, &self.lexer.file);
-                }
-            }
-            err
+            self.at_peek(error!(E0201, "{token_kind} expected; found {kind}").into())
+        }
+    }
+
+    /// Span from the start of the given span, up to and including
+    /// the most recently consumed token.
+    fn span_from(&self, start: (u32, u32)) -> (u32, u32) {
+        let end = self.prev_span.0 + self.prev_span.1;
+        (start.0, end.saturating_sub(start.0))
+    }
+
+    /// Location of the lookahead token, which must already have been peeked.
+    fn peek_span(&self) -> (u32, u32) {
+        match self.token.as_ref() {
+            Some(Ok(token)) => token.span,
+            _ => self.prev_span,
+        }
+    }
+
+    /// Attach the location of the lookahead token to an error result.
+    fn at_peek<T>(&self, result: Result<T>) -> Result<T> {
+        match self.token.as_ref() {
+            Some(Ok(token)) => result.with_location(token.span, &self.lexer.file),
+            _ => result,
         }
     }
 }
@@ -113,10 +137,10 @@ impl<'a> Parser<'a> {
                     break;
                 }
                 TK::Eof => {
-                    return error!("parser", "unexpected end-of-file").into();
+                    return self.at_peek(error!(E0202, "unexpected end-of-file").into());
                 }
                 kind => {
-                    return error!("parser", "expected comma or semicolon; found {kind}").into();
+                    return self.at_peek(error!(E0203, "expected comma or semicolon; found {kind}").into());
                 }
             }
         }
@@ -160,9 +184,9 @@ impl<'a> Parser<'a> {
                     break;
                 }
                 TK::Eof => {
-                    return error!("parser", "unexpected end-of-file").into();
+                    return self.at_peek(error!(E0202, "unexpected end-of-file").into());
                 }
-                kind => return error!("parser", "expected comma or semicolon; found {kind}").into(),
+                kind => return self.at_peek(error!(E0203, "expected comma or semicolon; found {kind}").into()),
             }
         }
 
@@ -207,10 +231,10 @@ impl<'a> Parser<'a> {
                     stmts.push(self.parse_stmt()?);
                 }
                 TK::Eof => {
-                    return error!("parser", "unexpected end-of-file").into();
+                    return self.at_peek(error!(E0202, "unexpected end-of-file").into());
                 }
                 kind => {
-                    return error!("parser", "expected semicolon or 'end'; found {kind}").into();
+                    return self.at_peek(error!(E0204, "expected semicolon or 'end'; found {kind}").into());
                 }
             }
         }
@@ -231,10 +255,10 @@ impl<'a> Parser<'a> {
                 KW::Begin => self.parse_begin().map(Stmt::SubBlock),
                 KW::If => self.parse_if().map(Box::new).map(Stmt::If),
                 KW::While => self.parse_while().map(Box::new).map(Stmt::While),
-                _ => error!("parser", "unexpected keyword: {kind}").into(),
+                _ => self.at_peek(error!(E0205, "unexpected keyword: {kind}").into()),
             },
-            TK::Eof => error!("parser", "unexpected end-of-file").into(),
-            _ => error!("parser", "unexpected token: {kind}").into(),
+            TK::Eof => self.at_peek(error!(E0202, "unexpected end-of-file").into()),
+            _ => self.at_peek(error!(E0205, "unexpected token: {kind}").into()),
         }
     }
 
@@ -249,62 +273,69 @@ impl<'a> Parser<'a> {
             )
         })?;
         let rhs = self.parse_expr()?;
-        Ok(AssignStmt { lhs, rhs })
+        let span = self.span_from(lhs.span);
+        Ok(AssignStmt { lhs, rhs, span })
     }
 
     fn parse_call(&mut self) -> Result<CallStmt> {
         trace!("parse_call");
-        self.consume(TK::Keyword(KW::Call))?;
+        let start = self.consume(TK::Keyword(KW::Call))?.span;
         let name = self.parse_ident()?;
-        Ok(CallStmt { name })
+        let span = self.span_from(start);
+        Ok(CallStmt { name, span })
     }
 
     fn parse_write(&mut self) -> Result<WriteStmt> {
         trace!("parse_write");
 
-        self.consume(TK::Keyword(KW::Write))?;
+        let start = self.consume(TK::Keyword(KW::Write))?.span;
         let expr = self.parse_expr()?;
+        let span = self.span_from(start);
 
-        Ok(WriteStmt { expr })
+        Ok(WriteStmt { expr, span })
     }
 
     fn parse_read(&mut self) -> Result<ReadStmt> {
         trace!("parse_read");
 
-        self.consume(TK::Keyword(KW::Read))?;
+        let start = self.consume(TK::Keyword(KW::Read))?.span;
         let name = self.parse_ident()?;
+        let span = self.span_from(start);
 
-        Ok(ReadStmt { name })
+        Ok(ReadStmt { name, span })
     }
 
     fn parse_begin(&mut self) -> Result<SubBlock> {
         trace!("parse_begin");
 
-        self.consume(TK::Keyword(KW::Begin))?;
+        let start = self.consume(TK::Keyword(KW::Begin))?.span;
         let stmts = self.parse_stmts()?;
         self.consume(TK::Keyword(KW::End))?;
+        let span = self.span_from(start);
 
-        Ok(SubBlock { stmts })
+        Ok(SubBlock { stmts, span })
     }
 
     fn parse_if(&mut self) -> Result<IfStmt> {
         trace!("parse_if");
 
-        self.consume(TK::Keyword(KW::If))?;
+        let start = self.consume(TK::Keyword(KW::If))?.span;
         let head = self.parse_cond()?;
         self.consume(TK::Keyword(KW::Then))?;
+        let span = self.span_from(start);
         let body = self.parse_stmt()?;
-        Ok(IfStmt { head, body })
+        Ok(IfStmt { head, body, span })
     }
 
     fn parse_while(&mut self) -> Result<WhileStmt> {
         trace!("parse_while");
 
-        self.consume(TK::Keyword(KW::While))?;
+        let start = self.consume(TK::Keyword(KW::While))?.span;
         let head = self.parse_cond()?;
         self.consume(TK::Keyword(KW::Do))?;
+        let span = self.span_from(start);
         let body = self.parse_stmt()?;
-        Ok(WhileStmt { head, body })
+        Ok(WhileStmt { head, body, span })
     }
 
     fn parse_cond(&mut self) -> Result<Cond> {
@@ -345,7 +376,11 @@ impl<'a> Parser<'a> {
             TK::LessEq => CondOp::LessEq,
             TK::Great => CondOp::Great,
             TK::GreatEq => CondOp::GreatEq,
-            kind => return error!("parser", "expected conditional operator; found {kind}").into(),
+            kind => {
+                return error!(E0206, "expected conditional operator; found {kind}")
+                    .with_location(token.span, &self.lexer.file)
+                    .into()
+            }
         };
         Ok(op)
     }
@@ -380,19 +415,30 @@ impl<'a> Parser<'a> {
     fn parse_expr(&mut self) -> Result<Expr> {
         trace!("parse_expr");
 
+        self.peek()?;
+        let start = self.peek_span();
+
         // Unary expression
         let mut lhs = match self.peek()? {
             TK::Plus => {
                 self.next_token()?; // +
                 self.parse_term()
-                    .map(|expr| UnExpr { op: UnOp::Pos, expr })
+                    .map(|expr| UnExpr {
+                        op: UnOp::Pos,
+                        expr,
+                        span: self.span_from(start),
+                    })
                     .map(Box::new)
                     .map(Expr::Unary)?
             }
             TK::Minus => {
                 self.next_token()?; // -
                 self.parse_term()
-                    .map(|expr| UnExpr { op: UnOp::Neg, expr })
+                    .map(|expr| UnExpr {
+                        op: UnOp::Neg,
+                        expr,
+                        span: self.span_from(start),
+                    })
                     .map(Box::new)
                     .map(Expr::Unary)?
             }
@@ -410,6 +456,7 @@ impl<'a> Parser<'a> {
                             op: BinOp::Add,
                             lhs,
                             rhs,
+                            span: self.span_from(start),
                         })
                         .map(Box::new)
                         .map(Expr::Binary)?;
@@ -422,6 +469,7 @@ impl<'a> Parser<'a> {
                             op: BinOp::Sub,
                             lhs,
                             rhs,
+                            span: self.span_from(start),
                         })
                         .map(Box::new)
                         .map(Expr::Binary)?;
@@ -436,6 +484,8 @@ impl<'a> Parser<'a> {
     fn parse_term(&mut self) -> Result<Expr> {
         trace!("parse_term");
 
+        self.peek()?;
+        let start = self.peek_span();
         let mut lhs = self.parse_factor()?;
 
         // Binary expression (zero or more)
@@ -444,11 +494,12 @@ impl<'a> Parser<'a> {
                 TK::Star => {
                     self.next_token()?; // *
                     lhs = self
-                        .parse_term()
+                        .parse_factor()
                         .map(|rhs| BinExpr {
                             op: BinOp::Mul,
                             lhs,
                             rhs,
+                            span: self.span_from(start),
                         })
                         .map(Box::new)
                         .map(Expr::Binary)?;
@@ -456,11 +507,12 @@ impl<'a> Parser<'a> {
                 TK::Slash => {
                     self.next_token()?; // /
                     lhs = self
-                        .parse_term()
+                        .parse_factor()
                         .map(|rhs| BinExpr {
                             op: BinOp::Div,
                             lhs,
                             rhs,
+                            span: self.span_from(start),
                         })
                         .map(Box::new)
                         .map(Expr::Binary)?;
@@ -479,7 +531,7 @@ impl<'a> Parser<'a> {
             TK::Ident => self.parse_ident().map(Expr::Name),
             TK::Num => self.parse_num().map(Expr::Num),
             TK::ParenLeft => self.parse_group(),
-            kind => error!("parser", "expected identifier, number or parentheses; found {kind}").into(),
+            kind => self.at_peek(error!(E0207, "expected identifier, number or parentheses; found {kind}").into()),
         }
     }
 
@@ -492,9 +544,12 @@ impl<'a> Parser<'a> {
                 let fragment = token.fragment(self.lexer.text());
                 Ok(Ident {
                     name: fragment.to_string(),
+                    span: token.span,
                 })
             }
-            kind => error!("parser", "identifier expected; found: {kind}").into(),
+            kind => error!(E0208, "identifier expected; found: {kind}")
+                .with_location(token.span, &self.lexer.file)
+                .into(),
         }
     }
 
@@ -503,9 +558,9 @@ impl<'a> Parser<'a> {
 
         let token = self.consume(TK::Num)?;
         let fragment = token.fragment(self.lexer.text());
-        let num = fragment
-            .parse::<i32>()
-            .map_err(|e| error!("parser", "failed to parse number literal: {e}"))?;
+        let num = fragment.parse::<i32>().map_err(|e| {
+            error!(E0209, "failed to parse number literal: {e}").with_location(token.span, &self.lexer.file)
+        })?;
         Ok(num)
     }
 
@@ -84,6 +84,24 @@ end.";
     println!("{program:#?}");
 }
 
+#[test]
+fn test_left_associative() {
+    const SOURCE: &str = "begin
+    write 100 / 10 / 5
+end.";
+
+    let program = parse_program(SOURCE).expect("parsing failed");
+    println!("{program:#?}");
+
+    // (100 / 10) / 5
+    let stmts = program.block.stmt.as_sub_block().unwrap().stmts.as_slice();
+    let Expr::Binary(outer) = &stmts[0].as_writeln().unwrap().expr else {
+        panic!("expression isn't binary");
+    };
+    assert!(matches!(outer.lhs, Expr::Binary(_)), "{outer:?}");
+    assert_eq!(outer.rhs.as_num(), Some(5));
+}
+
 #[test]
 fn test_procedures() {
     const SOURCE: &str = "
@@ -101,3 +119,36 @@ end.";
     let program = parse_program(SOURCE).expect("parsing failed");
     println!("{program:#?}");
 }
+
+#[test]
+fn test_print_roundtrip() {
+    const SOURCE: &str = "const k = 2147483647;
+var x, y;
+procedure p;
+    var z;
+    procedure q;
+    begin
+        z := -(x + 1) * 2;
+        y := 100 / (10 / 5) - (1 - 2)
+    end;
+call q;
+begin
+    read x;
+    if odd x then
+        write +x * (-2);
+    while x < 10 do
+    begin
+        call p;
+        x := x + 1
+    end;
+    if x # k then
+    begin
+        write x
+    end
+end.
+";
+
+    // Printed programs parse back into the same program.
+    let program = parse_program(SOURCE).expect("parsing failed");
+    assert_eq!(program.to_string(), SOURCE);
+}
@@ -0,0 +1,211 @@
+//! Peephole optimizer.
+//!
+//! Rewrites short, wasteful instruction sequences left behind by the
+//! code generator, then renumbers the remaining instructions and fixes
+//! up every jump target, procedure address and line table entry.
+use crate::bytecode::{Instr, Math, OpCode};
+use crate::Chunk;
+
+/// Limit on how many jumps are followed when collapsing a chain of
+/// jumps, in case the chain is a cycle.
+const MAX_JUMP_CHAIN: usize = 16;
+
+/// Maps instruction addresses from before an optimization pass to after.
+pub(crate) struct AddrMap {
+    /// New address of each old instruction. Removed instructions map to
+    /// the next instruction that was kept.
+    addrs: Vec<u16>,
+}
+
+impl AddrMap {
+    /// Map from the new address of each old instruction, followed by the new end of the code.
+    pub(crate) fn new(addrs: Vec<u16>) -> Self {
+        Self { addrs }
+    }
+
+    /// Follow this mapping with another one.
+    pub(crate) fn then(&self, next: &AddrMap) -> AddrMap {
+        AddrMap::new(self.addrs.iter().map(|addr| next.get(*addr)).collect())
+    }
+
+    /// New address of the instruction at the old address.
+    ///
+    /// Addresses past the end map to the new end of the code.
+    pub(crate) fn get(&self, addr: u16) -> u16 {
+        let idx = (addr as usize).min(self.addrs.len() - 1);
+        self.addrs[idx]
+    }
+}
+
+/// Run the peephole optimizer until nothing changes.
+///
+/// Returns the mapping from the original addresses to the optimized ones.
+pub(crate) fn optimize(chunk: &mut Chunk) -> AddrMap {
+    let mut total = AddrMap {
+        addrs: (0..=chunk.code.len() as u16).collect(),
+    };
+
+    loop {
+        collapse_jumps(&mut chunk.code);
+        let remove = rewrite(&mut chunk.code);
+        if !remove.contains(&true) {
+            break;
+        }
+
+        let map = remove_instrs(chunk, &remove);
+        total = total.then(&map);
+    }
+
+    total
+}
+
+/// Point jumps that land on an unconditional jump directly at its target.
+fn collapse_jumps(code: &mut [Instr]) {
+    for pc in 0..code.len() {
+        if !matches!(code[pc].opcode, OpCode::Jump | OpCode::JumpIfZero) {
+            continue;
+        }
+        let mut target = code[pc].a;
+        for _ in 0..MAX_JUMP_CHAIN {
+            match code.get(target as usize) {
+                // Jumps to address zero halt the machine, and must stay that way.
+                Some(next) if next.opcode == OpCode::Jump && target != 0 => target = next.a,
+                _ => break,
+            }
+        }
+        code[pc].a = target;
+    }
+}
+
+/// Rewrite patterns in place, and mark instructions to remove.
+fn rewrite(code: &mut [Instr]) -> Vec<bool> {
+    let targets = jump_targets(code);
+    let mut remove = vec![false; code.len()];
+
+    // The main program's entry point must stay at address zero.
+    let mut pc = 1;
+    while pc < code.len() {
+        let instr = code[pc];
+        let next = code.get(pc + 1).copied();
+        // Patterns of two instructions can only be rewritten when
+        // nothing jumps between them.
+        let pair = next.filter(|_| !targets[pc + 1]);
+
+        match (instr.opcode, pair.map(|next| next.opcode)) {
+            // Reserving no stack space.
+            (OpCode::IncTop, _) if instr.a == 0 => remove[pc] = true,
+            // Jumping to the next instruction.
+            (OpCode::Jump, _) if instr.a as usize == pc + 1 => remove[pc] = true,
+            // Adding or subtracting zero, or multiplying or dividing by one.
+            (OpCode::Lit, Some(OpCode::Math(Math::Add | Math::Sub))) if instr.a == 0 => {
+                remove[pc] = true;
+                remove[pc + 1] = true;
+                pc += 1;
+            }
+            (OpCode::Lit, Some(OpCode::Math(Math::Mul | Math::Div))) if instr.a == 1 => {
+                remove[pc] = true;
+                remove[pc + 1] = true;
+                pc += 1;
+            }
+            // Loading a variable and storing it straight back.
+            (OpCode::Load, Some(OpCode::Store))
+                if pair
+                    == Some(Instr {
+                        opcode: OpCode::Store,
+                        ..instr
+                    }) =>
+            {
+                remove[pc] = true;
+                remove[pc + 1] = true;
+                pc += 1;
+            }
+            // Storing a variable and loading it straight back keeps
+            // a copy on the stack instead.
+            (OpCode::Store, Some(OpCode::Load))
+                if pair
+                    == Some(Instr {
+                        opcode: OpCode::Load,
+                        ..instr
+                    }) =>
+            {
+                code[pc] = Instr {
+                    opcode: OpCode::Dup,
+                    l: 0,
+                    a: 0,
+                };
+                code[pc + 1] = instr;
+                pc += 1;
+            }
+            _ => {}
+        }
+        pc += 1;
+    }
+
+    remove
+}
+
+/// Addresses that control can be transferred to, other than by falling through.
+fn jump_targets(code: &[Instr]) -> Vec<bool> {
+    let mut targets = vec![false; code.len() + 1];
+    targets[0] = true;
+    for instr in code {
+        if matches!(
+            instr.opcode,
+            OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall
+        ) {
+            if let Some(target) = targets.get_mut(instr.a as usize) {
+                *target = true;
+            }
+        }
+    }
+    targets
+}
+
+/// Delete the marked instructions, and renumber everything that refers to addresses.
+///
+/// Procedures whose code was removed entirely are dropped from the debug info.
+pub(crate) fn remove_instrs(chunk: &mut Chunk, remove: &[bool]) -> AddrMap {
+    let mut addrs = Vec::with_capacity(remove.len() + 1);
+    let mut kept = 0;
+    for removed in remove {
+        addrs.push(kept);
+        if !removed {
+            kept += 1;
+        }
+    }
+    addrs.push(kept);
+    let map = AddrMap { addrs };
+
+    let mut idx = 0;
+    chunk.code.retain(|_| {
+        idx += 1;
+        !remove[idx - 1]
+    });
+    for instr in chunk.code.iter_mut() {
+        if matches!(
+            instr.opcode,
+            OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall
+        ) {
+            instr.a = map.get(instr.a);
+        }
+    }
+
+    if let Some(debug) = chunk.debug.as_mut() {
+        for entry in debug.lines.iter_mut() {
+            entry.pc = map.get(entry.pc);
+        }
+        // When all the instructions of a line were removed, the
+        // next line's entry takes over the address.
+        debug.lines.reverse();
+        debug.lines.dedup_by_key(|entry| entry.pc);
+        debug.lines.reverse();
+
+        for proc in debug.procs.iter_mut() {
+            proc.addr = map.get(proc.addr);
+            proc.end = map.get(proc.end);
+        }
+        debug.procs.retain(|proc| proc.addr < proc.end);
+    }
+
+    map
+}
@@ -0,0 +1,97 @@
+use crate::peephole::optimize;
+use crate::{assemble, compile_with_options, CompileOptions, SymbolKind};
+
+/// Optimize an assembled program, and compare it to the expected assembly.
+fn assert_optimized(source: &str, expected: &str) {
+    let mut chunk = assemble("<test>", source).expect("failed to assemble");
+    optimize(&mut chunk);
+    let expected = assemble("<test>", expected).expect("failed to assemble");
+    assert_eq!(chunk.code, expected.code, "\n{}", chunk.disassemble());
+}
+
+#[test]
+fn test_peephole_patterns() {
+    // Reserving nothing.
+    assert_optimized("int 0 3\nint 0 0\nopr 0 0", "int 0 3\nopr 0 0");
+    // Jumping to the next instruction.
+    assert_optimized("int 0 3\njmp 0 2\nopr 0 0", "int 0 3\nopr 0 0");
+    // Arithmetic identities.
+    assert_optimized(
+        "int 0 5\nlod 0 3\nlit 0 0\nopr 0 2\nlit 0 1\nopr 0 4\nsto 0 4\nopr 0 0",
+        "int 0 5\nlod 0 3\nsto 0 4\nopr 0 0",
+    );
+    // Loading a variable and storing it back, which is only found once
+    // the identities in between are gone.
+    assert_optimized(
+        "int 0 4\nlod 0 3\nlit 0 0\nopr 0 3\nsto 0 3\nopr 0 0",
+        "int 0 4\nopr 0 0",
+    );
+    // Storing a variable and loading it back.
+    assert_optimized(
+        "int 0 4\nlit 0 5\nsto 0 3\nlod 0 3\nopr 0 14\nopr 0 0",
+        "int 0 4\nlit 0 5\nopr 0 15\nsto 0 3\nopr 0 14\nopr 0 0",
+    );
+    // A different variable is left alone.
+    assert_optimized(
+        "int 0 5\nlod 0 3\nsto 0 4\nopr 0 0",
+        "int 0 5\nlod 0 3\nsto 0 4\nopr 0 0",
+    );
+}
+
+#[test]
+fn test_peephole_jump_targets() {
+    // The pair is split by a jump target, so it must be kept.
+    assert_optimized(
+        "int 0 4\nlit 0 0\njpc 0 skip\nlit 0 0\nskip: opr 0 2\nopr 0 14\nopr 0 0",
+        "int 0 4\nlit 0 0\njpc 0 skip\nlit 0 0\nskip: opr 0 2\nopr 0 14\nopr 0 0",
+    );
+    // Targets after removed instructions are renumbered.
+    assert_optimized(
+        "int 0 4\nint 0 0\nloop: lod 0 3\njpc 0 done\njmp 0 loop\ndone: opr 0 0",
+        "int 0 4\nloop: lod 0 3\njpc 0 done\njmp 0 loop\ndone: opr 0 0",
+    );
+    // Chains of jumps are collapsed, and the jump to the next
+    // instruction that's left behind is removed.
+    assert_optimized(
+        "jmp 0 a\na: jmp 0 b\nb: jmp 0 main\nmain: int 0 3\nopr 0 0",
+        "jmp 0 main\nmain: int 0 3\nopr 0 0",
+    );
+    // Calls follow the procedure to its new address.
+    assert_optimized(
+        "jmp 0 main\np: int 0 3\nint 0 0\nopr 0 0\nmain: int 0 3\ncal 0 p\nopr 0 0",
+        "jmp 0 main\np: int 0 3\nopr 0 0\nmain: int 0 3\ncal 0 p\nopr 0 0",
+    );
+}
+
+#[test]
+fn test_peephole_compiled() {
+    let sources = [
+        include_str!("../tests/conditionals.pas"),
+        include_str!("../tests/count.pas"),
+        include_str!("../tests/expressions.pas"),
+        include_str!("../tests/fibonacci.pas"),
+        include_str!("../tests/hello_world.pas"),
+        include_str!("../tests/procedures.pas"),
+        include_str!("../tests/read.pas"),
+    ];
+    let options = CompileOptions {
+        opt_level: 1,
+        ..CompileOptions::new()
+    };
+    for source in sources {
+        let (plain, _) = compile_with_options("<test>", source, &CompileOptions::new()).expect("failed to compile");
+        let (chunk, env) = compile_with_options("<test>", source, &options).expect("failed to compile");
+        chunk.verify().expect("failed to verify");
+        assert!(chunk.len() <= plain.len());
+
+        // Procedures in the symbol table and debug info agree on their new addresses.
+        let debug = chunk.debug_info().expect("debug info");
+        for proc in debug.procs.iter().filter(|proc| proc.addr != 0) {
+            let id = env.find_proc(&proc.name).expect("procedure symbol");
+            match env.symbol(id).kind {
+                SymbolKind::Proc { addr, .. } => assert_eq!(addr, Some(proc.addr), "procedure '{}'", proc.name),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
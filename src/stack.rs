@@ -0,0 +1,102 @@
+//! Static stack usage.
+use std::collections::{HashMap, HashSet};
+
+/// How much of the VM's stack a chunk uses.
+///
+/// Computed from the bytecode when a chunk is compiled, assembled or
+/// loaded, by the same analysis that [verifies](crate::Chunk::verify) it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackUsage {
+    /// Procedures sorted by entry address. The main program is at address zero.
+    pub procs: Vec<FrameUsage>,
+}
+
+/// Stack usage of a single procedure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameUsage {
+    /// Address the procedure is called at.
+    pub addr: u16,
+    /// Slots reserved with `int` for the block mark and variables.
+    pub frame: usize,
+    /// Most slots in use at once, counting the frame and operands.
+    ///
+    /// This is the cost of every call to the procedure.
+    pub max_height: usize,
+    /// Slots needed by the procedure and everything it calls,
+    /// or `None` when it can recurse.
+    pub need: Option<usize>,
+}
+
+/// A call from one procedure to another.
+pub(crate) struct CallSite {
+    pub(crate) caller: u16,
+    pub(crate) callee: u16,
+    /// Height of the caller's stack at the call, where the callee's frame starts.
+    pub(crate) height: usize,
+}
+
+impl StackUsage {
+    /// Combine the frames of the procedures with their call graph.
+    pub(crate) fn new(frames: &HashMap<u16, usize>, max_height: &HashMap<u16, usize>, calls: &[CallSite]) -> Self {
+        let needs = stack_needs(max_height, calls);
+        let mut addrs: Vec<u16> = max_height.keys().copied().collect();
+        addrs.sort_unstable();
+
+        let procs = addrs
+            .into_iter()
+            .map(|addr| FrameUsage {
+                addr,
+                frame: frames.get(&addr).copied().unwrap_or_default(),
+                max_height: max_height[&addr],
+                need: needs.get(&addr).copied().flatten(),
+            })
+            .collect();
+
+        Self { procs }
+    }
+
+    /// Slots needed to run the whole program, or `None` when it's recursive.
+    pub fn max(&self) -> Option<usize> {
+        self.proc(0).and_then(|proc| proc.need)
+    }
+
+    /// Find the procedure with the given entry address.
+    pub fn proc(&self, addr: u16) -> Option<&FrameUsage> {
+        self.procs.iter().find(|proc| proc.addr == addr)
+    }
+}
+
+/// Longest paths through the call graph, adding up the stack heights at each call.
+///
+/// Tail calls happen at height zero, so a cycle of tail calls doesn't grow
+/// the stack. Any other cycle does, so the procedures on it, and all their
+/// callers, have no bound.
+fn stack_needs(max_height: &HashMap<u16, usize>, calls: &[CallSite]) -> HashMap<u16, Option<usize>> {
+    let mut needs = max_height.clone();
+    let mut unbounded = HashSet::new();
+
+    // Without growing cycles, the longest paths settle within one round per
+    // procedure. Whatever still grows after that is on, or calls, such a cycle,
+    // which the same number of rounds again spreads to every caller.
+    let rounds = max_height.len() + 1;
+    for round in 0..rounds * 2 {
+        for call in calls {
+            if unbounded.contains(&call.callee) {
+                unbounded.insert(call.caller);
+            }
+            let need = call.height + needs.get(&call.callee).copied().unwrap_or_default();
+            let caller = needs.entry(call.caller).or_default();
+            if need > *caller {
+                *caller = need;
+                if round >= rounds {
+                    unbounded.insert(call.caller);
+                }
+            }
+        }
+    }
+
+    needs
+        .into_iter()
+        .map(|(addr, need)| (addr, Some(need).filter(|_| !unbounded.contains(&addr))))
+        .collect()
+}
@@ -0,0 +1,400 @@
+//! Bytecode verifier.
+//!
+//! The VM trusts that the bytecode it runs is well formed. Chunks from
+//! the compiler are, but chunks that were hand-written or loaded from
+//! disk may jump out of the code, address variables outside their frame,
+//! or unbalance the stack.
+//!
+//! The verifier follows every path through the program, starting at
+//! the main program and following calls into procedures, and tracks
+//! the height of the current frame's stack at each instruction.
+use std::collections::HashMap;
+
+use crate::bytecode::{Instr, Math, OpCode};
+use crate::errors::{Error, Result};
+use crate::limits::{CODE_SIZE, DATA_OFFSET, STACK_SIZE};
+use crate::stack::{CallSite, StackUsage};
+use crate::{error, Chunk};
+
+impl Chunk {
+    /// Check that the bytecode is safe to run.
+    ///
+    /// Verifies that jump and call targets are inside the code, that operands
+    /// are in range, that the stack height is the same on every path to an
+    /// instruction and never drops below the frame, and that the deepest
+    /// chain of calls fits on the stack when the program isn't recursive.
+    pub fn verify(&self) -> Result<()> {
+        let usage = self.analyze_stack()?;
+
+        // The main program's frame starts at index one.
+        match usage.max() {
+            Some(need) if need >= STACK_SIZE => Err(self.locate_verify_error((
+                0,
+                error!(
+                    E0705,
+                    "the program needs {need} stack slots, but only {} are available",
+                    STACK_SIZE - 1
+                ),
+            ))),
+            // Recursive programs have no bound, and fail at runtime instead.
+            _ => Ok(()),
+        }
+    }
+
+    /// Follow every path through the program, and measure its stack usage.
+    pub(crate) fn analyze_stack(&self) -> Result<StackUsage> {
+        let mut verifier = Verifier::new(self);
+        verifier.run().map_err(|err| self.locate_verify_error(err))?;
+        Ok(StackUsage::new(&verifier.frames, &verifier.max_height, &verifier.calls))
+    }
+
+    /// Follow every path through the program, and find out which
+    /// procedure each instruction belongs to.
+    pub(crate) fn flow(&self) -> Result<Flow> {
+        let mut verifier = Verifier::new(self);
+        verifier.run().map_err(|err| self.locate_verify_error(err))?;
+        let procs = verifier
+            .states
+            .iter()
+            .map(|state| state.as_ref().map(|state| *state.chain.last().unwrap_or(&0)))
+            .collect();
+        Ok(Flow {
+            procs,
+            frames: verifier.frames,
+            reserves: verifier.reserves,
+        })
+    }
+
+    /// Attach the location of the offending instruction to a verifier error.
+    fn locate_verify_error(&self, (pc, err): (usize, Error)) -> Error {
+        let err = err.with_note(format!("at bytecode address {pc:04}"));
+        match self
+            .debug
+            .as_ref()
+            .and_then(|debug| Some((debug, debug.line_entry(pc)?)))
+        {
+            Some((debug, entry)) => err.with_line_entry(entry, &debug.file),
+            None => err,
+        }
+    }
+}
+
+/// Control flow of a chunk, as found by the verifier.
+pub(crate) struct Flow {
+    /// Entry address of the procedure each instruction belongs to,
+    /// or `None` when the instruction can never be executed.
+    pub(crate) procs: Vec<Option<u16>>,
+    /// Size of each procedure's frame, by entry address.
+    pub(crate) frames: HashMap<u16, usize>,
+    /// Address of the `int` instruction reserving each procedure's frame, by entry address.
+    pub(crate) reserves: HashMap<u16, usize>,
+}
+
+/// Abstract machine state before executing an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    /// Entry addresses of the procedures along the static chain,
+    /// from the main program to the current procedure.
+    chain: Vec<u16>,
+    /// Number of stack slots in use by the current frame.
+    height: usize,
+    /// Size of the current frame's block mark and variables, once
+    /// it has been reserved with `int`.
+    frame: Option<usize>,
+}
+
+struct Verifier<'a> {
+    code: &'a [Instr],
+    constants: usize,
+    /// State before each reachable instruction.
+    states: Vec<Option<State>>,
+    /// Size of each procedure's frame, by entry address.
+    frames: HashMap<u16, usize>,
+    /// Address of the `int` instruction reserving each procedure's frame.
+    reserves: HashMap<u16, usize>,
+    /// Highest stack height reached in each procedure, by entry address.
+    max_height: HashMap<u16, usize>,
+    calls: Vec<CallSite>,
+}
+
+/// Verifier errors paired with the address of the offending instruction.
+type VerifyResult<T> = std::result::Result<T, (usize, Error)>;
+
+impl<'a> Verifier<'a> {
+    fn new(chunk: &'a Chunk) -> Self {
+        Self {
+            code: &chunk.code,
+            constants: chunk.constants.len(),
+            states: vec![None; chunk.code.len()],
+            frames: HashMap::new(),
+            reserves: HashMap::new(),
+            max_height: HashMap::new(),
+            calls: vec![],
+        }
+    }
+
+    fn run(&mut self) -> VerifyResult<()> {
+        if self.code.len() > CODE_SIZE {
+            return Err((
+                CODE_SIZE,
+                error!(E0706, "chunk has too many instructions ({})", self.code.len())
+                    .with_note(format!("the limit is {CODE_SIZE}")),
+            ));
+        }
+
+        let entry = State {
+            chain: vec![0],
+            height: 0,
+            frame: None,
+        };
+        let mut worklist = vec![(0, entry)];
+
+        while let Some((pc, state)) = worklist.pop() {
+            if pc >= self.code.len() {
+                return Err((
+                    pc.saturating_sub(1),
+                    error!(E0701, "execution runs past the end of the code"),
+                ));
+            }
+
+            match &self.states[pc] {
+                Some(seen) if *seen == state => continue,
+                Some(seen) => return Err((pc, inconsistent(seen, &state))),
+                None => self.states[pc] = Some(state.clone()),
+            }
+
+            let instr = self.code[pc];
+            self.check_operands(pc, &instr, &state)?;
+            let next = self.step(pc, &instr, state)?;
+
+            let proc = *next.chain.last().unwrap_or(&0);
+            let max = self.max_height.entry(proc).or_default();
+            *max = (*max).max(next.height);
+
+            // Jumping to address zero halts the machine.
+            match instr.opcode {
+                OpCode::Return => {}
+                OpCode::Jump if instr.a == 0 => {}
+                OpCode::Jump => worklist.push((instr.a as usize, next)),
+                OpCode::JumpIfZero => {
+                    if instr.a != 0 {
+                        worklist.push((instr.a as usize, next.clone()));
+                    }
+                    worklist.push((pc + 1, next));
+                }
+                OpCode::Call => {
+                    let callee = self.enter_proc(pc, &instr, &next)?;
+                    worklist.push((instr.a as usize, callee));
+                    worklist.push((pc + 1, next));
+                }
+                // The callee returns to the caller of the current procedure.
+                OpCode::TailCall => {
+                    let callee = self.enter_proc(pc, &instr, &next)?;
+                    worklist.push((instr.a as usize, callee));
+                }
+                _ => worklist.push((pc + 1, next)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check operands that don't depend on the flow of the program.
+    fn check_operands(&self, pc: usize, instr: &Instr, state: &State) -> VerifyResult<()> {
+        let Instr { opcode, l, a } = *instr;
+
+        let uses_level = matches!(opcode, OpCode::Load | OpCode::Store | OpCode::Call | OpCode::TailCall);
+        if !uses_level && l != 0 {
+            return Err((
+                pc,
+                error!(E0702, "level operand of '{}' must be zero", opcode.mnemonic()),
+            ));
+        }
+
+        let uses_arg = !matches!(
+            opcode,
+            OpCode::NoOp | OpCode::Return | OpCode::Math(_) | OpCode::Write | OpCode::Read | OpCode::Dup
+        );
+        if !uses_arg && a != 0 {
+            return Err((pc, error!(E0702, "argument of '{}' must be zero", opcode.mnemonic())));
+        }
+
+        let has_target = matches!(
+            opcode,
+            OpCode::Jump | OpCode::JumpIfZero | OpCode::Call | OpCode::TailCall
+        );
+        if has_target && a as usize >= self.code.len() {
+            return Err((pc, error!(E0701, "target address {a:04} is past the end of the code")));
+        }
+
+        if opcode == OpCode::Const && a as usize >= self.constants {
+            return Err((pc, error!(E0702, "constant #{a} is not in the constant pool")));
+        }
+
+        // The frame is reused by the callee, so its static link can't point to it.
+        if opcode == OpCode::TailCall && l == 0 {
+            return Err((
+                pc,
+                error!(
+                    E0702,
+                    "tail call at level 0 would overwrite the callee's enclosing frame"
+                ),
+            ));
+        }
+
+        if uses_level && l as usize >= state.chain.len() {
+            return Err((
+                pc,
+                error!(
+                    E0702,
+                    "level {l} is deeper than the static chain of {} procedures",
+                    state.chain.len()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Apply an instruction's effect on the stack.
+    fn step(&mut self, pc: usize, instr: &Instr, mut state: State) -> VerifyResult<State> {
+        let (pops, pushes) = match instr.opcode {
+            OpCode::NoOp | OpCode::Jump => return Ok(state),
+            OpCode::IncTop => {
+                state.height += instr.a as usize;
+                if state.frame.is_none() {
+                    self.reserve_frame(pc, &state)?;
+                    state.frame = Some(state.height);
+                }
+                return Ok(state);
+            }
+            OpCode::Lit | OpCode::Const | OpCode::Load | OpCode::Read => (0, 1),
+            OpCode::Store | OpCode::JumpIfZero | OpCode::Write => (1, 0),
+            OpCode::Math(Math::Neg | Math::Odd) => (1, 1),
+            OpCode::Dup => (1, 2),
+            OpCode::Math(_) => (2, 1),
+            OpCode::Call | OpCode::TailCall | OpCode::Return => (0, 0),
+        };
+
+        let Some(frame) = state.frame else {
+            return Err((pc, error!(E0703, "the frame is used before it is reserved with 'int'")));
+        };
+        if state.height < frame + pops {
+            return Err((
+                pc,
+                error!(
+                    E0703,
+                    "'{}' takes {pops} operands, but the stack has {}",
+                    instr.opcode.mnemonic(),
+                    state.height - frame
+                ),
+            ));
+        }
+
+        if matches!(instr.opcode, OpCode::Load | OpCode::Store) {
+            self.check_variable(pc, instr, &state)?;
+        }
+
+        state.height = state.height - pops + pushes;
+        Ok(state)
+    }
+
+    /// Record the frame size of the current procedure.
+    fn reserve_frame(&mut self, pc: usize, state: &State) -> VerifyResult<()> {
+        if state.height < DATA_OFFSET {
+            return Err((
+                pc,
+                error!(
+                    E0702,
+                    "frame of {} slots has no room for the block mark of {DATA_OFFSET}", state.height
+                ),
+            ));
+        }
+        let proc = *state.chain.last().unwrap_or(&0);
+        self.reserves.entry(proc).or_insert(pc);
+        match self.frames.insert(proc, state.height) {
+            Some(frame) if frame != state.height => Err((
+                pc,
+                error!(
+                    E0704,
+                    "frame is reserved with {} slots, but with {frame} slots on another path", state.height
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that a variable access is inside the target frame.
+    fn check_variable(&self, pc: usize, instr: &Instr, state: &State) -> VerifyResult<()> {
+        let depth = state.chain.len() - 1;
+        let owner = state.chain[depth - instr.l as usize];
+        let frame = if instr.l == 0 {
+            state.frame
+        } else {
+            self.frames.get(&owner).copied()
+        };
+        let frame = frame.unwrap_or_default();
+
+        let offset = instr.a as usize;
+        if offset < DATA_OFFSET || offset >= frame {
+            return Err((
+                pc,
+                error!(
+                    E0702,
+                    "offset {offset} is outside the variables of the frame at level {}", instr.l
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// State at the entry of a called procedure.
+    fn enter_proc(&mut self, pc: usize, instr: &Instr, caller: &State) -> VerifyResult<State> {
+        let depth = caller.chain.len() - 1;
+        let mut chain = caller.chain[..=depth - instr.l as usize].to_vec();
+        chain.push(instr.a);
+
+        // Jumping to address zero would halt the machine instead.
+        if instr.a == 0 {
+            return Err((pc, error!(E0702, "the main program can't be called")));
+        }
+
+        // A tail call's callee takes over the caller's frame.
+        let height = match instr.opcode {
+            OpCode::TailCall => 0,
+            _ => caller.height,
+        };
+        self.calls.push(CallSite {
+            caller: *caller.chain.last().unwrap_or(&0),
+            callee: instr.a,
+            height,
+        });
+
+        Ok(State {
+            chain,
+            height: 0,
+            frame: None,
+        })
+    }
+}
+
+fn inconsistent(seen: &State, state: &State) -> Error {
+    if seen.chain != state.chain {
+        error!(
+            E0704,
+            "instruction is reached from procedures at {:04} and {:04}",
+            seen.chain.last().unwrap_or(&0),
+            state.chain.last().unwrap_or(&0)
+        )
+    } else if seen.frame != state.frame {
+        error!(
+            E0704,
+            "instruction is reached both before and after the frame is reserved"
+        )
+    } else {
+        error!(
+            E0704,
+            "stack height is {} on one path, but {} on another", seen.height, state.height
+        )
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{assemble, compile, ErrorCode, Stage};
+
+/// Assemble a program and return the code of the verifier error.
+fn verify_err(source: &str) -> ErrorCode {
+    let chunk = assemble("<test>", source).expect("failed to assemble");
+    match chunk.verify() {
+        Ok(()) => panic!("verified invalid program:\n{source}"),
+        Err(err) => {
+            assert_eq!(err.stage(), Stage::Verifier);
+            err.code()
+        }
+    }
+}
+
+#[test]
+fn test_verify_compiled() {
+    let sources = [
+        include_str!("../tests/conditionals.pas"),
+        include_str!("../tests/count.pas"),
+        include_str!("../tests/expressions.pas"),
+        include_str!("../tests/fibonacci.pas"),
+        include_str!("../tests/hello_world.pas"),
+        include_str!("../tests/procedures.pas"),
+        include_str!("../tests/read.pas"),
+    ];
+    for source in sources {
+        let chunk = compile("<test>", source).expect("failed to compile");
+        chunk.verify().expect("failed to verify");
+    }
+}
+
+#[test]
+fn test_verify_recursion() {
+    const SOURCE: &str = r"
+var n;
+procedure countdown;
+begin
+    if n > 0 then
+    begin
+        n := n - 1;
+        call countdown
+    end
+end;
+begin
+    n := 10;
+    call countdown
+end.
+    ";
+    let chunk = compile("<test>", SOURCE).expect("failed to compile");
+    chunk.verify().expect("failed to verify");
+}
+
+#[test]
+fn test_verify_control_flow() {
+    // Jump past the end.
+    assert_eq!(verify_err("int 0 3\njmp 0 9"), ErrorCode::E0701);
+    // Falls off the end.
+    assert_eq!(verify_err("int 0 3\nlit 0 1\nopr 0 14"), ErrorCode::E0701);
+    // Calls the main program.
+    assert_eq!(verify_err("int 0 3\ncal 0 0\nopr 0 0"), ErrorCode::E0702);
+}
+
+#[test]
+fn test_verify_operands() {
+    // Level deeper than the static chain.
+    assert_eq!(verify_err("int 0 4\nlod 1 3\nopr 0 14\nopr 0 0"), ErrorCode::E0702);
+    // Variable in the block mark.
+    assert_eq!(verify_err("int 0 4\nlod 0 2\nopr 0 14\nopr 0 0"), ErrorCode::E0702);
+    // Variable past the end of the frame.
+    assert_eq!(verify_err("int 0 4\nlit 0 1\nsto 0 4\nopr 0 0"), ErrorCode::E0702);
+    // Frame without room for the block mark.
+    assert_eq!(verify_err("int 0 2\nopr 0 0"), ErrorCode::E0702);
+    // Unused operand.
+    assert_eq!(verify_err("int 0 3\nlit 1 1\nopr 0 14\nopr 0 0"), ErrorCode::E0702);
+
+    // Variables of the enclosing procedure are found through the static chain.
+    let chunk = assemble(
+        "<test>",
+        "
+main:   jmp 0 body
+proc:   int 0 3
+        lod 1 4
+        opr 0 14
+        opr 0 0
+body:   int 0 5
+        cal 0 proc
+        opr 0 0
+",
+    )
+    .expect("failed to assemble");
+    chunk.verify().expect("failed to verify");
+}
+
+#[test]
+fn test_verify_tail_calls() {
+    // Tail call that would overwrite the callee's enclosing frame.
+    assert_eq!(
+        verify_err(
+            "main: jmp 0 body
+p: int 0 3
+tcl 0 p
+body: int 0 3
+cal 0 p
+opr 0 0"
+        ),
+        ErrorCode::E0702
+    );
+
+    // A procedure that tail calls itself runs in a single frame.
+    let chunk = assemble(
+        "<test>",
+        "
+main:   jmp 0 body
+loop:   int 0 3
+        lod 1 3
+        jpc 0 done
+        tcl 1 loop
+done:   opr 0 0
+body:   int 0 4
+        cal 0 loop
+        opr 0 0
+",
+    )
+    .expect("failed to assemble");
+    chunk.verify().expect("failed to verify");
+    let usage = chunk.stack_usage().expect("missing stack usage");
+    assert_eq!(usage.proc(1).and_then(|proc| proc.need), Some(4));
+    assert_eq!(usage.max(), Some(8));
+
+    // But not when it also calls itself normally.
+    let chunk = assemble(
+        "<test>",
+        "
+main:   jmp 0 body
+loop:   int 0 3
+        lod 1 3
+        jpc 0 done
+        cal 1 loop
+        tcl 1 loop
+done:   opr 0 0
+body:   int 0 4
+        cal 0 loop
+        opr 0 0
+",
+    )
+    .expect("failed to assemble");
+    chunk.verify().expect("failed to verify");
+    let usage = chunk.stack_usage().expect("missing stack usage");
+    assert_eq!(usage.proc(1).and_then(|proc| proc.need), None);
+    assert_eq!(usage.max(), None);
+}
+
+#[test]
+fn test_verify_stack() {
+    // Pops more than was pushed.
+    assert_eq!(verify_err("int 0 3\nlit 0 1\nopr 0 2\nopr 0 0"), ErrorCode::E0703);
+    // Uses the frame before reserving it.
+    assert_eq!(verify_err("lit 0 1\nopr 0 14\nopr 0 0"), ErrorCode::E0703);
+    // Paths meet with different heights.
+    assert_eq!(
+        verify_err("int 0 3\nlit 0 1\njpc 0 done\nlit 0 2\ndone: opr 0 0"),
+        ErrorCode::E0704
+    );
+    // Loop that grows the stack.
+    assert_eq!(verify_err("int 0 3\nloop: lit 0 1\njmp 0 loop"), ErrorCode::E0704);
+    // Frame too large for the stack.
+    assert_eq!(verify_err("int 0 600\nopr 0 0"), ErrorCode::E0705);
+}
+
+#[test]
+fn test_verify_location() {
+    let mut chunk = compile("<test>", "var x;\nbegin\n  x := 1;\n  write x\nend.").expect("failed to compile");
+    // Corrupt the store's level.
+    let pc = chunk
+        .code
+        .iter()
+        .position(|instr| instr.opcode == crate::bytecode::OpCode::Store)
+        .unwrap();
+    chunk.code[pc].l = 3;
+
+    let err = chunk.verify().expect_err("verified invalid program");
+    assert_eq!(err.code(), ErrorCode::E0702);
+    let json = err.json("var x;\nbegin\n  x := 1;\n  write x\nend.").to_string();
+    assert!(json.contains("\"line\":3"), "{json}");
+    assert!(json.contains(&format!("at bytecode address {pc:04}")), "{json}");
+}
+
+#[test]
+fn test_verify_size() {
+    // Chunks from the assembler and loader never have more instructions
+    // than the VM holds, but ones put together in memory might.
+    let mut chunk = assemble("<test>", "opr 0 0").expect("failed to assemble");
+    chunk.code.resize(crate::limits::CODE_SIZE + 1, chunk.code[0]);
+    let err = chunk.verify().expect_err("verified too many instructions");
+    assert_eq!(err.code(), ErrorCode::E0706);
+}
@@ -2,8 +2,12 @@ use std::any::Any;
 use std::io::{self, BufRead};
 
 use crate::bytecode::{Instr, Math, OpCode};
+use crate::error_codes::ErrorCode;
+use crate::errors::{Error, Result};
+#[cfg(feature = "jit")]
+use crate::jit;
 use crate::limits::*;
-use crate::{Chunk, Num, Pl0Config};
+use crate::{error, Chunk, Num, Pl0Config};
 
 macro_rules! trace {
     ($($arg:tt)*) => {
@@ -25,13 +29,36 @@ pub struct Vm {
     /// Index to the top of the stack.
     top: usize,
     /// Operand stack.
-    stack: [i32; STACK_SIZE],
+    ///
+    /// Sized for each chunk that is run, up to [`STACK_SIZE`].
+    stack: Vec<i32>,
     /// Executable bytecode.
     code: [Instr; CODE_SIZE],
     /// User injected callbacks and data.
     config: Pl0Config,
+    /// Whether chunks are compiled to native code before they're run.
+    #[cfg(feature = "jit")]
+    jit: bool,
+}
+
+/// Snapshot of an active procedure call, for debugging.
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    /// Name of the procedure, if the chunk has debug info.
+    pub proc: Option<String>,
+    /// Address of the instruction being executed in this frame.
+    pub pc: usize,
+    /// Index of the frame's block mark on the stack.
+    pub base: usize,
+    /// Source location of `pc`, formatted as `file:line`.
+    pub location: Option<String>,
+    /// Names and current values of the frame's variables.
+    pub vars: Vec<(String, Num)>,
 }
 
+/// Maximum number of call frames listed in a runtime error.
+const BACKTRACE_NOTES: usize = 8;
+
 /// Default `write <expr>` handler.
 pub(crate) fn default_write(_user_data: Option<&dyn Any>, arg: Num) {
     println!("{arg}");
@@ -68,18 +95,42 @@ impl Vm {
             pc: 0,
             base: 0,
             top: 0,
-            stack: [0; STACK_SIZE],
+            stack: vec![],
             code: [Instr::default(); CODE_SIZE],
             config,
+            #[cfg(feature = "jit")]
+            jit: true,
         }
     }
 
-    pub fn eval(&mut self, chunk: &Chunk) {
+    /// Choose whether chunks are compiled to native code before they're
+    /// run, which is the default. Chunks that can't be compiled are always
+    /// interpreted.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.jit = enabled;
+    }
+
+    /// Run a chunk.
+    ///
+    /// The bytecode is trusted to be well formed. Chunks that weren't
+    /// produced by the compiler should be checked with [`Chunk::verify`] first.
+    ///
+    /// With the `jit` feature, chunks that verify are compiled to native
+    /// code and run that way, and the interpreter runs the rest.
+    pub fn eval(&mut self, chunk: &Chunk) -> Result<()> {
         // Initialise the machine to execute the top level program.
         self.top = 0;
         self.base = 1;
         self.pc = 0;
-        self.stack.fill(0);
+        // Programs without recursion get exactly the stack they need.
+        // The main program's frame starts at index one.
+        let stack_size = match chunk.stack.as_ref().and_then(|usage| usage.max()) {
+            Some(need) => (need + 1).min(STACK_SIZE),
+            None => STACK_SIZE,
+        };
+        self.stack.clear();
+        self.stack.resize(stack_size, 0);
 
         self.code.fill(Instr::default());
 
@@ -87,13 +138,154 @@ impl Vm {
             self.code[idx] = *instr;
         }
 
-        run_interpreter(self)
+        #[cfg(feature = "jit")]
+        if self.jit && !cfg!(feature = "trace_opcodes") {
+            if let Some(native) = jit::NativeCode::compile(chunk) {
+                let mut regs = jit::Registers {
+                    pc: self.pc,
+                    base: self.base,
+                    top: self.top,
+                };
+                let exit = native.run(&mut self.stack, &self.config, &mut regs);
+                (self.pc, self.base, self.top) = (regs.pc, regs.base, regs.top);
+                match exit {
+                    jit::Exit::Halted => return Ok(()),
+                    jit::Exit::Failed(err) => return Err(self.locate_error(err, chunk)),
+                    jit::Exit::Interpret => {}
+                }
+            }
+        }
+
+        run_interpreter(self, chunk).map_err(|err| self.locate_error(err, chunk))
+    }
+
+    /// Walk the chain of active procedure calls, innermost first.
+    ///
+    /// Only meaningful after [`Vm::eval`] stopped on a runtime error,
+    /// and `chunk` must be the chunk that was evaluated.
+    pub fn backtrace(&self, chunk: &Chunk) -> Vec<CallFrame> {
+        let mut frames = vec![];
+        // The program counter has already moved past the faulting instruction.
+        let (mut pc, mut base) = (self.pc.wrapping_sub(1) & (CODE_SIZE - 1), self.base);
+
+        // Bound the walk in case the stack was corrupted.
+        while frames.len() < self.stack.len() && base + DATA_OFFSET <= self.stack.len() {
+            let proc = chunk.debug.as_ref().and_then(|debug| debug.proc_at(pc));
+            let vars = proc
+                .map(|proc| {
+                    proc.vars
+                        .iter()
+                        .filter(|var| base + (var.offset as usize) < self.stack.len())
+                        .map(|var| (var.name.clone(), self.stack[base + var.offset as usize]))
+                        .collect()
+                })
+                .unwrap_or_default();
+            frames.push(CallFrame {
+                proc: proc.map(|proc| proc.name.clone()),
+                pc,
+                base,
+                location: chunk.location(pc),
+                vars,
+            });
+
+            // Follow the dynamic link back to the caller. The main
+            // program's return address of zero halts the machine.
+            let return_addr = self.stack[base + 2] as usize;
+            if return_addr == 0 {
+                break;
+            }
+            pc = return_addr - 1;
+            base = self.stack[base + 1] as usize;
+        }
+
+        frames
+    }
+
+    /// Attach the source location of the faulting instruction,
+    /// and a dump of the call frames, to a runtime error.
+    fn locate_error(&self, err: Error, chunk: &Chunk) -> Error {
+        let frames = self.backtrace(chunk);
+        let pc = frames.first().map(|frame| frame.pc).unwrap_or_default();
+        let mut err = err.with_note(format!("at bytecode address {pc:04}"));
+
+        for (idx, frame) in frames.iter().take(BACKTRACE_NOTES).enumerate() {
+            let mut note = if idx == 0 { "in" } else { "called from" }.to_string();
+            note.push_str(&format!(" '{}'", frame.proc.as_deref().unwrap_or("?")));
+            if let Some(location) = &frame.location {
+                note.push_str(&format!(" at {location}"));
+            }
+            for (var_idx, (name, value)) in frame.vars.iter().enumerate() {
+                let sep = if var_idx == 0 { " where" } else { "," };
+                note.push_str(&format!("{sep} {name} = {value}"));
+            }
+            err = err.with_note(note);
+        }
+        if frames.len() > BACKTRACE_NOTES {
+            err = err.with_note(format!("... and {} more frames", frames.len() - BACKTRACE_NOTES));
+        }
+
+        if err.code == ErrorCode::E0402 {
+            if let Some(note) = self.call_cost_note(chunk) {
+                err = err.with_note(note);
+            }
+        }
+
+        match chunk.debug.as_ref() {
+            Some(debug) => match debug.line_entry(pc) {
+                Some(entry) => err.with_line_entry(entry, &debug.file),
+                None => err,
+            },
+            None => err,
+        }
+    }
+
+    /// Describe the stack cost of the procedure that overflowed the stack.
+    fn call_cost_note(&self, chunk: &Chunk) -> Option<String> {
+        let pc = self.pc.wrapping_sub(1) & (CODE_SIZE - 1);
+        let addr = match chunk.debug.as_ref().and_then(|debug| debug.proc_at(pc)) {
+            Some(proc) => proc.addr,
+            // Without debug info, the call instruction just before the return
+            // address holds the procedure's address. Tail calls may have
+            // replaced the procedure since, so they can't be trusted.
+            None => {
+                let return_addr = *self.stack.get(self.base + 2)? as usize;
+                let call = chunk.code.get(return_addr.checked_sub(1)?)?;
+                if call.opcode != OpCode::Call {
+                    return None;
+                }
+                call.a
+            }
+        };
+        // The main program isn't called.
+        if addr == 0 {
+            return None;
+        }
+        let usage = chunk.stack.as_ref()?.proc(addr)?;
+
+        let name = match chunk.debug.as_ref().and_then(|debug| debug.proc_by_addr(addr)) {
+            Some(proc) => format!("'{}'", proc.name),
+            None => format!("the procedure at {addr:04}"),
+        };
+        Some(format!(
+            "each call to {name} uses up to {} stack slots, and the stack has {}",
+            usage.max_height,
+            self.stack.len()
+        ))
     }
 
     fn user_data(&self) -> Option<&dyn Any> {
         self.config.user_data.as_deref()
     }
 
+    /// Ensure the stack has room for `n` more values above the top.
+    #[inline(always)]
+    fn check_stack(&self, n: usize) -> Result<()> {
+        if self.top + n >= self.stack.len() {
+            return error!(E0402, "stack overflow").into();
+        }
+        Ok(())
+    }
+
     /// Find stack base `level` levels down.
     fn find_base(&self, level: u8) -> usize {
         let (mut base, mut l) = (self.base, level);
@@ -112,10 +304,18 @@ impl Default for Vm {
 }
 
 #[inline(always)]
-fn run_interpreter(vm: &mut Vm) {
+fn run_interpreter(vm: &mut Vm, chunk: &Chunk) -> Result<()> {
     assert!(CODE_SIZE.is_power_of_two(), "pc wrapping relies on bitwise mask");
 
     loop {
+        if cfg!(feature = "trace_opcodes") {
+            if let Some(debug) = chunk.debug.as_ref() {
+                if let Some(entry) = debug.line_entry(vm.pc).filter(|entry| entry.pc as usize == vm.pc) {
+                    println!("     --> {}:{}", debug.file, entry.line);
+                }
+            }
+        }
+
         let Instr { opcode, l, a } = vm.code[vm.pc];
         vm.pc = (vm.pc + 1) & (CODE_SIZE - 1);
 
@@ -123,9 +323,17 @@ fn run_interpreter(vm: &mut Vm) {
             OpCode::NoOp => { /* Only pc is increased */ }
             OpCode::Lit => {
                 trace!("{:04} lit {}", vm.pc, a as i32);
+                vm.check_stack(1)?;
                 vm.top += 1;
                 vm.stack[vm.top] = a as i32;
             }
+            OpCode::Const => {
+                let num = chunk.constants[a as usize];
+                trace!("{:04} const {}", vm.pc, num);
+                vm.check_stack(1)?;
+                vm.top += 1;
+                vm.stack[vm.top] = num;
+            }
             OpCode::Return => {
                 trace!("{:04} return", vm.pc);
                 vm.top = vm.base - 1;
@@ -135,27 +343,30 @@ fn run_interpreter(vm: &mut Vm) {
             OpCode::Math(m) => match m {
                 Math::Neg => {
                     trace!("{:04} neg", vm.pc);
-                    vm.stack[vm.top] = -vm.stack[vm.top];
+                    vm.stack[vm.top] = vm.stack[vm.top].wrapping_neg();
                 }
                 Math::Add => {
                     trace!("{:04} add", vm.pc);
                     vm.top -= 1;
-                    vm.stack[vm.top] += vm.stack[vm.top + 1];
+                    vm.stack[vm.top] = vm.stack[vm.top].wrapping_add(vm.stack[vm.top + 1]);
                 }
                 Math::Sub => {
                     trace!("{:04} sub", vm.pc);
                     vm.top -= 1;
-                    vm.stack[vm.top] -= vm.stack[vm.top + 1];
+                    vm.stack[vm.top] = vm.stack[vm.top].wrapping_sub(vm.stack[vm.top + 1]);
                 }
                 Math::Mul => {
                     trace!("{:04} mul", vm.pc);
                     vm.top -= 1;
-                    vm.stack[vm.top] *= vm.stack[vm.top + 1];
+                    vm.stack[vm.top] = vm.stack[vm.top].wrapping_mul(vm.stack[vm.top + 1]);
                 }
                 Math::Div => {
                     trace!("{:04} div", vm.pc);
                     vm.top -= 1;
-                    vm.stack[vm.top] /= vm.stack[vm.top + 1];
+                    if vm.stack[vm.top + 1] == 0 {
+                        return error!(E0401, "division by zero").into();
+                    }
+                    vm.stack[vm.top] = vm.stack[vm.top].wrapping_div(vm.stack[vm.top + 1]);
                 }
                 Math::Odd => {
                     trace!("{:04} odd", vm.pc);
@@ -194,16 +405,19 @@ fn run_interpreter(vm: &mut Vm) {
             },
             OpCode::Load => {
                 trace!("{:04} load {l} {a:04}", vm.pc);
+                vm.check_stack(1)?;
                 vm.top += 1;
                 vm.stack[vm.top] = vm.stack[vm.find_base(l) + a as usize];
             }
             OpCode::Store => {
                 trace!("{:04} store {l} {a:04}", vm.pc);
-                vm.stack[vm.find_base(l) + a as usize] = vm.stack[vm.top];
+                let base = vm.find_base(l);
+                vm.stack[base + a as usize] = vm.stack[vm.top];
                 vm.top -= 1;
             }
             OpCode::Call => {
                 trace!("{:04} call {l} {a:04}", vm.pc);
+                vm.check_stack(DATA_OFFSET)?;
                 // Generate new block mark
                 vm.stack[vm.top + 1] = vm.find_base(l) as i32;
                 vm.stack[vm.top + 2] = vm.base as i32;
@@ -218,9 +432,28 @@ fn run_interpreter(vm: &mut Vm) {
                 vm.base = vm.top + 1;
                 vm.pc = a as usize;
             }
+            OpCode::TailCall => {
+                trace!("{:04} tail_call {l} {a:04}", vm.pc);
+                // Replace the current frame's block mark. The static link is found
+                // through the current frame before it's overwritten, and the dynamic
+                // link and return address stay, so the callee returns to our caller.
+                let static_link = vm.find_base(l);
+                vm.stack[vm.base] = static_link as i32;
+                trace!("     block mark: SL={static_link}");
+
+                vm.top = vm.base - 1;
+                vm.pc = a as usize;
+            }
             OpCode::IncTop => {
                 trace!("{:04} inc_top {a:04}", vm.pc);
-                vm.top += a as usize;
+                vm.check_stack(a as usize)?;
+                // Variables start out as zero, and the block mark stays.
+                let start = (vm.top + 1).max(vm.base + DATA_OFFSET);
+                let end = vm.top + a as usize;
+                if start <= end {
+                    vm.stack[start..=end].fill(0);
+                }
+                vm.top = end;
             }
             OpCode::Jump => {
                 trace!("{:04} jump {a:04}", vm.pc);
@@ -238,8 +471,15 @@ fn run_interpreter(vm: &mut Vm) {
                 (vm.config.write)(vm.user_data(), vm.stack[vm.top]);
                 vm.top -= 1;
             }
+            OpCode::Dup => {
+                trace!("{:04} dup", vm.pc);
+                vm.check_stack(1)?;
+                vm.top += 1;
+                vm.stack[vm.top] = vm.stack[vm.top - 1];
+            }
             OpCode::Read => {
                 trace!("{:04} read", vm.pc);
+                vm.check_stack(1)?;
                 vm.top += 1;
                 vm.stack[vm.top] = (vm.config.read)(vm.user_data()).unwrap_or_default();
             }
@@ -250,4 +490,6 @@ fn run_interpreter(vm: &mut Vm) {
             break;
         }
     }
+
+    Ok(())
 }
@@ -0,0 +1,55 @@
+"use strict";
+
+/**
+ * Run the program. Every number written is passed to `io.write`, and
+ * every number read is returned by `io.read`, which returns `undefined`
+ * once the input runs out.
+ */
+function run(io = {}) {
+    const $write = io.write ?? console.log;
+    const $read = io.read ?? (() => undefined);
+
+    /* Integer division, truncating towards zero. */
+    function $div(a, b) {
+        if (b === 0) {
+            throw new Error("error[E0401]: division by zero");
+        }
+        return (a / b) | 0;
+    }
+
+    function main() {
+        let x = 0;
+        let i = 0;
+
+        let $block = 0;
+        for (;;) {
+            switch ($block) {
+            case 0:
+                x = 5;
+                if (x <= 3) { $block = 1; continue; }
+                $write(777);
+            case 1:
+                if (x >= 3) { $block = 2; continue; }
+                $write(999);
+            case 2:
+                i = 1;
+            case 3:
+                if (i > 11) { $block = 5; continue; }
+                if ((i & 1) === 0) { $block = 4; continue; }
+                $write(i);
+            case 4:
+                i = (i + 1) | 0;
+                $block = 3;
+                continue;
+            case 5:
+                return;
+            }
+        }
+    }
+
+    main();
+}
+
+if (typeof module !== "undefined") {
+    module.exports = run;
+}
@@ -0,0 +1,55 @@
+"use strict";
+
+/**
+ * Run the program. Every number written is passed to `io.write`, and
+ * every number read is returned by `io.read`, which returns `undefined`
+ * once the input runs out.
+ */
+function run(io = {}) {
+    const $write = io.write ?? console.log;
+    const $read = io.read ?? (() => undefined);
+
+    /* Integer division, truncating towards zero. */
+    function $div(a, b) {
+        if (b === 0) {
+            throw new Error("error[E0401]: division by zero");
+        }
+        return (a / b) | 0;
+    }
+
+    function main() {
+        let n = 0;
+        let a = 0;
+        let b = 0;
+        let i = 0;
+        let tmp = 0;
+
+        let $block = 0;
+        for (;;) {
+            switch ($block) {
+            case 0:
+                n = $read() | 0;
+                a = 0;
+                b = 1;
+                i = 2;
+            case 1:
+                if (i > n) { $block = 2; continue; }
+                tmp = b;
+                b = (a + b) | 0;
+                a = tmp;
+                i = (i + 1) | 0;
+                $block = 1;
+                continue;
+            case 2:
+                $write(b);
+                return;
+            }
+        }
+    }
+
+    main();
+}
+
+if (typeof module !== "undefined") {
+    module.exports = run;
+}
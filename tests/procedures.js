@@ -0,0 +1,43 @@
+"use strict";
+
+/**
+ * Run the program. Every number written is passed to `io.write`, and
+ * every number read is returned by `io.read`, which returns `undefined`
+ * once the input runs out.
+ */
+function run(io = {}) {
+    const $write = io.write ?? console.log;
+    const $read = io.read ?? (() => undefined);
+
+    /* Integer division, truncating towards zero. */
+    function $div(a, b) {
+        if (b === 0) {
+            throw new Error("error[E0401]: division by zero");
+        }
+        return (a / b) | 0;
+    }
+
+    function main() {
+        let x = 0;
+
+        function one() {
+            x = (x + 7) | 0;
+            $write(x);
+        }
+
+        function two() {
+            one();
+            $write(x);
+        }
+
+        x = 10;
+        two();
+        $write(x);
+    }
+
+    main();
+}
+
+if (typeof module !== "undefined") {
+    module.exports = run;
+}
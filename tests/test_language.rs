@@ -1,9 +1,16 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+
+use pl0::fuzz::{self, io_config, llvm_tool, Exit};
+
 #[test]
 fn test_hello_world() {
     const SOURCE: &str = include_str!("hello_world.pas");
     let chunk = pl0::compile("hello_world.pas", SOURCE).expect("failed to compile");
     let mut vm = pl0::Vm::new();
-    vm.eval(&chunk)
+    vm.eval(&chunk).expect("runtime error");
 }
 
 #[test]
@@ -11,7 +18,7 @@ fn test_expressions() {
     const SOURCE: &str = include_str!("expressions.pas");
     let chunk = pl0::compile("expressions.pas", SOURCE).expect("failed to compile");
     let mut vm = pl0::Vm::new();
-    vm.eval(&chunk)
+    vm.eval(&chunk).expect("runtime error");
 }
 
 #[test]
@@ -20,7 +27,7 @@ fn test_procedures() {
     let chunk = pl0::compile("procedures.pas", SOURCE).expect("failed to compile");
     chunk.dump();
     let mut vm = pl0::Vm::new();
-    vm.eval(&chunk);
+    vm.eval(&chunk).expect("runtime error");
 }
 
 #[test]
@@ -29,7 +36,7 @@ fn test_conditionals() {
     let chunk = pl0::compile("conditionals.pas", SOURCE).expect("failed to compile");
     chunk.dump();
     let mut vm = pl0::Vm::new();
-    vm.eval(&chunk);
+    vm.eval(&chunk).expect("runtime error");
 }
 
 // TODO: Test robot that can input like a user.
@@ -39,7 +46,7 @@ fn _test_read() {
     let chunk = pl0::compile("read.pas", SOURCE).expect("failed to compile");
     chunk.dump();
     let mut vm = pl0::Vm::new();
-    vm.eval(&chunk);
+    vm.eval(&chunk).expect("runtime error");
 }
 
 // TODO: Test robot that can input like a user.
@@ -49,7 +56,7 @@ fn _test_fibonacci() {
     let chunk = pl0::compile("fibonacci.pas", SOURCE).expect("failed to compile");
     chunk.dump();
     let mut vm = pl0::Vm::new();
-    vm.eval(&chunk);
+    vm.eval(&chunk).expect("runtime error");
 }
 
 #[test]
@@ -60,3 +67,1067 @@ fn test_parse_errors() {
         Err(err) => eprintln!("{}", err.pretty(SOURCE)),
     }
 }
+
+#[test]
+fn test_error_codes() {
+    const SOURCE: &str = include_str!("error.pl0");
+    let Err(err) = pl0::compile("error.pl0", SOURCE) else {
+        panic!("unexpected success")
+    };
+    assert_eq!(err.code(), pl0::ErrorCode::E0201);
+    assert_eq!(err.stage(), pl0::Stage::Parser);
+    assert_eq!(pl0::ErrorCode::lookup("E0201"), Some(pl0::ErrorCode::E0201));
+    assert_eq!(pl0::ErrorCode::lookup("e0201"), Some(pl0::ErrorCode::E0201));
+    assert_eq!(pl0::ErrorCode::lookup("E9999"), None);
+
+    let Err(err) = pl0::compile("<test>", "begin x := 1 end.") else {
+        panic!("unexpected success")
+    };
+    assert_eq!(err.code(), pl0::ErrorCode::E0301);
+}
+
+#[test]
+fn test_runtime_errors() {
+    const SOURCE: &str = "var x; begin x := 0; write 10 / x end.";
+    let chunk = pl0::compile("<test>", SOURCE).expect("failed to compile");
+    let mut vm = pl0::Vm::new();
+    let err = vm.eval(&chunk).expect_err("unexpected success");
+    assert_eq!(err.code(), pl0::ErrorCode::E0401);
+
+    // Recursion in tail position reuses the frame, so this one has more to do after the call.
+    const RECURSE: &str = "procedure forever; begin call forever; write 1 end; begin call forever end.";
+    let chunk = pl0::compile("<test>", RECURSE).expect("failed to compile");
+    let err = vm.eval(&chunk).expect_err("unexpected success");
+    assert_eq!(err.code(), pl0::ErrorCode::E0402);
+}
+
+#[test]
+fn test_json_diagnostics() {
+    const SOURCE: &str = "var x;\nbegin\n  call x\nend.";
+    let Err(err) = pl0::compile("call.pl0", SOURCE) else {
+        panic!("unexpected success")
+    };
+    let json = err.json(SOURCE).to_string();
+    assert!(json.starts_with(r#"{"severity":"error","code":"E0302","stage":"compiler","#));
+    assert!(json.contains(r#""file":"call.pl0""#));
+    assert!(json.contains(r#""span":{"start":20,"end":21,"line":3,"column":8,"end_line":3,"end_column":9}"#));
+    assert!(json.ends_with(r#""notes":[]}"#));
+    assert!(!json.contains('\n'));
+
+    const QUOTED: &str = "begin\n  x : 1\nend.";
+    let Err(err) = pl0::compile("quoted.pl0", QUOTED) else {
+        panic!("unexpected success")
+    };
+    let json = err.json(QUOTED).to_string();
+    assert!(json.contains(r#""message":"expected '=' after ':'""#));
+    assert!(json.contains(r#""line":2,"column":5"#));
+}
+
+#[test]
+fn test_debug_info() {
+    const SOURCE: &str = include_str!("procedures.pas");
+    let chunk = pl0::compile("procedures.pas", SOURCE).expect("failed to compile");
+    let debug = chunk.debug_info().expect("missing debug info");
+    assert_eq!(debug.file, "procedures.pas");
+    // Line table is sorted by address.
+    assert!(debug.lines.windows(2).all(|pair| pair[0].pc < pair[1].pc));
+    // The final return is attributed to the last statement of the main program.
+    assert_eq!(chunk.location(chunk.len() - 1).as_deref(), Some("procedures.pas:18"));
+
+    const DIV: &str = "var x;\n\nprocedure div;\nbegin\n    write 10 / x\nend;\n\nbegin\n    call div\nend.";
+    let chunk = pl0::compile("div.pl0", DIV).expect("failed to compile");
+    let mut vm = pl0::Vm::new();
+    let err = vm.eval(&chunk).expect_err("unexpected success");
+    let json = err.json(DIV).to_string();
+    assert!(json.contains(r#""file":"div.pl0""#));
+    assert!(json.contains(r#""line":5,"column":5"#));
+
+    // Without the source, or with a source that changed since, the line
+    // comes from the line table.
+    let shifted = format!("\n\n{DIV}");
+    let rewritten = "€".repeat(DIV.len());
+    for text in ["", shifted.as_str(), rewritten.as_str()] {
+        let json = err.json(text).to_string();
+        assert!(
+            json.contains(r#""line":5,"column":null,"end_line":null,"end_column":null}"#),
+            "{json}"
+        );
+        let pretty = err.pretty(text).to_string();
+        assert!(pretty.contains("file: div.pl0:5\n"), "{pretty}");
+    }
+}
+
+#[test]
+fn test_backtrace() {
+    const SOURCE: &str = "var x, y;
+procedure div;
+var a;
+begin
+    a := 17;
+    write a / x
+end;
+begin
+    x := 0;
+    y := 3;
+    call div
+end.";
+    let chunk = pl0::compile("div.pl0", SOURCE).expect("failed to compile");
+
+    let debug = chunk.debug_info().expect("missing debug info");
+    let main = debug.proc_by_addr(0).expect("missing main program");
+    assert_eq!(main.name, pl0::MAIN_PROC);
+    assert_eq!(main.level, 0);
+    let div = &debug.procs[1];
+    assert_eq!(div.name, "div");
+    assert_eq!(div.level, 1);
+    assert_eq!(div.vars.len(), 1);
+    assert_eq!((div.vars[0].name.as_str(), div.vars[0].offset), ("a", 3));
+
+    let mut vm = pl0::Vm::new();
+    let err = vm.eval(&chunk).expect_err("unexpected success");
+    assert_eq!(err.code(), pl0::ErrorCode::E0401);
+
+    let frames = vm.backtrace(&chunk);
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].proc.as_deref(), Some("div"));
+    assert_eq!(frames[0].location.as_deref(), Some("div.pl0:6"));
+    assert_eq!(frames[0].vars, vec![("a".to_string(), 17)]);
+    assert_eq!(frames[1].proc.as_deref(), Some(pl0::MAIN_PROC));
+    assert_eq!(frames[1].location.as_deref(), Some("div.pl0:11"));
+    assert_eq!(frames[1].vars, vec![("x".to_string(), 0), ("y".to_string(), 3)]);
+}
+
+#[test]
+fn test_disassemble() {
+    let source = include_str!("procedures.pas");
+    let chunk = pl0::compile("procedures.pas", source).expect("failed to compile");
+    let listing = chunk.disassemble_with_source(source);
+    let lines: Vec<&str> = listing.lines().map(str::trim_end).collect();
+
+    assert!(lines.contains(&"; procedure one, level 1"));
+    assert!(lines.contains(&"one:"));
+    assert!(lines.contains(&"two:"));
+    assert!(lines.contains(&"; procedures.pas:5: x := x + 7;"));
+    assert!(lines.contains(&"        cal 1 one               ; 0012"));
+    assert!(lines.contains(&"        opr 0 2                 ; 0005 add"));
+    assert!(lines.contains(&"        sto 1 3                 ; 0006 x"));
+    assert!(lines.contains(&"        jmp 0 .L0016            ; 0000"));
+}
+
+#[test]
+fn test_assemble_roundtrip() {
+    let sources = [
+        ("conditionals.pas", include_str!("conditionals.pas")),
+        ("count.pas", include_str!("count.pas")),
+        ("expressions.pas", include_str!("expressions.pas")),
+        ("fibonacci.pas", include_str!("fibonacci.pas")),
+        ("hello_world.pas", include_str!("hello_world.pas")),
+        ("procedures.pas", include_str!("procedures.pas")),
+        ("read.pas", include_str!("read.pas")),
+    ];
+
+    for (filename, source) in sources {
+        let mut chunk = pl0::compile(filename, source).expect("failed to compile");
+        let listing = chunk.disassemble_with_source(source);
+        let assembled = pl0::assemble(filename, listing.as_str()).expect("failed to assemble");
+        chunk.strip_debug_info();
+        assert_eq!(assembled, chunk, "{filename}");
+
+        // Without debug info the listing has only generated labels.
+        let listing = chunk.disassemble();
+        let assembled = pl0::assemble(filename, listing.as_str()).expect("failed to assemble");
+        assert_eq!(assembled, chunk, "{filename}");
+    }
+}
+
+#[test]
+fn test_assemble() {
+    const SOURCE: &str = "
+main:   jmp 0 start         ; forward reference
+start:
+        int 0 3
+        lit 0 7
+        opr 0 14            ; write
+        opr 0 0
+";
+    let chunk = pl0::assemble("seven.asm", SOURCE).expect("failed to assemble");
+    assert_eq!(chunk.len(), 5);
+    assert!(chunk.debug_info().is_none());
+    let mut vm = pl0::Vm::new();
+    vm.eval(&chunk).expect("runtime error");
+
+    let cases = [
+        ("push 0 7", pl0::ErrorCode::E0501, 0..4),
+        ("jmp 0 nowhere", pl0::ErrorCode::E0502, 6..13),
+        ("lit 7", pl0::ErrorCode::E0503, 4..5),
+        ("opr 0 99", pl0::ErrorCode::E0503, 6..8),
+        ("lod 256 3", pl0::ErrorCode::E0503, 4..7),
+        ("a: nop\na: nop", pl0::ErrorCode::E0504, 7..8),
+    ];
+    for (source, code, span) in cases {
+        let Err(err) = pl0::assemble("bad.asm", source) else {
+            panic!("assembled {source:?}");
+        };
+        assert_eq!(err.code(), code, "{source:?}");
+        assert_eq!(err.stage(), pl0::Stage::Assembler);
+        let json = err.json(source).to_string();
+        assert!(
+            json.contains(&format!("\"start\":{},\"end\":{}", span.start, span.end)),
+            "{source:?}: {json}"
+        );
+    }
+
+    // The VM holds 1024 instructions, and labels may follow the last one.
+    let full = format!("{}end:", "opr 0 0\n".repeat(1024));
+    assert_eq!(
+        pl0::assemble("full.asm", &full).expect("failed to assemble").len(),
+        1024
+    );
+    let source = "opr 0 0\n".repeat(1025);
+    let err = pl0::assemble("large.asm", &source).expect_err("assembled too many instructions");
+    assert_eq!(err.code(), pl0::ErrorCode::E0505);
+    let json = err.json(&source).to_string();
+    assert!(json.contains("\"start\":8192,\"end\":8195"), "{json}");
+}
+
+/// Run a chunk, collecting the numbers it writes.
+fn run_capture(chunk: &pl0::Chunk) -> pl0::Result<Vec<pl0::Num>> {
+    type Output = Rc<RefCell<Vec<pl0::Num>>>;
+
+    let output: Output = Rc::default();
+    let mut config = pl0::Pl0Config::new();
+    config.write = |user_data, num| {
+        let output = user_data.and_then(|data| data.downcast_ref::<Output>()).unwrap();
+        output.borrow_mut().push(num);
+    };
+    config.user_data = Some(Box::new(output.clone()));
+
+    let mut vm = pl0::Vm::from_config(config);
+    vm.eval(chunk)?;
+    let written = output.borrow().clone();
+    Ok(written)
+}
+
+#[test]
+fn test_large_literals() {
+    const SOURCE: &str = "const big = 100000;
+var x;
+begin
+    x := big * 3;
+    write x;
+    write 65535;
+    write big
+end.";
+    let chunk = pl0::compile("big.pl0", SOURCE).expect("failed to compile");
+    assert_eq!(chunk.constants(), &[300000, 100000]);
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![300000, 65535, 100000]);
+}
+
+#[test]
+fn test_binary_roundtrip() {
+    let sources = [
+        ("fibonacci.pas", include_str!("fibonacci.pas")),
+        ("procedures.pas", include_str!("procedures.pas")),
+        ("big.pl0", "begin write 100000; write 7 end."),
+    ];
+
+    for (filename, source) in sources {
+        let mut chunk = pl0::compile(filename, source).expect("failed to compile");
+        let bytes = chunk.to_bytes();
+        assert!(bytes.starts_with(pl0::MAGIC));
+        assert_eq!(pl0::Chunk::from_bytes(&bytes).expect("failed to load"), chunk);
+
+        chunk.strip_debug_info();
+        let stripped = chunk.to_bytes();
+        assert!(stripped.len() < bytes.len());
+        assert_eq!(pl0::Chunk::from_bytes(&stripped).expect("failed to load"), chunk);
+    }
+}
+
+#[test]
+fn test_binary_errors() {
+    let chunk = pl0::compile("big.pl0", "begin write 100000; write 7 end.").expect("failed to compile");
+    let bytes = chunk.to_bytes();
+
+    let load = |bytes: &[u8]| match pl0::Chunk::from_bytes(bytes) {
+        Ok(_) => panic!("loaded malformed chunk"),
+        Err(err) => {
+            assert_eq!(err.stage(), pl0::Stage::Loader);
+            err.code()
+        }
+    };
+
+    assert_eq!(load(b"begin write 1 end."), pl0::ErrorCode::E0601);
+    assert_eq!(load(&[]), pl0::ErrorCode::E0601);
+
+    let mut version = bytes.clone();
+    version[4] = 99;
+    assert_eq!(load(&version), pl0::ErrorCode::E0602);
+
+    for len in [6, 12, 15, bytes.len() - 1] {
+        assert_eq!(load(&bytes[..len]), pl0::ErrorCode::E0603, "truncated to {len}");
+    }
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(load(&trailing), pl0::ErrorCode::E0603);
+
+    // The first instruction starts after the header and instruction count.
+    let mut opcode = bytes.clone();
+    opcode[12] = 0x3f;
+    assert_eq!(load(&opcode), pl0::ErrorCode::E0603);
+
+    // Point the pooled literal past the end of the constant pool.
+    let pc = (0..chunk.len()).find(|pc| bytes[12 + pc * 4] == 0x09).unwrap();
+    let mut constant = bytes.clone();
+    constant[12 + pc * 4 + 2] = 1;
+    assert_eq!(load(&constant), pl0::ErrorCode::E0603);
+}
+
+#[test]
+fn test_stack_usage() {
+    const SOURCE: &str = include_str!("procedures.pas");
+    let chunk = pl0::compile("procedures.pas", SOURCE).expect("failed to compile");
+    let usage = chunk.stack_usage().expect("missing stack usage");
+
+    // Procedure `one` pushes two operands on top of its block mark.
+    let one = usage.proc(1).expect("missing procedure one");
+    assert_eq!((one.frame, one.max_height, one.need), (3, 5, Some(5)));
+    // Procedure `two` calls `one` with an empty operand stack.
+    let two = usage.proc(10).expect("missing procedure two");
+    assert_eq!((two.frame, two.max_height, two.need), (3, 4, Some(8)));
+    // The main program has one variable, and calls `two`.
+    assert_eq!(usage.max(), Some(12));
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![17, 17, 17]);
+
+    const RECURSIVE: &str = "var n;
+procedure countdown;
+begin
+    if n > 0 then begin n := n - 1; call countdown; write n end
+end;
+begin
+    n := 200;
+    call countdown
+end.";
+    let chunk = pl0::compile("countdown.pl0", RECURSIVE).expect("failed to compile");
+    let usage = chunk.stack_usage().expect("missing stack usage");
+    assert_eq!(usage.max(), None);
+    assert_eq!(usage.proc(1).expect("missing procedure countdown").max_height, 5);
+
+    let err = run_capture(&chunk).expect_err("unexpected success");
+    assert_eq!(err.code(), pl0::ErrorCode::E0402);
+    let pretty = err.pretty(RECURSIVE).to_string();
+    assert!(
+        pretty.contains("each call to 'countdown' uses up to 5 stack slots, and the stack has 512"),
+        "{pretty}"
+    );
+}
+
+#[test]
+fn test_optimized_output() {
+    const SOURCES: &[(&str, &str)] = &[
+        ("conditionals.pas", include_str!("conditionals.pas")),
+        ("expressions.pas", include_str!("expressions.pas")),
+        ("procedures.pas", include_str!("procedures.pas")),
+    ];
+    for opt_level in 1..=2 {
+        let options = pl0::CompileOptions {
+            opt_level,
+            ..pl0::CompileOptions::new()
+        };
+        for (filename, source) in SOURCES {
+            let plain = pl0::compile(filename, source).expect("failed to compile");
+            let (optimized, _) = pl0::compile_with_options(filename, source, &options).expect("failed to compile");
+            optimized.verify().expect("failed to verify");
+            assert!(optimized.len() <= plain.len(), "{filename} grew at -O{opt_level}");
+            assert_eq!(
+                run_capture(&optimized).expect("runtime error"),
+                run_capture(&plain).expect("runtime error"),
+                "{filename} at -O{opt_level}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_dead_code_output() {
+    const SOURCE: &str = "const debug = 0;
+var x;
+procedure trace;
+begin
+    write 0 - x
+end;
+procedure step;
+begin
+    if debug = 1 then call trace;
+    x := x + 1;
+    write x
+end;
+begin
+    x := 0;
+    while x < 3 do call step;
+    if odd debug then write 99
+end.";
+    let plain = pl0::compile("dead_code.pas", SOURCE).expect("failed to compile");
+    let options = pl0::CompileOptions {
+        opt_level: 2,
+        ..pl0::CompileOptions::new()
+    };
+    let (chunk, env, removed) = pl0::compile_with_report("dead_code.pas", SOURCE, &options).expect("failed to compile");
+
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![1, 2, 3]);
+    assert_eq!(run_capture(&plain).expect("runtime error"), vec![1, 2, 3]);
+
+    let removed: Vec<String> = removed
+        .iter()
+        .map(|removed| format!("{}: {removed}", removed.line))
+        .collect();
+    assert_eq!(
+        removed,
+        [
+            "3: procedure 'trace' is never called",
+            "9: statement can never run",
+            "16: statement can never run",
+        ]
+    );
+    let trace = env.find_proc("trace").expect("procedure symbol");
+    assert!(matches!(
+        env.symbol(trace).kind,
+        pl0::SymbolKind::Proc { addr: None, .. }
+    ));
+}
+
+#[test]
+fn test_inline_output() {
+    // `bump` is inlined into callers one and two levels below it, then `b`
+    // becomes a leaf and is inlined in turn.
+    const SOURCE: &str = "var g;
+procedure bump;
+    var t;
+begin
+    t := g;
+    g := t + 1
+end;
+procedure a;
+    var x;
+    procedure b;
+    begin
+        call bump;
+        x := x + g;
+        call bump
+    end;
+begin
+    x := 10;
+    call b;
+    call bump;
+    write x
+end;
+begin
+    g := 0;
+    call a;
+    write g
+end.";
+    let plain = pl0::compile("inline.pas", SOURCE).expect("failed to compile");
+    let options = pl0::CompileOptions {
+        opt_level: 2,
+        inline_threshold: 16,
+    };
+    let (chunk, _, removed) = pl0::compile_with_report("inline.pas", SOURCE, &options).expect("failed to compile");
+    chunk.verify().expect("failed to verify");
+
+    assert_eq!(run_capture(&plain).expect("runtime error"), vec![11, 3]);
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![11, 3]);
+    let disassembly = chunk.disassemble();
+    assert_eq!(disassembly.matches("cal ").count(), 1, "{disassembly}");
+
+    let removed: Vec<String> = removed.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        removed,
+        [
+            "procedure 'bump' is inlined at every call",
+            "procedure 'b' is inlined at every call",
+        ]
+    );
+}
+
+#[test]
+fn test_tail_calls() {
+    // Deeper than the stack could hold with a frame per call.
+    const SOURCE: &str = "var n, sum;
+procedure count;
+begin
+    if n > 0 then
+    begin
+        sum := sum + n;
+        n := n - 1;
+        call count
+    end
+end;
+begin
+    n := 10000;
+    sum := 0;
+    call count;
+    write sum
+end.";
+    let chunk = pl0::compile("count.pl0", SOURCE).expect("failed to compile");
+    assert!(chunk.disassemble().contains("tcl 1 count"));
+    let usage = chunk.stack_usage().expect("missing stack usage");
+    assert_eq!(usage.max(), Some(10));
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![50005000]);
+
+    // A nested procedure looping over its parent's variable, which
+    // needs the static link to be carried over to the new frame.
+    const NESTED: &str = "var total;
+procedure sum;
+    var i;
+    procedure step;
+    begin
+        if i > 0 then
+        begin
+            total := total + i;
+            i := i - 1;
+            call step
+        end
+    end;
+begin
+    i := 2000;
+    call step
+end;
+begin
+    total := 0;
+    call sum;
+    write total
+end.";
+    let chunk = pl0::compile("nested.pl0", NESTED).expect("failed to compile");
+    assert!(chunk.disassemble().contains("tcl 1 step"));
+    assert!(chunk.stack_usage().and_then(|usage| usage.max()).is_some());
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![2001000]);
+
+    // A tail call two levels out, from a nested procedure back to its parent.
+    const OUTWARD: &str = "var n;
+procedure ping;
+    var local;
+    procedure pong;
+    begin
+        write local;
+        n := n - 1;
+        call ping
+    end;
+begin
+    local := n * 10;
+    if n > 0 then call pong
+end;
+begin
+    n := 3;
+    call ping;
+    write n
+end.";
+    let chunk = pl0::compile("outward.pl0", OUTWARD).expect("failed to compile");
+    let disassembly = chunk.disassemble();
+    assert!(disassembly.contains("tcl 2 ping"), "{disassembly}");
+    // The nested procedure needs the frame of the one calling it.
+    assert!(disassembly.contains("cal 0 pong"), "{disassembly}");
+    assert_eq!(run_capture(&chunk).expect("runtime error"), vec![30, 20, 10, 0]);
+}
+
+#[test]
+fn test_propagation_opt_level() {
+    // Constants are only propagated through variables at -O2.
+    const SOURCE: &str = "var x;
+begin
+    x := 6;
+    write x * 7
+end.";
+
+    let disassemble = |opt_level| {
+        let options = pl0::CompileOptions {
+            opt_level,
+            ..pl0::CompileOptions::new()
+        };
+        let (chunk, _) = pl0::compile_with_options("propagate", SOURCE, &options).expect("failed to compile");
+        assert_eq!(run_capture(&chunk).expect("runtime error"), vec![42]);
+        chunk.disassemble()
+    };
+    for opt_level in 0..=1 {
+        let disassembly = disassemble(opt_level);
+        assert!(disassembly.contains("opr 0 4"), "-O{opt_level}:\n{disassembly}");
+    }
+    let disassembly = disassemble(2);
+    assert!(disassembly.contains("lit 0 42"), "{disassembly}");
+    assert!(!disassembly.contains("opr 0 4"), "{disassembly}");
+}
+
+#[test]
+fn test_term_associativity() {
+    // Multiplication and division group from the left, whether they're
+    // folded at compile time or not.
+    const SOURCE: &str = "var a, b;
+begin
+    a := 100;
+    b := 10;
+    write 100 / 10 / 5;
+    write 100 / 10 * 2;
+    write a / b / 5;
+    write a / b * 2;
+    write a * 3 / 7
+end.";
+
+    for opt_level in 0..=2 {
+        let options = pl0::CompileOptions {
+            opt_level,
+            ..pl0::CompileOptions::new()
+        };
+        let (chunk, _) = pl0::compile_with_options("terms", SOURCE, &options).expect("failed to compile");
+        let (written, result) = run_with_input(&chunk, &[]);
+        result.expect("runtime error");
+        assert_eq!(written, vec![2, 20, 2, 20, 42], "-O{opt_level}");
+    }
+}
+
+#[test]
+fn test_vars_start_at_zero() {
+    // Each call gets fresh variables, not the ones a previous call left
+    // on the stack, whether the procedure is inlined or not.
+    const SOURCE: &str = "var x;
+procedure p;
+    var t;
+begin
+    t := t + x;
+    write t
+end;
+procedure q;
+    var u;
+begin
+    u := u + 1;
+    write u;
+    call p
+end;
+begin
+    x := 3;
+    call p;
+    call p;
+    call q;
+    call q
+end.";
+
+    for opt_level in 0..=2 {
+        let options = pl0::CompileOptions {
+            opt_level,
+            ..pl0::CompileOptions::new()
+        };
+        let (chunk, _) = pl0::compile_with_options("fresh", SOURCE, &options).expect("failed to compile");
+        let (written, result) = run_with_input(&chunk, &[]);
+        result.expect("runtime error");
+        assert_eq!(written, vec![3, 3, 1, 3, 1, 3], "-O{opt_level}");
+
+        #[cfg(feature = "jit")]
+        {
+            let (written, result) = run_with_vm(&chunk, &[], |vm| vm.set_jit(false));
+            result.expect("runtime error");
+            assert_eq!(written, vec![3, 3, 1, 3, 1, 3], "-O{opt_level} without the JIT");
+        }
+    }
+}
+
+/// Run a chunk with the given input, collecting the numbers it writes
+/// up to the end or to the runtime error it stops with.
+fn run_with_input(chunk: &pl0::Chunk, input: &[pl0::Num]) -> (Vec<pl0::Num>, pl0::Result<()>) {
+    run_with_vm(chunk, input, |_| {})
+}
+
+/// Like [`run_with_input`], setting up the VM with `setup` first.
+fn run_with_vm(
+    chunk: &pl0::Chunk,
+    input: &[pl0::Num],
+    setup: impl FnOnce(&mut pl0::Vm),
+) -> (Vec<pl0::Num>, pl0::Result<()>) {
+    let (config, io) = io_config(input);
+    let mut vm = pl0::Vm::from_config(config);
+    setup(&mut vm);
+    let result = vm.eval(chunk).map(|_| ());
+    let written = io.borrow().1.clone();
+    (written, result)
+}
+
+/// Run a program in the VM with the given input, expecting it to succeed
+/// or to divide by zero.
+fn run_vm(name: &str, source: &str, input: &[pl0::Num]) -> (Vec<pl0::Num>, pl0::Result<()>) {
+    let chunk = pl0::compile(name, source).expect("failed to compile");
+    let (written, result) = run_with_input(&chunk, input);
+    if let Err(err) = &result {
+        assert_eq!(err.code(), pl0::ErrorCode::E0401, "{name}");
+    }
+    (written, result)
+}
+
+/// Programs run by the other backends and compared to the VM, with their input.
+const BACKEND_SOURCES: &[(&str, &str, &[pl0::Num])] = &[
+    ("hello_world", include_str!("hello_world.pas"), &[]),
+    ("expressions", include_str!("expressions.pas"), &[]),
+    ("conditionals", include_str!("conditionals.pas"), &[]),
+    ("procedures", include_str!("procedures.pas"), &[]),
+    ("fibonacci", include_str!("fibonacci.pas"), &[20]),
+    ("read", include_str!("read.pas"), &[-12]),
+    ("eof", include_str!("read.pas"), &[]),
+    (
+        "nested",
+        "var n, total;
+procedure sum;
+var i;
+procedure step;
+begin
+    if i > 0 then
+    begin
+        total := total + i * n;
+        i := i - 1;
+        call step
+    end
+end;
+begin
+i := n;
+call step
+end;
+begin
+read n;
+total := 0;
+call sum;
+write total
+end.",
+        &[1000],
+    ),
+    (
+        "wrapping",
+        "var x;
+begin
+x := 2147483647;
+write x + 1;
+write x * x;
+write -(x + 1);
+write (x + 1) / (0 - 1);
+write (0 - 7) / 2;
+if odd (0 - 3) then write 1
+end.",
+        &[],
+    ),
+    (
+        "div_zero",
+        "var x;\nbegin\n    write 1;\n    write 1 / x;\n    write 2\nend.",
+        &[],
+    ),
+];
+
+/// Translate the programs in [`BACKEND_SOURCES`] with `emit` at `-O0`
+/// and `-O2`, and compare what they do to the VM. The backends translate
+/// the IR, which only changes at `-O2`.
+///
+/// `run` builds the output, written to the path it's given, and returns
+/// the command that runs it, or `None` if it can only be built.
+fn check_backend_output<T: AsRef<[u8]>>(
+    extension: &str,
+    emit: impl Fn(&str, &str, &pl0::CompileOptions) -> pl0::Result<T>,
+    run: impl Fn(&Path) -> Option<Command>,
+) {
+    let dir = std::env::temp_dir().join(format!("pl0-{extension}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create temp dir");
+
+    for opt_level in [0, 2] {
+        let options = pl0::CompileOptions {
+            opt_level,
+            ..pl0::CompileOptions::new()
+        };
+        for (name, source, input) in BACKEND_SOURCES {
+            let output = emit(name, source, &options).unwrap_or_else(|err| panic!("{name}: {err}"));
+            let path = dir.join(format!("{name}-O{opt_level}.{extension}"));
+            let Some((written, success)) = run_output(&path, output.as_ref(), &run, input) else {
+                continue;
+            };
+            let (expected, result) = run_vm(name, source, input);
+            assert_eq!(written, expected, "{name} at -O{opt_level}");
+            assert_eq!(success, result.is_ok(), "{name} at -O{opt_level}");
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Write a backend's output to `path`, then build and run it with `run`,
+/// returning what it writes and whether it succeeded.
+fn run_output(
+    path: &Path,
+    output: &[u8],
+    run: impl Fn(&Path) -> Option<Command>,
+    input: &[pl0::Num],
+) -> Option<(Vec<pl0::Num>, bool)> {
+    std::fs::write(path, output).expect("write output");
+    run(path).map(|command| {
+        let (written, exit) = fuzz::run_process(command, input);
+        assert_ne!(exit, Exit::TimedOut, "{} ran for too long", path.display());
+        let written = written.unwrap_or_else(|| panic!("{} wrote something other than numbers", path.display()));
+        (written, exit == Exit::Success)
+    })
+}
+
+/// Build a C program or an assembly file with the system C compiler,
+/// returning the command that runs the executable.
+fn cc(path: &Path, args: &[&str]) -> Option<Command> {
+    let exe = path.with_extension("exe");
+    let mut cc = Command::new("cc");
+    cc.args(args).arg("-o").arg(&exe).arg(path);
+    if let Err(err) = fuzz::build(cc) {
+        panic!("failed to build {}:\n{err}", path.display());
+    }
+    Some(Command::new(exe))
+}
+
+#[test]
+fn test_c_output() {
+    if !fuzz::installed("cc") {
+        eprintln!("skipping C output test, no C compiler");
+        return;
+    }
+    check_backend_output("c", pl0::compile_to_c, |path| {
+        cc(path, &["-std=c99", "-O2", "-Wall", "-Werror"])
+    });
+}
+
+#[test]
+fn test_wasm_output() {
+    if !fuzz::installed("node") {
+        eprintln!("skipping WebAssembly output test, no Node.js");
+        return;
+    }
+    check_backend_output("wasm", pl0::compile_to_wasm, |path| {
+        let mut command = Command::new("node");
+        command.args(["-e", fuzz::WASM_RUNNER]).arg(path);
+        Some(command)
+    });
+}
+
+#[test]
+fn test_js_output() {
+    if !fuzz::installed("node") {
+        eprintln!("skipping JavaScript output test, no Node.js");
+        return;
+    }
+    check_backend_output("js", pl0::compile_to_js, |path| {
+        let mut command = Command::new("node");
+        command.args(["-e", fuzz::JS_RUNNER]).arg(path);
+        Some(command)
+    });
+}
+
+/// JavaScript translations of the example programs are kept next to them,
+/// to show what changes in the output. Run the test with `PL0_BLESS=1` to
+/// update them.
+#[test]
+fn test_js_golden() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    for name in ["conditionals", "fibonacci", "procedures"] {
+        let source_path = dir.join(format!("{name}.pas"));
+        let source = std::fs::read_to_string(&source_path).expect("read source");
+        let js = pl0::compile_to_js(&format!("{name}.pas"), &source, &pl0::CompileOptions::new())
+            .expect("failed to compile to JavaScript");
+
+        let golden_path = source_path.with_extension("js");
+        if std::env::var_os("PL0_BLESS").is_some() {
+            std::fs::write(&golden_path, &js).expect("write golden file");
+            continue;
+        }
+        let golden = std::fs::read_to_string(&golden_path).expect("read golden file");
+        assert_eq!(
+            js, golden,
+            "{name}.js is out of date, run with PL0_BLESS=1 to update it"
+        );
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn test_x86_output() {
+    if !fuzz::installed("cc") {
+        eprintln!("skipping x86-64 output test, no C compiler");
+        return;
+    }
+    check_backend_output("s", pl0::compile_to_x86, |path| cc(path, &[]));
+
+    // Tail calls run in constant stack space, while other deep recursion
+    // runs out of stack like in the VM.
+    const DEEP: &str = "var n;
+procedure down;
+begin
+    if n > 0 then
+    begin
+        n := n - 1;
+        call down
+    end
+end;
+procedure forever;
+begin
+    call forever;
+    write 1
+end;
+begin
+    read n;
+    call down;
+    write n;
+    call forever
+end.";
+    let asm = pl0::compile_to_x86("deep", DEEP, &pl0::CompileOptions::new()).expect("failed to compile to assembly");
+    let path = std::env::temp_dir().join(format!("pl0-deep-{}.s", std::process::id()));
+    let output = run_output(&path, asm.as_bytes(), |path| cc(path, &[]), &[10_000_000]);
+    assert_eq!(output, Some((vec![0], false)));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(path.with_extension("exe"));
+}
+
+#[test]
+#[cfg(feature = "jit")]
+fn test_jit_output() {
+    // Runtime errors deep in the call chain, whose notes show the frames.
+    const ERRORS: &[(&str, &str, &[pl0::Num])] = &[
+        (
+            "deep",
+            "var n;
+procedure down;
+    var depth;
+begin
+    depth := n;
+    if n > 0 then
+    begin
+        n := n - 1;
+        call down;
+        write depth
+    end
+end;
+begin
+    read n;
+    call down
+end.",
+            &[12],
+        ),
+        (
+            "overflow",
+            "var n;
+procedure down;
+    var depth;
+begin
+    depth := n;
+    n := n + 1;
+    call down;
+    write depth
+end;
+begin
+    call down
+end.",
+            &[],
+        ),
+        (
+            "countdown",
+            "var n, total;
+procedure down;
+begin
+    total := total + 100 / n;
+    n := n - 1;
+    call down
+end;
+begin
+    read n;
+    call down
+end.",
+            &[5],
+        ),
+    ];
+
+    for opt_level in 0..=2 {
+        let options = pl0::CompileOptions {
+            opt_level,
+            ..pl0::CompileOptions::new()
+        };
+        for (name, source, input) in BACKEND_SOURCES.iter().chain(ERRORS) {
+            let (chunk, _) = pl0::compile_with_options(name, source, &options).expect("failed to compile");
+            let (expected, expected_result) = run_with_vm(&chunk, input, |vm| vm.set_jit(false));
+            let (written, result) = run_with_vm(&chunk, input, |_| {});
+
+            assert_eq!(written, expected, "{name} at -O{opt_level}");
+            let describe = |result: pl0::Result<()>| result.map_err(|err| err.json(source).to_string());
+            assert_eq!(describe(result), describe(expected_result), "{name} at -O{opt_level}");
+        }
+    }
+}
+
+#[test]
+fn test_llvm_output() {
+    if llvm_tool("llvm-as").is_none() {
+        eprintln!("skipping LLVM output test, no llvm-as");
+        return;
+    }
+    check_backend_output("ll", pl0::compile_to_llvm, |path| {
+        let bitcode = path.with_extension("bc");
+        let mut llvm_as = llvm_tool("llvm-as")?;
+        llvm_as.arg(path).arg("-o").arg(&bitcode);
+        if let Err(err) = fuzz::build(llvm_as) {
+            panic!("invalid LLVM IR in {}:\n{err}", path.display());
+        }
+
+        // Run it too, if the interpreter is around.
+        let mut lli = llvm_tool("lli")?;
+        lli.arg(bitcode);
+        Some(lli)
+    });
+}
+
+#[test]
+fn test_interpreter_output() {
+    for (name, source, input) in BACKEND_SOURCES {
+        let (config, io) = io_config(input);
+        let result = pl0::Interpreter::from_config(config).run(name, source);
+        let written = io.borrow().1.clone();
+
+        // The interpreter is the reference, so every optimization level
+        // has to agree with it, including where errors happen.
+        for opt_level in 0..=2 {
+            let options = pl0::CompileOptions {
+                opt_level,
+                ..pl0::CompileOptions::new()
+            };
+            let (chunk, _) = pl0::compile_with_options(name, source, &options).expect("failed to compile");
+            let (expected, expected_result) = run_with_input(&chunk, input);
+
+            assert_eq!(written, expected, "{name} at -O{opt_level}");
+            let code = |result: &pl0::Result<()>| result.as_ref().map_err(|err| err.code()).copied();
+            assert_eq!(code(&result), code(&expected_result), "{name} at -O{opt_level}");
+        }
+    }
+}
+
+/// Fail with the smallest program that still shows a mismatch.
+fn check_fuzz_cases(fuzzer: &fuzz::Fuzzer, seeds: std::ops::Range<u64>) {
+    for seed in seeds {
+        let case = fuzz::FuzzCase::generate(seed);
+        if let Some(mismatch) = fuzzer.check(&case) {
+            let case = fuzzer.minimize(&case, mismatch.engine);
+            let mismatch = fuzzer.check_engine(&case, mismatch.engine).unwrap_or(mismatch);
+            panic!(
+                "seed {seed}: {} did {}, the interpreter did {}\ninput: {:?}\n{}",
+                mismatch.engine,
+                mismatch.actual,
+                mismatch.expected,
+                case.input(),
+                case.source()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_fuzz_in_process() {
+    let engines = (0..=2).map(fuzz::Engine::Vm);
+    #[cfg(feature = "jit")]
+    let engines = engines.chain((0..=2).map(fuzz::Engine::Jit));
+    check_fuzz_cases(&fuzz::Fuzzer::with_engines(engines.collect()), 0..300);
+}
+
+#[test]
+fn test_fuzz_all_engines() {
+    // Compiling with external tools is slow, so only a few programs.
+    check_fuzz_cases(&fuzz::Fuzzer::new(), 1000..1010);
+}